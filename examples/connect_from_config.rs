@@ -64,12 +64,9 @@ async fn bump_counter(db: impl DatabaseClient) -> Result<String> {
 
 #[tokio::main]
 async fn main() {
-    let db = new_client_from_config(libsql_client::Config {
-        url: url::Url::parse("libsql://localhost:8080").unwrap(),
-        auth_token: None,
-    })
-    .await
-    .unwrap();
+    let db = new_client_from_config(libsql_client::Config::new("libsql://localhost:8080").unwrap())
+        .await
+        .unwrap();
     let response = bump_counter(db)
         .await
         .unwrap_or_else(|e| format!("Error: {e}"));
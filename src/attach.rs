@@ -0,0 +1,398 @@
+//! `AttachedTenants` implements multi-tenancy with a database file per tenant,
+//! attached on demand to a single connection of the local backend.
+
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::task::{Poll, Waker};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Maximum number of attached databases allowed by SQLite by default
+const SQLITE_MAX_ATTACHED: usize = 10;
+
+#[derive(Default)]
+struct Slot {
+    /// Tenant whose database is attached under the alias of the slot
+    tenant: Option<String>,
+    /// Tenant whose database is being attached in place of the current one
+    attaching: Option<String>,
+    last_used: u64,
+    /// Number of statements in progress using the alias, which must not be detached meanwhile
+    pins: usize,
+}
+
+/// Manager of per-tenant database files, attached to a client under
+/// a pool of schema aliases (`tenant0`, `tenant1`, ...). Once all slots are taken,
+/// the least recently used tenant is detached to make room for a new one.
+/// A tenant is never detached while one of its statements is in progress:
+/// a new tenant waits for a slot to become free instead.
+///
+/// The database file of a tenant is `{directory}/{tenant}.db`, created if it does not exist.
+/// It's meant for backends which keep a single connection, i.e. the local backend;
+/// `ATTACH` is not allowed within a transaction.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::DatabaseClient;
+///   use libsql_client::attach::AttachedTenants;
+///
+///   let db = libsql_client::local::Client::in_memory()?;
+///   let tenants = AttachedTenants::new(&db, "/var/lib/tenants", 4);
+///   let tenant = tenants.tenant("acme")?;
+///   // Executed as SELECT * FROM tenant0.users, with acme.db attached as tenant0
+///   tenant.execute("SELECT * FROM users").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct AttachedTenants<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    directory: PathBuf,
+    slots: RefCell<Vec<Slot>>,
+    clock: Cell<u64>,
+    waiters: RefCell<Vec<Waker>>,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> AttachedTenants<'a, Client> {
+    /// Creates a manager attaching at most `slots` tenant databases at a time.
+    /// The number of slots is capped at 10, the default limit of attached databases in SQLite.
+    pub fn new(client: &'a Client, directory: impl Into<PathBuf>, slots: usize) -> Self {
+        let slots = slots.clamp(1, SQLITE_MAX_ATTACHED);
+        Self {
+            client,
+            directory: directory.into(),
+            slots: RefCell::new((0..slots).map(|_| Slot::default()).collect()),
+            clock: Cell::new(0),
+            waiters: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns a client which routes statements to the database of `tenant`.
+    /// The database is attached lazily, before each statement is executed,
+    /// so that it can be detached in the meantime without breaking the client.
+    /// Tenant names may only contain ASCII letters, digits, `_` and `-`.
+    pub fn tenant(&self, tenant: impl Into<String>) -> Result<TenantClient<'_, 'a, Client>> {
+        let tenant = tenant.into();
+        let valid = !tenant.is_empty()
+            && tenant
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-');
+        if !valid {
+            anyhow::bail!("Invalid tenant name: {tenant:?}");
+        }
+        Ok(TenantClient {
+            tenants: self,
+            tenant,
+        })
+    }
+
+    /// Returns the tenants currently attached
+    pub fn attached(&self) -> Vec<String> {
+        self.slots
+            .borrow()
+            .iter()
+            .filter_map(|slot| slot.tenant.clone())
+            .collect()
+    }
+
+    /// Detaches the database of `tenant`, if it is attached.
+    /// Fails if one of its statements is in progress.
+    pub async fn detach(&self, tenant: &str) -> Result<()> {
+        let idx = {
+            let mut slots = self.slots.borrow_mut();
+            let Some(idx) = slots
+                .iter()
+                .position(|slot| slot.tenant.as_deref() == Some(tenant))
+            else {
+                return Ok(());
+            };
+            let slot = &mut slots[idx];
+            if slot.pins > 0 || slot.attaching.is_some() {
+                anyhow::bail!("The database of tenant {tenant} is in use");
+            }
+            // Keeps other tenants from claiming the slot until it's detached
+            slot.attaching = Some(tenant.to_string());
+            idx
+        };
+        let _claim = Claim { tenants: self, idx };
+        self.detach_slot(idx).await
+    }
+
+    fn alias(idx: usize) -> String {
+        format!("tenant{idx}")
+    }
+
+    fn tick(&self) -> u64 {
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        now
+    }
+
+    fn wake_waiters(&self) {
+        let waiters = std::mem::take(&mut *self.waiters.borrow_mut());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+
+    async fn detach_slot(&self, idx: usize) -> Result<()> {
+        self.client
+            .execute(format!("DETACH DATABASE {}", Self::alias(idx)))
+            .await?;
+        if let Some(tenant) = self.slots.borrow_mut()[idx].tenant.take() {
+            tracing::debug!("Detached the database of tenant {tenant}");
+        }
+        Ok(())
+    }
+
+    /// Attaches the database of `tenant` if needed, returning its schema alias,
+    /// which stays attached until the returned guard is dropped
+    async fn ensure_attached(&self, tenant: &str) -> Result<Attachment<'_, 'a, Client>> {
+        let now = self.tick();
+        let claimed = std::future::poll_fn(|cx| {
+            let mut slots = self.slots.borrow_mut();
+            if let Some(idx) = slots
+                .iter()
+                .position(|slot| slot.tenant.as_deref() == Some(tenant) && slot.attaching.is_none())
+            {
+                let slot = &mut slots[idx];
+                slot.pins += 1;
+                slot.last_used = now;
+                return Poll::Ready(Ok(idx));
+            }
+            let being_attached = slots
+                .iter()
+                .any(|slot| slot.attaching.as_deref() == Some(tenant));
+            // A free slot, or the least recently used one no statement is using
+            let free = slots
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| slot.pins == 0 && slot.attaching.is_none())
+                .min_by_key(|(_, slot)| (slot.tenant.is_some(), slot.last_used))
+                .map(|(idx, _)| idx);
+            match free {
+                Some(idx) if !being_attached => {
+                    slots[idx].attaching = Some(tenant.to_string());
+                    Poll::Ready(Err(idx))
+                }
+                _ => {
+                    let mut waiters = self.waiters.borrow_mut();
+                    if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                        waiters.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        let idx = match claimed {
+            Ok(idx) => {
+                return Ok(Attachment {
+                    tenants: self,
+                    idx,
+                    alias: Self::alias(idx),
+                })
+            }
+            Err(idx) => idx,
+        };
+        // Releases the slot if attaching fails or is cancelled
+        let claim = Claim { tenants: self, idx };
+        let alias = Self::alias(idx);
+        if self.slots.borrow()[idx].tenant.is_some() {
+            self.detach_slot(idx).await?;
+        }
+        let path = self.directory.join(format!("{tenant}.db"));
+        self.client
+            .execute(Statement::with_args(
+                format!("ATTACH DATABASE ? AS {alias}"),
+                &[path.to_string_lossy().into_owned()],
+            ))
+            .await?;
+        tracing::debug!("Attached the database of tenant {tenant} as {alias}");
+        {
+            let mut slots = self.slots.borrow_mut();
+            let slot = &mut slots[idx];
+            slot.tenant = Some(tenant.to_string());
+            slot.last_used = now;
+            slot.pins += 1;
+        }
+        drop(claim);
+        Ok(Attachment {
+            tenants: self,
+            idx,
+            alias,
+        })
+    }
+}
+
+/// Slot being attached or detached, released once dropped
+struct Claim<'t, 'a, Client: DatabaseClient + ?Sized> {
+    tenants: &'t AttachedTenants<'a, Client>,
+    idx: usize,
+}
+
+impl<Client: DatabaseClient + ?Sized> Drop for Claim<'_, '_, Client> {
+    fn drop(&mut self) {
+        self.tenants.slots.borrow_mut()[self.idx].attaching = None;
+        self.tenants.wake_waiters();
+    }
+}
+
+/// Alias of an attached tenant database, which is not detached until this is dropped
+struct Attachment<'t, 'a, Client: DatabaseClient + ?Sized> {
+    tenants: &'t AttachedTenants<'a, Client>,
+    idx: usize,
+    alias: String,
+}
+
+impl<Client: DatabaseClient + ?Sized> Drop for Attachment<'_, '_, Client> {
+    fn drop(&mut self) {
+        self.tenants.slots.borrow_mut()[self.idx].pins -= 1;
+        self.tenants.wake_waiters();
+    }
+}
+
+/// Client which routes statements to the database of a single tenant,
+/// by qualifying unqualified table names with its schema alias.
+/// Created with `AttachedTenants::tenant()`.
+pub struct TenantClient<'t, 'a, Client: DatabaseClient + ?Sized> {
+    tenants: &'t AttachedTenants<'a, Client>,
+    tenant: String,
+}
+
+impl<Client: DatabaseClient + ?Sized> TenantClient<'_, '_, Client> {
+    /// Returns the name of the tenant
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Rewrites a statement to target the given schema, keeping its arguments
+    fn route(stmt: impl Into<Statement>, alias: &str) -> Statement {
+        let mut stmt = stmt.into();
+        stmt.sql = crate::sql::qualify_tables(&stmt.sql, alias);
+        stmt
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient + ?Sized> DatabaseClient for TenantClient<'_, '_, Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let attachment = self.tenants.ensure_attached(&self.tenant).await?;
+        self.tenants
+            .client
+            .execute(Self::route(stmt, &attachment.alias))
+            .await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let attachment = self.tenants.ensure_attached(&self.tenant).await?;
+        let stmts: Vec<Statement> = stmts
+            .into_iter()
+            .map(|s| Self::route(s, &attachment.alias))
+            .collect();
+        self.tenants.client.raw_batch(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.tenants.client.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.tenants.client.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.tenants.client.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.tenants.client.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.tenants.client.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libsql-attach-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_tenant_is_detached() {
+        let db = crate::local::Client::in_memory().unwrap();
+        let tenants = AttachedTenants::new(&db, directory("lru"), 2);
+        for tenant in ["a", "b", "c"] {
+            let client = tenants.tenant(tenant).unwrap();
+            client
+                .execute("CREATE TABLE IF NOT EXISTS t (x)")
+                .await
+                .unwrap();
+            client
+                .execute(Statement::with_args("INSERT INTO t VALUES (?)", &[tenant]))
+                .await
+                .unwrap();
+        }
+        let mut attached = tenants.attached();
+        attached.sort();
+        assert_eq!(attached, ["b", "c"]);
+        // The database of a is attached again, with its rows
+        let result = tenants
+            .tenant("a")
+            .unwrap()
+            .execute("SELECT x FROM t")
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert!(!tenants.attached().contains(&"b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn invalid_tenant_names_are_rejected() {
+        let db = crate::local::Client::in_memory().unwrap();
+        let tenants = AttachedTenants::new(&db, directory("names"), 1);
+        for name in ["", "a.b", "../a", "a b"] {
+            assert!(tenants.tenant(name).is_err(), "{name:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn tenant_in_use_is_not_detached() {
+        let db = crate::local::Client::in_memory().unwrap();
+        let tenants = AttachedTenants::new(&db, directory("pinned"), 1);
+        let attachment = tenants.ensure_attached("a").await.unwrap();
+        assert!(tenants.detach("a").await.is_err());
+        {
+            // Another tenant waits for the slot instead of taking it over
+            let mut other = pin!(tenants.ensure_attached("b"));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(other.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(tenants.attached(), ["a"]);
+        }
+        drop(attachment);
+        let attachment = tenants.ensure_attached("b").await.unwrap();
+        assert_eq!(attachment.alias, "tenant0");
+        assert_eq!(tenants.attached(), ["b"]);
+        drop(attachment);
+        tenants.detach("b").await.unwrap();
+        assert!(tenants.attached().is_empty());
+    }
+}
@@ -0,0 +1,511 @@
+//! `BatchBuilder` builds batches whose steps are executed depending on the outcome
+//! of previous steps, e.g. to roll back a transaction if one of its statements failed,
+//! without resorting to the protocol types.
+//!
+//! Steps can be named with labels, so that conditions and results refer to them by
+//! name rather than by their position in the batch.
+//!
+//! `DatabaseClient::batch_with_savepoints()` builds on top of conditional batches
+//! to execute a transaction whose failing statements are skipped.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::batch::BatchBuilder;
+//!   use libsql_client::DatabaseClient;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let batch = BatchBuilder::new()
+//!       .begin()
+//!       .step("UPDATE accounts SET balance = balance - 10 WHERE id = 1")
+//!       .step_if_ok("UPDATE accounts SET balance = balance + 10 WHERE id = 2")
+//!       .commit()
+//!       .step("SELECT balance FROM accounts WHERE id = 1")
+//!       .label("balance")
+//!       .build()?;
+//!   let result = db.run_batch(batch.clone()).await?;
+//!   let balance: Vec<i64> = batch.rows_as(&result, "balance")?;
+//!   # Ok(())
+//!   # }
+//! ```
+//!
+//! Every step reports the rows it returned, including writes with a `RETURNING` clause,
+//! whichever its position in the batch. `Batch::result()` and `Batch::rows_as()` read them
+//! by label or position, e.g. the IDs assigned by an `INSERT ... RETURNING id`.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{proto, BatchResult, ResultSet, Statement};
+
+/// Reference to a step of a batch, by position or by label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepRef {
+    Index(usize),
+    Label(String),
+}
+
+impl From<usize> for StepRef {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl From<&str> for StepRef {
+    fn from(label: &str) -> Self {
+        Self::Label(label.to_string())
+    }
+}
+
+/// Condition on the outcome of previous steps, which decides if a step is executed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The step was executed successfully
+    Ok(StepRef),
+    /// The step was executed and failed
+    Error(StepRef),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    /// The step was executed successfully
+    pub fn ok(step: impl Into<StepRef>) -> Self {
+        Self::Ok(step.into())
+    }
+
+    /// The step was executed and failed
+    pub fn error(step: impl Into<StepRef>) -> Self {
+        Self::Error(step.into())
+    }
+
+    /// Replaces labels with positions, checking that only previous steps are referenced
+    fn resolve(&self, labels: &HashMap<String, usize>, current: usize) -> Result<Self> {
+        let index = |step: &StepRef| -> Result<StepRef> {
+            let index = match step {
+                StepRef::Index(index) => *index,
+                StepRef::Label(label) => *labels
+                    .get(label)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown step label: {label}"))?,
+            };
+            if index >= current {
+                anyhow::bail!("Step {current} depends on step {index}, which is not before it");
+            }
+            Ok(StepRef::Index(index))
+        };
+        Ok(match self {
+            Self::Ok(step) => Self::Ok(index(step)?),
+            Self::Error(step) => Self::Error(index(step)?),
+            Self::Not(cond) => Self::Not(Box::new(cond.resolve(labels, current)?)),
+            Self::And(conds) => Self::And(
+                conds
+                    .iter()
+                    .map(|c| c.resolve(labels, current))
+                    .collect::<Result<_>>()?,
+            ),
+            Self::Or(conds) => Self::Or(
+                conds
+                    .iter()
+                    .map(|c| c.resolve(labels, current))
+                    .collect::<Result<_>>()?,
+            ),
+        })
+    }
+
+    /// Evaluates a resolved condition against the outcomes of executed steps:
+    /// `Some(true)` if a step succeeded, `Some(false)` if it failed, `None` if it was skipped
+    pub(crate) fn eval(&self, outcomes: &[Option<bool>]) -> bool {
+        let outcome = |step: &StepRef| match step {
+            StepRef::Index(index) => outcomes.get(*index).copied().flatten(),
+            StepRef::Label(_) => None,
+        };
+        match self {
+            Self::Ok(step) => outcome(step) == Some(true),
+            Self::Error(step) => outcome(step) == Some(false),
+            Self::Not(cond) => !cond.eval(outcomes),
+            Self::And(conds) => conds.iter().all(|c| c.eval(outcomes)),
+            Self::Or(conds) => conds.iter().any(|c| c.eval(outcomes)),
+        }
+    }
+
+    /// Converts a resolved condition, shifting steps by `offset`
+    #[cfg_attr(
+        not(any(feature = "hrana_backend", feature = "workers_backend")),
+        allow(dead_code)
+    )]
+    pub(crate) fn to_proto(&self, offset: usize) -> proto::BatchCond {
+        let step = |step: &StepRef| match step {
+            StepRef::Index(index) => (index + offset) as i32,
+            StepRef::Label(_) => i32::MAX,
+        };
+        match self {
+            Self::Ok(s) => proto::BatchCond::Ok { step: step(s) },
+            Self::Error(s) => proto::BatchCond::Error { step: step(s) },
+            Self::Not(cond) => proto::BatchCond::Not {
+                cond: Box::new(cond.to_proto(offset)),
+            },
+            Self::And(conds) => proto::BatchCond::And {
+                conds: conds.iter().map(|c| c.to_proto(offset)).collect(),
+            },
+            Self::Or(conds) => proto::BatchCond::Or {
+                conds: conds.iter().map(|c| c.to_proto(offset)).collect(),
+            },
+        }
+    }
+
+    /// Converts a resolved condition to Hrana v2, shifting steps by `offset`
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn to_v2(&self, offset: usize) -> proto::v2::BatchCond {
+        let step = |step: &StepRef| match step {
+            StepRef::Index(index) => (index + offset) as u32,
+            StepRef::Label(_) => u32::MAX,
+        };
+        match self {
+            Self::Ok(s) => proto::v2::BatchCond::Ok { step: step(s) },
+            Self::Error(s) => proto::v2::BatchCond::Error { step: step(s) },
+            Self::Not(cond) => proto::v2::BatchCond::Not {
+                cond: Box::new(cond.to_v2(offset)),
+            },
+            Self::And(conds) => proto::v2::BatchCond::And {
+                conds: conds.iter().map(|c| c.to_v2(offset)).collect(),
+            },
+            Self::Or(conds) => proto::v2::BatchCond::Or {
+                conds: conds.iter().map(|c| c.to_v2(offset)).collect(),
+            },
+        }
+    }
+}
+
+impl std::ops::Not for Condition {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+}
+
+/// Step of a batch
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub stmt: Statement,
+    /// Condition for executing the step, which is always executed if `None`
+    pub condition: Option<Condition>,
+    pub label: Option<String>,
+}
+
+/// Batch of conditional steps, built with `BatchBuilder` and executed with
+/// `DatabaseClient::run_batch()`. Results are reported for each step, in order,
+/// and skipped steps have neither a result nor an error.
+#[derive(Clone, Debug)]
+pub struct Batch {
+    steps: Vec<Step>,
+}
+
+impl Batch {
+    /// Returns the steps of the batch, with conditions referring to steps by position
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Returns the position of the step with the given label
+    pub fn step_index(&self, label: &str) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|step| step.label.as_deref() == Some(label))
+    }
+
+    /// Whether some steps are conditional, i.e. the batch cannot be sent as plain statements
+    pub fn is_conditional(&self) -> bool {
+        self.steps.iter().any(|step| step.condition.is_some())
+    }
+
+    /// Returns the result of a step of the batch once it was executed with `run_batch()`,
+    /// with the rows it returned, e.g. with a `RETURNING` clause. Fails with the error
+    /// of the step if it failed, and returns `None` if it was skipped by its condition.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   use libsql_client::batch::BatchBuilder;
+    ///   use libsql_client::DatabaseClient;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let batch = BatchBuilder::new()
+    ///       .step("INSERT INTO users (name) VALUES ('ann') RETURNING id")
+    ///       .label("ann")
+    ///       .step("INSERT INTO users (name) VALUES ('bob') RETURNING id")
+    ///       .label("bob")
+    ///       .build()?;
+    ///   let result = db.run_batch(batch.clone()).await?;
+    ///   let ann = batch.result(&result, "ann")?;
+    ///   let bob = batch.result(&result, 1)?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn result(
+        &self,
+        result: &BatchResult,
+        step: impl Into<StepRef>,
+    ) -> Result<Option<ResultSet>> {
+        let idx = self.position(step.into())?;
+        if let Some(Some(error)) = result.step_errors.get(idx) {
+            anyhow::bail!("Step {idx} failed: {}", error.message);
+        }
+        Ok(result
+            .step_results
+            .get(idx)
+            .and_then(Option::as_ref)
+            .map(|r| ResultSet::from(r.clone())))
+    }
+
+    /// Deserializes the rows returned by a step into `T`, see `result()` and
+    /// `ResultSet::deserialize_rows()`. Fails if the step failed or was skipped.
+    pub fn rows_as<T: serde::de::DeserializeOwned>(
+        &self,
+        result: &BatchResult,
+        step: impl Into<StepRef>,
+    ) -> Result<Vec<T>> {
+        let idx = self.position(step.into())?;
+        match self.result(result, idx)? {
+            Some(rows) => rows.deserialize_rows(),
+            None => anyhow::bail!("Step {idx} was skipped"),
+        }
+    }
+
+    fn position(&self, step: StepRef) -> Result<usize> {
+        match step {
+            StepRef::Index(idx) if idx < self.steps.len() => Ok(idx),
+            StepRef::Index(idx) => anyhow::bail!("The batch has no step {idx}"),
+            StepRef::Label(label) => self
+                .step_index(&label)
+                .ok_or_else(|| anyhow::anyhow!("No step is labeled {label}")),
+        }
+    }
+
+    /// Returns the statements of the batch, in order
+    pub fn into_statements(self) -> Vec<Statement> {
+        self.steps.into_iter().map(|step| step.stmt).collect()
+    }
+
+    /// Converts the batch to the protocol representation, after the `init` statements
+    /// which are executed unconditionally
+    #[cfg_attr(
+        not(any(feature = "hrana_backend", feature = "workers_backend")),
+        allow(dead_code)
+    )]
+    pub(crate) fn to_proto(&self, init: &[Statement]) -> proto::Batch {
+        let mut batch = proto::Batch::new();
+        for stmt in init {
+            batch.step(None, proto_stmt(stmt));
+        }
+        for step in &self.steps {
+            let condition = step.condition.as_ref().map(|c| c.to_proto(init.len()));
+            batch.step(condition, proto_stmt(&step.stmt));
+        }
+        batch
+    }
+
+    /// Converts the batch to Hrana v2 like `to_proto()`
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn to_v2(&self, init: &[Statement]) -> proto::v2::Batch {
+        let step = |condition, stmt: &Statement| proto::v2::BatchStep {
+            condition,
+            stmt: proto::v2::Stmt {
+                args: stmt.args.clone(),
+                ..proto::v2::Stmt::new(stmt.sql.clone(), true)
+            },
+        };
+        let mut steps: Vec<_> = init.iter().map(|stmt| step(None, stmt)).collect();
+        for s in &self.steps {
+            steps.push(step(
+                s.condition.as_ref().map(|c| c.to_v2(init.len())),
+                &s.stmt,
+            ));
+        }
+        proto::v2::Batch { steps }
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "hrana_backend", feature = "workers_backend")),
+    allow(dead_code)
+)]
+fn proto_stmt(stmt: &Statement) -> proto::Stmt {
+    let mut proto_stmt = proto::Stmt::new(stmt.sql.clone(), true);
+    for arg in &stmt.args {
+        proto_stmt.bind(arg.clone());
+    }
+    proto_stmt
+}
+
+/// Builder of a `Batch`
+#[derive(Clone, Debug, Default)]
+pub struct BatchBuilder {
+    steps: Vec<Step>,
+    /// Position of the `BEGIN` step of the open transaction, if any
+    begin: Option<usize>,
+}
+
+impl BatchBuilder {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a step which is always executed
+    pub fn step(self, stmt: impl Into<Statement>) -> Self {
+        self.push(stmt.into(), None)
+    }
+
+    /// Adds a step which is executed if the previous step succeeded
+    pub fn step_if_ok(self, stmt: impl Into<Statement>) -> Self {
+        let condition = self.previous().map(Condition::ok);
+        self.push(stmt.into(), condition)
+    }
+
+    /// Adds a step which is executed if the previous step failed
+    pub fn step_if_error(self, stmt: impl Into<Statement>) -> Self {
+        let condition = self.previous().map(Condition::error);
+        self.push(stmt.into(), condition)
+    }
+
+    /// Adds a step which is executed if `condition` holds
+    pub fn step_if(self, condition: Condition, stmt: impl Into<Statement>) -> Self {
+        self.push(stmt.into(), Some(condition))
+    }
+
+    /// Names the last step, so that conditions and `Batch::step_index()` can refer to it
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.label = Some(label.into());
+        }
+        self
+    }
+
+    /// Starts a transaction, which is finished by `commit()`
+    pub fn begin(mut self) -> Self {
+        self.begin = Some(self.steps.len());
+        self.push(Statement::new("BEGIN"), None)
+    }
+
+    /// Commits the transaction started by `begin()` if all of its steps succeeded or
+    /// were skipped because of their conditions, and rolls it back otherwise
+    pub fn commit(mut self) -> Self {
+        let Some(begin) = self.begin.take() else {
+            return self;
+        };
+        let end = self.steps.len();
+        let mut conds = vec![Condition::ok(begin)];
+        conds.extend((begin + 1..end).map(|step| !Condition::error(step)));
+        self = self.push(Statement::new("COMMIT"), Some(Condition::And(conds)));
+        let rollback = Condition::And(vec![Condition::ok(begin), !Condition::ok(end)]);
+        self.push(Statement::new("ROLLBACK"), Some(rollback))
+    }
+
+    /// Builds the batch, checking that labels are unique and that conditions
+    /// only refer to previous steps. A transaction which is not committed yet
+    /// is left open after the batch.
+    pub fn build(self) -> Result<Batch> {
+        let mut labels = HashMap::new();
+        let mut steps = Vec::with_capacity(self.steps.len());
+        for (idx, step) in self.steps.into_iter().enumerate() {
+            let condition = match &step.condition {
+                Some(condition) => Some(condition.resolve(&labels, idx)?),
+                None => None,
+            };
+            if let Some(label) = &step.label {
+                if labels.insert(label.clone(), idx).is_some() {
+                    anyhow::bail!("Several steps are labeled {label}");
+                }
+            }
+            steps.push(Step { condition, ..step });
+        }
+        Ok(Batch { steps })
+    }
+
+    fn previous(&self) -> Option<usize> {
+        self.steps.len().checked_sub(1)
+    }
+
+    fn push(mut self, stmt: Statement, condition: Option<Condition>) -> Self {
+        self.steps.push(Step {
+            stmt,
+            condition,
+            label: None,
+        });
+        self
+    }
+}
+
+/// Status of a statement of a batch executed by `DatabaseClient::batch_with_savepoints()`
+#[derive(Clone, Debug)]
+pub enum StepStatus {
+    /// The statement was applied, with its result
+    Applied(ResultSet),
+    /// The statement failed and its changes were rolled back, with the error
+    Skipped(String),
+}
+
+/// Builds a transaction in which each statement runs in its own savepoint,
+/// which is rolled back if the statement fails. Returns the batch,
+/// along with the positions of the statements and of the `COMMIT` step.
+pub(crate) fn savepoint_batch(stmts: Vec<Statement>) -> Result<(Batch, Vec<usize>, usize)> {
+    let mut builder = BatchBuilder::new().step("BEGIN");
+    let mut positions = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        let savepoint = builder.steps.len();
+        builder = builder
+            .step_if(Condition::ok(0), "SAVEPOINT libsql_step")
+            .step_if(Condition::ok(savepoint), stmt)
+            .step_if(Condition::error(savepoint + 1), "ROLLBACK TO libsql_step")
+            .step_if(Condition::ok(savepoint), "RELEASE libsql_step");
+        positions.push(savepoint + 1);
+    }
+    let commit = builder.steps.len();
+    let batch = builder
+        .step_if(Condition::ok(0), "COMMIT")
+        .step_if(
+            Condition::And(vec![Condition::ok(0), !Condition::ok(commit)]),
+            "ROLLBACK",
+        )
+        .build()?;
+    Ok((batch, positions, commit))
+}
+
+/// Reads the status of each statement of a batch built by `savepoint_batch()`
+pub(crate) fn savepoint_statuses(
+    mut result: BatchResult,
+    positions: &[usize],
+    commit: usize,
+) -> Result<Vec<StepStatus>> {
+    let error = |result: &BatchResult, idx: usize| {
+        result
+            .step_errors
+            .get(idx)
+            .and_then(|e| e.as_ref())
+            .map(|e| e.message.clone())
+    };
+    if let Some(error) = error(&result, 0) {
+        anyhow::bail!("Failed to begin the transaction: {error}");
+    }
+    if let Some(error) = error(&result, commit) {
+        anyhow::bail!("Failed to commit the transaction: {error}");
+    }
+    if !matches!(result.step_results.get(commit), Some(Some(_))) {
+        anyhow::bail!("The transaction was not committed");
+    }
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, &idx)| {
+            let applied = result.step_results.get_mut(idx).and_then(Option::take);
+            match (applied, error(&result, idx)) {
+                (Some(applied), _) => Ok(StepStatus::Applied(ResultSet::from(applied))),
+                (None, Some(error)) => Ok(StepStatus::Skipped(error)),
+                (None, None) => anyhow::bail!("Statement {i} was not executed"),
+            }
+        })
+        .collect()
+}
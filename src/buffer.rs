@@ -0,0 +1,172 @@
+//! `BufferedWriter` defers writes to telemetry-style tables, e.g. events or metrics,
+//! appending them to an in-memory buffer which is flushed as a single batch,
+//! so that frequent small writes do not each cost a round trip.
+//!
+//! The buffer is flushed once it holds `BufferedWriter::max_statements()` statements,
+//! on the first write after its oldest statement waited `BufferedWriter::max_delay()`,
+//! and whenever `flush()` is called, e.g. from a periodic task and before shutting down.
+//! Each flush executes the buffered statements in a transaction. If it fails, they are kept
+//! in front of the buffer for the next flush, up to `BufferedWriter::max_pending()`
+//! statements, beyond which the oldest ones are dropped.
+//!
+//! Buffered writes are lost if the process crashes or the writer is dropped before they are
+//! flushed, which is why writes only go through this distinct handle instead of a client
+//! wrapper: `write()` returns once the statement is buffered, not once it is applied.
+//! In WebAssembly outside of WASI, where no clock is available, the buffer is only flushed
+//! by its size and by `flush()`.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{args, Statement};
+//!   use libsql_client::buffer::BufferedWriter;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let events = BufferedWriter::new(&db)
+//!       .max_statements(500)
+//!       .max_delay(Duration::from_secs(5));
+//!   for page in ["/", "/pricing", "/docs"] {
+//!       events
+//!           .write(Statement::with_args("INSERT INTO page_views (path) VALUES (?)", args!(page)))
+//!           .await?;
+//!   }
+//!   events.flush().await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::priority::Permits;
+use crate::{DatabaseClient, Priority, Statement};
+
+/// Buffered statements, and when the oldest of them was buffered
+#[derive(Default)]
+struct Buffer {
+    stmts: VecDeque<Statement>,
+    oldest: Option<Instant>,
+}
+
+/// Handle buffering writes and flushing them in batches. Writes which are not
+/// flushed yet are lost if the process crashes or the writer is dropped.
+pub struct BufferedWriter<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    buffer: Mutex<Buffer>,
+    /// Held while flushing, so that flushes apply statements in order
+    flushing: Permits,
+    max_statements: usize,
+    max_delay: Duration,
+    max_pending: usize,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> BufferedWriter<'a, Client> {
+    /// Creates a writer flushing every 100 statements or 1 second,
+    /// keeping at most 10000 statements which failed to be flushed
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            buffer: Mutex::new(Buffer::default()),
+            flushing: Permits::new(1),
+            max_statements: 100,
+            max_delay: Duration::from_secs(1),
+            max_pending: 10_000,
+        }
+    }
+
+    /// Sets the number of buffered statements which triggers a flush
+    pub fn max_statements(mut self, max_statements: usize) -> Self {
+        self.max_statements = max_statements.max(1);
+        self
+    }
+
+    /// Sets how long the oldest buffered statement waits before a write triggers a flush
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the number of statements kept when flushes fail, the oldest ones being dropped
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(self.max_statements);
+        self
+    }
+
+    /// Returns the number of statements waiting to be flushed
+    pub fn pending(&self) -> usize {
+        self.buffer().stmts.len()
+    }
+
+    fn buffer(&self) -> std::sync::MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Buffers a write, flushing the buffer if it is full or its oldest statement is due.
+    /// Statements which cannot write, e.g. `SELECT`, are rejected, since their rows
+    /// would be discarded.
+    pub async fn write(&self, stmt: impl Into<Statement>) -> Result<()> {
+        let stmt: Statement = stmt.into();
+        if crate::sql::is_read_only(&stmt.sql) {
+            anyhow::bail!("Only writes can be buffered, not {}", stmt.sql);
+        }
+        let due = {
+            let mut buffer = self.buffer();
+            buffer.stmts.push_back(stmt);
+            let now = crate::timings::now();
+            if buffer.oldest.is_none() {
+                buffer.oldest = now;
+            }
+            let expired = match (now, buffer.oldest) {
+                (Some(now), Some(oldest)) => now.duration_since(oldest) >= self.max_delay,
+                _ => false,
+            };
+            buffer.stmts.len() >= self.max_statements || expired
+        };
+        if due {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Executes all buffered statements in a transaction. If it fails, the statements
+    /// are kept to be flushed again, and the error is returned.
+    pub async fn flush(&self) -> Result<()> {
+        let _flushing = self.flushing.acquire(Priority::Normal).await;
+        let stmts: Vec<Statement> = {
+            let mut buffer = self.buffer();
+            buffer.oldest = None;
+            buffer.stmts.drain(..).collect()
+        };
+        if stmts.is_empty() {
+            return Ok(());
+        }
+        let count = stmts.len();
+        let flushed = self.client.batch(stmts.iter().cloned()).await;
+        if let Err(e) = &flushed {
+            tracing::warn!("Failed to flush {count} buffered writes: {e}");
+            let mut buffer = self.buffer();
+            for stmt in stmts.into_iter().rev() {
+                buffer.stmts.push_front(stmt);
+            }
+            let excess = buffer.stmts.len().saturating_sub(self.max_pending);
+            if excess > 0 {
+                tracing::warn!("Dropping {excess} buffered writes which could not be flushed");
+                buffer.stmts.drain(..excess);
+            }
+            buffer.oldest = crate::timings::now();
+        }
+        flushed.map(|_| ())
+    }
+}
+
+impl<Client: DatabaseClient + ?Sized> Drop for BufferedWriter<'_, Client> {
+    fn drop(&mut self) {
+        let pending = self.pending();
+        if pending > 0 {
+            tracing::warn!("Dropping {pending} buffered writes which were never flushed");
+        }
+    }
+}
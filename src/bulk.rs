@@ -0,0 +1,388 @@
+//! `bulk` contains helpers for large writes which are sent in chunks,
+//! e.g. importing rows or running a migration script, with progress reports
+//! for rendering progress bars or emitting heartbeat logs.
+//!
+//! Each chunk is executed as a transactional batch, so a failure leaves
+//! the chunks which completed before it applied, and none of the failed one.
+//!
+//! A resumable writer also records its high-water mark, i.e. the number of rows or
+//! statements applied so far, in a side table within the batch of each chunk.
+//! An interrupted operation started again with the same input skips what was already
+//! applied: since the mark is committed atomically with its chunk, the boundary chunk
+//! is either applied and recorded, or neither, and re-applying it is safe.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default number of rows inserted, or statements executed, per chunk
+const DEFAULT_CHUNK_SIZE: usize = 500;
+
+/// Default name of the table recording the high-water marks of resumable operations
+const DEFAULT_CHECKPOINT_TABLE: &str = "_libsql_bulk_checkpoints";
+
+/// Maximum number of parameters of a statement, the default limit since SQLite 3.32
+const SQLITE_MAX_VARIABLE_NUMBER: usize = 32766;
+
+/// Progress of a bulk operation, reported after each chunk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    /// Number of chunks completed so far
+    pub chunks_done: usize,
+    /// Total number of chunks, if known upfront
+    pub chunks_total: Option<usize>,
+    /// Number of rows inserted, or statements executed, so far,
+    /// including the ones applied by previous attempts of a resumable operation
+    pub rows: u64,
+    /// Number of rows or statements skipped because previous attempts applied them
+    pub resumed_from: u64,
+    /// Size of the SQL text and arguments sent so far, in bytes
+    pub bytes: u64,
+    /// Time elapsed since the operation started,
+    /// unless the clock is not available on the platform
+    pub elapsed: Option<Duration>,
+    /// Estimated time left, based on the average duration of completed chunks,
+    /// if the total number of chunks is known
+    pub eta: Option<Duration>,
+}
+
+/// Writer executing bulk operations in chunks, reporting progress after each one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   use libsql_client::bulk::BulkWriter;
+///   use libsql_client::Value;
+///
+///   let db = libsql_client::new_client().await?;
+///   let rows = (0..100_000i64).map(|i| vec![Value::from(i), Value::from(format!("user{i}"))]);
+///   let mut writer = BulkWriter::new(&db).chunk_size(1000).on_progress(|p| {
+///       println!("{}/{:?} chunks, {} rows, eta {:?}", p.chunks_done, p.chunks_total, p.rows, p.eta)
+///   });
+///   writer.insert_many("users", &["id", "name"], rows).await?;
+///   writer.execute_script("CREATE INDEX users_name ON users(name); ANALYZE;").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct BulkWriter<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    chunk_size: usize,
+    on_progress: Option<ProgressCallback<'a>>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Callback notified of the progress of a `BulkWriter`
+type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+/// Location of the high-water mark of a resumable operation
+struct Checkpoint {
+    table: String,
+    id: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> BulkWriter<'a, Client> {
+    /// Creates a writer sending chunks of 500 rows or statements
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            on_progress: None,
+            checkpoint: None,
+        }
+    }
+
+    /// Sets the number of rows inserted, or statements executed, per chunk.
+    /// Chunks of `insert_many()` are capped further by the number of parameters
+    /// a single statement can have.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Sets a callback called with the progress after each chunk
+    pub fn on_progress(mut self, callback: impl FnMut(&Progress) + 'a) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Makes the operations of the writer resumable, recording their high-water mark
+    /// under `id` in the `_libsql_bulk_checkpoints` table, created if it does not exist.
+    /// The id stands for a single operation, whose input must be the same on every attempt.
+    /// The mark is kept once the operation completes, so that running it again is a no-op,
+    /// until it's removed with `reset()`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(rows: Vec<Vec<libsql_client::Value>>) -> anyhow::Result<()> {
+    ///   use libsql_client::bulk::BulkWriter;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let mut writer = BulkWriter::new(&db).resumable("users-2023-04-01");
+    ///   // Continues from the last completed chunk if a previous attempt was interrupted
+    ///   writer.insert_many("users", &["id", "name"], rows).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn resumable(mut self, id: impl Into<String>) -> Self {
+        self.checkpoint = Some(Checkpoint {
+            table: DEFAULT_CHECKPOINT_TABLE.to_string(),
+            id: id.into(),
+        });
+        self
+    }
+
+    /// Sets the table recording high-water marks of a resumable writer
+    pub fn checkpoint_table(mut self, table: impl Into<String>) -> Self {
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.table = table.into();
+        }
+        self
+    }
+
+    /// Returns the high-water mark of a resumable writer,
+    /// i.e. the number of rows or statements already applied
+    pub async fn checkpoint(&self) -> Result<u64> {
+        let Some(checkpoint) = &self.checkpoint else {
+            return Ok(0);
+        };
+        let table = crate::sql::quote_ident(&checkpoint.table);
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {table} (id TEXT PRIMARY KEY, applied INTEGER NOT NULL)"
+            ))
+            .await?;
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("SELECT applied FROM {table} WHERE id = ?"),
+                &[checkpoint.id.as_str()],
+            ))
+            .await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(Value::Integer { value }) => Ok(*value as u64),
+            Some(value) => anyhow::bail!("Invalid high-water mark {value:?} of {}", checkpoint.id),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes the high-water mark of a resumable writer,
+    /// so that its operation starts from the beginning
+    pub async fn reset(&self) -> Result<()> {
+        let Some(checkpoint) = &self.checkpoint else {
+            return Ok(());
+        };
+        // Makes sure the table exists
+        self.checkpoint().await?;
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "DELETE FROM {} WHERE id = ?",
+                    crate::sql::quote_ident(&checkpoint.table)
+                ),
+                &[checkpoint.id.as_str()],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts rows into `table`, with a multi-row `INSERT` statement per chunk.
+    /// Every row must have a value for each of `columns`.
+    /// Returns the number of rows inserted, including the ones skipped when resuming.
+    pub async fn insert_many<R>(
+        &mut self,
+        table: &str,
+        columns: &[&str],
+        rows: impl IntoIterator<Item = R>,
+    ) -> Result<u64>
+    where
+        R: IntoIterator,
+        R::Item: Into<Value>,
+    {
+        if columns.is_empty() {
+            anyhow::bail!("At least one column is required to insert rows into {table}");
+        }
+        let chunk_size = self
+            .chunk_size
+            .min(SQLITE_MAX_VARIABLE_NUMBER / columns.len())
+            .max(1);
+        let resumed_from = self.checkpoint().await?;
+        let rows = rows.into_iter().skip(resumed_from as usize);
+        let mut tracker = Tracker::new(
+            exact_len(&rows).map(|n| n.div_ceil(chunk_size)),
+            resumed_from,
+        );
+        let prefix = format!(
+            "INSERT INTO {} ({}) VALUES ",
+            crate::sql::quote_ident(table),
+            columns
+                .iter()
+                .map(|c| crate::sql::quote_ident(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+
+        let mut rows = rows.peekable();
+        while rows.peek().is_some() {
+            let mut args = Vec::with_capacity(chunk_size * columns.len());
+            let mut count = 0u64;
+            for row in rows.by_ref().take(chunk_size) {
+                let len = args.len();
+                args.extend(row.into_iter().map(Into::into));
+                if args.len() - len != columns.len() {
+                    anyhow::bail!(
+                        "Row {} has {} values, expected {}",
+                        tracker.progress.rows + count,
+                        args.len() - len,
+                        columns.len()
+                    );
+                }
+                count += 1;
+            }
+            let sql = format!(
+                "{prefix}{}",
+                vec![placeholders.as_str(); count as usize].join(", ")
+            );
+            let stmt = Statement::with_args(sql, &args);
+            self.apply(&mut tracker, vec![stmt], count).await?;
+        }
+        Ok(tracker.progress.rows)
+    }
+
+    /// Executes an SQL script, split into statements at semicolons.
+    /// Returns the number of statements executed, including the ones skipped when resuming.
+    pub async fn execute_script(&mut self, script: &str) -> Result<u64> {
+        self.execute_many(
+            crate::sql::split_statements(script)
+                .into_iter()
+                .map(Statement::new),
+        )
+        .await
+    }
+
+    /// Executes statements in chunks.
+    /// Returns the number of statements executed, including the ones skipped when resuming.
+    pub async fn execute_many(
+        &mut self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<u64> {
+        let resumed_from = self.checkpoint().await?;
+        let stmts = stmts.into_iter().skip(resumed_from as usize);
+        let mut tracker = Tracker::new(
+            exact_len(&stmts).map(|n| n.div_ceil(self.chunk_size)),
+            resumed_from,
+        );
+        let mut stmts = stmts.map(Into::into).peekable();
+        while stmts.peek().is_some() {
+            let chunk: Vec<Statement> = stmts.by_ref().take(self.chunk_size).collect();
+            let count = chunk.len() as u64;
+            self.apply(&mut tracker, chunk, count).await?;
+        }
+        Ok(tracker.progress.rows)
+    }
+
+    /// Executes a chunk of `rows` rows or statements as a batch,
+    /// along with the update of the high-water mark, and reports the progress
+    async fn apply(
+        &mut self,
+        tracker: &mut Tracker,
+        mut chunk: Vec<Statement>,
+        rows: u64,
+    ) -> Result<()> {
+        let bytes = chunk.iter().map(statement_size).sum();
+        if let Some(checkpoint) = &self.checkpoint {
+            chunk.push(Statement::with_args(
+                format!(
+                    "INSERT INTO {} (id, applied) VALUES (?, ?) ON CONFLICT (id) DO UPDATE SET applied = excluded.applied",
+                    crate::sql::quote_ident(&checkpoint.table)
+                ),
+                &[
+                    Value::from(checkpoint.id.clone()),
+                    Value::from((tracker.progress.rows + rows) as i64),
+                ],
+            ));
+        }
+        self.client.batch(chunk).await?;
+        tracker.advance(rows, bytes);
+        tracing::debug!(
+            chunks_done = tracker.progress.chunks_done,
+            rows = tracker.progress.rows,
+            "Bulk chunk completed"
+        );
+        if let Some(callback) = &mut self.on_progress {
+            callback(&tracker.progress);
+        }
+        Ok(())
+    }
+}
+
+/// Progress of a single operation, along with its start time
+struct Tracker {
+    progress: Progress,
+    started: Option<Instant>,
+}
+
+impl Tracker {
+    fn new(chunks_total: Option<usize>, resumed_from: u64) -> Self {
+        Self {
+            progress: Progress {
+                chunks_total,
+                rows: resumed_from,
+                resumed_from,
+                ..Default::default()
+            },
+            started: now(),
+        }
+    }
+
+    fn advance(&mut self, rows: u64, bytes: u64) {
+        let progress = &mut self.progress;
+        progress.chunks_done += 1;
+        progress.rows += rows;
+        progress.bytes += bytes;
+        progress.elapsed = self.started.map(|started| started.elapsed());
+        progress.eta = match (progress.elapsed, progress.chunks_total) {
+            (Some(elapsed), Some(total)) => {
+                let left = total.saturating_sub(progress.chunks_done) as u32;
+                Some(elapsed / progress.chunks_done as u32 * left)
+            }
+            _ => None,
+        };
+    }
+}
+
+/// Returns the current time, unless the clock is not available,
+/// i.e. in WebAssembly outside of WASI
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// Returns the number of items left if the iterator knows it exactly
+fn exact_len(iter: &impl Iterator) -> Option<usize> {
+    match iter.size_hint() {
+        (lower, Some(upper)) if lower == upper => Some(lower),
+        _ => None,
+    }
+}
+
+/// Approximate size of a statement on the wire
+fn statement_size(stmt: &Statement) -> u64 {
+    let args: usize = stmt
+        .args
+        .iter()
+        .map(|arg| match arg {
+            Value::Text { value } => value.len(),
+            Value::Blob { value } => value.len(),
+            _ => 8,
+        })
+        .sum();
+    (stmt.sql.len() + args) as u64
+}
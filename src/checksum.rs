@@ -0,0 +1,315 @@
+//! `checksum` summarizes result sets into 64-bit checksums, so that copies of the same data,
+//! e.g. on replicas or in an embedded replica and its primary, can be compared without
+//! transferring the data itself.
+//!
+//! Checksums cover the column names and every value with its type: the integer `1`,
+//! the float `1.0` and the text `'1'` differ. Floats are compared by value, so `0.0` and
+//! `-0.0` are equal, as are all NaNs. Checksums are stable across processes, platforms
+//! and versions of the crate, but they are not cryptographic: they detect accidental
+//! divergence, not tampering. `verify_consistency()` builds on them to compare whole tables.
+//!
+//! `Row::hash()` and `ResultSet::row_hashes()` expose the hash of each row, e.g. for a sync
+//! or cache which stores the hashes of the rows it has, and only fetches or updates the rows
+//! whose hash changed. The hash covers the values of the row, not the column names, and is
+//! the 64-bit FNV-1a hash of their encoding one after the other:
+//!
+//! | Value | Encoding |
+//! |---|---|
+//! | `NULL` | `0x00` |
+//! | Integer | `0x01`, then the 8 bytes of the integer, little-endian |
+//! | Float | `0x02`, then the 8 bytes of its IEEE 754 bits, little-endian |
+//! | Text | `0x03`, then its length in bytes as 8 bytes little-endian, then its UTF-8 bytes |
+//! | Blob | `0x04`, then its length as 8 bytes little-endian, then its bytes |
+//!
+//! Before encoding, `-0.0` is replaced by `0.0` and every NaN by `f64::NAN`, so that equal
+//! floats have the same hash.
+//!
+//! ```rust,no_run
+//!   # use libsql_client::DatabaseClient;
+//!   # async fn f(primary: &impl DatabaseClient, replica: &impl DatabaseClient) -> anyhow::Result<()> {
+//!   let query = "SELECT id, balance FROM accounts";
+//!   let expected = primary.execute(query).await?.checksum_unordered();
+//!   let actual = replica.execute(query).await?.checksum_unordered();
+//!   if expected != actual {
+//!       tracing::warn!("Replica diverged from the primary");
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::{Context, Result};
+
+use crate::redact::fnv1a;
+use crate::{DatabaseClient, ResultSet, Row, Statement, Value};
+
+impl ResultSet {
+    /// Returns a checksum of the columns and rows, which depends on the order of the rows.
+    /// Use it for queries with an `ORDER BY` clause.
+    pub fn checksum(&self) -> u64 {
+        let mut bytes = header(self);
+        for row in &self.rows {
+            bytes.extend_from_slice(&row_hash(&row.values).to_le_bytes());
+        }
+        fnv1a(&bytes)
+    }
+
+    /// Returns a checksum of the columns and rows, regardless of the order of the rows,
+    /// e.g. for queries without an `ORDER BY` clause, whose order may differ between
+    /// databases. Duplicate rows are counted.
+    pub fn checksum_unordered(&self) -> u64 {
+        let rows = self.rows.iter().fold(0u64, |sum, row| {
+            sum.wrapping_add(mix(row_hash(&row.values)))
+        });
+        let mut bytes = header(self);
+        bytes.extend_from_slice(&rows.to_le_bytes());
+        fnv1a(&bytes)
+    }
+
+    /// Returns the hash of each row, in order, see `Row::hash()`
+    pub fn row_hashes(&self) -> Vec<u64> {
+        self.rows.iter().map(Row::hash).collect()
+    }
+}
+
+impl Row {
+    /// Returns a hash of the values of the row and their types, following the scheme
+    /// described in the `checksum` module, which is stable across processes, platforms
+    /// and versions of the crate
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(known: std::collections::HashMap<i64, u64>) -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Value};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let result = db.execute("SELECT id, name, price FROM products").await?;
+    ///   for row in &result.rows {
+    ///       let Value::Integer { value: id } = row.values[0] else { continue };
+    ///       if known.get(&id) != Some(&row.hash()) {
+    ///           println!("Product {id} changed");
+    ///       }
+    ///   }
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn hash(&self) -> u64 {
+        row_hash(&self.values)
+    }
+}
+
+/// Encodes the column names and the number of rows
+fn header(result: &ResultSet) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(result.columns.len() as u64).to_le_bytes());
+    for column in &result.columns {
+        encode_bytes(&mut bytes, column.as_bytes());
+    }
+    bytes.extend_from_slice(&(result.rows.len() as u64).to_le_bytes());
+    bytes
+}
+
+fn row_hash(values: &[Value]) -> u64 {
+    let mut bytes = Vec::new();
+    for value in values {
+        match value {
+            Value::Null => bytes.push(0),
+            Value::Integer { value } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::Float { value } => {
+                bytes.push(2);
+                let value = match *value {
+                    v if v.is_nan() => f64::NAN,
+                    // Turns -0.0 into 0.0
+                    v => v + 0.0,
+                };
+                bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            Value::Text { value } => {
+                bytes.push(3);
+                encode_bytes(&mut bytes, value.as_bytes());
+            }
+            Value::Blob { value } => {
+                bytes.push(4);
+                encode_bytes(&mut bytes, value);
+            }
+        }
+    }
+    fnv1a(&bytes)
+}
+
+/// Encodes bytes prefixed with their length, so that adjacent values cannot be confused
+fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Scrambles the bits of a row hash before summing, so that rows with related
+/// hashes do not cancel each other out (the finalizer of SplitMix64)
+fn mix(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Number of rows compared at a time by `verify_consistency()`
+const CHUNK_SIZE: u64 = 1000;
+
+/// Outcome of `verify_consistency()`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// Number of rows of the primary which were compared
+    pub rows_compared: u64,
+    /// Differences found, in the order of the tables
+    pub divergences: Vec<Divergence>,
+}
+
+impl ConsistencyReport {
+    /// Whether no differences were found
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Difference between a table on the primary and on the replica
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The table has a different number of rows
+    RowCount {
+        table: String,
+        primary: u64,
+        replica: u64,
+    },
+    /// Rows within a range of rowids differ, are missing or are extra on the replica.
+    /// `last_rowid` is `None` for rows past the last row of the primary.
+    Rows {
+        table: String,
+        first_rowid: i64,
+        last_rowid: Option<i64>,
+    },
+}
+
+/// Compares tables on a primary database and on its replica, chunk by chunk in the order
+/// of their rowids, reporting the ranges of rows which differ. Only checksums of chunks
+/// are compared, but the rows are still read from both databases by the client.
+///
+/// Tables must have rowids, i.e. not be `WITHOUT ROWID`. Writes applied while verifying,
+/// or not yet replicated, show up as divergences, so an idle database or a repeated
+/// verification tells lag from actual inconsistencies.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # use libsql_client::DatabaseClient;
+///   # async fn f(primary: &impl DatabaseClient, replica: &impl DatabaseClient) -> anyhow::Result<()> {
+///   let report =
+///       libsql_client::checksum::verify_consistency(primary, replica, &["users", "orders"])
+///           .await?;
+///   for divergence in &report.divergences {
+///       println!("{divergence:?}");
+///   }
+///   # Ok(())
+///   # }
+/// ```
+pub async fn verify_consistency(
+    primary: &(impl DatabaseClient + ?Sized),
+    replica: &(impl DatabaseClient + ?Sized),
+    tables: &[&str],
+) -> Result<ConsistencyReport> {
+    let mut report = ConsistencyReport::default();
+    for table in tables {
+        verify_table(primary, replica, table, &mut report)
+            .await
+            .with_context(|| format!("Failed to verify table {table}"))?;
+    }
+    Ok(report)
+}
+
+async fn verify_table(
+    primary: &(impl DatabaseClient + ?Sized),
+    replica: &(impl DatabaseClient + ?Sized),
+    table: &str,
+    report: &mut ConsistencyReport,
+) -> Result<()> {
+    let quoted = crate::sql::quote_ident(table);
+    let count = format!("SELECT COUNT(*) FROM {quoted}");
+    let (primary_rows, replica_rows) = (
+        count_rows(primary.execute(count.as_str()).await?),
+        count_rows(replica.execute(count.as_str()).await?),
+    );
+    if primary_rows != replica_rows {
+        report.divergences.push(Divergence::RowCount {
+            table: table.to_string(),
+            primary: primary_rows,
+            replica: replica_rows,
+        });
+    }
+
+    let next_chunk =
+        format!("SELECT rowid, * FROM {quoted} WHERE rowid > ? ORDER BY rowid LIMIT ?");
+    let range =
+        format!("SELECT rowid, * FROM {quoted} WHERE rowid > ? AND rowid <= ? ORDER BY rowid");
+    // Rowids are compared from the one after `after`, so that rows which only exist
+    // on the replica between two chunks of the primary are included
+    let mut after = i64::MIN;
+    loop {
+        let chunk = primary
+            .execute(Statement::with_args(
+                next_chunk.as_str(),
+                &[after, CHUNK_SIZE as i64],
+            ))
+            .await?;
+        let Some(last) = chunk
+            .rows
+            .last()
+            .map(|row| rowid(&row.values))
+            .transpose()?
+        else {
+            break;
+        };
+        let copy = replica
+            .execute(Statement::with_args(range.as_str(), &[after, last]))
+            .await?;
+        if chunk.checksum() != copy.checksum() {
+            let first = rowid(&chunk.rows[0].values)?;
+            let first = match copy.rows.first() {
+                Some(row) => first.min(rowid(&row.values)?),
+                None => first,
+            };
+            report.divergences.push(Divergence::Rows {
+                table: table.to_string(),
+                first_rowid: first,
+                last_rowid: Some(last),
+            });
+        }
+        report.rows_compared += chunk.rows.len() as u64;
+        after = last;
+    }
+    // Rows past the last one of the primary
+    let extra = replica
+        .execute(Statement::with_args(next_chunk.as_str(), &[after, 1]))
+        .await?;
+    if let Some(row) = extra.rows.first() {
+        report.divergences.push(Divergence::Rows {
+            table: table.to_string(),
+            first_rowid: rowid(&row.values)?,
+            last_rowid: None,
+        });
+    }
+    Ok(())
+}
+
+fn count_rows(result: ResultSet) -> u64 {
+    match result.rows.first().and_then(|row| row.values.first()) {
+        Some(Value::Integer { value }) => *value as u64,
+        _ => 0,
+    }
+}
+
+fn rowid(values: &[Value]) -> Result<i64> {
+    match values.first() {
+        Some(Value::Integer { value }) => Ok(*value),
+        _ => anyhow::bail!("Table has no rowid"),
+    }
+}
@@ -4,7 +4,14 @@ use async_trait::async_trait;
 
 use anyhow::{anyhow, Result};
 
-use crate::{proto, BatchResult, Col, ResultSet, Statement, Transaction, Value};
+use crate::batch::{Batch, StepStatus};
+use crate::text::InvalidUtf8;
+use crate::time::TimestampFormat;
+use crate::transport::BlobEncoding;
+use crate::{
+    proto, BatchResult, ClientStats, ResultSet, RetryBudget, RetryPolicy, SchemaPrefixed, Scope,
+    Statement, Transaction,
+};
 
 /// Trait describing capabilities of a database client:
 /// - executing statements, batches, transactions
@@ -15,14 +22,175 @@ pub trait DatabaseClient {
     /// # Arguments
     /// * `stmt` - the SQL statement
     async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
-        let results = self.raw_batch(std::iter::once(stmt)).await?;
+        let mut stmt: Statement = stmt.into();
+        let options = ResultOptions::take(&mut stmt);
+        let batch = crate::server_stats::collect(self.raw_batch(std::iter::once(stmt)));
+        let ((results, server_stats), timings) =
+            crate::timings::collect(self.collects_timings(), batch).await;
+        let results = results?;
         match (results.step_results.first(), results.step_errors.first()) {
-            (Some(Some(result)), Some(None)) => Ok(ResultSet::from(result.clone())),
+            (Some(Some(result)), Some(None)) => {
+                options.finish(result.clone(), timings, server_stats)
+            }
             (Some(None), Some(Some(err))) => Err(anyhow::anyhow!(err.message.clone())),
             _ => unreachable!(),
         }
     }
 
+    /// Executes a single SQL statement and deserializes the returned rows into `T`.
+    /// Struct fields are matched with column names, see the `de` module for details.
+    ///
+    /// # Arguments
+    /// * `stmt` - the SQL statement
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   #[derive(serde::Deserialize)]
+    ///   struct Item {
+    ///       name: String,
+    ///       price: f64,
+    ///   }
+    ///   let db = libsql_client::new_client().await?;
+    ///   let items: Vec<Item> = db.query_as("SELECT name, price FROM items").await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn query_as<T: serde::de::DeserializeOwned>(
+        &self,
+        stmt: impl Into<Statement>,
+    ) -> Result<Vec<T>> {
+        let stmt: Statement = stmt.into();
+        let fingerprint = crate::fingerprint(&stmt.sql);
+        self.execute(stmt).await?.deserialize_rows().map_err(|e| {
+            match e.downcast::<crate::de::Error>() {
+                Ok(mut e) => {
+                    e.statement = Some(fingerprint);
+                    e.into()
+                }
+                Err(e) => e,
+            }
+        })
+    }
+
+    /// Counts the rows a query returns, without fetching them, e.g. to show the number
+    /// of pages of a listing. The query is rewritten into `SELECT COUNT(*) FROM (query)`,
+    /// keeping its arguments, and must be a single statement returning rows, e.g. `SELECT`.
+    ///
+    /// # Arguments
+    /// * `stmt` - the query whose rows are counted
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Statement};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let query = "SELECT * FROM posts WHERE author = ? ORDER BY created_at DESC";
+    ///   let total = db.count(Statement::with_args(query, &["ann"])).await?;
+    ///   let pages = total.div_ceil(20);
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn count(&self, stmt: impl Into<Statement>) -> Result<u64> {
+        let mut stmt: Statement = stmt.into();
+        stmt.sql = crate::sql::count_query(&stmt.sql)?;
+        // Decoded and ordered columns of the query are not part of the count
+        stmt.decodes.clear();
+        stmt.order_keys.clear();
+        let result = self.execute(stmt).await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(proto::Value::Integer { value }) => Ok(u64::try_from(*value)?),
+            value => anyhow::bail!("Unexpected result of COUNT(*): {value:?}"),
+        }
+    }
+
+    /// Checks if a query returns any row, reading at most one, e.g. to tell if a name
+    /// is taken. The query is rewritten into `SELECT EXISTS (SELECT 1 FROM (query) LIMIT 1)`,
+    /// keeping its arguments, and must be a single statement returning rows, e.g. `SELECT`.
+    ///
+    /// # Arguments
+    /// * `stmt` - the query whose rows are checked
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Statement};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let query = "SELECT id FROM users WHERE username = ?";
+    ///   if db.exists(Statement::with_args(query, &["ann"])).await? {
+    ///       println!("This username is taken");
+    ///   }
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn exists(&self, stmt: impl Into<Statement>) -> Result<bool> {
+        let mut stmt: Statement = stmt.into();
+        stmt.sql = crate::sql::exists_query(&stmt.sql)?;
+        stmt.decodes.clear();
+        stmt.order_keys.clear();
+        let result = self.execute(stmt).await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(proto::Value::Integer { value }) => Ok(*value != 0),
+            value => anyhow::bail!("Unexpected result of EXISTS: {value:?}"),
+        }
+    }
+
+    /// Executes an SQL string made of several statements separated by semicolons,
+    /// e.g. a script typed by a user, returning the result of each statement in order.
+    /// `execute()` only runs the first statement of such strings.
+    ///
+    /// The statements are sent in a single batch whose steps only run if the previous one
+    /// succeeded, so execution stops at the first failing statement, whose error is returned.
+    /// They are not wrapped in a transaction: the changes of the preceding statements stay
+    /// applied, unless the string itself contains `BEGIN` and `COMMIT`. The spin and http
+    /// backends, which reject conditional batches, execute the statements one request at a time.
+    ///
+    /// # Arguments
+    /// * `sql` - SQL statements separated by semicolons
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   let db = libsql_client::new_client().await?;
+    ///   let results = db
+    ///       .execute_multi("INSERT INTO notes (body) VALUES ('hello'); SELECT COUNT(*) FROM notes;")
+    ///       .await?;
+    ///   let count = &results[1].rows[0].values[0];
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn execute_multi(&self, sql: &str) -> Result<Vec<ResultSet>> {
+        let stmts = crate::sql::split_statements(sql);
+        match stmts.as_slice() {
+            [] => return Ok(Vec::new()),
+            [stmt] => return Ok(vec![self.execute(*stmt).await?]),
+            _ => (),
+        }
+        let batch = stmts
+            .iter()
+            .fold(crate::BatchBuilder::new(), |batch, stmt| {
+                batch.step_if_ok(*stmt)
+            })
+            .build()?;
+        let result = self.run_batch(batch).await?;
+        let mut results = Vec::with_capacity(stmts.len());
+        let steps = result.step_results.into_iter().zip(result.step_errors);
+        for (stmt, step) in stmts.iter().zip(steps) {
+            match step {
+                (Some(result), None) => results.push(ResultSet::from(result)),
+                (_, Some(error)) => anyhow::bail!("{}: {stmt}", error.message),
+                (None, None) => anyhow::bail!("Statement was not executed: {stmt}"),
+            }
+        }
+        Ok(results)
+    }
+
     /// Executes a batch of SQL statements.
     /// Each statement is going to run in its own transaction,
     /// unless they're wrapped in BEGIN and END
@@ -44,10 +212,14 @@ pub trait DatabaseClient {
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> Result<Vec<ResultSet>> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        if self.validates_batches() {
+            self.validate_batch(&stmts).await?;
+        }
         let batch_results = self
             .raw_batch(
                 std::iter::once(Statement::new("BEGIN"))
-                    .chain(stmts.into_iter().map(|s| s.into()))
+                    .chain(stmts)
                     .chain(std::iter::once(Statement::new("END"))),
             )
             .await?;
@@ -75,12 +247,388 @@ pub trait DatabaseClient {
         step_results.into_iter().collect::<Result<Vec<ResultSet>>>()
     }
 
+    /// Executes a batch built with `BatchBuilder`, whose steps may depend on the outcome
+    /// of previous steps. The result has an entry for each step, and skipped steps
+    /// have neither a result nor an error.
+    ///
+    /// The hrana, workers and reqwest backends send a conditional batch in a single request.
+    /// Other clients execute its steps one at a time, which requires a connection keeping
+    /// its state between requests, e.g. the local backend; the spin and http backends
+    /// reject conditional batches.
+    ///
+    /// # Arguments
+    /// * `batch` - batch built with `BatchBuilder`
+    async fn run_batch(&self, batch: Batch) -> Result<BatchResult> {
+        if !batch.is_conditional() {
+            return self.raw_batch(batch.into_statements()).await;
+        }
+        let mut outcomes = Vec::with_capacity(batch.steps().len());
+        let mut result = BatchResult {
+            step_results: Vec::with_capacity(batch.steps().len()),
+            step_errors: Vec::with_capacity(batch.steps().len()),
+        };
+        for step in batch.steps() {
+            let run = step
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.eval(&outcomes));
+            if !run {
+                outcomes.push(None);
+                result.step_results.push(None);
+                result.step_errors.push(None);
+                continue;
+            }
+            let step_result = self.raw_batch([step.stmt.clone()]).await?;
+            let error = step_result.step_errors.into_iter().next().flatten();
+            outcomes.push(Some(error.is_none()));
+            result
+                .step_results
+                .push(step_result.step_results.into_iter().next().flatten());
+            result.step_errors.push(error);
+        }
+        Ok(result)
+    }
+
+    /// Executes a batch of SQL statements in a transaction, each in its own savepoint.
+    /// A failing statement is rolled back and skipped, while the changes of the other
+    /// statements are committed. The status of each statement is returned in order.
+    ///
+    /// The batch is executed with `run_batch()`, see its documentation for the backends
+    /// which support it.
+    ///
+    /// # Arguments
+    /// * `stmts` - SQL statements
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use libsql_client::batch::StepStatus;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let statuses = db
+    ///       .batch_with_savepoints([
+    ///           "INSERT INTO users (id, name) VALUES (1, 'ann')",
+    ///           "INSERT INTO users (id, name) VALUES (1, 'duplicate')",
+    ///           "INSERT INTO users (id, name) VALUES (2, 'bob')",
+    ///       ])
+    ///       .await?;
+    ///   if let StepStatus::Skipped(error) = &statuses[1] {
+    ///       println!("skipped: {error}");
+    ///   }
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn batch_with_savepoints(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<Vec<StepStatus>> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        let (batch, positions, commit) = crate::batch::savepoint_batch(stmts)?;
+        let result = self.run_batch(batch).await?;
+        crate::batch::savepoint_statuses(result, &positions, commit)
+    }
+
+    /// Checks that all statements of a batch are valid, without executing any of them.
+    /// The number of arguments is checked against the parameters of each statement,
+    /// and the statements are compiled by the database with `EXPLAIN`, which reports
+    /// syntax errors and unknown tables or columns.
+    ///
+    /// Statements which follow a schema change in the same batch, e.g. an `INSERT`
+    /// into a table created by a preceding step, can only be checked client-side.
+    ///
+    /// # Arguments
+    /// * `stmts` - SQL statements
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Statement, args};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let stmts = vec![Statement::with_args("INSERT INTO users (name) VALUES (?)", args!["John"])];
+    ///   db.validate_batch(&stmts).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn validate_batch(&self, stmts: &[Statement]) -> Result<()> {
+        let mut explained = Vec::new();
+        let mut explain = true;
+        for (idx, stmt) in stmts.iter().enumerate() {
+            let expected = crate::sql::parameter_count(&stmt.sql);
+            if expected != stmt.args.len() {
+                anyhow::bail!(
+                    "Statement {idx} expects {expected} parameters, but {} were given: {}",
+                    stmt.args.len(),
+                    stmt.sql
+                );
+            }
+            let keyword = crate::sql::leading_keyword(&stmt.sql);
+            if explain && keyword.as_deref() != Some("EXPLAIN") {
+                let mut explain_stmt =
+                    Statement::with_args(format!("EXPLAIN {}", stmt.sql), &stmt.args)
+                        .priority(stmt.priority);
+                explain_stmt.timeout = stmt.timeout;
+                explained.push((idx, explain_stmt));
+            }
+            // Following statements may depend on the new schema, which does not exist yet
+            if matches!(keyword.as_deref(), Some("CREATE" | "DROP" | "ALTER")) {
+                explain = false;
+            }
+        }
+        if explained.is_empty() {
+            return Ok(());
+        }
+        let result = self
+            .raw_batch(explained.iter().map(|(_, stmt)| stmt.clone()))
+            .await?;
+        for ((idx, _), error) in explained.iter().zip(result.step_errors) {
+            if let Some(error) = error {
+                anyhow::bail!(
+                    "Statement {idx} is invalid: {}: {}",
+                    error.message,
+                    stmts[*idx].sql
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares statements which are about to be executed often, e.g. at startup,
+    /// and checks them against the current schema, so that a schema drift is reported
+    /// at deploy time rather than by the first request using them.
+    /// All invalid statements are reported in a single error.
+    ///
+    /// The local backend compiles the statements into its cache of prepared statements.
+    /// Remote servers do not keep statements across requests, so they are only checked
+    /// with `EXPLAIN`.
+    ///
+    /// # Arguments
+    /// * `stmts` - SQL statements, along with their arguments if any
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Statement};
+    ///   let db = libsql_client::new_client().await?;
+    ///   db.prewarm(&[
+    ///       Statement::new("SELECT * FROM users WHERE id = ?"),
+    ///       Statement::new("UPDATE users SET last_seen = ? WHERE id = ?"),
+    ///   ])
+    ///   .await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let explained = stmts.iter().map(|stmt| {
+            if crate::sql::leading_keyword(&stmt.sql).as_deref() == Some("EXPLAIN") {
+                return Statement::new(stmt.sql.clone());
+            }
+            Statement::new(format!("EXPLAIN {}", stmt.sql))
+        });
+        let result = self.raw_batch(explained).await?;
+        let errors = stmts
+            .iter()
+            .zip(result.step_errors)
+            .enumerate()
+            .filter_map(|(idx, (stmt, error))| Some((idx, stmt, error?.message)));
+        report_invalid(errors)
+    }
+
+    /// Whether `batch()` validates all statements with `validate_batch()`
+    /// before executing any of them, as set by `Config::validate_batches()`
+    fn validates_batches(&self) -> bool {
+        false
+    }
+
+    /// Whether `execute()` attaches the timings of the statement to its result,
+    /// as set by `Config::collect_timings()`
+    fn collects_timings(&self) -> bool {
+        false
+    }
+
+    /// Called when a `Transaction` is dropped without `commit()` or `rollback()`, e.g. while
+    /// a panic unwinds, so that it does not stay open on the connection. Clients keeping
+    /// a connection roll the transaction back, right away or before their next request,
+    /// and return true. The default does nothing and returns false.
+    fn schedule_rollback(&self) -> bool {
+        false
+    }
+
+    /// Waits until the writes issued through this client are visible to its reads, e.g. before
+    /// reading back data written by another part of the application, when reads are sent to
+    /// replicas which may lag behind the primary. Clients sending reads and writes to the same
+    /// database return right away, since writes are durable and visible once acknowledged.
+    /// Clients reading from replicas wait for them to catch up, see `discovery::Discovered`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(db: &impl libsql_client::DatabaseClient) -> anyhow::Result<()> {
+    ///   db.execute("UPDATE users SET name = 'ann' WHERE id = 1").await?;
+    ///   db.barrier().await?;
+    ///   let user = db.execute("SELECT name FROM users WHERE id = 1").await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn barrier(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Starts an interactive transaction and returns a `Transaction` object.
     /// The object can be later used to `execute()`, `commit()` or `rollback()`
     /// the interactive transaction.
     async fn transaction<'a>(&'a self) -> Result<Transaction<'a, Self>> {
         Transaction::new(self).await
     }
+
+    /// Returns cumulative counters describing the activity of this client
+    fn stats(&self) -> ClientStats {
+        ClientStats::default()
+    }
+
+    /// Runs `f` with a client scoped to it. A transaction started in the scope
+    /// and still open when `f` returns, fails or panics is rolled back,
+    /// so that it does not leak into subsequent statements on the same connection.
+    /// Panics are propagated after the rollback.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   let db = libsql_client::new_client().await?;
+    ///   db.scope(|c| async move {
+    ///       let tx = c.transaction().await?;
+    ///       tx.execute("UPDATE accounts SET balance = balance - 10 WHERE id = 1").await?;
+    ///       // If this fails, the transaction is rolled back when the scope exits
+    ///       tx.execute("UPDATE accounts SET balance = balance + 10 WHERE id = 2").await?;
+    ///       tx.commit().await
+    ///   })
+    ///   .await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn scope<'a, F, Fut, T>(&'a self, f: F) -> Result<T>
+    where
+        F: FnOnce(Scope<'a, Self>) -> Fut + 'a,
+        Fut: std::future::Future<Output = Result<T>> + 'a,
+        T: 'a,
+    {
+        crate::scope::run(self, f).await
+    }
+
+    /// Executes a single SQL statement and writes the returned rows into `writer`,
+    /// as NDJSON or CSV, returning the number of rows written.
+    /// Rows are encoded and written in chunks, without building the whole output in memory.
+    /// The format can be given with options, e.g. `CopyFormat::Csv.with_floats()`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(response: &mut (impl futures_util::io::AsyncWrite + Unpin)) -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use libsql_client::copy::CopyFormat;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   db.copy_out("SELECT * FROM orders", CopyFormat::Csv, response).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    #[cfg(feature = "copy")]
+    async fn copy_out<W: futures_util::io::AsyncWrite + Unpin>(
+        &self,
+        stmt: impl Into<Statement>,
+        format: impl Into<crate::copy::CopyOptions>,
+        writer: &mut W,
+    ) -> Result<u64> {
+        crate::copy::copy_out(self, stmt.into(), format.into(), writer).await
+    }
+
+    /// Reads rows as NDJSON or CSV from `reader` and inserts them into `table`,
+    /// in transactional batches of 500 rows, returning the number of rows inserted.
+    /// Columns are named by the CSV header line, or by the keys of the first NDJSON object,
+    /// and values are converted according to the declared types of the columns.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(upload: impl futures_util::io::AsyncRead + Unpin) -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use libsql_client::copy::CopyFormat;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let inserted = db.copy_in("orders", CopyFormat::Ndjson, upload).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    #[cfg(feature = "copy")]
+    async fn copy_in<R: futures_util::io::AsyncRead + Unpin>(
+        &self,
+        table: &str,
+        format: crate::copy::CopyFormat,
+        reader: R,
+    ) -> Result<u64> {
+        crate::copy::copy_in(self, table, format, reader).await
+    }
+
+    /// Tries to acquire the advisory lock `name`, held until it's released
+    /// or until `ttl` passes without it being renewed. Returns `None` if another owner
+    /// holds it. Locks are stored in the `_libsql_locks` table, created if needed.
+    /// See the `lock` module for details.
+    async fn try_advisory_lock<'a>(
+        &'a self,
+        name: &str,
+        ttl: std::time::Duration,
+    ) -> Result<Option<crate::lock::LockGuard<'a, Self>>> {
+        crate::lock::try_lock(self, name, ttl).await
+    }
+
+    /// Checks that the schema of the database contains the tables, indexes, views and triggers
+    /// of `expected`, e.g. at startup, failing with a readable list of differences otherwise.
+    /// See `migrations::assert_schema()` for details.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use libsql_client::migrations::Schema;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let expected = Schema::parse(&std::fs::read_to_string("schema.sql")?)?;
+    ///   // e.g. "table users: missing column `email TEXT NOT NULL`"
+    ///   db.assert_schema(&expected).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    async fn assert_schema(&self, expected: &crate::migrations::Schema) -> Result<()> {
+        crate::migrations::assert_schema(self, expected).await
+    }
+
+    /// Returns a client which rewrites unqualified table names of all statements
+    /// to start with `prefix`, for schemes which keep a set of tables per tenant.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   let db = libsql_client::new_client().await?;
+    ///   let tenant = db.with_schema_prefix("tenant_42_");
+    ///   // Executed as SELECT * FROM tenant_42_users
+    ///   tenant.execute("SELECT * FROM users").await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    fn with_schema_prefix(&self, prefix: impl Into<String>) -> SchemaPrefixed<'_, Self>
+    where
+        Self: Sized,
+    {
+        SchemaPrefixed::new(self, prefix)
+    }
 }
 
 /// A generic client struct, wrapping possible backends.
@@ -97,6 +645,21 @@ pub enum GenericClient {
     Workers(crate::workers::Client),
     #[cfg(feature = "spin_backend")]
     Spin(crate::spin::Client),
+    #[cfg(feature = "http_backend")]
+    Http(crate::http::Client<Box<dyn crate::http::HttpTransport>>),
+}
+
+impl GenericClient {
+    /// Closes the client, e.g. the WebSocket connection of the hrana backend.
+    /// Backends without a persistent connection are simply dropped.
+    pub async fn shutdown(self) -> Result<()> {
+        match self {
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.shutdown().await,
+            #[allow(unreachable_patterns)]
+            _ => Ok(()),
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -115,7 +678,60 @@ impl DatabaseClient for GenericClient {
             #[cfg(feature = "workers_backend")]
             Self::Workers(w) => w.raw_batch(stmts).await,
             #[cfg(feature = "spin_backend")]
-            Self::Spin(s) => s.raw_batch(stmts),
+            Self::Spin(s) => s.raw_batch(stmts).await,
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.raw_batch(stmts).await,
+        }
+    }
+
+    async fn run_batch(&self, batch: Batch) -> Result<BatchResult> {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.run_batch(batch).await,
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.run_batch(batch).await,
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.run_batch(batch).await,
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.run_batch(batch).await,
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.run_batch(batch).await,
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.run_batch(batch).await,
+        }
+    }
+
+    async fn execute_multi(&self, sql: &str) -> Result<Vec<ResultSet>> {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.execute_multi(sql).await,
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.execute_multi(sql).await,
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.execute_multi(sql).await,
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.execute_multi(sql).await,
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.execute_multi(sql).await,
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.execute_multi(sql).await,
+        }
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.prewarm(stmts).await,
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.prewarm(stmts).await,
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.prewarm(stmts).await,
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.prewarm(stmts).await,
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.prewarm(stmts).await,
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.prewarm(stmts).await,
         }
     }
 
@@ -127,22 +743,446 @@ impl DatabaseClient for GenericClient {
             Self::Hrana(_) => Transaction::new(self).await,
             #[cfg(feature = "reqwest_backend")]
             Self::Reqwest(_) => {
-                anyhow::bail!("Interactive transactions are not supported with the reqwest backend. Use batch() instead.")
+                anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are not supported with the reqwest backend. Use batch() instead."))
             }
             #[cfg(feature = "workers_backend")]
             Self::Workers(_) => Transaction::new(self).await,
             #[cfg(feature = "spin_backend")]
             Self::Spin(_) => {
-                anyhow::bail!("Interactive ransactions are not supported with the spin backend. Use batch() instead.")
+                anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are not supported with the spin backend. Use batch() instead."))
+            }
+            #[cfg(feature = "http_backend")]
+            Self::Http(_) => {
+                anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are not supported with the http backend. Use batch() instead."))
             }
         }
     }
+
+    fn validates_batches(&self) -> bool {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.validates_batches(),
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.validates_batches(),
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.validates_batches(),
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.validates_batches(),
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.validates_batches(),
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.validates_batches(),
+        }
+    }
+
+    fn collects_timings(&self) -> bool {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.collects_timings(),
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.collects_timings(),
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.collects_timings(),
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.collects_timings(),
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.collects_timings(),
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.collects_timings(),
+        }
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.schedule_rollback(),
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.schedule_rollback(),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    fn stats(&self) -> ClientStats {
+        match self {
+            #[cfg(feature = "local_backend")]
+            Self::Local(l) => l.stats(),
+            #[cfg(feature = "reqwest_backend")]
+            Self::Reqwest(r) => r.stats(),
+            #[cfg(feature = "hrana_backend")]
+            Self::Hrana(h) => h.stats(),
+            #[cfg(feature = "workers_backend")]
+            Self::Workers(w) => w.stats(),
+            #[cfg(feature = "spin_backend")]
+            Self::Spin(s) => s.stats(),
+            #[cfg(feature = "http_backend")]
+            Self::Http(h) => h.stats(),
+        }
+    }
 }
 
-/// Configuration for the database client
+/// Configuration for the database client. New options may be added in minor versions,
+/// so configurations are built with `new()` rather than a struct literal.
+#[non_exhaustive]
 pub struct Config {
     pub url: url::Url,
     pub auth_token: Option<String>,
+    /// Whether foreign key constraints are enforced on each new connection.
+    /// `None` leaves the server default untouched.
+    pub foreign_keys: Option<bool>,
+    /// Statements executed on each new connection, after `foreign_keys` is applied.
+    pub init_statements: Vec<Statement>,
+    /// Policy for retrying requests rejected by the server, e.g. due to rate limiting
+    pub retry_policy: RetryPolicy,
+    /// Budget bounding the retries of all requests of the client, `None` for no bound
+    pub retry_budget: Option<RetryBudget>,
+    /// Whether batches are validated before any of their statements is executed
+    pub validate_batches: bool,
+    /// Table in which keys of applied idempotent writes are persisted
+    pub idempotency_table: Option<String>,
+    /// Policy for reading TEXT values which are not valid UTF-8
+    pub invalid_utf8: InvalidUtf8,
+    /// Representation of timestamps encoded by the application with `TimestampFormat::encode()`
+    pub timestamp_format: TimestampFormat,
+    /// Whether the timings of executed statements are attached to their results
+    pub collect_timings: bool,
+    /// Whether writes are rejected: local databases are opened read-only,
+    /// and remote clients refuse to send statements which may write
+    pub read_only: bool,
+    /// Limit on the time taken to establish a connection to the server
+    pub connect_timeout: Option<std::time::Duration>,
+    /// Limit on the time a connection may stay silent before it is considered dead
+    pub read_timeout: Option<std::time::Duration>,
+    /// Interval after which pooled connections are dropped, to resolve the server again
+    pub dns_refresh: Option<std::time::Duration>,
+    /// Limit on the number of requests a client sends, enforced by the workers and spin backends
+    pub max_requests: Option<u64>,
+    /// Encoding of blobs in the requests of the http backend
+    pub blob_encoding: BlobEncoding,
+}
+
+impl Config {
+    /// Creates a configuration for the given database URL, with no auth token
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("file:////tmp/example.db").unwrap();
+    /// ```
+    pub fn new<T: TryInto<url::Url>>(url: T) -> Result<Config>
+    where
+        <T as TryInto<url::Url>>::Error: std::fmt::Display,
+    {
+        let url = url.try_into().map_err(|e| anyhow!(format!("{e}")))?;
+        Ok(Self {
+            url,
+            auth_token: None,
+            foreign_keys: None,
+            init_statements: vec![],
+            retry_policy: RetryPolicy::default(),
+            retry_budget: None,
+            validate_batches: false,
+            idempotency_table: None,
+            invalid_utf8: InvalidUtf8::default(),
+            timestamp_format: TimestampFormat::default(),
+            collect_timings: false,
+            read_only: false,
+            connect_timeout: None,
+            read_timeout: None,
+            dns_refresh: None,
+            max_requests: None,
+            blob_encoding: BlobEncoding::default(),
+        })
+    }
+
+    /// Sets the authentication token for the database
+    pub fn with_auth_token(mut self, auth_token: impl Into<String>) -> Self {
+        self.auth_token = Some(auth_token.into());
+        self
+    }
+
+    /// Enables or disables foreign key enforcement with `PRAGMA foreign_keys`.
+    /// SQLite keeps this setting per connection, so it is re-applied to every
+    /// new connection or stream established by the client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("file:////tmp/example.db").unwrap().foreign_keys(true);
+    /// ```
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = Some(enabled);
+        self
+    }
+
+    /// Sets statements executed on every new connection or stream,
+    /// e.g. per-connection PRAGMAs, `ATTACH` or application settings.
+    /// They are executed in order, before any other statement is sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::{Config, Statement};
+    /// let config = Config::new("file:////tmp/example.db")
+    ///     .unwrap()
+    ///     .init_statements(vec![Statement::new("PRAGMA busy_timeout = 5000")]);
+    /// ```
+    pub fn init_statements(mut self, stmts: Vec<Statement>) -> Self {
+        self.init_statements = stmts;
+        self
+    }
+
+    /// Sets the policy for retrying requests rejected by the server.
+    /// Requests are only retried if the server did not process them,
+    /// and a `Retry-After` delay requested by the server is honored.
+    /// Retries are currently performed by the reqwest backend.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets a budget bounding the retries of all requests of the client together, on top of
+    /// the retries of each request bounded by `retry_policy()`. See `RetryBudget`.
+    /// Retries are currently performed by the reqwest backend.
+    pub fn retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// Enables validating every batch with `DatabaseClient::validate_batch()`
+    /// before executing it, so that an invalid statement fails the whole batch
+    /// up front instead of after some of its steps were applied.
+    /// Validation costs an extra round trip per batch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("file:////tmp/example.db").unwrap().validate_batches(true);
+    /// ```
+    pub fn validate_batches(mut self, enabled: bool) -> Self {
+        self.validate_batches = enabled;
+        self
+    }
+
+    /// Persists the keys of statements with an idempotency key in the given table,
+    /// in the same request as the statements themselves. When the outcome of a request
+    /// is unknown, e.g. because the connection was lost before a response arrived,
+    /// the table is consulted before retrying, so that statements which were applied
+    /// are skipped instead of being executed twice.
+    /// The table is created if it does not exist.
+    /// Keys are used by the reqwest backend, which retries requests automatically.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .idempotency_table("_idempotency_keys");
+    /// ```
+    pub fn idempotency_table(mut self, table: impl Into<String>) -> Self {
+        self.idempotency_table = Some(table.into());
+        self
+    }
+
+    /// Sets the policy for reading TEXT values which are not valid UTF-8.
+    /// By default, invalid sequences are replaced with U+FFFD.
+    /// Only the local backend reads raw values, remote servers always send valid UTF-8.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// use libsql_client::text::InvalidUtf8;
+    ///
+    /// let config = Config::new("file:////tmp/example.db").unwrap().invalid_utf8(InvalidUtf8::Blob);
+    /// ```
+    pub fn invalid_utf8(mut self, policy: InvalidUtf8) -> Self {
+        self.invalid_utf8 = policy;
+        self
+    }
+
+    /// Sets the representation of timestamps stored by the application,
+    /// so that all of them are encoded consistently with `TimestampFormat::encode()`.
+    /// The client itself does not rewrite values, the format is read from the config.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// use libsql_client::time::TimestampFormat;
+    ///
+    /// let config = Config::new("file:////tmp/example.db")
+    ///     .unwrap()
+    ///     .timestamp_format(TimestampFormat::UnixEpoch);
+    /// ```
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Enables collecting the time spent in each phase of the execution of statements,
+    /// e.g. on the network, which `execute()` attaches to `ResultSet::timings`.
+    /// See the `timings` module for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("file:////tmp/example.db").unwrap().collect_timings(true);
+    /// ```
+    pub fn collect_timings(mut self, enabled: bool) -> Self {
+        self.collect_timings = enabled;
+        self
+    }
+
+    /// Rejects writes, e.g. for analytics or debugging connections to a production database.
+    /// Local databases are opened read-only. Remote clients fail statements which may write
+    /// before sending them, e.g. `INSERT` or `PRAGMA name = value`, and also set
+    /// `PRAGMA query_only` on every new connection or stream, in case a write gets through.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("file:////tmp/example.db").unwrap().read_only(true);
+    /// ```
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// Sets a limit on the time taken to establish a connection to the server.
+    /// It is currently enforced by the reqwest backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .connect_timeout(std::time::Duration::from_secs(5));
+    /// ```
+    pub fn connect_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a connection may stay silent while a response is awaited before it is
+    /// considered dead, e.g. after the server vanished without closing it, and the request
+    /// fails. It bounds the wait for the response headers and for each chunk of the body.
+    /// This is distinct from `Statement::timeout()`, which bounds the execution of a statement
+    /// on the server. The server only responds once its statements completed, so `timeout`
+    /// should exceed the time taken by the slowest of them. Connections are also probed
+    /// with TCP keepalives sent after `timeout` of silence, including idle pooled ones.
+    /// It is currently enforced by the reqwest backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .read_timeout(std::time::Duration::from_secs(30));
+    /// ```
+    pub fn read_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how often pooled connections to the server are dropped, so that the next
+    /// connection resolves its hostname again and a failover done via DNS is picked up
+    /// without restarting the process. Keep it close to the TTL of the DNS record.
+    /// Requests in flight complete on their connection. Addresses are resolved by the
+    /// system resolver, or with trust-dns, which caches them for their TTL, with the
+    /// `trust_dns` feature, and IPv4 and IPv6 addresses are tried concurrently
+    /// (happy eyeballs). It is currently enforced by the reqwest backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .dns_refresh(std::time::Duration::from_secs(60));
+    /// ```
+    pub fn dns_refresh(mut self, interval: std::time::Duration) -> Self {
+        self.dns_refresh = Some(interval);
+        self
+    }
+
+    /// Caps the number of requests the client sends, so that an edge invocation fails fast
+    /// with a clear error instead of reaching the subrequest limit of the platform, possibly
+    /// in the middle of a transaction. Requests are counted in `ClientStats::requests_sent`.
+    /// It is enforced by the spin backend, which sends a request per batch, and by the workers
+    /// backend, which counts the opening of its WebSocket and every request sent over it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .max_requests(45);
+    /// ```
+    pub fn max_requests(mut self, max_requests: u64) -> Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
+
+    /// Sets the encoding of blobs in the JSON bodies of the requests sent by the http backend,
+    /// for servers which do not expect base64. With `BlobEncoding::Detect`, the encoding
+    /// follows the blobs found in the responses of the server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// use libsql_client::transport::BlobEncoding;
+    ///
+    /// let config = Config::new("https://example.turso.io")
+    ///     .unwrap()
+    ///     .blob_encoding(BlobEncoding::Detect);
+    /// ```
+    pub fn blob_encoding(mut self, encoding: BlobEncoding) -> Self {
+        self.blob_encoding = encoding;
+        self
+    }
+
+    /// Statements executed on every new connection, before any user statement
+    pub(crate) fn connection_statements(&self) -> Vec<Statement> {
+        let mut stmts = Vec::new();
+        if let Some(enabled) = self.foreign_keys {
+            let state = if enabled { "ON" } else { "OFF" };
+            stmts.push(Statement::new(format!("PRAGMA foreign_keys = {state}")));
+        }
+        if self.read_only {
+            stmts.push(Statement::new("PRAGMA query_only = ON"));
+        }
+        stmts.extend(self.init_statements.iter().cloned());
+        stmts
+    }
+}
+
+/// Executes the statements of an SQL string one request at a time, stopping at the first
+/// failing one, for `execute_multi()` on backends which reject conditional batches
+#[cfg_attr(
+    not(any(feature = "spin_backend", feature = "http_backend")),
+    allow(dead_code)
+)]
+pub(crate) async fn execute_sequentially<C: DatabaseClient + ?Sized>(
+    db: &C,
+    sql: &str,
+) -> Result<Vec<ResultSet>> {
+    let mut results = Vec::new();
+    for stmt in crate::sql::split_statements(sql) {
+        let result = db.execute(stmt).await.map_err(|e| anyhow!("{e}: {stmt}"))?;
+        results.push(result);
+    }
+    Ok(results)
 }
 
 /// Establishes a database client based on `Config` struct
@@ -152,16 +1192,16 @@ pub struct Config {
 /// ```
 /// # async fn f() {
 /// # use libsql_client::{DatabaseClient, Config};
-/// let config = Config { url: url::Url::parse("file:////tmp/example.db").unwrap(), auth_token: None };
+/// let config = Config::new("file:////tmp/example.db").unwrap();
 /// let db = libsql_client::new_client_from_config(config).await.unwrap();
 /// # }
 /// ```
-pub async fn new_client_from_config<'a>(config: Config) -> anyhow::Result<GenericClient> {
+pub async fn new_client_from_config(config: Config) -> anyhow::Result<GenericClient> {
     let scheme = config.url.scheme();
     Ok(match scheme {
         #[cfg(feature = "local_backend")]
         "file" => {
-            GenericClient::Local(crate::local::Client::new(config.url.to_string())?)
+            GenericClient::Local(crate::local::Client::from_config(config)?)
         },
         #[cfg(feature = "hrana_backend")]
         "ws" | "wss" => {
@@ -216,181 +1256,142 @@ pub async fn new_client() -> anyhow::Result<GenericClient> {
     let url = std::env::var("LIBSQL_CLIENT_URL").map_err(|_| {
         anyhow::anyhow!("LIBSQL_CLIENT_URL variable should point to your libSQL/sqld database")
     })?;
-    let auth_token = std::env::var("LIBSQL_CLIENT_TOKEN").ok();
-    new_client_from_config(Config {
-        url: url::Url::parse(&url)?,
-        auth_token,
-    })
-    .await
+    let mut config = Config::new(url.as_str())?;
+    config.auth_token = std::env::var("LIBSQL_CLIENT_TOKEN").ok();
+    new_client_from_config(config).await
 }
 
-// FIXME: serialize and deserialize with existing routines from sqld
-pub(crate) fn statements_to_string(
-    stmts: impl IntoIterator<Item = impl Into<Statement>>,
-) -> (String, usize) {
-    let mut body = "{\"statements\": [".to_string();
-    let mut stmts_count = 0;
-    for stmt in stmts {
-        body += &format!("{},", stmt.into());
-        stmts_count += 1;
+/// Fails with a single error listing the statements which could not be prepared by `prewarm()`
+pub(crate) fn report_invalid<'s>(
+    errors: impl IntoIterator<Item = (usize, &'s Statement, String)>,
+) -> Result<()> {
+    let errors: Vec<String> = errors
+        .into_iter()
+        .map(|(idx, stmt, error)| format!("statement {idx}: {error}: {}", stmt.sql))
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
     }
-    if stmts_count > 0 {
-        body.pop();
-    }
-    body += "]}";
-    (body, stmts_count)
+    anyhow::bail!(
+        "{} statements are invalid against the current schema:\n{}",
+        errors.len(),
+        errors.join("\n")
+    )
 }
 
-pub(crate) fn parse_columns(
-    columns: Vec<serde_json::Value>,
-    result_idx: usize,
-) -> Result<Vec<Col>> {
-    let mut result = Vec::with_capacity(columns.len());
-    for (idx, column) in columns.into_iter().enumerate() {
-        match column {
-            serde_json::Value::String(column) => result.push(Col { name: Some(column) }),
-            _ => {
-                return Err(anyhow!(format!(
-                    "Result {result_idx} column name {idx} not a string",
-                )))
-            }
-        }
+/// Fails if the client is read-only and one of the statements may write,
+/// as set by `Config::read_only()`
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend",
+        feature = "hrana_backend",
+        feature = "workers_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn check_read_only(read_only: bool, stmts: &[Statement]) -> Result<()> {
+    if !read_only {
+        return Ok(());
+    }
+    match stmts
+        .iter()
+        .find(|stmt| !crate::sql::is_read_only(&stmt.sql))
+    {
+        Some(stmt) => anyhow::bail!("The client is read-only, refusing to execute: {}", stmt.sql),
+        None => Ok(()),
     }
-    Ok(result)
 }
 
-pub(crate) fn parse_value(
-    cell: serde_json::Value,
-    result_idx: usize,
-    row_idx: usize,
-    cell_idx: usize,
-) -> Result<Value> {
-    match cell {
-        serde_json::Value::Null => Ok(Value::Null),
-        serde_json::Value::Number(v) => match v.as_i64() {
-            Some(v) => Ok(Value::Integer{value: v} ),
-            None => match v.as_f64() {
-                Some(v) => Ok(Value::Float{value: v}),
-                None => Err(anyhow!(
-                    "Result {result_idx} row {row_idx} cell {cell_idx} had unknown number value: {v}",
-                )),
-            },
-        },
-        serde_json::Value::String(v) => Ok(Value::Text{value: v}),
-        _ => Err(anyhow!(
-            "Result {result_idx} row {row_idx} cell {cell_idx} had unknown type",
-        )),
+/// Records the composition of a batch about to be executed: the number of steps,
+/// per-step SQL fingerprints and the total size of the serialized statements.
+pub(crate) fn trace_batch(stmts: &[Statement]) {
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return;
+    }
+    let fingerprints: Vec<String> = stmts
+        .iter()
+        .map(|stmt| crate::sql::fingerprint(&stmt.sql))
+        .collect();
+    let payload_size: usize = stmts
+        .iter()
+        .map(|stmt| crate::pipeline::encode_statement(stmt).to_string().len())
+        .sum();
+    tracing::debug!(
+        steps = stmts.len(),
+        payload_size,
+        fingerprints = ?fingerprints,
+        "Executing batch"
+    );
+    // Arguments are rendered according to the redaction policy
+    for stmt in stmts {
+        tracing::trace!(%stmt, "Batch step");
     }
 }
 
-pub(crate) fn parse_rows(
-    rows: Vec<serde_json::Value>,
-    cols_len: usize,
-    result_idx: usize,
-) -> Result<Vec<Vec<Value>>> {
-    let mut result = Vec::with_capacity(rows.len());
-    for (idx, row) in rows.into_iter().enumerate() {
-        match row {
-            serde_json::Value::Array(row) => {
-                if row.len() != cols_len {
-                    return Err(anyhow!(
-                        "Result {result_idx} row {idx} had wrong number of cells",
-                    ));
-                }
-                let mut cells: Vec<Value> = Vec::with_capacity(cols_len);
-                for (cell_idx, value) in row.into_iter().enumerate() {
-                    cells.push(parse_value(value, result_idx, idx, cell_idx)?);
-                }
-                result.push(cells)
-            }
-            _ => return Err(anyhow!("Result {result_idx} row {idx} was not an array",)),
-        }
-    }
-    Ok(result)
+/// Options of a statement which shape or check its result, taken out of it before it is sent
+/// and applied to its result by `finish()`, so that all implementations of `execute()`
+/// post-process results alike
+pub(crate) struct ResultOptions {
+    limits: Option<(crate::guardrails::Limits, String)>,
+    decodes: Vec<(String, crate::decode::Decode)>,
+    order_keys: Vec<String>,
+    blob_policy: crate::decode::BlobPolicy,
 }
 
-pub(crate) fn parse_query_result(
-    result: serde_json::Value,
-    idx: usize,
-) -> Result<(Option<proto::StmtResult>, Option<proto::Error>)> {
-    match result {
-        serde_json::Value::Object(obj) => {
-            if let Some(err) = obj.get("error") {
-                return match err {
-                    serde_json::Value::Object(obj) => match obj.get("message") {
-                        Some(serde_json::Value::String(msg)) => Ok((
-                            None,
-                            Some(proto::Error {
-                                message: msg.clone(),
-                            }),
-                        )),
-                        _ => Err(anyhow!("Result {idx} error message was not a string",)),
-                    },
-                    _ => Err(anyhow!("Result {idx} results was not an object",)),
-                };
-            }
+impl ResultOptions {
+    pub(crate) fn take(stmt: &mut Statement) -> Self {
+        Self {
+            limits: (!stmt.limits.is_empty()).then(|| (stmt.limits, stmt.sql.clone())),
+            decodes: std::mem::take(&mut stmt.decodes),
+            order_keys: std::mem::take(&mut stmt.order_keys),
+            blob_policy: stmt.blob_policy,
+        }
+    }
 
-            let results = obj.get("results");
-            match results {
-                Some(serde_json::Value::Object(obj)) => {
-                    let columns = obj
-                        .get("columns")
-                        .ok_or_else(|| anyhow!(format!("Result {idx} had no columns")))?;
-                    let rows = obj
-                        .get("rows")
-                        .ok_or_else(|| anyhow!(format!("Result {idx} had no rows")))?;
-                    match (rows, columns) {
-                        (serde_json::Value::Array(rows), serde_json::Value::Array(columns)) => {
-                            let cols = parse_columns(columns.to_vec(), idx)?;
-                            let rows = parse_rows(rows.to_vec(), columns.len(), idx)?;
-                            // FIXME: affected_row_count and last_insert_rowid are not implemented yet
-                            let result_set = proto::StmtResult {
-                                cols,
-                                rows,
-                                affected_row_count: 0,
-                                last_insert_rowid: None,
-                            };
-                            Ok((Some(result_set), None))
-                        }
-                        _ => Err(anyhow!(
-                            "Result {idx} had rows or columns that were not an array",
-                        )),
-                    }
-                }
-                Some(_) => Err(anyhow!("Result {idx} was not an object",)),
-                None => Err(anyhow!("Result {idx} did not contain results or error",)),
-            }
+    /// Builds the result set of the statement, applying its options
+    pub(crate) fn finish(
+        self,
+        result: proto::StmtResult,
+        timings: Option<crate::timings::Timings>,
+        server_stats: Option<crate::server_stats::ServerStats>,
+    ) -> Result<ResultSet> {
+        let mut result = ResultSet {
+            timings,
+            server_stats,
+            ..ResultSet::from(result)
+        };
+        self.blob_policy.apply(&mut result);
+        crate::decode::apply(&self.decodes, &mut result)?;
+        crate::statement::check_order_keys(&self.order_keys, &result)?;
+        if let Some((limits, sql)) = self.limits {
+            limits.check(&sql, &result)?;
         }
-        _ => Err(anyhow!("Result {idx} was not an object",)),
+        Ok(result)
     }
 }
 
-pub(crate) fn http_json_to_batch_result(
-    response_json: serde_json::Value,
-    stmts_count: usize,
-) -> anyhow::Result<BatchResult> {
-    match response_json {
-        serde_json::Value::Array(results) => {
-            if results.len() != stmts_count {
-                return Err(anyhow::anyhow!(
-                    "Response array did not contain expected {stmts_count} results"
-                ));
-            }
-
-            let mut step_results: Vec<Option<proto::StmtResult>> = Vec::with_capacity(stmts_count);
-            let mut step_errors: Vec<Option<proto::Error>> = Vec::with_capacity(stmts_count);
-            for (idx, result) in results.into_iter().enumerate() {
-                let (step_result, step_error) =
-                    parse_query_result(result, idx).map_err(|e| anyhow::anyhow!("{e}"))?;
-                step_results.push(step_result);
-                step_errors.push(step_error);
-            }
-
-            Ok(BatchResult {
-                step_results,
-                step_errors,
-            })
-        }
-        e => Err(anyhow::anyhow!("Error: {}", e)),
+/// Drops the results of connection initialization statements, which stateless
+/// backends prepend to every request, failing if any of them did not succeed.
+#[cfg_attr(
+    not(any(
+        feature = "local_backend",
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn strip_init_results(
+    mut result: BatchResult,
+    init_count: usize,
+) -> Result<BatchResult> {
+    if let Some(err) = result.step_errors.iter().take(init_count).flatten().next() {
+        anyhow::bail!("Connection initialization failed: {}", err.message);
     }
+    let init_count = init_count.min(result.step_results.len());
+    result.step_results.drain(..init_count);
+    result.step_errors.drain(..init_count);
+    Ok(result)
 }
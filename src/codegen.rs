@@ -0,0 +1,316 @@
+//! `codegen` generates typed Rust functions from a directory of `.sql` files, from a build
+//! script. It is only available with the `codegen` feature.
+//!
+//! Each file holds a single statement and becomes an async function named after the file.
+//! Statements are described against a local database, created from a schema snapshot
+//! with `Codegen::schema()` or opened with `Codegen::database()`, so that building does not
+//! require a server. Statements are checked against the schema on every build, and an invalid
+//! statement fails the build.
+//!
+//! ```rust,no_run
+//!   // build.rs
+//!   fn main() -> anyhow::Result<()> {
+//!       let out = std::path::Path::new(&std::env::var("OUT_DIR")?).join("queries.rs");
+//!       libsql_client::codegen::Codegen::new("queries")
+//!           .schema("schema.sql")
+//!           .write_to(out)
+//!   }
+//! ```
+//!
+//! The generated module is then included in the crate, which needs `serde` with its `derive`
+//! feature: `mod queries { include!(concat!(env!("OUT_DIR"), "/queries.rs")); }`.
+//! For `queries/find_user.sql` containing `SELECT id, name FROM users WHERE id = :id`,
+//! it contains:
+//!
+//! ```text
+//! pub struct FindUserRow {
+//!     pub id: Option<i64>,
+//!     pub name: Option<String>,
+//! }
+//!
+//! pub async fn find_user(
+//!     db: &(impl libsql_client::DatabaseClient + ?Sized),
+//!     id: impl Into<libsql_client::Value>,
+//! ) -> anyhow::Result<Vec<FindUserRow>>
+//! ```
+//!
+//! Statements which do not return rows return the number of affected rows instead.
+//! Columns are typed after the affinity of their declared type, and are optional since
+//! SQLite does not report whether they can be NULL. Columns which are not read directly
+//! from a table, e.g. `COUNT(*)`, have no declared type and are generated as
+//! `serde_json::Value`, unless their type is set in their name: `COUNT(*) AS "total: i64"`.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::proto::v2::DescribeResult;
+use crate::sql::Affinity;
+
+/// Rust keywords which cannot be used as raw identifiers
+const RESERVED: &[&str] = &["crate", "self", "Self", "super", "_"];
+
+/// Rust keywords, which are escaped as raw identifiers
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+    "abstract", "become", "box", "do", "final", "gen", "macro", "override", "priv", "try",
+    "typeof", "unsized", "virtual", "yield",
+];
+
+/// Database the statements are described against
+enum Source {
+    Schema(PathBuf),
+    Database(PathBuf),
+}
+
+/// Generator of typed functions for the `.sql` files of a directory
+pub struct Codegen {
+    queries: PathBuf,
+    source: Option<Source>,
+}
+
+impl Codegen {
+    /// Creates a generator for the `.sql` files of the `queries` directory
+    pub fn new(queries: impl Into<PathBuf>) -> Self {
+        Self {
+            queries: queries.into(),
+            source: None,
+        }
+    }
+
+    /// Describes statements against an in-memory database created by the SQL script
+    /// at `path`, e.g. a schema dump or a migration applied in order
+    pub fn schema(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source = Some(Source::Schema(path.into()));
+        self
+    }
+
+    /// Describes statements against the local database at `path`, e.g. a development database
+    pub fn database(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source = Some(Source::Database(path.into()));
+        self
+    }
+
+    /// Generates the source code of the functions, in the order of the file names
+    pub fn generate(&self) -> Result<String> {
+        let db = self.open()?;
+        let mut files = Vec::new();
+        let entries = std::fs::read_dir(&self.queries)
+            .with_context(|| format!("Failed to read {}", self.queries.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "sql") {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        let mut code = String::from("// Generated by libsql_client::codegen, do not edit\n");
+        for path in files {
+            let sql = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let sql = sql.trim().trim_end_matches(';').trim_end();
+            let description = db
+                .describe(sql)
+                .with_context(|| format!("Failed to describe {}", path.display()))?;
+            render_query(&mut code, &path, sql, &description)
+                .with_context(|| format!("Failed to generate code for {}", path.display()))?;
+        }
+        Ok(code)
+    }
+
+    /// Generates the functions into the file at `out`, e.g. in `OUT_DIR`,
+    /// and asks Cargo to run the build script again whenever the queries or the schema change
+    pub fn write_to(&self, out: impl AsRef<Path>) -> Result<()> {
+        let code = self.generate()?;
+        let out = out.as_ref();
+        std::fs::write(out, code).with_context(|| format!("Failed to write {}", out.display()))?;
+        println!("cargo:rerun-if-changed={}", self.queries.display());
+        match &self.source {
+            Some(Source::Schema(path) | Source::Database(path)) => {
+                println!("cargo:rerun-if-changed={}", path.display())
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> Result<crate::local::Client> {
+        match &self.source {
+            Some(Source::Schema(path)) => {
+                let schema = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let db = crate::local::Client::in_memory()?;
+                db.execute_script(&schema)
+                    .with_context(|| format!("Failed to apply {}", path.display()))?;
+                Ok(db)
+            }
+            Some(Source::Database(path)) => {
+                // Opening a missing database would create an empty one
+                if !path.exists() {
+                    anyhow::bail!("Database {} does not exist", path.display());
+                }
+                crate::local::Client::new(path)
+            }
+            None => anyhow::bail!("Set a schema or a database to describe the queries against"),
+        }
+    }
+}
+
+/// Appends the function of a statement to `code`, along with the struct of its rows
+fn render_query(
+    code: &mut String,
+    path: &Path,
+    sql: &str,
+    description: &DescribeResult,
+) -> Result<()> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let name = ident(stem);
+    if name != stem {
+        anyhow::bail!("File name {stem:?} is not a valid function name");
+    }
+    let row_type = format!("{}Row", pascal_case(stem));
+
+    let mut args = Vec::new();
+    for (idx, param) in description.params.iter().enumerate() {
+        let arg = match param.name.as_deref() {
+            Some(name) if !name.starts_with('?') => ident(&name[1..]),
+            _ => format!("p{}", idx + 1),
+        };
+        if arg == "db" {
+            anyhow::bail!("Parameter {arg} clashes with the client argument of the function");
+        }
+        if args.contains(&arg) {
+            anyhow::bail!("Several parameters are named {arg}");
+        }
+        args.push(arg);
+    }
+
+    let returns_rows = !description.cols.is_empty();
+    if returns_rows {
+        let mut fields = HashSet::new();
+        writeln!(code, "\n#[derive(Clone, Debug, serde::Deserialize)]")?;
+        writeln!(code, "pub struct {row_type} {{")?;
+        for col in &description.cols {
+            let (field, ty) = match col.name.split_once(':') {
+                Some((field, ty)) => (ident(field.trim()), ty.trim().to_string()),
+                None => (ident(&col.name), column_type(col.decltype.as_deref())),
+            };
+            if !fields.insert(field.clone()) {
+                anyhow::bail!("Several columns are named {field}, rename them with AS");
+            }
+            if field.trim_start_matches("r#") != col.name {
+                writeln!(code, "    #[serde(rename = {:?})]", col.name)?;
+            }
+            writeln!(code, "    pub {field}: {ty},")?;
+        }
+        writeln!(code, "}}")?;
+    }
+
+    writeln!(code, "\n/// Executes `{}`:", path.display())?;
+    writeln!(code, "///\n/// ```sql")?;
+    for line in sql.lines() {
+        writeln!(code, "/// {line}")?;
+    }
+    writeln!(code, "/// ```")?;
+    write!(
+        code,
+        "pub async fn {name}(\n    db: &(impl libsql_client::DatabaseClient + ?Sized),\n"
+    )?;
+    for arg in &args {
+        writeln!(code, "    {arg}: impl Into<libsql_client::Value>,")?;
+    }
+    let output = if returns_rows {
+        format!("Vec<{row_type}>")
+    } else {
+        "u64".to_string()
+    };
+    writeln!(code, ") -> anyhow::Result<{output}> {{")?;
+    if args.is_empty() {
+        writeln!(
+            code,
+            "    let stmt = libsql_client::Statement::new({sql:?});"
+        )?;
+    } else {
+        let values: Vec<String> = args.iter().map(|arg| format!("{arg}.into()")).collect();
+        writeln!(
+            code,
+            "    let args: [libsql_client::Value; {}] = [{}];",
+            args.len(),
+            values.join(", ")
+        )?;
+        writeln!(
+            code,
+            "    let stmt = libsql_client::Statement::with_args({sql:?}, &args);"
+        )?;
+    }
+    if returns_rows {
+        writeln!(
+            code,
+            "    libsql_client::DatabaseClient::query_as(db, stmt).await"
+        )?;
+    } else {
+        writeln!(
+            code,
+            "    Ok(libsql_client::DatabaseClient::execute(db, stmt).await?.rows_affected)"
+        )?;
+    }
+    writeln!(code, "}}")?;
+    Ok(())
+}
+
+/// Rust type of a column, after the affinity of its declared type
+fn column_type(decltype: Option<&str>) -> String {
+    let ty = match decltype.map(crate::sql::affinity_of) {
+        None => return "serde_json::Value".to_string(),
+        Some(Affinity::Integer) => "i64",
+        Some(Affinity::Text) => "String",
+        Some(Affinity::Blob) => "Vec<u8>",
+        Some(Affinity::Real | Affinity::Numeric) => "f64",
+    };
+    format!("Option<{ty}>")
+}
+
+/// Turns a name into a snake case Rust identifier, escaping keywords
+fn ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if RESERVED.contains(&ident.as_str()) {
+        ident.push('_');
+    } else if KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    ident
+}
+
+/// Turns a snake case name into a Rust type name, e.g. `find_user` into `FindUser`
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,514 @@
+//! `conformance` checks that a client behaves like the backends of this crate, e.g. a new
+//! backend, a third-party one, or a wrapper around one. It is only available with
+//! the `test-support` feature.
+//!
+//! `run()` exercises the surface of `DatabaseClient` against a scratch table, which it creates
+//! and drops: values of every type and their edge cases, column names, counts of changes,
+//! batches and their errors, rows returned by each step of a batch with `RETURNING`,
+//! conditional batches, interactive transactions and errors
+//! reported by the database. Each check is reported separately, so that a single difference
+//! does not hide the others. Backends which reject a feature on purpose, e.g. interactive
+//! transactions over HTTP, fail with `error::Unsupported` and are reported as unsupported
+//! rather than failed.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   let db = libsql_client::local::Client::in_memory()?;
+//!   let report = libsql_client::conformance::run(&db).await;
+//!   report.assert_passed();
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::{ensure, Result};
+
+use crate::batch::BatchBuilder;
+use crate::error::Unsupported;
+use crate::testing::same_value;
+use crate::{DatabaseClient, Statement, Value};
+
+/// Table created, and dropped, by the checks
+const TABLE: &str = "libsql_client_conformance";
+
+/// Outcome of a single check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The client behaved differently than expected, as described
+    Failed(String),
+    /// The client rejected the feature on purpose, with the given error
+    Unsupported(String),
+}
+
+/// Check run by `run()`, and its outcome
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+/// Outcomes of all checks run by `run()`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns the checks which failed
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Failed(_)))
+    }
+
+    /// Checks if no check failed. Unsupported features do not count as failures.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Asserts that no check failed.
+    ///
+    /// # Panics
+    /// Panics with the description of every failed check.
+    #[track_caller]
+    pub fn assert_passed(&self) {
+        let failures: Vec<String> = self
+            .failures()
+            .map(|check| match &check.outcome {
+                Outcome::Failed(reason) => format!("{}: {reason}", check.name),
+                _ => check.name.to_string(),
+            })
+            .collect();
+        assert!(
+            failures.is_empty(),
+            "{} conformance checks failed:\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+}
+
+/// Runs every check against `client`, in a scratch table which is dropped afterwards
+pub async fn run(client: &impl DatabaseClient) -> Report {
+    let mut report = Report::default();
+    let mut record = |name, result: Result<()>| {
+        let outcome = outcome(result);
+        report.checks.push(Check { name, outcome });
+    };
+    record("values", values(client).await);
+    record("literals", literals(client).await);
+    record("columns", columns(client).await);
+    record("changes", changes(client).await);
+    record("batch", batch(client).await);
+    record("batch_errors", batch_errors(client).await);
+    record("conditional_batch", conditional_batch(client).await);
+    record("batch_returning", batch_returning(client).await);
+    record("execute_multi", execute_multi(client).await);
+    record("transactions", transactions(client).await);
+    record("errors", errors(client).await);
+    record("count_exists", count_exists(client).await);
+    if let Err(e) = client
+        .execute(format!("DROP TABLE IF EXISTS {TABLE}"))
+        .await
+    {
+        tracing::warn!("Failed to drop the conformance table: {e}");
+    }
+    report
+}
+
+/// Classifies the result of a check, features rejected on purpose are not failures
+fn outcome(result: Result<()>) -> Outcome {
+    match result {
+        Ok(()) => Outcome::Passed,
+        Err(e) if e.downcast_ref::<Unsupported>().is_some() => Outcome::Unsupported(e.to_string()),
+        Err(e) => Outcome::Failed(format!("{e:#}")),
+    }
+}
+
+/// Creates the scratch table anew, without any row
+async fn reset(client: &impl DatabaseClient) -> Result<()> {
+    client
+        .batch([
+            format!("DROP TABLE IF EXISTS {TABLE}"),
+            // Without a declared type, values are stored as they are sent
+            format!("CREATE TABLE {TABLE} (id INTEGER PRIMARY KEY, value)"),
+        ])
+        .await?;
+    Ok(())
+}
+
+async fn row_count(client: &impl DatabaseClient) -> Result<u64> {
+    client.count(format!("SELECT * FROM {TABLE}")).await
+}
+
+/// Values of every type survive being bound as parameters and read back
+async fn values(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let values = [
+        Value::Null,
+        Value::from(0i64),
+        Value::from(i64::MIN),
+        Value::from(i64::MAX),
+        Value::Float { value: 0.1 },
+        Value::Float { value: f64::MAX },
+        Value::from(""),
+        Value::from("zażółć gęślą jaźń 🦀"),
+        Value::from("\"'\\"),
+        Value::Blob { value: vec![] },
+        Value::Blob {
+            value: vec![0, 255, 1],
+        },
+    ];
+    let insert = format!("INSERT INTO {TABLE} (value) VALUES (?)");
+    client
+        .batch(
+            values
+                .iter()
+                .map(|value| Statement::with_args(insert.as_str(), std::slice::from_ref(value))),
+        )
+        .await?;
+    let result = client
+        .execute(format!("SELECT value FROM {TABLE} ORDER BY id"))
+        .await?;
+    ensure!(
+        result.rows.len() == values.len(),
+        "{} rows were read back instead of {}",
+        result.rows.len(),
+        values.len()
+    );
+    for (row, expected) in result.rows.iter().zip(&values) {
+        let actual = &row.values[0];
+        ensure!(
+            same_value(actual, expected),
+            "{expected:?} was read back as {actual:?}"
+        );
+    }
+    Ok(())
+}
+
+/// Literals are returned with their type
+async fn literals(client: &impl DatabaseClient) -> Result<()> {
+    let result = client
+        .execute("SELECT 1, 1.5, 'text', x'0102', NULL")
+        .await?;
+    let row = result
+        .rows
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No row was returned"))?;
+    let expected = [
+        Value::from(1i64),
+        Value::Float { value: 1.5 },
+        Value::from("text"),
+        Value::Blob { value: vec![1, 2] },
+        Value::Null,
+    ];
+    ensure!(
+        row.values.len() == expected.len(),
+        "{} values were returned instead of {}",
+        row.values.len(),
+        expected.len()
+    );
+    for (actual, expected) in row.values.iter().zip(&expected) {
+        ensure!(
+            same_value(actual, expected),
+            "{expected:?} was returned as {actual:?}"
+        );
+    }
+    Ok(())
+}
+
+/// Columns are named after their alias, including results without rows
+async fn columns(client: &impl DatabaseClient) -> Result<()> {
+    let result = client.execute("SELECT 1 AS a, 2 AS \"b c\"").await?;
+    ensure!(
+        result.columns == ["a", "b c"],
+        "Columns were named {:?}",
+        result.columns
+    );
+    reset(client).await?;
+    let result = client
+        .execute(format!("SELECT id, value FROM {TABLE}"))
+        .await?;
+    ensure!(
+        result.rows.is_empty(),
+        "{} rows were returned by an empty table",
+        result.rows.len()
+    );
+    ensure!(
+        result.columns == ["id", "value"],
+        "Columns of a result without rows were named {:?}",
+        result.columns
+    );
+    Ok(())
+}
+
+/// Writes report the number of rows they changed and the rowid they inserted
+async fn changes(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let result = client
+        .execute(format!("INSERT INTO {TABLE} (value) VALUES ('a'), ('b')"))
+        .await?;
+    ensure!(
+        result.rows_affected == 2,
+        "INSERT of 2 rows reported {} changes",
+        result.rows_affected
+    );
+    ensure!(
+        result.last_insert_rowid == Some(2),
+        "INSERT reported the rowid {:?} instead of 2",
+        result.last_insert_rowid
+    );
+    let result = client
+        .execute(format!("UPDATE {TABLE} SET value = 'c' WHERE id = 1"))
+        .await?;
+    ensure!(
+        result.rows_affected == 1,
+        "UPDATE of 1 row reported {} changes",
+        result.rows_affected
+    );
+    let result = client.execute("SELECT 1").await?;
+    ensure!(
+        result.rows_affected == 0,
+        "SELECT reported {} changes",
+        result.rows_affected
+    );
+    Ok(())
+}
+
+/// `batch()` returns a result per statement, and fails if one of them fails
+async fn batch(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let results = client
+        .batch([
+            format!("INSERT INTO {TABLE} (id, value) VALUES (1, 'a')"),
+            format!("SELECT COUNT(*) FROM {TABLE}"),
+        ])
+        .await?;
+    ensure!(
+        results.len() == 2,
+        "{} results were returned for 2 statements",
+        results.len()
+    );
+    let count = results[1].rows.first().map(|row| &row.values[0]);
+    ensure!(
+        matches!(count, Some(Value::Integer { value: 1 })),
+        "A statement did not see the changes of the previous one: {count:?}"
+    );
+    let failed = client
+        .batch([
+            format!("INSERT INTO {TABLE} (id, value) VALUES (2, 'b')"),
+            format!("INSERT INTO {TABLE} (id, value) VALUES (1, 'duplicate')"),
+        ])
+        .await;
+    ensure!(
+        failed.is_err(),
+        "A batch with a failing statement succeeded"
+    );
+    Ok(())
+}
+
+/// `raw_batch()` reports the error of each failing step, and executes the other steps
+async fn batch_errors(client: &impl DatabaseClient) -> Result<()> {
+    let result = client
+        .raw_batch([
+            "SELECT 1",
+            "SELECT * FROM libsql_client_conformance_missing",
+            "SELECT 2",
+        ])
+        .await?;
+    ensure!(
+        result.step_results.len() == 3 && result.step_errors.len() == 3,
+        "{} results and {} errors were returned for 3 steps",
+        result.step_results.len(),
+        result.step_errors.len()
+    );
+    ensure!(
+        result.step_errors[1].is_some() && result.step_results[1].is_none(),
+        "The failing step did not report an error"
+    );
+    for step in [0, 2] {
+        ensure!(
+            result.step_results[step].is_some() && result.step_errors[step].is_none(),
+            "Step {step} did not report a result"
+        );
+    }
+    Ok(())
+}
+
+/// A conditional batch rolls back its transaction if one of its steps fails
+async fn conditional_batch(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let batch = BatchBuilder::new()
+        .begin()
+        .step(format!("INSERT INTO {TABLE} (id, value) VALUES (1, 'a')"))
+        .step_if_ok(format!(
+            "INSERT INTO {TABLE} (id, value) VALUES (1, 'duplicate')"
+        ))
+        .commit()
+        .build()?;
+    let result = client.run_batch(batch).await?;
+    ensure!(
+        result.step_errors.iter().any(Option::is_some),
+        "The failing step did not report an error"
+    );
+    let rows = row_count(client).await?;
+    ensure!(
+        rows == 0,
+        "The transaction was not rolled back, {rows} rows were left"
+    );
+    Ok(())
+}
+
+/// Each step of a batch returns the rows of its `RETURNING` clause, not only the last one
+async fn batch_returning(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let batch = BatchBuilder::new()
+        .step(format!(
+            "INSERT INTO {TABLE} (id, value) VALUES (1, 'a') RETURNING id"
+        ))
+        .label("first")
+        .step(format!(
+            "INSERT INTO {TABLE} (id, value) VALUES (2, 'b'), (3, 'c') RETURNING id"
+        ))
+        .label("second")
+        .step(format!("SELECT COUNT(*) FROM {TABLE}"))
+        .build()?;
+    let result = client.run_batch(batch.clone()).await?;
+    for (label, expected) in [("first", vec![1]), ("second", vec![2, 3])] {
+        let mut ids: Vec<i64> = batch.rows_as(&result, label)?;
+        ids.sort_unstable();
+        ensure!(
+            ids == expected,
+            "Step {label} returned {ids:?} instead of {expected:?}"
+        );
+    }
+    Ok(())
+}
+
+/// `execute_multi()` executes every statement of a script
+async fn execute_multi(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let results = client
+        .execute_multi(&format!(
+            "INSERT INTO {TABLE} (value) VALUES ('a'); SELECT COUNT(*) FROM {TABLE};"
+        ))
+        .await?;
+    ensure!(
+        results.len() == 2,
+        "{} results were returned for 2 statements",
+        results.len()
+    );
+    let count = results[1].rows.first().map(|row| &row.values[0]);
+    ensure!(
+        matches!(count, Some(Value::Integer { value: 1 })),
+        "The second statement returned {count:?}"
+    );
+    Ok(())
+}
+
+/// Interactive transactions see their own changes, and apply them only once committed
+async fn transactions(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    let tx = client.transaction().await?;
+    tx.execute(format!("INSERT INTO {TABLE} (value) VALUES ('a')"))
+        .await?;
+    let seen = tx.execute(format!("SELECT * FROM {TABLE}")).await?;
+    ensure!(
+        seen.rows.len() == 1,
+        "A transaction did not see its own changes"
+    );
+    tx.rollback().await?;
+    let rows = row_count(client).await?;
+    ensure!(rows == 0, "A rolled back transaction left {rows} rows");
+    let tx = client.transaction().await?;
+    tx.execute(format!("INSERT INTO {TABLE} (value) VALUES ('a')"))
+        .await?;
+    tx.commit().await?;
+    let rows = row_count(client).await?;
+    ensure!(
+        rows == 1,
+        "A committed transaction left {rows} rows instead of 1"
+    );
+    Ok(())
+}
+
+/// Errors reported by the database fail the statement, and the client stays usable
+async fn errors(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    client
+        .execute(format!("INSERT INTO {TABLE} (id, value) VALUES (1, 'a')"))
+        .await?;
+    let failing = [
+        ("a syntax error", "SELEC 1".to_string()),
+        (
+            "a missing table",
+            "SELECT * FROM libsql_client_conformance_missing".to_string(),
+        ),
+        (
+            "a constraint violation",
+            format!("INSERT INTO {TABLE} (id, value) VALUES (1, 'duplicate')"),
+        ),
+    ];
+    for (kind, sql) in failing {
+        ensure!(
+            client.execute(sql).await.is_err(),
+            "A statement with {kind} succeeded"
+        );
+    }
+    let result = client.execute("SELECT 1").await?;
+    ensure!(
+        result.rows.len() == 1,
+        "The client returned {} rows after errors",
+        result.rows.len()
+    );
+    Ok(())
+}
+
+/// `count()` and `exists()` rewrite queries keeping their arguments
+async fn count_exists(client: &impl DatabaseClient) -> Result<()> {
+    reset(client).await?;
+    client
+        .execute(format!(
+            "INSERT INTO {TABLE} (value) VALUES ('a'), ('b'), ('b')"
+        ))
+        .await?;
+    let query = format!("SELECT * FROM {TABLE} WHERE value = ?");
+    let count = client
+        .count(Statement::with_args(query.as_str(), &["b"]))
+        .await?;
+    ensure!(count == 2, "count() returned {count} instead of 2");
+    let exists = client
+        .exists(Statement::with_args(query.as_str(), &["c"]))
+        .await?;
+    ensure!(!exists, "exists() found a row which does not exist");
+    Ok(())
+}
+
+#[cfg(all(test, feature = "local_backend"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_backend_conforms() {
+        let db = crate::local::Client::in_memory().unwrap();
+        let report = run(&db).await;
+        report.assert_passed();
+        assert!(report
+            .checks
+            .iter()
+            .all(|check| check.outcome == Outcome::Passed));
+    }
+
+    #[test]
+    fn rejected_features_are_unsupported() {
+        let rejected = Err(anyhow::Error::from(Unsupported::new("Not here")).context("Running"));
+        assert_eq!(
+            outcome(rejected),
+            Outcome::Unsupported("Running".to_string())
+        );
+        // Only the type of the error counts, not its message
+        let failed = Err(anyhow::anyhow!("Transactions are not supported"));
+        assert!(matches!(outcome(failed), Outcome::Failed(_)));
+        assert_eq!(outcome(Ok(())), Outcome::Passed);
+    }
+}
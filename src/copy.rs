@@ -0,0 +1,530 @@
+//! `copy` streams rows between the database and byte streams as NDJSON or CSV,
+//! e.g. for export endpoints which proxy query results into HTTP responses.
+//! It requires the `copy` feature, and works with any `futures` I/O types.
+//!
+//! Values are mapped as follows: NULL is `null` in NDJSON and an empty field in CSV,
+//! numbers and text are written as is, and blobs are written as base64 text. Floats are
+//! written as the shortest text reading back into the same value, or with the format set
+//! with `CopyFormat::with_floats()`, see `libsql_client::float`.
+//! Rows read by `copy_in()` are converted back according to the declared types of
+//! the table columns, following the SQLite type affinity rules.
+
+use anyhow::Result;
+use base64::Engine;
+use futures_util::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use futures_util::StreamExt;
+
+use crate::bulk::BulkWriter;
+use crate::float::FloatFormat;
+use crate::sql::Affinity;
+use crate::{DatabaseClient, Statement, Value};
+
+/// Number of rows inserted per batch by `copy_in()`
+const INSERT_CHUNK_SIZE: usize = 500;
+
+/// Size of the buffer of encoded rows, written to the output once full
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Text format of copied rows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// A JSON object per line, keyed by column names
+    Ndjson,
+    /// RFC 4180 comma-separated values, with a header line of column names
+    Csv,
+}
+
+impl CopyFormat {
+    /// Writes floats with the given format, e.g. with a fixed number of decimals
+    pub fn with_floats(self, floats: FloatFormat) -> CopyOptions {
+        CopyOptions {
+            format: self,
+            floats,
+        }
+    }
+}
+
+/// Options of `DatabaseClient::copy_out()`, created from a `CopyFormat`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    /// How floats are written
+    pub floats: FloatFormat,
+}
+
+impl From<CopyFormat> for CopyOptions {
+    fn from(format: CopyFormat) -> Self {
+        format.with_floats(FloatFormat::default())
+    }
+}
+
+/// Executes `stmt` and writes the returned rows into `writer`, see `DatabaseClient::copy_out()`
+pub(crate) async fn copy_out<C, W>(
+    client: &C,
+    stmt: Statement,
+    options: CopyOptions,
+    writer: &mut W,
+) -> Result<u64>
+where
+    C: DatabaseClient + ?Sized,
+    W: AsyncWrite + Unpin,
+{
+    let CopyOptions { format, floats } = options;
+    let result = client.execute(stmt).await?;
+    let mut buffer = String::with_capacity(WRITE_BUFFER_SIZE);
+    if format == CopyFormat::Csv {
+        let header: Vec<String> = result
+            .columns
+            .iter()
+            .map(String::as_str)
+            .map(csv_field)
+            .collect();
+        buffer.push_str(&header.join(","));
+        buffer.push_str("\r\n");
+    }
+    let keys: Vec<String> = result
+        .columns
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<serde_json::Result<_>>()?;
+    for row in &result.rows {
+        match format {
+            CopyFormat::Ndjson => {
+                buffer.push('{');
+                for (i, (key, value)) in keys.iter().zip(&row.values).enumerate() {
+                    if i > 0 {
+                        buffer.push(',');
+                    }
+                    buffer.push_str(key);
+                    buffer.push(':');
+                    buffer.push_str(&json_value(value, floats)?);
+                }
+                buffer.push_str("}\n");
+            }
+            CopyFormat::Csv => {
+                let fields: Vec<String> = row.values.iter().map(|v| csv_value(v, floats)).collect();
+                buffer.push_str(&fields.join(","));
+                buffer.push_str("\r\n");
+            }
+        }
+        if buffer.len() >= WRITE_BUFFER_SIZE {
+            writer.write_all(buffer.as_bytes()).await?;
+            buffer.clear();
+        }
+    }
+    writer.write_all(buffer.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(result.rows.len() as u64)
+}
+
+fn json_value(value: &Value, floats: FloatFormat) -> Result<String> {
+    let json = match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer { value } => (*value).into(),
+        // Non-finite floats have no JSON representation
+        Value::Float { value } => serde_json::Number::from_f64(floats.round(*value))
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Text { value } => value.as_str().into(),
+        Value::Blob { value } => base64::engine::general_purpose::STANDARD
+            .encode(value)
+            .into(),
+    };
+    Ok(serde_json::to_string(&json)?)
+}
+
+fn csv_value(value: &Value, floats: FloatFormat) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer { value } => value.to_string(),
+        Value::Float { value } => floats.format(*value),
+        Value::Text { value } => csv_field(value),
+        Value::Blob { value } => base64::engine::general_purpose::STANDARD.encode(value),
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Reads rows from `reader` and inserts them into `table`, see `DatabaseClient::copy_in()`
+pub(crate) async fn copy_in<C, R>(
+    client: &C,
+    table: &str,
+    format: CopyFormat,
+    reader: R,
+) -> Result<u64>
+where
+    C: DatabaseClient + ?Sized,
+    R: AsyncRead + Unpin,
+{
+    let table_columns = column_affinities(client, table).await?;
+    let mut lines = BufReader::new(reader).lines();
+    let mut columns: Option<Vec<(String, Affinity)>> = None;
+    let mut chunk: Vec<Vec<Value>> = Vec::with_capacity(INSERT_CHUNK_SIZE);
+    let mut inserted = 0;
+    let mut line_number = 0;
+    let mut pending = String::new();
+    while let Some(line) = lines.next().await.transpose()? {
+        line_number += 1;
+        let row = match format {
+            CopyFormat::Ndjson if line.trim().is_empty() => continue,
+            CopyFormat::Ndjson => {
+                let object: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(&line)
+                        .map_err(|e| anyhow::anyhow!("Invalid JSON on line {line_number}: {e}"))?;
+                // Columns are the keys of the first object, missing keys are NULL afterwards
+                let columns = match &mut columns {
+                    Some(columns) => columns,
+                    slot => slot.insert(select_columns(&table_columns, object.keys(), table)?),
+                };
+                if let Some(key) = object
+                    .keys()
+                    .find(|k| !columns.iter().any(|(c, _)| c.eq_ignore_ascii_case(k)))
+                {
+                    anyhow::bail!("Unknown column `{key}` on line {line_number}");
+                }
+                columns
+                    .iter()
+                    .map(|(name, affinity)| {
+                        match object.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)) {
+                            None => Value::Null,
+                            Some((_, value)) => from_json(value, *affinity),
+                        }
+                    })
+                    .collect()
+            }
+            CopyFormat::Csv => {
+                if !pending.is_empty() {
+                    pending.push('\n');
+                }
+                pending.push_str(&line);
+                // A quoted field may span several lines
+                let Some(fields) = parse_csv_record(&pending) else {
+                    continue;
+                };
+                pending.clear();
+                let Some(columns) = &columns else {
+                    columns = Some(select_columns(&table_columns, fields.iter(), table)?);
+                    continue;
+                };
+                if fields.len() != columns.len() {
+                    anyhow::bail!(
+                        "Line {line_number} has {} fields, expected {}",
+                        fields.len(),
+                        columns.len()
+                    );
+                }
+                fields
+                    .into_iter()
+                    .zip(columns)
+                    .map(|(field, (_, affinity))| match field {
+                        field if field.is_empty() => Value::Null,
+                        field => from_text(field, *affinity),
+                    })
+                    .collect()
+            }
+        };
+        chunk.push(row);
+        if chunk.len() >= INSERT_CHUNK_SIZE {
+            inserted += insert(
+                client,
+                table,
+                columns.as_deref(),
+                std::mem::take(&mut chunk),
+            )
+            .await?;
+        }
+    }
+    if !pending.is_empty() {
+        anyhow::bail!("Unterminated quoted field at the end of the input");
+    }
+    inserted += insert(client, table, columns.as_deref(), chunk).await?;
+    Ok(inserted)
+}
+
+async fn insert<C: DatabaseClient + ?Sized>(
+    client: &C,
+    table: &str,
+    columns: Option<&[(String, Affinity)]>,
+    rows: Vec<Vec<Value>>,
+) -> Result<u64> {
+    let Some(columns) = columns.filter(|_| !rows.is_empty()) else {
+        return Ok(0);
+    };
+    let names: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+    BulkWriter::new(client)
+        .chunk_size(INSERT_CHUNK_SIZE)
+        .insert_many(table, &names, rows)
+        .await
+}
+
+/// Returns the names and affinities of the columns of a table
+async fn column_affinities<C: DatabaseClient + ?Sized>(
+    client: &C,
+    table: &str,
+) -> Result<Vec<(String, Affinity)>> {
+    let result = client
+        .execute(format!(
+            "PRAGMA table_info({})",
+            crate::sql::quote_ident(table)
+        ))
+        .await?;
+    let text = |value: Option<&Value>| match value {
+        Some(Value::Text { value }) => value.clone(),
+        _ => String::new(),
+    };
+    let columns: Vec<(String, Affinity)> = result
+        .rows
+        .iter()
+        .map(|row| {
+            let name = text(row.values.get(1));
+            let affinity = crate::sql::affinity_of(&text(row.values.get(2)));
+            (name, affinity)
+        })
+        .collect();
+    if columns.is_empty() {
+        anyhow::bail!("Table {table} does not exist");
+    }
+    Ok(columns)
+}
+
+/// Picks the columns of the table named in the input, in the order of the input
+fn select_columns<'a>(
+    table_columns: &[(String, Affinity)],
+    names: impl Iterator<Item = &'a String>,
+    table: &str,
+) -> Result<Vec<(String, Affinity)>> {
+    names
+        .map(|name| {
+            table_columns
+                .iter()
+                .find(|(column, _)| column.eq_ignore_ascii_case(name))
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Table {table} has no column `{name}`"))
+        })
+        .collect()
+}
+
+/// Converts text into a value, according to the affinity of its column
+fn from_text(text: String, affinity: Affinity) -> Value {
+    match affinity {
+        Affinity::Integer | Affinity::Numeric => match text.parse::<i64>() {
+            Ok(value) => Value::Integer { value },
+            Err(_) => match text.parse::<f64>() {
+                Ok(value) => Value::Float { value },
+                Err(_) => Value::Text { value: text },
+            },
+        },
+        Affinity::Real => match text.parse::<f64>() {
+            Ok(value) => Value::Float { value },
+            Err(_) => Value::Text { value: text },
+        },
+        Affinity::Blob => match base64::engine::general_purpose::STANDARD.decode(&text) {
+            Ok(value) => Value::Blob { value },
+            Err(_) => Value::Text { value: text },
+        },
+        Affinity::Text => Value::Text { value: text },
+    }
+}
+
+fn from_json(value: &serde_json::Value, affinity: Affinity) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(value) => Value::Integer {
+            value: i64::from(*value),
+        },
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) if affinity != Affinity::Real => Value::Integer { value },
+            _ => Value::Float {
+                value: number.as_f64().unwrap_or(f64::NAN),
+            },
+        },
+        serde_json::Value::String(text) if text.is_empty() => Value::Text {
+            value: String::new(),
+        },
+        serde_json::Value::String(text) => from_text(text.clone(), affinity),
+        // Nested documents are stored as JSON text
+        value => Value::Text {
+            value: value.to_string(),
+        },
+    }
+}
+
+/// Parses the fields of a CSV record, or returns `None` if it ends within a quoted field
+fn parse_csv_record(record: &str) -> Option<Vec<String>> {
+    let record = record.strip_suffix('\r').unwrap_or(record);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares values by their debug representation, since `Value` is not `PartialEq`
+    #[track_caller]
+    fn assert_same(actual: Value, expected: Value) {
+        assert_eq!(format!("{actual:?}"), format!("{expected:?}"));
+    }
+
+    fn fields(record: &str) -> Option<Vec<String>> {
+        parse_csv_record(record)
+    }
+
+    #[test]
+    fn csv_records() {
+        assert_eq!(
+            fields("a,b,c"),
+            Some(vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            fields("a,,\r"),
+            Some(vec!["a".into(), "".into(), "".into()])
+        );
+        assert_eq!(fields(""), Some(vec!["".into()]));
+        assert_eq!(
+            fields("\"a,b\",\"say \"\"hi\"\"\""),
+            Some(vec!["a,b".into(), "say \"hi\"".into()])
+        );
+        assert_eq!(
+            fields("\"two\nlines\",x"),
+            Some(vec!["two\nlines".into(), "x".into()])
+        );
+        // Quotes within an unquoted field are kept as is
+        assert_eq!(fields("a\"b"), Some(vec!["a\"b".into()]));
+        assert_eq!(fields("\"unterminated"), None);
+        assert_eq!(fields("a,\"open\nfield"), None);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\r\nlines"), "\"two\r\nlines\"");
+        for field in ["plain", "a,b", "say \"hi\"", "two\r\nlines"] {
+            assert_eq!(fields(&csv_field(field)), Some(vec![field.to_string()]));
+        }
+    }
+
+    #[test]
+    fn text_is_converted_by_affinity() {
+        let text = |value: &str| Value::Text {
+            value: value.to_string(),
+        };
+        assert_same(
+            from_text("42".into(), Affinity::Integer),
+            Value::Integer { value: 42 },
+        );
+        assert_same(
+            from_text("1.5".into(), Affinity::Numeric),
+            Value::Float { value: 1.5 },
+        );
+        assert_same(from_text("n/a".into(), Affinity::Integer), text("n/a"));
+        assert_same(
+            from_text("2".into(), Affinity::Real),
+            Value::Float { value: 2.0 },
+        );
+        assert_same(from_text("2".into(), Affinity::Text), text("2"));
+        assert_same(
+            from_text("AAH/".into(), Affinity::Blob),
+            Value::Blob {
+                value: vec![0, 1, 255],
+            },
+        );
+        assert_same(
+            from_text("not base64!".into(), Affinity::Blob),
+            text("not base64!"),
+        );
+    }
+
+    #[test]
+    fn json_is_converted_by_affinity() {
+        let json = |s: &str| serde_json::from_str::<serde_json::Value>(s).unwrap();
+        assert_same(from_json(&json("null"), Affinity::Integer), Value::Null);
+        assert_same(
+            from_json(&json("true"), Affinity::Integer),
+            Value::Integer { value: 1 },
+        );
+        assert_same(
+            from_json(&json("3"), Affinity::Text),
+            Value::Integer { value: 3 },
+        );
+        assert_same(
+            from_json(&json("3"), Affinity::Real),
+            Value::Float { value: 3.0 },
+        );
+        assert_same(
+            from_json(&json("\"7\""), Affinity::Integer),
+            Value::Integer { value: 7 },
+        );
+        assert_same(
+            from_json(&json("\"\""), Affinity::Integer),
+            Value::Text {
+                value: String::new(),
+            },
+        );
+        assert_same(
+            from_json(&json("{\"a\": [1]}"), Affinity::Text),
+            Value::Text {
+                value: "{\"a\":[1]}".to_string(),
+            },
+        );
+    }
+
+    #[cfg(feature = "local_backend")]
+    #[tokio::test]
+    async fn rows_round_trip() {
+        let db = crate::local::Client::in_memory().unwrap();
+        db.batch([
+            "CREATE TABLE src (id INTEGER, name TEXT, score REAL, data BLOB)",
+            "CREATE TABLE dst (id INTEGER, name TEXT, score REAL, data BLOB)",
+            "INSERT INTO src VALUES (1, 'a,\"b\"', 0.5, x'00ff'), (2, 'two\nlines', NULL, NULL)",
+        ])
+        .await
+        .unwrap();
+        for format in [CopyFormat::Csv, CopyFormat::Ndjson] {
+            db.execute("DELETE FROM dst").await.unwrap();
+            let mut output = futures_util::io::Cursor::new(Vec::new());
+            let written = copy_out(&db, "SELECT * FROM src".into(), format.into(), &mut output)
+                .await
+                .unwrap();
+            assert_eq!(written, 2);
+            let input = futures_util::io::Cursor::new(output.into_inner());
+            let inserted = copy_in(&db, "dst", format, input).await.unwrap();
+            assert_eq!(inserted, 2);
+            let copied = db.execute("SELECT * FROM dst ORDER BY id").await.unwrap();
+            let original = db.execute("SELECT * FROM src ORDER BY id").await.unwrap();
+            assert_eq!(
+                format!("{:?}", copied.rows),
+                format!("{:?}", original.rows),
+                "{format:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! `counter` implements atomic counters and rate limiting stored in tables of the database,
+//! for edge workers which already have a connection to the database and no other shared store.
+//!
+//! Each operation is a single upsert, so that it stays atomic across clients
+//! without a transaction, and takes a single round trip.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::counter::{Counters, RateLimiter};
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let counters = Counters::new(&db);
+//!   counters.create().await?;
+//!   let views = counters.increment("page_views", 1).await?;
+//!
+//!   let limiter = RateLimiter::new(&db, 100, Duration::from_secs(60));
+//!   limiter.create().await?;
+//!   if !limiter.try_acquire("client:203.0.113.7").await? {
+//!       println!("Too many requests");
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing counters
+const DEFAULT_COUNTERS_TABLE: &str = "_libsql_counters";
+
+/// Default name of the table storing the hits of rate limiters
+const DEFAULT_RATE_LIMITS_TABLE: &str = "_libsql_rate_limits";
+
+/// Named counters, stored in the `_libsql_counters` table by default
+pub struct Counters<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Counters<'a, Client> {
+    /// Creates counters stored in the `_libsql_counters` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_COUNTERS_TABLE),
+        }
+    }
+
+    /// Sets the table storing counters
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Creates the table of the counters, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, value INTEGER NOT NULL)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Adds `by` to a counter, which starts at 0, returning its new value
+    pub async fn increment(&self, name: &str, by: i64) -> Result<i64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT INTO {} (name, value) VALUES (?, ?) \
+                     ON CONFLICT (name) DO UPDATE SET value = value + excluded.value \
+                     RETURNING value",
+                    self.table
+                ),
+                &[Value::from(name), Value::from(by)],
+            ))
+            .await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(Value::Integer { value }) => Ok(*value),
+            value => anyhow::bail!("Unexpected value of counter {name}: {value:?}"),
+        }
+    }
+
+    /// Subtracts `by` from a counter, returning its new value
+    pub async fn decrement(&self, name: &str, by: i64) -> Result<i64> {
+        self.increment(name, -by).await
+    }
+
+    /// Returns the value of a counter, 0 if it was never incremented
+    pub async fn get(&self, name: &str) -> Result<i64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("SELECT value FROM {} WHERE name = ?", self.table),
+                &[name],
+            ))
+            .await?;
+        Ok(counter_value(&result))
+    }
+
+    /// Resets a counter to 0, returning its value beforehand, atomically
+    pub async fn get_and_reset(&self, name: &str) -> Result<i64> {
+        // Deleting the counter returns its former value, unlike updating it
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE name = ? RETURNING value", self.table),
+                &[name],
+            ))
+            .await?;
+        Ok(counter_value(&result))
+    }
+}
+
+fn counter_value(result: &crate::ResultSet) -> i64 {
+    match result.rows.first().and_then(|row| row.values.first()) {
+        Some(Value::Integer { value }) => *value,
+        _ => 0,
+    }
+}
+
+/// Sliding window rate limiter, allowing `limit` hits per key within any `window`,
+/// stored in the `_libsql_rate_limits` table by default.
+///
+/// Hits are counted in buckets of the duration of the window. The count of the sliding
+/// window is estimated from the current bucket and the previous one, weighted by how much
+/// of it the sliding window still overlaps, which takes two rows per key.
+pub struct RateLimiter<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+    limit: u64,
+    window: Duration,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> RateLimiter<'a, Client> {
+    /// Creates a rate limiter allowing `limit` hits per key within any `window`
+    pub fn new(client: &'a Client, limit: u64, window: Duration) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_RATE_LIMITS_TABLE),
+            limit,
+            window: window.max(Duration::from_millis(1)),
+        }
+    }
+
+    /// Sets the table storing the hits of the rate limiter.
+    /// Rate limiters with different windows must not share a table.
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Creates the table of the rate limiter, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (key TEXT NOT NULL, bucket INTEGER NOT NULL, \
+                 hits INTEGER NOT NULL, PRIMARY KEY (key, bucket))",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Records a hit for `key` and returns true, unless it would exceed the limit
+    pub async fn try_acquire(&self, key: &str) -> Result<bool> {
+        self.try_acquire_many(key, 1).await
+    }
+
+    /// Records `cost` hits for `key` and returns true, unless they would exceed the limit.
+    /// Rejected hits are not recorded.
+    pub async fn try_acquire_many(&self, key: &str, cost: u64) -> Result<bool> {
+        let table = &self.table;
+        // ?1 is the key, ?2 the cost, ?3 the window in milliseconds and ?4 the limit
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT INTO {table} (key, bucket, hits) \
+                     SELECT ?1, now.bucket, ?2 FROM (SELECT {NOW_MS} / ?3 AS bucket, {NOW_MS} % ?3 AS elapsed) AS now \
+                     WHERE coalesce((SELECT hits FROM {table} WHERE key = ?1 AND bucket = now.bucket), 0) \
+                       + coalesce((SELECT hits FROM {table} WHERE key = ?1 AND bucket = now.bucket - 1), 0) \
+                         * (?3 - now.elapsed) / CAST(?3 AS REAL) \
+                       + ?2 <= ?4 \
+                     ON CONFLICT (key, bucket) DO UPDATE SET hits = hits + excluded.hits \
+                     RETURNING hits"
+                ),
+                &[
+                    Value::from(key),
+                    Value::from(cost as i64),
+                    Value::from(self.window.as_millis() as i64),
+                    Value::from(self.limit as i64),
+                ],
+            ))
+            .await?;
+        let allowed = !result.rows.is_empty();
+        if !allowed {
+            tracing::debug!(key, "Rate limit exceeded");
+        }
+        Ok(allowed)
+    }
+
+    /// Forgets the hits of `key`
+    pub async fn reset(&self, key: &str) -> Result<()> {
+        self.client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE key = ?", self.table),
+                &[key],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the buckets which have no influence on the limit anymore,
+    /// returning how many were deleted. Meant to be called periodically.
+    pub async fn purge(&self) -> Result<u64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE bucket < {NOW_MS} / ? - 1", self.table),
+                &[self.window.as_millis() as i64],
+            ))
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
@@ -0,0 +1,381 @@
+//! `dataloader` coalesces concurrent lookups of rows by key into a single
+//! `WHERE key IN (...)` query, which solves the N+1 queries pattern of
+//! e.g. GraphQL resolvers fetching related rows one at a time.
+//!
+//! Keys requested by `load()` calls running concurrently, e.g. joined futures, are collected
+//! until all of them yielded once, then fetched together. Loaded rows are cached by the loader,
+//! which is meant to live for the duration of a single request.
+//!
+//! Relationships are loaded the same way: `belongs_to()` fetches the parent of each row,
+//! e.g. the author of each post, with a loader, and `has_many()` fetches the children
+//! of each parent, e.g. the comments of each post, with one query per 1000 parents.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::dataloader::DataLoader;
+//!
+//!   #[derive(Clone, serde::Deserialize)]
+//!   struct User {
+//!       id: i64,
+//!       name: String,
+//!   }
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let users = DataLoader::<_, i64, User>::new(&db, "users", "id");
+//!   // A single query fetches both users
+//!   let (author, reviewer) = tokio::join!(users.load(1), users.load(2));
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::task::{Poll, Waker};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+
+use crate::de::ValueDeserializer;
+use crate::sql::quote_ident;
+use crate::{DatabaseClient, Statement, Value};
+
+/// Maximum number of keys fetched by a single query
+const MAX_KEYS_PER_QUERY: usize = 1000;
+
+/// Loader of rows of type `T` from a table, by the values of type `K` of a key column
+pub struct DataLoader<'a, Client: DatabaseClient + ?Sized, K, T> {
+    client: &'a Client,
+    table: String,
+    key_column: String,
+    state: RefCell<State<K, T>>,
+}
+
+struct State<K, T> {
+    /// Keys to be fetched by the next query
+    pending: Vec<K>,
+    /// Keys being fetched
+    loading: Vec<K>,
+    loaded: HashMap<K, Option<T>>,
+    failed: HashMap<K, String>,
+    /// Loads waiting for the keys being fetched
+    waiters: Vec<Waker>,
+}
+
+impl<'a, Client, K, T> DataLoader<'a, Client, K, T>
+where
+    Client: DatabaseClient + ?Sized,
+    K: Clone + Eq + Hash + Into<Value> + DeserializeOwned,
+    T: Clone + DeserializeOwned,
+{
+    /// Creates a loader of the rows of `table`, looked up by `key_column`.
+    /// The key column should be unique, since a single row is returned per key.
+    pub fn new(client: &'a Client, table: &str, key_column: &str) -> Self {
+        Self {
+            client,
+            table: quote_ident(table),
+            key_column: key_column.to_string(),
+            state: RefCell::new(State {
+                pending: Vec::new(),
+                loading: Vec::new(),
+                loaded: HashMap::new(),
+                failed: HashMap::new(),
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Returns the row with the given key, or `None` if there is none
+    pub async fn load(&self, key: K) -> Result<Option<T>> {
+        enum Step<K> {
+            Yield,
+            Wait,
+            Fetch(Vec<K>),
+        }
+
+        loop {
+            let step = {
+                let mut state = self.state.borrow_mut();
+                if let Some(row) = state.loaded.get(&key) {
+                    return Ok(row.clone());
+                }
+                if let Some(e) = state.failed.remove(&key) {
+                    anyhow::bail!(e);
+                }
+                if state.loading.contains(&key) {
+                    Step::Wait
+                } else if !state.pending.contains(&key) {
+                    state.pending.push(key.clone());
+                    Step::Yield
+                } else if !state.loading.is_empty() {
+                    Step::Wait
+                } else {
+                    let keys = std::mem::take(&mut state.pending);
+                    state.loading = keys.clone();
+                    Step::Fetch(keys)
+                }
+            };
+            match step {
+                // Lets the other loads running concurrently add their keys
+                Step::Yield => {
+                    let mut yielded = false;
+                    std::future::poll_fn(|cx| {
+                        if yielded {
+                            return Poll::Ready(());
+                        }
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    })
+                    .await
+                }
+                Step::Wait => {
+                    std::future::poll_fn(|cx| {
+                        let mut state = self.state.borrow_mut();
+                        if state.loading.is_empty() {
+                            return Poll::Ready(());
+                        }
+                        state.waiters.push(cx.waker().clone());
+                        Poll::Pending
+                    })
+                    .await
+                }
+                Step::Fetch(keys) => {
+                    // Lets the waiting loads proceed even if this one is cancelled
+                    let flight = Flight { state: &self.state };
+                    let result = self.fetch(&keys).await;
+                    let mut state = self.state.borrow_mut();
+                    match result {
+                        Ok(mut rows) => {
+                            for key in keys {
+                                let row = rows.remove(&key);
+                                state.loaded.insert(key, row);
+                            }
+                        }
+                        Err(e) => {
+                            for key in keys {
+                                state.failed.insert(key, e.to_string());
+                            }
+                        }
+                    }
+                    drop(state);
+                    drop(flight);
+                }
+            }
+        }
+    }
+
+    /// Returns the rows with the given keys, in the same order
+    pub async fn load_many(&self, keys: impl IntoIterator<Item = K>) -> Result<Vec<Option<T>>> {
+        let loads: Vec<_> = keys.into_iter().map(|key| self.load(key)).collect();
+        join_all(loads).await
+    }
+
+    /// Adds a row to the cache, e.g. one returned by an insert
+    pub fn prime(&self, key: K, row: T) {
+        self.state.borrow_mut().loaded.insert(key, Some(row));
+    }
+
+    /// Removes a row from the cache, so that the next load fetches it again
+    pub fn clear(&self, key: &K) {
+        self.state.borrow_mut().loaded.remove(key);
+    }
+
+    /// Removes all the rows from the cache
+    pub fn clear_all(&self) {
+        self.state.borrow_mut().loaded.clear();
+    }
+
+    async fn fetch(&self, keys: &[K]) -> Result<HashMap<K, T>> {
+        let rows = fetch_rows(self.client, &self.table, &self.key_column, keys).await?;
+        Ok(rows.into_iter().collect())
+    }
+}
+
+/// Loads the parent row of each child, e.g. the author of each post, by the foreign key
+/// returned by `foreign_key`, with `loader` fetching the parents by their key.
+/// Parents are fetched in as few queries as possible and cached by the loader,
+/// and children without a foreign key, e.g. a `NULL` column, have no parent.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   use libsql_client::dataloader::{belongs_to, DataLoader};
+///
+///   # #[derive(Clone, serde::Deserialize)]
+///   # struct User { id: i64, name: String }
+///   #[derive(Clone, serde::Deserialize)]
+///   struct Post {
+///       id: i64,
+///       author_id: Option<i64>,
+///       title: String,
+///   }
+///
+///   # async fn f(posts: Vec<Post>) -> anyhow::Result<()> {
+///   let db = libsql_client::new_client().await?;
+///   let users = DataLoader::<_, i64, User>::new(&db, "users", "id");
+///   let authors = belongs_to(&users, &posts, |post| post.author_id).await?;
+///   for (post, author) in posts.iter().zip(authors) {
+///       println!("{} by {:?}", post.title, author.map(|user| user.name));
+///   }
+///   # Ok(())
+///   # }
+/// ```
+pub async fn belongs_to<Client, K, Parent, Child>(
+    loader: &DataLoader<'_, Client, K, Parent>,
+    children: &[Child],
+    foreign_key: impl Fn(&Child) -> Option<K>,
+) -> Result<Vec<Option<Parent>>>
+where
+    Client: DatabaseClient + ?Sized,
+    K: Clone + Eq + Hash + Into<Value> + DeserializeOwned,
+    Parent: Clone + DeserializeOwned,
+{
+    let loads = children.iter().map(|child| {
+        let key = foreign_key(child);
+        async move {
+            match key {
+                Some(key) => loader.load(key).await,
+                None => Ok(None),
+            }
+        }
+    });
+    join_all(loads.collect()).await
+}
+
+/// Loads the child rows of each parent key, e.g. the comments of each post, from the rows
+/// of `table` whose `foreign_key_column` holds the key. Children are fetched with
+/// `WHERE foreign_key_column IN (...)` queries of up to 1000 keys, and returned
+/// in the order of `keys`, with an empty list for parents without children.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f(post_ids: Vec<i64>) -> anyhow::Result<()> {
+///   use libsql_client::dataloader::has_many;
+///
+///   #[derive(Clone, serde::Deserialize)]
+///   struct Comment {
+///       post_id: i64,
+///       body: String,
+///   }
+///
+///   let db = libsql_client::new_client().await?;
+///   let comments: Vec<Vec<Comment>> = has_many(&db, "comments", "post_id", &post_ids).await?;
+///   # Ok(())
+///   # }
+/// ```
+pub async fn has_many<Client, K, T>(
+    client: &Client,
+    table: &str,
+    foreign_key_column: &str,
+    keys: &[K],
+) -> Result<Vec<Vec<T>>>
+where
+    Client: DatabaseClient + ?Sized,
+    K: Clone + Eq + Hash + Into<Value> + DeserializeOwned,
+    T: Clone + DeserializeOwned,
+{
+    let mut seen = HashSet::with_capacity(keys.len());
+    let distinct: Vec<K> = keys
+        .iter()
+        .filter(|key| seen.insert(*key))
+        .cloned()
+        .collect();
+    let rows: Vec<(K, T)> =
+        fetch_rows(client, &quote_ident(table), foreign_key_column, &distinct).await?;
+    let mut children: HashMap<K, Vec<T>> = HashMap::new();
+    for (key, row) in rows {
+        children.entry(key).or_default().push(row);
+    }
+    Ok(keys
+        .iter()
+        .map(|key| children.get(key).cloned().unwrap_or_default())
+        .collect())
+}
+
+/// Fetches the rows of `table`, already quoted, whose `key_column` is one of `keys`,
+/// along with their key, in queries of up to `MAX_KEYS_PER_QUERY` keys
+async fn fetch_rows<Client, K, T>(
+    client: &Client,
+    table: &str,
+    key_column: &str,
+    keys: &[K],
+) -> Result<Vec<(K, T)>>
+where
+    Client: DatabaseClient + ?Sized,
+    K: Clone + Into<Value> + DeserializeOwned,
+    T: DeserializeOwned,
+{
+    let mut rows = Vec::with_capacity(keys.len());
+    for keys in keys.chunks(MAX_KEYS_PER_QUERY) {
+        let placeholders = vec!["?"; keys.len()].join(", ");
+        let args: Vec<Value> = keys.iter().cloned().map(Into::into).collect();
+        tracing::trace!(table = %table, keys = keys.len(), "Loading rows");
+        let result = client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT * FROM {table} WHERE {} IN ({placeholders})",
+                    quote_ident(key_column)
+                ),
+                &args,
+            ))
+            .await?;
+        let key_index = result
+            .columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(key_column))
+            .ok_or_else(|| anyhow::anyhow!("Table {table} has no column `{key_column}`"))?;
+        for row in &result.rows {
+            let key = K::deserialize(ValueDeserializer::new(&row.values[key_index]))?;
+            let value = T::deserialize(row.deserializer(&result.columns))?;
+            rows.push((key, value));
+        }
+    }
+    Ok(rows)
+}
+
+/// Marks the end of a fetch, when dropped
+struct Flight<'s, K, T> {
+    state: &'s RefCell<State<K, T>>,
+}
+
+impl<K, T> Drop for Flight<'_, K, T> {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.loading.clear();
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Polls all the futures concurrently, and returns their outputs in order,
+/// or the first error
+async fn join_all<F, T>(futures: Vec<F>) -> Result<Vec<T>>
+where
+    F: std::future::Future<Output = Result<T>>,
+{
+    let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<T>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut done = true;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_some() {
+                continue;
+            }
+            match future.as_mut().poll(cx) {
+                Poll::Ready(Ok(value)) => *output = Some(value),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => done = false,
+            }
+        }
+        if done {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await?;
+    Ok(outputs.into_iter().flatten().collect())
+}
@@ -0,0 +1,887 @@
+//! `de` implements deserialization of rows into any type implementing `serde::Deserialize`.
+//!
+//! Struct fields are matched with column names, while tuples are filled
+//! with column values in order. Text and blob values can be borrowed
+//! from the result set, e.g. into `&str` fields.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   #[derive(serde::Deserialize)]
+//!   struct User {
+//!       id: i64,
+//!       name: String,
+//!       email: Option<String>,
+//!   }
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let users: Vec<User> = db.query_as("SELECT id, name, email FROM users").await?;
+//!
+//!   // Borrowing from a result set
+//!   let result = db.execute("SELECT name FROM users").await?;
+//!   let names: Vec<(&str,)> = result.deserialize_rows()?;
+//!   # Ok(())
+//!   # }
+//! ```
+//!
+//! Missing columns are handled with `#[serde(default)]`, and custom conversions
+//! with `#[serde(deserialize_with = "...")]`, which makes decoding robust against
+//! evolving schemas. A few common converters are provided by this module:
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   #[derive(serde::Deserialize)]
+//!   struct Settings {
+//!       theme: String,
+//!       // Column added in a later schema version
+//!       #[serde(default)]
+//!       font_size: Option<i64>,
+//!       // JSON document stored in a TEXT column
+//!       #[serde(deserialize_with = "libsql_client::de::json")]
+//!       shortcuts: std::collections::HashMap<String, String>,
+//!       // Nullable column decoded into a plain value
+//!       #[serde(deserialize_with = "libsql_client::de::null_as_default")]
+//!       visits: i64,
+//!   }
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let settings: Vec<Settings> = db.query_as("SELECT * FROM settings").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::{ResultSet, Row, Value};
+
+/// Error returned when a row cannot be deserialized into the requested type
+#[derive(Clone, Debug)]
+pub struct Error {
+    /// Kind of the failure
+    pub kind: ErrorKind,
+    /// Description of the failure
+    pub message: String,
+    /// Name of the column whose value could not be deserialized, if known
+    pub column: Option<String>,
+    /// Index of the row in the result set, if known
+    pub row: Option<usize>,
+    /// Fingerprint of the statement which returned the row, if known
+    pub statement: Option<String>,
+}
+
+/// Kind of a deserialization failure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// NULL value found for a field which is not an `Option`
+    UnexpectedNull,
+    /// No column matches a struct field which has no default value
+    MissingColumn,
+    /// Any other failure, e.g. a value of an incompatible type
+    Other,
+}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Self {
+            kind: ErrorKind::Other,
+            message: msg.to_string(),
+            column: None,
+            row: None,
+            statement: None,
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        // The message is filled in by the row deserializer, which knows the columns
+        Self {
+            kind: ErrorKind::MissingColumn,
+            column: Some(field.to_string()),
+            ..Self::custom("")
+        }
+    }
+
+    fn invalid_type(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        // NULL values are visited as units
+        if unexp != de::Unexpected::Unit {
+            return Self::custom(format_args!("invalid type: {unexp}, expected {exp}"));
+        }
+        Self {
+            kind: ErrorKind::UnexpectedNull,
+            ..Self::custom(format_args!(
+                "unexpected NULL, expected {exp}; nullable columns need an Option field"
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(row) = self.row {
+            write!(f, "row {row}: ")?;
+        }
+        if let Some(column) = &self.column {
+            write!(f, "column `{column}`: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(statement) = &self.statement {
+            write!(f, " (in `{statement}`)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResultSet {
+    /// Deserializes all rows of the result set into `T`.
+    /// Text and blob values can be borrowed from the result set.
+    pub fn deserialize_rows<'de, T: de::Deserialize<'de>>(&'de self) -> anyhow::Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(idx, row)| {
+                T::deserialize(row.deserializer(&self.columns)).map_err(|mut e| {
+                    e.row = Some(idx);
+                    // Scalars are deserialized from single-column rows
+                    if e.column.is_none() && self.columns.len() == 1 {
+                        e.column = self.columns.first().cloned();
+                    }
+                    anyhow::Error::from(e)
+                })
+            })
+            .collect()
+    }
+}
+
+impl Row {
+    /// Returns a deserializer of this row, matching values with given column names
+    pub fn deserializer<'de>(&'de self, columns: &'de [String]) -> RowDeserializer<'de> {
+        RowDeserializer {
+            columns,
+            values: &self.values,
+            prefix: String::new(),
+        }
+    }
+}
+
+/// Deserializer of a single row. Structs and maps are deserialized by column names,
+/// sequences and tuples by column order, and scalars from single-column rows.
+///
+/// A struct field without a matching column is deserialized from the columns
+/// prefixed with the field name and an underscore, which allows decoding `JOIN`
+/// results into nested structs. The prefix can be changed with `#[serde(rename)]`,
+/// and an `Option` of a nested struct is `None` if all of its columns are NULL:
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::DatabaseClient;
+///   #[derive(serde::Deserialize)]
+///   struct User {
+///       id: i64,
+///       name: String,
+///   }
+///
+///   #[derive(serde::Deserialize)]
+///   struct Post {
+///       id: i64,
+///       title: String,
+///       author: User,
+///       #[serde(rename = "editor")]
+///       last_edited_by: Option<User>,
+///   }
+///
+///   let db = libsql_client::new_client().await?;
+///   let posts: Vec<Post> = db
+///       .query_as(
+///           "SELECT p.id, p.title, a.id AS author_id, a.name AS author_name, \
+///                   e.id AS editor_id, e.name AS editor_name \
+///            FROM posts p JOIN users a ON a.id = p.author_id \
+///            LEFT JOIN users e ON e.id = p.editor_id",
+///       )
+///       .await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct RowDeserializer<'de> {
+    columns: &'de [String],
+    values: &'de [Value],
+    /// Prefix of the columns belonging to this (possibly nested) row
+    prefix: String,
+}
+
+impl<'de> RowDeserializer<'de> {
+    /// Returns indexes and prefix-stripped names of the columns belonging to this row
+    fn entries(&self) -> impl Iterator<Item = (usize, &'de str)> + '_ {
+        let columns: &'de [String] = self.columns;
+        columns
+            .iter()
+            .take(self.values.len())
+            .enumerate()
+            .filter_map(|(idx, name)| name.strip_prefix(self.prefix.as_str()).map(|n| (idx, n)))
+    }
+
+    fn single(&self) -> Result<ValueDeserializer<'de>, Error> {
+        let all_values: &'de [Value] = self.values;
+        let values: Vec<&'de Value> = if self.prefix.is_empty() {
+            all_values.iter().collect()
+        } else {
+            self.entries().map(|(idx, _)| &all_values[idx]).collect()
+        };
+        match values[..] {
+            [value] => Ok(ValueDeserializer { value }),
+            _ => Err(de::Error::custom(format!(
+                "expected a single column, got {}",
+                values.len()
+            ))),
+        }
+    }
+
+    /// Describes a missing column with the columns which don't match any field,
+    /// suggesting the ones whose names are close to the missing one
+    fn describe_missing_column(&self, mut e: Error, fields: &[&str]) -> Error {
+        let Some(field) = e.column.take() else {
+            return e;
+        };
+        if e.kind != ErrorKind::MissingColumn || !e.message.is_empty() {
+            e.column = Some(field);
+            return e;
+        }
+        let unmatched: Vec<&str> = self
+            .entries()
+            .map(|(_, name)| name)
+            .filter(|name| {
+                !fields
+                    .iter()
+                    .any(|f| f == name || name.starts_with(&format!("{f}_")))
+            })
+            .collect();
+        e.message = "no column matches the field".to_string();
+        if !unmatched.is_empty() {
+            let list: Vec<String> = unmatched.iter().map(|c| format!("`{c}`")).collect();
+            e.message += &format!("; columns without a matching field: {}", list.join(", "));
+        }
+        let threshold = (field.len() / 3).max(1);
+        let closest = unmatched
+            .iter()
+            .map(|c| (edit_distance(&c.to_lowercase(), &field.to_lowercase()), c))
+            .filter(|(distance, _)| *distance <= threshold)
+            .min_by_key(|(distance, _)| *distance);
+        if let Some((_, column)) = closest {
+            e.message += &format!("; did you mean `{column}`?");
+        }
+        e.column = Some(format!("{}{field}", self.prefix));
+        e
+    }
+
+    fn access(&self, nested: Vec<&'static str>) -> RowAccess<'de> {
+        RowAccess {
+            columns: self.columns,
+            values: self.values,
+            prefix: self.prefix.clone(),
+            idx: 0,
+            nested: nested.into_iter(),
+            pending: None,
+        }
+    }
+}
+
+macro_rules! forward_to_single_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.columns.is_empty() {
+            visitor.visit_seq(self.access(vec![]))
+        } else {
+            visitor.visit_map(self.access(vec![]))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // A row is missing if all of its columns are NULL, e.g. a nested row after a LEFT JOIN
+        // or a scalar read from a NULL column
+        let missing = self.entries().next().is_some()
+            && self
+                .entries()
+                .all(|(idx, _)| matches!(self.values[idx], Value::Null));
+        if missing {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(self.access(vec![]))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(self.access(vec![]))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(self.access(vec![]))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self.access(vec![]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // Fields without a matching column are looked up as nested rows, by prefix
+        let nested = fields
+            .iter()
+            .copied()
+            .filter(|field| {
+                let prefix = format!("{field}_");
+                !self.entries().any(|(_, name)| name == *field)
+                    && self.entries().any(|(_, name)| name.starts_with(&prefix))
+            })
+            .collect();
+        visitor
+            .visit_map(self.access(nested))
+            .map_err(|e| self.describe_missing_column(e, fields))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+        deserialize_identifier
+    }
+}
+
+/// Entry whose key was returned by `RowAccess`, waiting for its value to be deserialized
+enum Pending {
+    /// Value of the column with the given index
+    Value(usize),
+    /// Nested row of the struct field with the given name
+    Nested(&'static str),
+}
+
+/// Access to the values of a row, either as a map keyed by column names or as a sequence
+struct RowAccess<'de> {
+    columns: &'de [String],
+    values: &'de [Value],
+    prefix: String,
+    idx: usize,
+    nested: std::vec::IntoIter<&'static str>,
+    pending: Option<Pending>,
+}
+
+impl<'de> RowAccess<'de> {
+    /// Returns the index and prefix-stripped name of the next column belonging to this row
+    fn next_column(&mut self) -> Option<(usize, &'de str)> {
+        let columns: &'de [String] = self.columns;
+        while self.idx < self.values.len().min(columns.len()) {
+            let idx = self.idx;
+            self.idx += 1;
+            if let Some(name) = columns[idx].strip_prefix(self.prefix.as_str()) {
+                return Some((idx, name));
+            }
+        }
+        None
+    }
+
+    fn value<T: DeserializeSeed<'de>>(&self, idx: usize, seed: T) -> Result<T::Value, Error> {
+        let values: &'de [Value] = self.values;
+        seed.deserialize(ValueDeserializer {
+            value: &values[idx],
+        })
+        .map_err(|mut e| {
+            e.column = e.column.or_else(|| self.columns.get(idx).cloned());
+            e
+        })
+    }
+}
+
+impl<'de> MapAccess<'de> for RowAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let (pending, key) = match self.next_column() {
+            Some((idx, name)) => (Pending::Value(idx), name),
+            None => match self.nested.next() {
+                Some(field) => (Pending::Nested(field), field),
+                None => return Ok(None),
+            },
+        };
+        self.pending = Some(pending);
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.pending.take() {
+            Some(Pending::Value(idx)) => self.value(idx, seed),
+            Some(Pending::Nested(field)) => seed.deserialize(RowDeserializer {
+                columns: self.columns,
+                values: self.values,
+                prefix: format!("{}{field}_", self.prefix),
+            }),
+            _ => Err(de::Error::custom("value requested before its key")),
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for RowAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.columns.is_empty() {
+            // Without column names, all values belong to the row
+            if self.idx >= self.values.len() {
+                return Ok(None);
+            }
+            self.idx += 1;
+            return self.value(self.idx - 1, seed).map(Some);
+        }
+        match self.next_column() {
+            Some((idx, _)) => self.value(idx, seed).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Deserializer of a single value
+pub struct ValueDeserializer<'de> {
+    value: &'de Value,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    /// Creates a deserializer of the given value
+    pub fn new(value: &'de Value) -> Self {
+        Self { value }
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Integer { value } => visitor.visit_i64(*value),
+            Value::Float { value } => visitor.visit_f64(*value),
+            Value::Text { value } => visitor.visit_borrowed_str(value),
+            Value::Blob { value } => visitor.visit_borrowed_bytes(value),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // SQLite has no boolean type, booleans are stored as integers
+        match self.value {
+            Value::Integer { value } => visitor.visit_bool(*value != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // Allows deserializing blobs into `Vec<u8>`
+        match self.value {
+            Value::Blob { value } => visitor.visit_seq(
+                de::value::SeqDeserializer::<_, Error>::new(value.iter().copied()),
+            ),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // Unit variants are represented by their names
+        match self.value {
+            Value::Text { value } => {
+                visitor.visit_enum(BorrowedStrDeserializer::<Error>::new(value))
+            }
+            _ => Err(de::Error::custom(format!(
+                "expected text for enum {name} (one of {variants:?}), got {}",
+                type_name(self.value),
+            ))),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Converter which parses a JSON document stored in a TEXT column,
+/// to be used with `#[serde(deserialize_with = "libsql_client::de::json")]`
+pub fn json<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: de::DeserializeOwned,
+{
+    let text = String::deserialize(deserializer)?;
+    serde_json::from_str(&text).map_err(de::Error::custom)
+}
+
+/// Converter which maps NULL to the default value of the field type,
+/// to be used with `#[serde(deserialize_with = "libsql_client::de::null_as_default")]`
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: de::Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Levenshtein distance between two strings, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+/// Name of the SQLite type of a value, used in error messages
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "NULL",
+        Value::Integer { .. } => "INTEGER",
+        Value::Float { .. } => "REAL",
+        Value::Text { .. } => "TEXT",
+        Value::Blob { .. } => "BLOB",
+    }
+}
+
+/// Converter which decodes a timestamp stored as text or as a unix epoch number,
+/// with the rules of `libsql_client::time::parse_timestamp()`.
+/// To be used with `#[serde(deserialize_with = "libsql_client::de::timestamp")]`
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::DatabaseClient;
+///   #[derive(serde::Deserialize)]
+///   struct Event {
+///       #[serde(deserialize_with = "libsql_client::de::timestamp")]
+///       created_at: chrono::DateTime<chrono::FixedOffset>,
+///   }
+///
+///   let db = libsql_client::new_client().await?;
+///   let events: Vec<Event> = db.query_as("SELECT created_at FROM events").await?;
+///   # Ok(())
+///   # }
+/// ```
+#[cfg(feature = "chrono")]
+pub fn timestamp<'de, D>(deserializer: D) -> Result<chrono::DateTime<chrono::FixedOffset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TimestampVisitor;
+
+    impl Visitor<'_> for TimestampVisitor {
+        type Value = Value;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a timestamp")
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+            Ok(Value::Integer { value })
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+            let value = i64::try_from(value).map_err(E::custom)?;
+            Ok(Value::Integer { value })
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+            Ok(Value::Float { value })
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+            Ok(Value::Text {
+                value: value.to_string(),
+            })
+        }
+    }
+
+    let value = deserializer.deserialize_any(TimestampVisitor)?;
+    crate::time::parse_timestamp(&value).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{Col, StmtResult};
+
+    fn result(columns: &[&str], rows: Vec<Vec<Value>>) -> ResultSet {
+        ResultSet::from(StmtResult {
+            cols: columns
+                .iter()
+                .map(|name| Col {
+                    name: Some(name.to_string()),
+                })
+                .collect(),
+            rows,
+            affected_row_count: 0,
+            last_insert_rowid: None,
+        })
+    }
+
+    fn integer(value: i64) -> Value {
+        Value::Integer { value }
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Text {
+            value: value.to_string(),
+        }
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct User {
+        id: i64,
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Post {
+        id: i64,
+        title: String,
+        author: User,
+        #[serde(rename = "editor")]
+        last_edited_by: Option<User>,
+    }
+
+    #[test]
+    fn structs_are_matched_by_column_names() {
+        let result = result(
+            &["name", "id", "unused"],
+            vec![vec![text("ann"), integer(1), Value::Null]],
+        );
+        let users: Vec<User> = result.deserialize_rows().unwrap();
+        assert_eq!(
+            users,
+            [User {
+                id: 1,
+                name: "ann".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn tuples_scalars_and_borrowed_values() {
+        let result = result(
+            &["id", "name", "data", "flag"],
+            vec![vec![
+                integer(2),
+                text("bob"),
+                Value::Blob { value: vec![1, 2] },
+                integer(1),
+            ]],
+        );
+        let rows: Vec<(i64, &str, Vec<u8>, bool)> = result.deserialize_rows().unwrap();
+        assert_eq!(rows, [(2, "bob", vec![1, 2], true)]);
+        let single = super::tests::result(&["n"], vec![vec![integer(7)], vec![Value::Null]]);
+        let values: Vec<Option<i64>> = single.deserialize_rows().unwrap();
+        assert_eq!(values, [Some(7), None]);
+    }
+
+    #[test]
+    fn nested_structs_are_matched_by_prefix() {
+        let columns = [
+            "id",
+            "title",
+            "author_id",
+            "author_name",
+            "editor_id",
+            "editor_name",
+        ];
+        let result = result(
+            &columns,
+            vec![
+                vec![
+                    integer(1),
+                    text("first"),
+                    integer(10),
+                    text("ann"),
+                    integer(11),
+                    text("bob"),
+                ],
+                vec![
+                    integer(2),
+                    text("second"),
+                    integer(10),
+                    text("ann"),
+                    Value::Null,
+                    Value::Null,
+                ],
+            ],
+        );
+        let posts: Vec<Post> = result.deserialize_rows().unwrap();
+        let ann = || User {
+            id: 10,
+            name: "ann".to_string(),
+        };
+        assert_eq!(
+            posts,
+            [
+                Post {
+                    id: 1,
+                    title: "first".to_string(),
+                    author: ann(),
+                    last_edited_by: Some(User {
+                        id: 11,
+                        name: "bob".to_string()
+                    }),
+                },
+                Post {
+                    id: 2,
+                    title: "second".to_string(),
+                    author: ann(),
+                    last_edited_by: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_columns_are_described() {
+        let result = result(&["id", "nme"], vec![vec![integer(1), text("ann")]]);
+        let e = result.deserialize_rows::<User>().unwrap_err();
+        let e = e.downcast_ref::<Error>().unwrap();
+        assert_eq!(e.kind, ErrorKind::MissingColumn);
+        assert_eq!(e.row, Some(0));
+        assert_eq!(e.column.as_deref(), Some("name"));
+        assert_eq!(
+            e.to_string(),
+            "row 0: column `name`: no column matches the field; \
+             columns without a matching field: `nme`; did you mean `nme`?"
+        );
+    }
+
+    #[test]
+    fn unexpected_nulls_are_reported() {
+        let result = result(&["id", "name"], vec![vec![integer(1), Value::Null]]);
+        let e = result.deserialize_rows::<User>().unwrap_err();
+        let e = e.downcast_ref::<Error>().unwrap();
+        assert_eq!(e.kind, ErrorKind::UnexpectedNull);
+        assert_eq!(e.column.as_deref(), Some("name"));
+    }
+
+    #[test]
+    fn converters() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Settings {
+            #[serde(deserialize_with = "json")]
+            tags: Vec<String>,
+            #[serde(deserialize_with = "null_as_default")]
+            visits: i64,
+            #[serde(default)]
+            theme: Option<String>,
+        }
+        let result = result(
+            &["tags", "visits"],
+            vec![vec![text("[\"a\",\"b\"]"), Value::Null]],
+        );
+        let settings: Vec<Settings> = result.deserialize_rows().unwrap();
+        assert_eq!(
+            settings,
+            [Settings {
+                tags: vec!["a".to_string(), "b".to_string()],
+                visits: 0,
+                theme: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("name", "name"), 0);
+        assert_eq!(edit_distance("name", "nmae"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("zażółć", "zazolc"), 4);
+    }
+}
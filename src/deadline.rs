@@ -0,0 +1,103 @@
+//! `deadline` propagates an end-to-end request budget to database calls,
+//! without passing it explicitly to every call.
+//!
+//! A future wrapped with `with_deadline()` or `with_timeout()`, e.g. the handler
+//! of an incoming request, carries the deadline in its task context: every statement
+//! executed while the future is polled fails with `DeadlineExceeded` once the deadline
+//! passes, and HTTP requests are bounded with the time remaining.
+//! Nested deadlines can only shorten the budget.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::deadline::{with_timeout, DeadlineExceeded};
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let handler = async {
+//!       db.execute("SELECT * FROM users").await?;
+//!       db.execute("SELECT * FROM orders").await
+//!   };
+//!   match with_timeout(Duration::from_millis(500), handler).await {
+//!       Err(e) if e.downcast_ref::<DeadlineExceeded>().is_some() => println!("Too slow"),
+//!       result => println!("{:?}", result?),
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static CURRENT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Error returned when a statement is executed after the deadline of its task passed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadlineExceeded;
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Future carrying a deadline, created with `with_deadline()` or `with_timeout()`
+pub struct WithDeadline<F: Future> {
+    inner: Pin<Box<F>>,
+    deadline: Instant,
+}
+
+impl<F: Future> Future for WithDeadline<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let outer = CURRENT.with(|current| current.get());
+        let deadline = match outer {
+            Some(outer) => outer.min(self.deadline),
+            None => self.deadline,
+        };
+        CURRENT.with(|current| current.set(Some(deadline)));
+        let result = self.inner.as_mut().poll(cx);
+        CURRENT.with(|current| current.set(outer));
+        result
+    }
+}
+
+/// Runs a future with a deadline for all the statements it executes
+pub fn with_deadline<F: Future>(deadline: Instant, future: F) -> WithDeadline<F> {
+    WithDeadline {
+        inner: Box::pin(future),
+        deadline,
+    }
+}
+
+/// Runs a future with a deadline `timeout` from now for all the statements it executes
+pub fn with_timeout<F: Future>(timeout: Duration, future: F) -> WithDeadline<F> {
+    with_deadline(Instant::now() + timeout, future)
+}
+
+/// Returns the deadline of the current task, if any
+pub fn current() -> Option<Instant> {
+    CURRENT.with(|current| current.get())
+}
+
+/// Returns the time left until the deadline of the current task, if any
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Fails if the deadline of the current task passed,
+/// otherwise returns the time left, if there is a deadline
+pub(crate) fn check() -> anyhow::Result<Option<Duration>> {
+    match remaining() {
+        Some(remaining) if remaining.is_zero() => Err(DeadlineExceeded.into()),
+        remaining => Ok(remaining),
+    }
+}
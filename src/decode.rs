@@ -0,0 +1,370 @@
+//! `decode` overrides how the values of a column are read, for legacy schemas whose columns
+//! hold values of mixed types, e.g. amounts stored as integers in some rows and as text
+//! in others, which SQLite keeps as they were inserted unless the column affinity converts them.
+//!
+//! Overrides are set per statement with `Statement::decode_column()`, and convert each value
+//! of the column into the requested type, failing the statement if a value cannot be
+//! converted without losing information. `NULL` values are left as they are.
+//! Overrides apply to statements passed to `DatabaseClient::execute()`, and thus to
+//! `DatabaseClient::query_as()`, not to the statements of batches.
+//!
+//! `Statement::blob_policy()` similarly replaces blobs with their length or a prefix,
+//! e.g. for admin tools previewing tables whose blobs may be large. The local backend only
+//! reads the bytes which are kept, while remote servers send whole blobs, which are then
+//! dropped, so the result set stays small but the response does not.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Statement};
+//!   use libsql_client::decode::Decode;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let stmt = Statement::new("SELECT id, amount, reference FROM payments")
+//!       .decode_column("amount", Decode::Real)
+//!       .decode_column("reference", Decode::Text);
+//!   let payments = db.execute(stmt).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+
+use crate::{ResultSet, Value};
+
+/// Type a column is decoded into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Decode {
+    /// Text, with numbers rendered in decimal and blobs read as UTF-8
+    Text,
+    /// 64-bit integers, parsed from text and converted from floats without a fractional part
+    Integer,
+    /// Floats, parsed from text and converted from integers
+    Real,
+    /// Blobs, with text and numbers taken as their UTF-8 bytes
+    Blob,
+    /// Like a column of `NUMERIC` affinity: text holding a number, and floats without
+    /// a fractional part, are converted into integers or floats, other values are kept
+    Numeric,
+}
+
+impl Decode {
+    fn name(self) -> &'static str {
+        match self {
+            Decode::Text => "TEXT",
+            Decode::Integer => "INTEGER",
+            Decode::Real => "REAL",
+            Decode::Blob => "BLOB",
+            Decode::Numeric => "NUMERIC",
+        }
+    }
+
+    /// Converts a value, or returns `None` if it cannot be converted exactly
+    fn convert(self, value: Value) -> Option<Value> {
+        let value = match (self, value) {
+            (_, Value::Null) => Value::Null,
+            (Decode::Text, Value::Integer { value }) => Value::Text {
+                value: value.to_string(),
+            },
+            (Decode::Text, Value::Float { value }) => Value::Text {
+                value: float_text(value),
+            },
+            (Decode::Text, Value::Blob { value }) => Value::Text {
+                value: String::from_utf8(value).ok()?,
+            },
+            (Decode::Integer, Value::Float { value }) => Value::Integer {
+                value: float_integer(value)?,
+            },
+            (Decode::Integer, Value::Text { value }) => Value::Integer {
+                value: text_integer(&value)?,
+            },
+            (Decode::Real, Value::Integer { value }) => Value::Float {
+                value: value as f64,
+            },
+            (Decode::Real, Value::Text { value }) => Value::Float {
+                value: text_float(&value)?,
+            },
+            (Decode::Blob, Value::Integer { value }) => Value::Blob {
+                value: value.to_string().into_bytes(),
+            },
+            (Decode::Blob, Value::Float { value }) => Value::Blob {
+                value: float_text(value).into_bytes(),
+            },
+            (Decode::Blob, Value::Text { value }) => Value::Blob {
+                value: value.into_bytes(),
+            },
+            (Decode::Numeric, Value::Float { value }) => match float_integer(value) {
+                Some(value) => Value::Integer { value },
+                None => Value::Float { value },
+            },
+            (Decode::Numeric, Value::Text { value }) => match text_float(&value) {
+                Some(float) => match text_integer(&value) {
+                    Some(value) => Value::Integer { value },
+                    None => Value::Float { value: float },
+                },
+                None => Value::Text { value },
+            },
+            (Decode::Integer, Value::Blob { .. }) | (Decode::Real, Value::Blob { .. }) => {
+                return None
+            }
+            (_, value) => value,
+        };
+        Some(value)
+    }
+}
+
+/// Renders a float like SQLite, keeping a decimal point for integral values
+fn float_text(value: f64) -> String {
+    if value.is_finite() && value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{value:.1}")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Converts a float without a fractional part which fits in 64 bits
+fn float_integer(value: f64) -> Option<i64> {
+    // i64::MAX is not exactly representable, so the bound is exclusive
+    let fits = value >= i64::MIN as f64 && value < i64::MAX as f64;
+    (value.fract() == 0.0 && fits).then_some(value as i64)
+}
+
+/// Parses text holding an integer, or a float without a fractional part
+fn text_integer(text: &str) -> Option<i64> {
+    let text = text.trim();
+    text.parse()
+        .ok()
+        .or_else(|| text_float(text).and_then(float_integer))
+}
+
+/// Parses text holding a finite numeric literal
+fn text_float(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if !crate::numeric::is_numeric_literal(text) {
+        return None;
+    }
+    text.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+/// How blobs are returned, set with `Statement::blob_policy()`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlobPolicy {
+    /// The whole blob
+    #[default]
+    Full,
+    /// The length of the blob in bytes, as an integer
+    SizeOnly,
+    /// The first bytes of the blob, at most the given number
+    Prefix(usize),
+}
+
+impl BlobPolicy {
+    /// Returns the value of a blob according to the policy
+    pub(crate) fn read(self, blob: &[u8]) -> Value {
+        match self {
+            BlobPolicy::Full => Value::Blob {
+                value: blob.to_vec(),
+            },
+            BlobPolicy::SizeOnly => Value::Integer {
+                value: blob.len() as i64,
+            },
+            BlobPolicy::Prefix(len) => Value::Blob {
+                value: blob[..len.min(blob.len())].to_vec(),
+            },
+        }
+    }
+
+    /// Replaces the blobs of a result according to the policy
+    pub(crate) fn apply(self, result: &mut ResultSet) {
+        if self == BlobPolicy::Full {
+            return;
+        }
+        for row in &mut result.rows {
+            for value in &mut row.values {
+                if let Value::Blob { value: blob } = value {
+                    *value = self.read(blob);
+                }
+            }
+            #[cfg(feature = "mapping_names_to_values_in_rows")]
+            for (column, value) in result.columns.iter().zip(&row.values) {
+                row.value_map.insert(column.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Converts the values of the columns of a result as requested by a statement
+pub(crate) fn apply(decodes: &[(String, Decode)], result: &mut ResultSet) -> Result<()> {
+    for (column, decode) in decodes {
+        let Some(index) = result
+            .columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(column))
+        else {
+            anyhow::bail!("Cannot decode column {column}, which is not returned by the statement");
+        };
+        for row in &mut result.rows {
+            let value = std::mem::replace(&mut row.values[index], Value::Null);
+            let type_name = crate::de::type_name(&value);
+            let Some(value) = decode.convert(value) else {
+                anyhow::bail!(
+                    "Cannot decode a {type_name} value of column {column} as {}",
+                    decode.name()
+                );
+            };
+            #[cfg(feature = "mapping_names_to_values_in_rows")]
+            row.value_map
+                .insert(result.columns[index].clone(), value.clone());
+            row.values[index] = value;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{Col, StmtResult};
+
+    fn text(value: &str) -> Value {
+        Value::Text {
+            value: value.to_string(),
+        }
+    }
+
+    fn blob(value: &[u8]) -> Value {
+        Value::Blob {
+            value: value.to_vec(),
+        }
+    }
+
+    /// Converts a value, rendering the outcome for comparison since `Value` is not `PartialEq`
+    fn convert(decode: Decode, value: Value) -> String {
+        format!("{:?}", decode.convert(value))
+    }
+
+    fn converted(value: Value) -> String {
+        format!("{:?}", Some(value))
+    }
+
+    #[test]
+    fn values_are_converted_into_text() {
+        let cases = [
+            (Value::Integer { value: -3 }, text("-3")),
+            (Value::Float { value: 2.0 }, text("2.0")),
+            (Value::Float { value: 0.25 }, text("0.25")),
+            (Value::Float { value: 1e20 }, text("100000000000000000000")),
+            (blob(b"abc"), text("abc")),
+            (text("kept"), text("kept")),
+            (Value::Null, Value::Null),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(convert(Decode::Text, value), converted(expected));
+        }
+        assert_eq!(convert(Decode::Text, blob(&[0xff])), "None");
+    }
+
+    #[test]
+    fn values_are_converted_into_numbers() {
+        let integer = |value| Value::Integer { value };
+        let float = |value| Value::Float { value };
+        let cases = [
+            (Decode::Integer, float(3.0), Some(integer(3))),
+            (Decode::Integer, float(3.5), None),
+            (Decode::Integer, float(9.3e18), None),
+            (Decode::Integer, text(" 42 "), Some(integer(42))),
+            (Decode::Integer, text("1e3"), Some(integer(1000))),
+            (Decode::Integer, text("abc"), None),
+            (Decode::Integer, blob(b"1"), None),
+            (Decode::Real, integer(2), Some(float(2.0))),
+            (Decode::Real, text("0.5"), Some(float(0.5))),
+            (Decode::Real, text("inf"), None),
+            (Decode::Real, blob(b"1"), None),
+            (Decode::Numeric, float(4.0), Some(integer(4))),
+            (Decode::Numeric, float(4.5), Some(float(4.5))),
+            (Decode::Numeric, text("12"), Some(integer(12))),
+            (Decode::Numeric, text("1.25"), Some(float(1.25))),
+            (Decode::Numeric, text("n/a"), Some(text("n/a"))),
+        ];
+        for (decode, value, expected) in cases {
+            let input = format!("{value:?}");
+            assert_eq!(
+                convert(decode, value),
+                format!("{expected:?}"),
+                "{input} as {decode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn values_are_converted_into_blobs() {
+        assert_eq!(
+            convert(Decode::Blob, Value::Integer { value: 7 }),
+            converted(blob(b"7"))
+        );
+        assert_eq!(
+            convert(Decode::Blob, Value::Float { value: 1.0 }),
+            converted(blob(b"1.0"))
+        );
+        assert_eq!(convert(Decode::Blob, text("ab")), converted(blob(b"ab")));
+    }
+
+    fn result(rows: Vec<Vec<Value>>) -> ResultSet {
+        ResultSet::from(StmtResult {
+            cols: vec![
+                Col {
+                    name: Some("id".to_string()),
+                },
+                Col {
+                    name: Some("amount".to_string()),
+                },
+            ],
+            rows,
+            affected_row_count: 0,
+            last_insert_rowid: None,
+        })
+    }
+
+    #[test]
+    fn columns_are_decoded_by_name() {
+        let mut result = result(vec![
+            vec![Value::Integer { value: 1 }, text("10")],
+            vec![Value::Integer { value: 2 }, Value::Null],
+        ]);
+        apply(&[("AMOUNT".to_string(), Decode::Real)], &mut result).unwrap();
+        let amounts: Vec<String> = result
+            .rows
+            .iter()
+            .map(|row| format!("{:?}", row.values[1]))
+            .collect();
+        assert_eq!(amounts, ["Float { value: 10.0 }", "Null"]);
+    }
+
+    #[test]
+    fn unconvertible_values_fail() {
+        let mut rows = result(vec![vec![Value::Integer { value: 1 }, text("ten")]]);
+        let error = apply(&[("amount".to_string(), Decode::Integer)], &mut rows).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot decode a TEXT value of column amount as INTEGER"
+        );
+        let error = apply(&[("missing".to_string(), Decode::Text)], &mut rows).unwrap_err();
+        assert!(error.to_string().contains("not returned by the statement"));
+    }
+
+    #[test]
+    fn blobs_are_read_by_policy() {
+        let bytes = [1, 2, 3];
+        assert_eq!(
+            format!("{:?}", BlobPolicy::SizeOnly.read(&bytes)),
+            "Integer { value: 3 }"
+        );
+        assert_eq!(
+            format!("{:?}", BlobPolicy::Prefix(2).read(&bytes)),
+            format!("{:?}", blob(&[1, 2]))
+        );
+        assert_eq!(
+            format!("{:?}", BlobPolicy::Prefix(5).read(&bytes)),
+            format!("{:?}", blob(&bytes))
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! `discovery` finds the endpoints of a database at runtime instead of configuring them once,
+//! so that a primary moved by a failover or a replica added by an operator is picked up
+//! without restarting the process.
+//!
+//! A `Resolver` returns the current `Endpoints`: the URL of the primary and the URLs of the
+//! replicas, e.g. from DNS SRV records, a control plane API or a file. `FileResolver` reads
+//! them from a file, and other sources are plugged in by implementing the trait.
+//!
+//! `Discovered` is a client routing statements to the endpoints returned by its resolver,
+//! which it asks again every `Discovered::refresh_every()`: statements which cannot write
+//! go to the replicas in turn, or to the primary if a replica is unreachable or there is
+//! none, and all other statements, batches and transactions go to the primary. A client is
+//! established for each endpoint, and kept as long as the endpoint is returned. If the
+//! resolver fails, the previous endpoints are used until the next refresh. In WebAssembly
+//! outside of WASI, where no clock is available, endpoints are only resolved again
+//! by `Discovered::refresh()`.
+//!
+//! Since replicas may lag behind the primary, a read following a write may not observe it.
+//! `DatabaseClient::barrier()` waits until they caught up with the writes issued through
+//! the client: it updates a marker row on the primary, in the `_libsql_barrier` table
+//! by default, then reads it from each replica until it has the new value. As replicas
+//! apply the changes of the primary in order, the writes preceding the marker are visible.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{Config, DatabaseClient};
+//!   use libsql_client::discovery::{Discovered, FileResolver};
+//!   use std::time::Duration;
+//!
+//!   // One URL per line, the primary first
+//!   let resolver = FileResolver::new("/etc/myapp/database-endpoints");
+//!   let db = Discovered::new(resolver, |url| Ok(Config::new(url)?.with_auth_token("<token>")))
+//!       .refresh_every(Duration::from_secs(10));
+//!   db.execute("SELECT * FROM products").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::client::GenericClient;
+use crate::priority::Permits;
+use crate::sql::{quote_ident, TransactionControl};
+use crate::{
+    BatchResult, ClientStats, Config, DatabaseClient, Priority, ResultSet, Statement, Value,
+};
+
+const DEFAULT_BARRIER_TABLE: &str = "_libsql_barrier";
+
+/// How many times `barrier()` reads the marker from a replica before giving up
+const BARRIER_CHECKS: u32 = 100;
+
+/// Delay between reads of the marker, with the `tokio` feature
+#[cfg(feature = "tokio")]
+const BARRIER_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Current endpoints of a database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoints {
+    /// Endpoint receiving writes
+    pub primary: url::Url,
+    /// Endpoints receiving reads, if any
+    pub replicas: Vec<url::Url>,
+}
+
+impl Endpoints {
+    /// Describes a single endpoint, without replicas
+    pub fn new(primary: url::Url) -> Self {
+        Self {
+            primary,
+            replicas: Vec::new(),
+        }
+    }
+
+    /// Adds a replica
+    pub fn replica(mut self, url: url::Url) -> Self {
+        self.replicas.push(url);
+        self
+    }
+}
+
+/// Source of the current endpoints of a database
+#[async_trait(?Send)]
+pub trait Resolver {
+    /// Returns the current endpoints
+    async fn resolve(&self) -> Result<Endpoints>;
+}
+
+/// Fixed endpoints, e.g. for tests or as a fallback
+#[async_trait(?Send)]
+impl Resolver for Endpoints {
+    async fn resolve(&self) -> Result<Endpoints> {
+        Ok(self.clone())
+    }
+}
+
+/// Resolver reading the endpoints from a file, with a URL per line: the primary first, then
+/// the replicas. Empty lines and lines starting with `#` are ignored. The file is read every
+/// time endpoints are resolved, so it can be rewritten by e.g. a configuration agent.
+#[derive(Clone, Debug)]
+pub struct FileResolver {
+    path: std::path::PathBuf,
+}
+
+impl FileResolver {
+    /// Creates a resolver reading the given file
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait(?Send)]
+impl Resolver for FileResolver {
+    async fn resolve(&self) -> Result<Endpoints> {
+        let contents = std::fs::read_to_string(&self.path).map_err(|e| {
+            anyhow::anyhow!("Failed to read endpoints from {}: {e}", self.path.display())
+        })?;
+        let mut urls = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                url::Url::parse(line).map_err(|e| anyhow::anyhow!("Invalid endpoint {line}: {e}"))
+            });
+        let Some(primary) = urls.next() else {
+            anyhow::bail!("No endpoints in {}", self.path.display());
+        };
+        Ok(Endpoints {
+            primary: primary?,
+            replicas: urls.collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Clients of the endpoints last resolved
+struct Routes {
+    endpoints: Endpoints,
+    primary: Arc<GenericClient>,
+    replicas: Vec<Arc<GenericClient>>,
+    resolved_at: Option<Instant>,
+}
+
+impl Routes {
+    /// Returns the client of each endpoint
+    fn clients(&self) -> impl Iterator<Item = (url::Url, Arc<GenericClient>)> + '_ {
+        std::iter::once(&self.endpoints.primary)
+            .chain(&self.endpoints.replicas)
+            .cloned()
+            .zip(
+                std::iter::once(&self.primary)
+                    .chain(&self.replicas)
+                    .cloned(),
+            )
+    }
+}
+
+/// Client routing statements to the endpoints returned by a resolver
+pub struct Discovered<R: Resolver> {
+    resolver: R,
+    configure: Box<dyn Fn(url::Url) -> Result<Config>>,
+    refresh_every: Duration,
+    routes: Mutex<Option<Routes>>,
+    /// Held while endpoints are resolved, so that a single task resolves them
+    resolving: Permits,
+    next_replica: AtomicUsize,
+    /// Whether a transaction was started, during which all statements go to the primary
+    in_transaction: AtomicBool,
+    /// Whether statements which may write were sent since the last `barrier()`
+    written: AtomicBool,
+    barrier_table: String,
+}
+
+impl<R: Resolver> Discovered<R> {
+    /// Creates a client of the endpoints returned by `resolver`, whose clients are configured
+    /// by `configure` from their URL, e.g. to set their auth token. Endpoints are resolved
+    /// when the first statement is executed, then every 30 seconds.
+    pub fn new(resolver: R, configure: impl Fn(url::Url) -> Result<Config> + 'static) -> Self {
+        Self {
+            resolver,
+            configure: Box::new(configure),
+            refresh_every: Duration::from_secs(30),
+            routes: Mutex::new(None),
+            resolving: Permits::new(1),
+            next_replica: AtomicUsize::new(0),
+            in_transaction: AtomicBool::new(false),
+            written: AtomicBool::new(false),
+            barrier_table: quote_ident(DEFAULT_BARRIER_TABLE),
+        }
+    }
+
+    /// Sets the table holding the marker row of `barrier()`, `_libsql_barrier` by default
+    pub fn barrier_table(mut self, table: &str) -> Self {
+        self.barrier_table = quote_ident(table);
+        self
+    }
+
+    /// Sets how often endpoints are resolved again
+    pub fn refresh_every(mut self, interval: Duration) -> Self {
+        self.refresh_every = interval;
+        self
+    }
+
+    /// Returns the endpoints last resolved, if any
+    pub fn endpoints(&self) -> Option<Endpoints> {
+        self.lock().as_ref().map(|routes| routes.endpoints.clone())
+    }
+
+    /// Resolves the endpoints now, establishing clients for the new ones
+    pub async fn refresh(&self) -> Result<()> {
+        let _resolving = self.resolving.acquire(Priority::High).await;
+        let endpoints = self.resolver.resolve().await?;
+        let known: Vec<(url::Url, Arc<GenericClient>)> = {
+            let mut routes = self.lock();
+            if let Some(routes) = routes.as_mut().filter(|r| r.endpoints == endpoints) {
+                routes.resolved_at = crate::timings::now();
+                return Ok(());
+            }
+            routes.iter().flat_map(Routes::clients).collect()
+        };
+        let routes = self.connect(endpoints, &known).await?;
+        tracing::info!(
+            "Using primary {} and {} replicas",
+            routes.endpoints.primary,
+            routes.replicas.len()
+        );
+        *self.lock() = Some(routes);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Routes>> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Establishes clients for new endpoints, reusing the `known` clients of endpoints
+    /// which did not change. Replicas which cannot be reached are left out until
+    /// the next refresh.
+    async fn connect(
+        &self,
+        endpoints: Endpoints,
+        known: &[(url::Url, Arc<GenericClient>)],
+    ) -> Result<Routes> {
+        // Arc is Send and Sync whenever GenericClient is, which depends on the enabled backends
+        #[allow(clippy::arc_with_non_send_sync)]
+        let client = |url: &url::Url| {
+            let known = known
+                .iter()
+                .find(|(known, _)| known == url)
+                .map(|(_, client)| client.clone());
+            let config = (self.configure)(url.clone());
+            async move {
+                match known {
+                    Some(client) => Ok::<_, anyhow::Error>(client),
+                    None => Ok(Arc::new(crate::new_client_from_config(config?).await?)),
+                }
+            }
+        };
+        let primary = client(&endpoints.primary).await?;
+        let mut connected = Endpoints::new(endpoints.primary.clone());
+        let mut replicas = Vec::with_capacity(endpoints.replicas.len());
+        for url in endpoints.replicas {
+            match client(&url).await {
+                Ok(replica) => {
+                    replicas.push(replica);
+                    connected.replicas.push(url);
+                }
+                Err(e) => tracing::warn!("Failed to connect to replica {url}: {e}"),
+            }
+        }
+        Ok(Routes {
+            endpoints: connected,
+            primary,
+            replicas,
+            resolved_at: crate::timings::now(),
+        })
+    }
+
+    /// Returns the clients of the current endpoints, resolving them first if they are due
+    async fn routes(&self) -> Result<(Arc<GenericClient>, Vec<Arc<GenericClient>>)> {
+        let due = match &*self.lock() {
+            None => true,
+            Some(routes) => match (routes.resolved_at, crate::timings::now()) {
+                (Some(at), Some(now)) => now.duration_since(at) >= self.refresh_every,
+                _ => false,
+            },
+        };
+        if due {
+            if let Err(e) = self.refresh().await {
+                let mut routes = self.lock();
+                let Some(routes) = routes.as_mut() else {
+                    return Err(e);
+                };
+                // Waits for the next refresh instead of failing every statement until then
+                routes.resolved_at = crate::timings::now();
+                tracing::warn!("Failed to resolve endpoints, using the previous ones: {e}");
+            }
+        }
+        let routes = self.lock();
+        let routes = routes
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Endpoints are not resolved"))?;
+        Ok((routes.primary.clone(), routes.replicas.clone()))
+    }
+
+    /// Updates the marker row on the primary, then reads it from each replica until
+    /// it has the new value
+    async fn wait_for_replicas(&self) -> Result<()> {
+        let (primary, _) = self.routes().await?;
+        let replicas: Vec<(url::Url, Arc<GenericClient>)> = match &*self.lock() {
+            Some(routes) => routes.clients().skip(1).collect(),
+            None => Vec::new(),
+        };
+        if replicas.is_empty() {
+            return Ok(());
+        }
+        let table = &self.barrier_table;
+        let marker = primary
+            .batch([
+                format!("CREATE TABLE IF NOT EXISTS {table} (id INTEGER PRIMARY KEY, marker TEXT)"),
+                format!(
+                    "INSERT INTO {table} (id, marker) VALUES (1, hex(randomblob(16))) \
+                     ON CONFLICT (id) DO UPDATE SET marker = excluded.marker RETURNING marker"
+                ),
+            ])
+            .await?
+            .pop()
+            .and_then(|result| result.rows.into_iter().next())
+            .and_then(|row| row.values.into_iter().next());
+        let marker = match marker {
+            Some(Value::Text { value }) => value,
+            marker => anyhow::bail!("Unexpected marker returned by {table}: {marker:?}"),
+        };
+        let query = Statement::with_args(format!("SELECT marker FROM {table} WHERE id = ?"), &[1]);
+        for (url, replica) in replicas {
+            let mut checks = 0;
+            loop {
+                let caught_up = match replica.execute(query.clone()).await {
+                    Ok(result) => matches!(
+                        result.rows.first().and_then(|row| row.values.first()),
+                        Some(Value::Text { value }) if *value == marker
+                    ),
+                    Err(e) if crate::error::is_outage(&e) => {
+                        tracing::warn!("Replica {url} is unreachable, skipping it: {e}");
+                        break;
+                    }
+                    // The table may not be replicated yet
+                    Err(_) => false,
+                };
+                if caught_up {
+                    break;
+                }
+                checks += 1;
+                if checks == BARRIER_CHECKS {
+                    anyhow::bail!("Replica {url} did not catch up with the primary");
+                }
+                #[cfg(feature = "tokio")]
+                tokio::time::sleep(BARRIER_CHECK_INTERVAL).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records whether `stmt` starts or finishes a transaction, returning how it controls it
+    fn track_transaction(&self, stmt: &Statement) -> Option<TransactionControl> {
+        if !crate::sql::is_read_only(&stmt.sql) {
+            self.written.store(true, Ordering::Relaxed);
+        }
+        let control = crate::sql::transaction_control(&stmt.sql);
+        match control {
+            Some(TransactionControl::Begin) => self.in_transaction.store(true, Ordering::Relaxed),
+            Some(TransactionControl::Finish) => self.in_transaction.store(false, Ordering::Relaxed),
+            None => {}
+        }
+        control
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: Resolver> DatabaseClient for Discovered<R> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let (primary, replicas) = self.routes().await?;
+        let control = self.track_transaction(&stmt);
+        let is_read = crate::sql::is_read_only(&stmt.sql)
+            && control.is_none()
+            && !self.in_transaction.load(Ordering::Relaxed);
+        if !is_read || replicas.is_empty() {
+            return primary.execute(stmt).await;
+        }
+        let next = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        let replica = &replicas[next % replicas.len()];
+        match replica.execute(stmt.clone()).await {
+            Err(e) if crate::error::is_outage(&e) => {
+                tracing::warn!("Replica is unreachable, reading from the primary: {e}");
+                primary.execute(stmt).await
+            }
+            result => result,
+        }
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        for stmt in &stmts {
+            self.track_transaction(stmt);
+        }
+        let (primary, _) = self.routes().await?;
+        primary.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let (primary, _) = self.routes().await?;
+        primary.prewarm(stmts).await
+    }
+
+    /// Waits until every replica returns the marker row just updated on the primary,
+    /// skipping replicas which are unreachable, since reads fall back to the primary then.
+    /// Returns right away if nothing was written since the previous barrier, or during
+    /// a transaction, whose statements all go to the primary.
+    async fn barrier(&self) -> Result<()> {
+        if self.in_transaction.load(Ordering::Relaxed)
+            || !self.written.swap(false, Ordering::Relaxed)
+        {
+            return Ok(());
+        }
+        let result = self.wait_for_replicas().await;
+        if result.is_err() {
+            self.written.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.in_transaction.store(false, Ordering::Relaxed);
+        match &*self.lock() {
+            Some(routes) => routes.primary.schedule_rollback(),
+            None => false,
+        }
+    }
+
+    fn stats(&self) -> ClientStats {
+        match &*self.lock() {
+            Some(routes) => routes.primary.stats(),
+            None => ClientStats::default(),
+        }
+    }
+}
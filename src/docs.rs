@@ -0,0 +1,192 @@
+//! `docs` stores serde-serializable documents as JSON in a table of the database,
+//! and queries them by the values at JSON paths, using the JSON1 functions of SQLite.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::docs::Collection;
+//!
+//!   #[derive(serde::Serialize, serde::Deserialize)]
+//!   struct User {
+//!       name: String,
+//!       address: Address,
+//!   }
+//!
+//!   #[derive(serde::Serialize, serde::Deserialize)]
+//!   struct Address {
+//!       city: String,
+//!   }
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let users = Collection::<_, User>::new(&db, "users");
+//!   users.create().await?;
+//!   users.create_index("$.address.city").await?;
+//!   users
+//!       .put("john", &User { name: "John".into(), address: Address { city: "Oslo".into() } })
+//!       .await?;
+//!   for (id, user) in users.query_by_json_path("$.address.city", "Oslo").await? {
+//!       println!("{id}: {}", user.name);
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::marker::PhantomData;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Collection of documents of type `T`, stored in a table
+pub struct Collection<'a, Client: DatabaseClient + ?Sized, T> {
+    client: &'a Client,
+    name: String,
+    table: String,
+    documents: PhantomData<fn() -> T>,
+}
+
+impl<'a, Client, T> Collection<'a, Client, T>
+where
+    Client: DatabaseClient + ?Sized,
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a collection stored in the `table` table
+    pub fn new(client: &'a Client, table: &str) -> Self {
+        Self {
+            client,
+            name: table.to_string(),
+            table: quote_ident(table),
+            documents: PhantomData,
+        }
+    }
+
+    /// Creates the table of the collection, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY, json TEXT NOT NULL CHECK (json_valid(json)), \
+                 updated_at INTEGER NOT NULL)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Creates an index on the values at `path`, if it does not exist yet,
+    /// which speeds up `query_by_json_path()` for this path
+    pub async fn create_index(&self, path: &str) -> Result<()> {
+        let index = format!(
+            "{}_{}",
+            self.name,
+            path.trim_start_matches('$')
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+        );
+        self.client
+            .execute(format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} (json_extract(json, {}))",
+                quote_ident(&index),
+                self.table,
+                path_literal(path)?
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts or replaces the document `id`
+    pub async fn put(&self, id: &str, document: &T) -> Result<()> {
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT OR REPLACE INTO {} (id, json, updated_at) VALUES (?, ?, {NOW_MS})",
+                    self.table
+                ),
+                &[id, serde_json::to_string(document)?.as_str()],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the document `id`, or `None` if it does not exist
+    pub async fn get(&self, id: &str) -> Result<Option<T>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("SELECT id, json FROM {} WHERE id = ?", self.table),
+                &[id],
+            ))
+            .await?;
+        Ok(self.documents(&result)?.pop().map(|(_, document)| document))
+    }
+
+    /// Deletes the document `id`, returning whether it existed
+    pub async fn delete(&self, id: &str) -> Result<bool> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE id = ?", self.table),
+                &[id],
+            ))
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Returns the documents whose value at `path`, e.g. `$.address.city`, equals `value`,
+    /// ordered by id. JSON booleans are compared as integers, and JSON objects and arrays
+    /// as their minified text.
+    pub async fn query_by_json_path(
+        &self,
+        path: &str,
+        value: impl Into<Value>,
+    ) -> Result<Vec<(String, T)>> {
+        // The path is inlined, so that the query can use an index created by `create_index()`
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT id, json FROM {} WHERE json_extract(json, {}) = ? ORDER BY id",
+                    self.table,
+                    path_literal(path)?
+                ),
+                &[value.into()],
+            ))
+            .await?;
+        self.documents(&result)
+    }
+
+    /// Returns all the documents, ordered by id
+    pub async fn all(&self) -> Result<Vec<(String, T)>> {
+        let result = self
+            .client
+            .execute(format!("SELECT id, json FROM {} ORDER BY id", self.table))
+            .await?;
+        self.documents(&result)
+    }
+
+    fn documents(&self, result: &crate::ResultSet) -> Result<Vec<(String, T)>> {
+        result
+            .rows
+            .iter()
+            .map(|row| match &row.values[..] {
+                [Value::Text { value: id }, Value::Text { value: json }] => {
+                    let document = serde_json::from_str(json).map_err(|e| {
+                        anyhow::anyhow!("Invalid document {id} in collection {}: {e}", self.name)
+                    })?;
+                    Ok((id.clone(), document))
+                }
+                values => anyhow::bail!("Invalid document: {values:?}"),
+            })
+            .collect()
+    }
+}
+
+/// Returns `path` as an SQL string literal, after checking that it's a JSON path
+fn path_literal(path: &str) -> Result<String> {
+    if !path.starts_with('$') {
+        anyhow::bail!("Invalid JSON path {path}, expected a path starting with $");
+    }
+    Ok(format!("'{}'", path.replace('\'', "''")))
+}
@@ -0,0 +1,181 @@
+//! `Drainable` lets a service finish its work before shutting down, e.g. during a rolling
+//! deploy. It is only available with the `tokio` feature, which provides the timer.
+//!
+//! `Drainable::drain()` stops accepting new statements, which fail from then on, and waits
+//! until the statements in flight complete and the transaction in progress, if any, is
+//! committed or rolled back. Statements of that transaction are still accepted, so that it
+//! can finish. Once the timeout elapses, the transaction is rolled back, and `drain()` returns
+//! without waiting for the statements still in flight. `Drainable::close()` then shuts down
+//! the connections of the client.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::drain::Drainable;
+//!   use std::time::Duration;
+//!
+//!   let db = Drainable::new(libsql_client::new_client().await?);
+//!   db.execute("SELECT 1").await?;
+//!   // Once the service was asked to stop, e.g. on SIGTERM
+//!   db.close(Duration::from_secs(10)).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::client::GenericClient;
+use crate::sql::TransactionControl;
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// How often `drain()` checks if the work in progress completed
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Client wrapper which can stop accepting statements and wait for the work in progress
+pub struct Drainable<Client: DatabaseClient> {
+    inner: Client,
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    /// Whether a transaction was started and not finished yet
+    in_transaction: AtomicBool,
+}
+
+/// Counts a request in flight until it is dropped
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<Client: DatabaseClient> Drainable<Client> {
+    /// Wraps a client, accepting statements until it is drained
+    pub fn new(inner: Client) -> Self {
+        Self {
+            inner,
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            in_transaction: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+
+    /// Checks if the client stopped accepting new statements
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting new statements and waits for the work in progress to complete,
+    /// for at most `timeout`. Returns false if the timeout elapsed, in which case the
+    /// transaction in progress, if any, was rolled back.
+    pub async fn drain(&self, timeout: Duration) -> Result<bool> {
+        self.draining.store(true, Ordering::Relaxed);
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.in_flight() > 0 || self.in_transaction.load(Ordering::Relaxed) {
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!(
+                    "Drain timed out after {timeout:?} with {} requests in flight",
+                    self.in_flight()
+                );
+                if self.in_transaction.swap(false, Ordering::Relaxed) {
+                    self.inner.execute("ROLLBACK").await?;
+                }
+                return Ok(false);
+            }
+            tokio::time::sleep(POLL_INTERVAL.min(deadline - tokio::time::Instant::now())).await;
+        }
+        Ok(true)
+    }
+
+    /// Accepts a request unless the client is draining, counting it until the returned
+    /// guard is dropped. Statements of the transaction in progress are always accepted.
+    fn admit(&self, stmts: &[Statement]) -> Result<InFlight<'_>> {
+        if self.is_draining() && !self.in_transaction.load(Ordering::Relaxed) {
+            anyhow::bail!("Client is draining and does not accept new statements");
+        }
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        for stmt in stmts {
+            match crate::sql::transaction_control(&stmt.sql) {
+                Some(TransactionControl::Begin) => {
+                    self.in_transaction.store(true, Ordering::Relaxed)
+                }
+                Some(TransactionControl::Finish) => {
+                    self.in_transaction.store(false, Ordering::Relaxed)
+                }
+                None => {}
+            }
+        }
+        Ok(InFlight(&self.in_flight))
+    }
+}
+
+impl Drainable<GenericClient> {
+    /// Drains the client for at most `timeout`, then shuts down its connections
+    pub async fn close(self, timeout: Duration) -> Result<()> {
+        self.drain(timeout).await?;
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for Drainable<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let _in_flight = self.admit(std::slice::from_ref(&stmt))?;
+        self.inner.execute(stmt).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        let _in_flight = self.admit(&stmts)?;
+        self.inner.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let _in_flight = self.admit(&[])?;
+        self.inner.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.in_transaction.store(false, Ordering::Relaxed);
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
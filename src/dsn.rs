@@ -0,0 +1,236 @@
+//! `dsn` parses connection strings which carry the whole configuration of a client,
+//! so that it can be passed around as a single environment variable or CLI argument.
+//!
+//! A DSN is a database URL whose query parameters set the options of `Config`:
+//!
+//! | Option | Values | Sets |
+//! |---|---|---|
+//! | `authToken` (or `auth_token`, `token`) | any | `Config::with_auth_token()` |
+//! | `tls` | `0`, `1`, `false`, `true` | whether `libsql://` connects over `wss://` or `ws://` |
+//! | `connect_timeout` | `500ms`, `5s`, `2m`, `1h`, or a number of seconds | `Config::connect_timeout()` |
+//! | `read_timeout` | `500ms`, `5s`, `2m`, `1h`, or a number of seconds | `Config::read_timeout()` |
+//! | `dns_refresh` | `500ms`, `5s`, `2m`, `1h`, or a number of seconds | `Config::dns_refresh()` |
+//! | `mode` | `ro`, `rw` | `Config::read_only()` |
+//! | `mode` in `file:` URLs | `ro`, `rw`, `rwc`, `memory` | how the local database is opened |
+//! | `foreign_keys` | `0`, `1`, `false`, `true` | `Config::foreign_keys()` |
+//! | `validate_batches` | `0`, `1`, `false`, `true` | `Config::validate_batches()` |
+//! | `collect_timings` | `0`, `1`, `false`, `true` | `Config::collect_timings()` |
+//!
+//! ```rust
+//!   # fn f() -> anyhow::Result<()> {
+//!   use libsql_client::Config;
+//!
+//!   let config = Config::from_dsn(
+//!       "libsql://localhost:8080/analytics?authToken=secret&tls=0&connect_timeout=5s&mode=ro",
+//!   )?;
+//!   assert_eq!(config.url.as_str(), "ws://localhost:8080/analytics");
+//!   assert_eq!(config.auth_token.as_deref(), Some("secret"));
+//!   assert!(config.read_only);
+//!
+//!   // Local databases keep their open mode in the URL
+//!   let config = Config::from_dsn("file:/tmp/example.db?mode=rwc&foreign_keys=1")?;
+//!   assert_eq!(config.url.as_str(), "file:///tmp/example.db?mode=rwc");
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::Config;
+
+/// Reason why a connection string was rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DsnError {
+    /// The connection string is not a valid URL
+    InvalidUrl(String),
+    /// The URL carries a username or password, which cannot be represented by `Config`
+    Credentials,
+    /// A query parameter is not a known option
+    UnknownOption(String),
+    /// An option is set more than once, possibly through different aliases
+    DuplicateOption(String),
+    /// An option is set to a value it does not accept
+    InvalidValue {
+        option: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// An option contradicts the URL, e.g. `tls=0` with `https://`
+    Conflict(String),
+}
+
+impl std::fmt::Display for DsnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(e) => write!(f, "Invalid connection string: {e}"),
+            Self::Credentials => write!(
+                f,
+                "Credentials in the connection string are not supported, use authToken instead"
+            ),
+            Self::UnknownOption(option) => write!(f, "Unknown connection option: {option}"),
+            Self::DuplicateOption(option) => {
+                write!(f, "Connection option set more than once: {option}")
+            }
+            Self::InvalidValue {
+                option,
+                value,
+                expected,
+            } => write!(
+                f,
+                "Invalid value for connection option {option}: {value:?}, expected {expected}"
+            ),
+            Self::Conflict(reason) => write!(f, "Conflicting connection options: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DsnError {}
+
+impl Config {
+    /// Creates a configuration from a connection string.
+    /// See the `dsn` module for the supported options.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::Config;
+    /// use libsql_client::dsn::DsnError;
+    ///
+    /// let err = Config::from_dsn("libsql://localhost:8080?mode=rx").err().unwrap();
+    /// assert!(matches!(err, DsnError::InvalidValue { .. }));
+    /// ```
+    pub fn from_dsn(dsn: &str) -> Result<Config, DsnError> {
+        let mut url = url::Url::parse(dsn).map_err(|e| DsnError::InvalidUrl(e.to_string()))?;
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(DsnError::Credentials);
+        }
+
+        let scheme = url.scheme().to_string();
+        let mut seen = HashSet::new();
+        let mut kept = Vec::new();
+        let mut auth_token = None;
+        let mut tls = None;
+        let mut connect_timeout = None;
+        let mut read_timeout = None;
+        let mut dns_refresh = None;
+        let mut read_only = false;
+        let mut foreign_keys = None;
+        let mut validate_batches = false;
+        let mut collect_timings = false;
+        for (name, value) in url.query_pairs().into_owned() {
+            let option = match name.as_str() {
+                "authToken" | "auth_token" | "token" => "authToken",
+                "tls" => "tls",
+                "connect_timeout" => "connect_timeout",
+                "read_timeout" => "read_timeout",
+                "dns_refresh" => "dns_refresh",
+                "mode" => "mode",
+                "foreign_keys" => "foreign_keys",
+                "validate_batches" => "validate_batches",
+                "collect_timings" => "collect_timings",
+                _ => return Err(DsnError::UnknownOption(name)),
+            };
+            if !seen.insert(option) {
+                return Err(DsnError::DuplicateOption(name));
+            }
+            match option {
+                "authToken" => auth_token = Some(value),
+                "tls" => tls = Some(parse_bool(name, value)?),
+                "connect_timeout" => connect_timeout = Some(parse_duration(name, value)?),
+                "read_timeout" => read_timeout = Some(parse_duration(name, value)?),
+                "dns_refresh" => dns_refresh = Some(parse_duration(name, value)?),
+                "mode" if scheme == "file" => match value.as_str() {
+                    "ro" | "rw" | "rwc" | "memory" => kept.push((name, value)),
+                    _ => return Err(invalid_value(name, value, "`ro`, `rw`, `rwc` or `memory`")),
+                },
+                "mode" => {
+                    read_only = match value.as_str() {
+                        "ro" => true,
+                        "rw" => false,
+                        _ => return Err(invalid_value(name, value, "`ro` or `rw`")),
+                    }
+                }
+                "foreign_keys" => foreign_keys = Some(parse_bool(name, value)?),
+                "validate_batches" => validate_batches = parse_bool(name, value)?,
+                _ => collect_timings = parse_bool(name, value)?,
+            }
+        }
+        url.set_query(None);
+        if !kept.is_empty() {
+            url.query_pairs_mut().extend_pairs(kept);
+        }
+
+        let url = match (scheme.as_str(), tls) {
+            (_, None) | ("libsql", Some(true)) => url,
+            // url::Url::set_scheme() cannot turn a non-special scheme into ws
+            ("libsql", Some(false)) => {
+                url::Url::parse(&url.as_str().replacen("libsql://", "ws://", 1))
+                    .map_err(|e| DsnError::InvalidUrl(e.to_string()))?
+            }
+            ("https" | "wss", Some(true)) | ("http" | "ws", Some(false)) => url,
+            (scheme, Some(tls)) => {
+                return Err(DsnError::Conflict(format!(
+                    "tls={} cannot be used with {scheme}://",
+                    tls as u8
+                )))
+            }
+        };
+
+        let mut config = Config::new(url).map_err(|e| DsnError::InvalidUrl(e.to_string()))?;
+        config.auth_token = auth_token;
+        config.connect_timeout = connect_timeout;
+        config.read_timeout = read_timeout;
+        config.dns_refresh = dns_refresh;
+        config.read_only = read_only;
+        config.foreign_keys = foreign_keys;
+        config.validate_batches = validate_batches;
+        config.collect_timings = collect_timings;
+        Ok(config)
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = DsnError;
+
+    fn from_str(dsn: &str) -> Result<Self, Self::Err> {
+        Config::from_dsn(dsn)
+    }
+}
+
+fn invalid_value(option: String, value: String, expected: &'static str) -> DsnError {
+    DsnError::InvalidValue {
+        option,
+        value,
+        expected,
+    }
+}
+
+fn parse_bool(option: String, value: String) -> Result<bool, DsnError> {
+    match value.as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(invalid_value(option, value, "`0`, `1`, `false` or `true`")),
+    }
+}
+
+/// Parses durations like `500ms`, `5s`, `2m` or `1h`. A bare number is a number of seconds.
+fn parse_duration(option: String, value: String) -> Result<Duration, DsnError> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "" | "s" => Some(1.0),
+        "ms" => Some(0.001),
+        "m" => Some(60.0),
+        "h" => Some(3600.0),
+        _ => None,
+    };
+    match (number.parse::<f64>(), scale) {
+        (Ok(number), Some(scale)) => Duration::try_from_secs_f64(number * scale).ok(),
+        _ => None,
+    }
+    .ok_or_else(|| invalid_value(option, value, "a duration like `500ms`, `5s` or `2m`"))
+}
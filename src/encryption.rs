@@ -0,0 +1,551 @@
+//! `encryption` encrypts designated columns client-side, e.g. PII fields in a database
+//! shared with other services, so that the server only ever stores their ciphertext.
+//! It is only available with the `encryption` feature.
+//!
+//! `ColumnCipher` is the codec: it encrypts a value with AES-256-GCM into a blob holding
+//! the id of the key, a random nonce and the ciphertext, bound to the name of its column,
+//! and decrypts it back into a value of its original type. `NULL` is not encrypted.
+//! Keys come from a `KeyProvider`, so that they can be loaded from a secret manager
+//! and rotated: new values are encrypted with the current key, and values encrypted
+//! with previous keys are still decrypted.
+//!
+//! `Encrypted` is the interceptor: a client wrapper encrypting the arguments bound to the
+//! designated columns, in `INSERT ... (columns) VALUES (?, ...)` and `UPDATE ... SET column = ?`
+//! statements, and decrypting the values of result columns named after them. Since a value
+//! encrypts differently every time, statements comparing an encrypted column in SQL,
+//! e.g. `WHERE email = ?`, are rejected, as they could never match. Columns selected
+//! under another name, e.g. `SELECT email AS contact`, are not decrypted, and can be
+//! decrypted with `ColumnCipher::decrypt()`. Likewise, arguments are only encrypted when
+//! the statement names their column: `INSERT INTO users VALUES (?, ?)` is sent as is.
+//!
+//! ```rust,no_run
+//!   # async fn f(key: [u8; 32]) -> anyhow::Result<()> {
+//!   # use libsql_client::{args, DatabaseClient, Statement};
+//!   use libsql_client::encryption::{ColumnCipher, Encrypted, StaticKeys};
+//!
+//!   let cipher = ColumnCipher::new(StaticKeys::new(1, key));
+//!   let db = Encrypted::new(libsql_client::new_client().await?, cipher)
+//!       .column("email")
+//!       .column("phone");
+//!   db.execute(Statement::with_args(
+//!       "INSERT INTO users (id, email, phone) VALUES (?, ?, ?)",
+//!       args!(1, "ann@example.com", "+33 6 12 34 56 78"),
+//!   ))
+//!   .await?;
+//!   let users = db.execute("SELECT id, email FROM users").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::HashMap;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use crate::sql::{tokenize, unquote, Token};
+use crate::{proto, BatchResult, ClientStats, DatabaseClient, ResultSet, Statement, Value};
+
+/// Version of the layout of encrypted values
+const VERSION: u8 = 1;
+
+/// Length of the header of encrypted values: version, key id and nonce
+const HEADER_LEN: usize = 1 + 4 + 12;
+
+/// Source of the keys encrypting columns
+pub trait KeyProvider {
+    /// Returns the id and the bytes of the key encrypting new values
+    fn current_key(&self) -> Result<(u32, [u8; 32])>;
+
+    /// Returns the key with the given id, to decrypt the values encrypted with it
+    fn key(&self, id: u32) -> Result<[u8; 32]>;
+}
+
+/// Keys known in advance: the current one, and previous ones after a rotation
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: u32,
+    keys: HashMap<u32, [u8; 32]>,
+}
+
+impl StaticKeys {
+    /// Uses a single key, identified by `id`
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        Self {
+            current: id,
+            keys: HashMap::from([(id, key)]),
+        }
+    }
+
+    /// Adds a previous key, which only decrypts the values encrypted with it
+    pub fn previous(mut self, id: u32, key: [u8; 32]) -> Self {
+        self.keys.entry(id).or_insert(key);
+        self
+    }
+}
+
+impl std::fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keys are never printed
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current)
+            .field("ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key(&self) -> Result<(u32, [u8; 32])> {
+        Ok((self.current, self.key(self.current)?))
+    }
+
+    fn key(&self, id: u32) -> Result<[u8; 32]> {
+        self.keys
+            .get(&id)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown encryption key {id}"))
+    }
+}
+
+/// Codec encrypting and decrypting the values of columns
+pub struct ColumnCipher<Keys: KeyProvider> {
+    keys: Keys,
+}
+
+impl<Keys: KeyProvider> ColumnCipher<Keys> {
+    /// Creates a codec with keys from `keys`
+    pub fn new(keys: Keys) -> Self {
+        Self { keys }
+    }
+
+    /// Encrypts a value of a column into a blob. `NULL` is returned as is.
+    pub fn encrypt(&self, column: &str, value: &Value) -> Result<Value> {
+        let (tag, bytes) = match value {
+            Value::Null => return Ok(Value::Null),
+            Value::Integer { value } => (1, value.to_be_bytes().to_vec()),
+            Value::Float { value } => (2, value.to_bits().to_be_bytes().to_vec()),
+            Value::Text { value } => (3, value.as_bytes().to_vec()),
+            Value::Blob { value } => (4, value.clone()),
+        };
+        let plaintext = [vec![tag], bytes].concat();
+        let (id, key) = self.keys.current_key()?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, payload(&aad(column), &plaintext))
+            .map_err(|_| anyhow!("Failed to encrypt a value of column {column}"))?;
+        let mut encrypted = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        encrypted.push(VERSION);
+        encrypted.extend_from_slice(&id.to_be_bytes());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(Value::Blob { value: encrypted })
+    }
+
+    /// Decrypts a value of a column encrypted by `encrypt()`. `NULL` is returned as is,
+    /// and any other value which is not an encrypted blob is an error.
+    pub fn decrypt(&self, column: &str, value: &Value) -> Result<Value> {
+        let encrypted = match value {
+            Value::Null => return Ok(Value::Null),
+            Value::Blob { value } if value.len() > HEADER_LEN && value[0] == VERSION => value,
+            value => anyhow::bail!(
+                "Column {column} holds a {} value which is not encrypted",
+                crate::de::type_name(value)
+            ),
+        };
+        let id = u32::from_be_bytes([encrypted[1], encrypted[2], encrypted[3], encrypted[4]]);
+        let key = self.keys.key(id)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let nonce = Nonce::from_slice(&encrypted[5..HEADER_LEN]);
+        let plaintext = cipher
+            .decrypt(nonce, payload(&aad(column), &encrypted[HEADER_LEN..]))
+            .map_err(|_| {
+                anyhow!("Failed to decrypt a value of column {column}: wrong key or tampered value")
+            })?;
+        let corrupted = || anyhow!("Decrypted value of column {column} is corrupted");
+        let (tag, bytes) = plaintext.split_first().ok_or_else(corrupted)?;
+        let value = match tag {
+            1 => Value::Integer {
+                value: i64::from_be_bytes(bytes.try_into().map_err(|_| corrupted())?),
+            },
+            2 => Value::Float {
+                value: f64::from_bits(u64::from_be_bytes(
+                    bytes.try_into().map_err(|_| corrupted())?,
+                )),
+            },
+            3 => Value::Text {
+                value: String::from_utf8(bytes.to_vec()).map_err(|_| corrupted())?,
+            },
+            4 => Value::Blob {
+                value: bytes.to_vec(),
+            },
+            _ => return Err(corrupted()),
+        };
+        Ok(value)
+    }
+}
+
+/// Binds a ciphertext to its column, so that it cannot be moved to another column
+fn aad(column: &str) -> String {
+    column.to_ascii_lowercase()
+}
+
+fn payload<'a>(aad: &'a str, msg: &'a [u8]) -> Payload<'a, 'a> {
+    Payload {
+        msg,
+        aad: aad.as_bytes(),
+    }
+}
+
+/// Client wrapper encrypting the arguments bound to designated columns,
+/// and decrypting the result columns named after them
+pub struct Encrypted<Client: DatabaseClient, Keys: KeyProvider> {
+    inner: Client,
+    cipher: ColumnCipher<Keys>,
+    columns: Vec<String>,
+}
+
+impl<Client: DatabaseClient, Keys: KeyProvider> Encrypted<Client, Keys> {
+    /// Wraps a client, without any encrypted column yet
+    pub fn new(inner: Client, cipher: ColumnCipher<Keys>) -> Self {
+        Self {
+            inner,
+            cipher,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Designates a column to encrypt, by its name in every table
+    pub fn column(mut self, name: impl Into<String>) -> Self {
+        self.columns.push(name.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Returns the codec encrypting the columns
+    pub fn cipher(&self) -> &ColumnCipher<Keys> {
+        &self.cipher
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+
+    fn encrypted_column(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.columns
+            .iter()
+            .find(|column| **column == name)
+            .map(String::as_str)
+    }
+
+    /// Encrypts the arguments of a statement which are bound to encrypted columns
+    fn encrypt(&self, mut stmt: Statement) -> Result<Statement> {
+        for (index, column) in bound_columns(&stmt.sql, |name| self.encrypted_column(name))? {
+            let Some(arg) = stmt.args.get_mut(index) else {
+                anyhow::bail!(
+                    "Missing argument for encrypted column {column}: {}",
+                    stmt.sql
+                );
+            };
+            *arg = self.cipher.encrypt(column, arg)?;
+        }
+        Ok(stmt)
+    }
+
+    /// Decrypts the values of the result columns named after encrypted columns
+    fn decrypt_rows(&self, columns: &[String], rows: &mut [Vec<Value>]) -> Result<()> {
+        for (index, name) in columns.iter().enumerate() {
+            let Some(column) = self.encrypted_column(name) else {
+                continue;
+            };
+            for row in rows.iter_mut() {
+                row[index] = self.cipher.decrypt(column, &row[index])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decrypt_result(&self, result: proto::StmtResult) -> Result<proto::StmtResult> {
+        let mut result = result;
+        let columns: Vec<String> = result
+            .cols
+            .iter()
+            .map(|c| c.name.clone().unwrap_or_default())
+            .collect();
+        self.decrypt_rows(&columns, &mut result.rows)?;
+        Ok(result)
+    }
+}
+
+/// Finds the positional arguments bound to encrypted columns, either as values of an
+/// `INSERT` or as assignments of an `UPDATE`, and rejects statements comparing them
+fn bound_columns<'c>(
+    sql: &str,
+    encrypted: impl Fn(&str) -> Option<&'c str>,
+) -> Result<Vec<(usize, &'c str)>> {
+    let tokens = tokenize(sql);
+    let ident = |token: &Token| match token {
+        Token::Word(w) => Some(w.to_string()),
+        Token::QuotedIdent(q) => Some(unquote(q)),
+        _ => None,
+    };
+    if !tokens
+        .iter()
+        .any(|token| ident(token).is_some_and(|name| encrypted(&name).is_some()))
+    {
+        return Ok(Vec::new());
+    }
+    if let Some(param) = tokens
+        .iter()
+        .find(|token| matches!(token, Token::Param(p) if *p != "?"))
+    {
+        anyhow::bail!(
+            "Statements using encrypted columns only support ? parameters, not {}",
+            param.text()
+        );
+    }
+    // Positions of the parameters among the arguments, by token index
+    let mut ordinals = HashMap::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if matches!(token, Token::Param(_)) {
+            ordinals.insert(i, ordinals.len());
+        }
+    }
+
+    let mut bound = Vec::new();
+    // Tokens of the encrypted columns which are assigned rather than compared
+    let mut assigned = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth = depth.saturating_sub(1),
+            Token::Word(w) if w.eq_ignore_ascii_case("INTO") => {
+                // INTO [schema.]table (columns) VALUES (values), ...
+                let mut j = i + 2;
+                if matches!(tokens.get(j), Some(Token::Punct("."))) {
+                    j += 2;
+                }
+                if matches!(tokens.get(j), Some(Token::Punct("("))) {
+                    let mut columns = Vec::new();
+                    j += 1;
+                    while let Some(token) = tokens.get(j) {
+                        match token {
+                            Token::Punct(")") => break,
+                            Token::Punct(",") => {}
+                            token => {
+                                if ident(token).is_some_and(|name| encrypted(&name).is_some()) {
+                                    assigned.push(j);
+                                }
+                                columns.push(ident(token));
+                            }
+                        }
+                        j += 1;
+                    }
+                    let values = tokens.get(j + 1).map(Token::text);
+                    if values.is_some_and(|w| w.eq_ignore_ascii_case("VALUES")) {
+                        j = bind_values(
+                            &tokens,
+                            j + 2,
+                            &columns,
+                            &ordinals,
+                            &encrypted,
+                            &mut bound,
+                        );
+                    }
+                    i = j;
+                    continue;
+                }
+            }
+            Token::Word(w) if w.eq_ignore_ascii_case("SET") && depth == 0 => {
+                // SET column = value, ... up to the next clause
+                let mut j = i + 1;
+                while j < tokens.len() {
+                    let end = assignment_end(&tokens, j);
+                    let column = ident(&tokens[j]).and_then(|name| encrypted(&name));
+                    if let (Some(column), Some(Token::Punct("="))) = (column, tokens.get(j + 1)) {
+                        assigned.push(j);
+                        let value = &tokens[j + 2..end];
+                        match value {
+                            [Token::Param(_)] => bound.push((ordinals[&(j + 2)], column)),
+                            value if value.iter().any(|t| matches!(t, Token::Param(_))) => {
+                                anyhow::bail!(
+                                    "Encrypted column {column} can only be assigned a ? parameter \
+                                     as a whole, not within an expression"
+                                )
+                            }
+                            // e.g. NULL or excluded.column, which are already encrypted
+                            _ => {}
+                        }
+                    }
+                    j = end;
+                    if !matches!(tokens.get(j), Some(Token::Punct(","))) {
+                        break;
+                    }
+                    j += 1;
+                }
+                i = j;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    for (i, token) in tokens.iter().enumerate() {
+        let Some(column) = ident(token).and_then(|name| encrypted(&name)) else {
+            continue;
+        };
+        let compared = match tokens.get(i + 1) {
+            Some(Token::Punct(op)) => ["=", "==", "!=", "<>", "<", "<=", ">", ">="].contains(op),
+            Some(Token::Word(w)) => ["LIKE", "GLOB", "IN", "BETWEEN", "MATCH", "REGEXP"]
+                .iter()
+                .any(|op| w.eq_ignore_ascii_case(op)),
+            _ => false,
+        };
+        if compared && !assigned.contains(&i) {
+            anyhow::bail!("Encrypted column {column} cannot be compared in SQL");
+        }
+    }
+    Ok(bound)
+}
+
+/// Clauses which may follow the `SET` clause of an `UPDATE`
+const CLAUSES: [&str; 5] = ["WHERE", "FROM", "RETURNING", "ORDER", "LIMIT"];
+
+/// Returns the index of the token ending the assignment of a `SET` clause starting at `start`
+fn assignment_end(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0usize;
+    for (j, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") if depth == 0 => return j,
+            Token::Punct(")") => depth -= 1,
+            Token::Punct("," | ";") if depth == 0 => return j,
+            Token::Word(w)
+                if depth == 0 && CLAUSES.iter().any(|clause| w.eq_ignore_ascii_case(clause)) =>
+            {
+                return j
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// Binds the parameters of `VALUES` tuples to the columns at the same position,
+/// returning the index of the token following the tuples
+fn bind_values<'c>(
+    tokens: &[Token],
+    mut j: usize,
+    columns: &[Option<String>],
+    ordinals: &HashMap<usize, usize>,
+    encrypted: &impl Fn(&str) -> Option<&'c str>,
+    bound: &mut Vec<(usize, &'c str)>,
+) -> usize {
+    while matches!(tokens.get(j), Some(Token::Punct("("))) {
+        let mut depth = 0usize;
+        let mut position = 0;
+        let mut element_start = j + 1;
+        while let Some(token) = tokens.get(j) {
+            match token {
+                Token::Punct("(") => depth += 1,
+                Token::Punct(")") | Token::Punct(",") if depth == 1 => {
+                    let column = columns.get(position).cloned().flatten();
+                    if let Some(column) = column.as_deref().and_then(encrypted) {
+                        if let (1, Some(ordinal)) =
+                            (j - element_start, ordinals.get(&element_start))
+                        {
+                            bound.push((*ordinal, column));
+                        }
+                    }
+                    position += 1;
+                    element_start = j + 1;
+                    if matches!(token, Token::Punct(")")) {
+                        j += 1;
+                        break;
+                    }
+                }
+                Token::Punct(")") => depth -= 1,
+                _ => {}
+            }
+            j += 1;
+        }
+        if !matches!(tokens.get(j), Some(Token::Punct(","))) {
+            break;
+        }
+        j += 1;
+    }
+    j
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient, Keys: KeyProvider> DatabaseClient for Encrypted<Client, Keys> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt = self.encrypt(stmt.into())?;
+        let mut result = self.inner.execute(stmt).await?;
+        let mut rows: Vec<Vec<Value>> = result
+            .rows
+            .iter_mut()
+            .map(|row| std::mem::take(&mut row.values))
+            .collect();
+        self.decrypt_rows(&result.columns, &mut rows)?;
+        for (row, values) in result.rows.iter_mut().zip(rows) {
+            #[cfg(feature = "mapping_names_to_values_in_rows")]
+            for (column, value) in result.columns.iter().zip(&values) {
+                row.value_map.insert(column.clone(), value.clone());
+            }
+            row.values = values;
+        }
+        Ok(result)
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts = stmts
+            .into_iter()
+            .map(|stmt| self.encrypt(stmt.into()))
+            .collect::<Result<Vec<Statement>>>()?;
+        let mut result = self.inner.raw_batch(stmts).await?;
+        result.step_results = result
+            .step_results
+            .into_iter()
+            .map(|step| step.map(|step| self.decrypt_result(step)).transpose())
+            .collect::<Result<_>>()?;
+        Ok(result)
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.inner.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
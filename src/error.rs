@@ -0,0 +1,221 @@
+//! `Error` represents typed failures reported by the database server.
+//!
+//! Client methods return `anyhow::Result`, so the typed error can be
+//! recovered with `downcast_ref`:
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   let db = libsql_client::new_client().await?;
+//!   if let Err(e) = db.execute("SELECT 1").await {
+//!       match e.downcast_ref::<libsql_client::Error>() {
+//!           Some(libsql_client::Error::Unauthorized(_)) => println!("check your token"),
+//!           _ => return Err(e),
+//!       }
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+/// Details of an unsuccessful HTTP response
+#[derive(Clone, Debug)]
+pub struct HttpError {
+    /// HTTP status code
+    pub status: u16,
+    /// Error message reported by the server, or the raw response body
+    pub message: String,
+    /// Machine-readable error code, if the server reported one
+    pub code: Option<String>,
+    /// How long the server asked to wait before retrying, from the `Retry-After` header
+    pub retry_after: Option<std::time::Duration>,
+}
+
+/// Typed error reported by the database server
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Authentication or authorization failed (HTTP 401 or 403)
+    Unauthorized(HttpError),
+    /// The server is rate limiting requests (HTTP 429)
+    RateLimited(HttpError),
+    /// The server failed to process the request (HTTP 5xx)
+    Server(HttpError),
+    /// Any other unsuccessful HTTP response
+    Http(HttpError),
+}
+
+impl Error {
+    /// Builds an error from the status and body of an HTTP response.
+    /// sqld reports errors as JSON objects with a `message` (or legacy `error`)
+    /// field and an optional `code` - anything else is kept as a raw message.
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn from_http_response(status: u16, body: &str) -> Self {
+        let json: Option<serde_json::Value> = serde_json::from_str(body).ok();
+        let field = |name: &str| {
+            json.as_ref()
+                .and_then(|json| json.get(name))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())
+        };
+        let message = field("message")
+            .or_else(|| field("error"))
+            .unwrap_or_else(|| {
+                if body.is_empty() {
+                    format!("HTTP status {status}")
+                } else {
+                    body.to_string()
+                }
+            });
+        let details = HttpError {
+            status,
+            message,
+            code: field("code"),
+            retry_after: None,
+        };
+        match status {
+            401 | 403 => Self::Unauthorized(details),
+            429 => Self::RateLimited(details),
+            500..=599 => Self::Server(details),
+            _ => Self::Http(details),
+        }
+    }
+
+    /// Attaches the delay requested by the server via the `Retry-After` header
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn with_retry_after(mut self, retry_after: Option<&str>) -> Self {
+        if let Some(http) = self.http_mut() {
+            http.retry_after = retry_after.and_then(crate::retry::parse_retry_after);
+        }
+        self
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    fn http_mut(&mut self) -> Option<&mut HttpError> {
+        match self {
+            Self::Unauthorized(e) | Self::RateLimited(e) | Self::Server(e) | Self::Http(e) => {
+                Some(e)
+            }
+        }
+    }
+
+    /// Returns the HTTP response details, if the error originates from one
+    pub fn http(&self) -> Option<&HttpError> {
+        match self {
+            Self::Unauthorized(e) | Self::RateLimited(e) | Self::Server(e) | Self::Http(e) => {
+                Some(e)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, e) = match self {
+            Self::Unauthorized(e) => ("Unauthorized", e),
+            Self::RateLimited(e) => ("Rate limited", e),
+            Self::Server(e) => ("Server error", e),
+            Self::Http(e) => ("HTTP error", e),
+        };
+        write!(f, "{kind} (HTTP {}): {}", e.status, e.message)?;
+        if let Some(code) = &e.code {
+            write!(f, " [{code}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Error returned when a backend rejects a feature on purpose,
+/// e.g. interactive transactions over HTTP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsupported {
+    message: String,
+}
+
+impl Unsupported {
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Checks if an error message reports that the schema changed since the statement
+/// was prepared (`SQLITE_SCHEMA`), in which case it can be safely retried
+#[cfg_attr(not(feature = "hrana_backend"), allow(dead_code))]
+pub(crate) fn is_schema_change(message: &str) -> bool {
+    message.contains("SQLITE_SCHEMA") || message.contains("database schema has changed")
+}
+
+/// Checks if an error means the server could not be reached or failed to process the request,
+/// e.g. a connection failure, a timeout or an HTTP 5xx status, rather than an error
+/// reported by the database for the statement itself
+pub(crate) fn is_outage(error: &anyhow::Error) -> bool {
+    if let Some(Error::Server(_)) = error.downcast_ref::<Error>() {
+        return true;
+    }
+    if error
+        .chain()
+        .any(|cause| cause.downcast_ref::<std::io::Error>().is_some())
+    {
+        return true;
+    }
+    let message = error.to_string().to_lowercase();
+    [
+        "connection",
+        "timed out",
+        "unreachable",
+        "dns error",
+        "no response",
+        "broken pipe",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Checks if an error message reports that the database is locked by another connection
+/// (`SQLITE_BUSY` or `SQLITE_LOCKED`), in which case the statement was not executed
+pub(crate) fn is_busy(message: &str) -> bool {
+    message.contains("SQLITE_BUSY")
+        || message.contains("SQLITE_LOCKED")
+        || message.contains("database is locked")
+        || message.contains("database table is locked")
+}
@@ -0,0 +1,158 @@
+//! `ClientFactory` caches database clients of multiple tenants.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use crate::client::{Config, GenericClient};
+
+/// Identity of a tenant: the database URL and the auth token
+type TenantKey = (String, Option<String>);
+
+struct CachedClient {
+    key: TenantKey,
+    client: Arc<GenericClient>,
+    last_used: u64,
+}
+
+/// Factory which caches a client per tenant, identified by its database URL and auth token.
+/// At most `max_clients` clients are kept, and the least recently used one is evicted
+/// and shut down once the limit is exceeded. An evicted client which is still in use
+/// is closed when its last reference is dropped.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::{ClientFactory, Config, DatabaseClient};
+///   let factory = ClientFactory::new(100);
+///   let config = Config::new("libsql://tenant-42.turso.io")?.with_auth_token("<token>");
+///   let db = factory.get(config).await?;
+///   db.execute("SELECT 1").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct ClientFactory {
+    max_clients: usize,
+    clients: Mutex<Vec<CachedClient>>,
+    clock: std::sync::atomic::AtomicU64,
+}
+
+impl ClientFactory {
+    /// Creates a factory caching at most `max_clients` clients
+    pub fn new(max_clients: usize) -> Self {
+        Self {
+            max_clients: max_clients.max(1),
+            clients: Mutex::new(Vec::new()),
+            clock: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached client of the tenant described by `config`,
+    /// or establishes a new one with `new_client_from_config()`.
+    /// Settings other than the URL and the auth token only apply to new clients.
+    pub async fn get(&self, config: Config) -> Result<Arc<GenericClient>> {
+        let key: TenantKey = (config.url.to_string(), config.auth_token.clone());
+        if let Some(client) = self.lookup(&key) {
+            return Ok(client);
+        }
+        // Connecting may take a while, so the cache is not locked in the meantime
+        // Arc is Send and Sync whenever GenericClient is, which depends on the enabled backends
+        #[allow(clippy::arc_with_non_send_sync)]
+        let client = Arc::new(crate::new_client_from_config(config).await?);
+        let (client, evicted) = self.insert(key, client);
+        for evicted in evicted {
+            Self::close(evicted).await;
+        }
+        Ok(client)
+    }
+
+    /// Removes the client of a tenant from the cache and shuts it down
+    pub async fn evict(&self, url: &str, auth_token: Option<&str>) {
+        let evicted = self.clients.lock().ok().and_then(|mut clients| {
+            let idx = clients
+                .iter()
+                .position(|c| c.key.0 == url && c.key.1.as_deref() == auth_token)?;
+            Some(clients.swap_remove(idx).client)
+        });
+        if let Some(evicted) = evicted {
+            Self::close(evicted).await;
+        }
+    }
+
+    /// Removes all clients from the cache and shuts them down
+    pub async fn clear(&self) {
+        let evicted: Vec<_> = match self.clients.lock() {
+            Ok(mut clients) => clients.drain(..).map(|c| c.client).collect(),
+            Err(_) => return,
+        };
+        for evicted in evicted {
+            Self::close(evicted).await;
+        }
+    }
+
+    /// Returns the number of cached clients
+    pub fn len(&self) -> usize {
+        self.clients.lock().map(|c| c.len()).unwrap_or_default()
+    }
+
+    /// Returns true if no clients are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn lookup(&self, key: &TenantKey) -> Option<Arc<GenericClient>> {
+        let mut clients = self.clients.lock().ok()?;
+        let cached = clients.iter_mut().find(|c| &c.key == key)?;
+        cached.last_used = self.tick();
+        Some(cached.client.clone())
+    }
+
+    /// Caches a new client, returning the cached one if another task won the race,
+    /// together with the clients evicted to stay within the limit
+    fn insert(
+        &self,
+        key: TenantKey,
+        client: Arc<GenericClient>,
+    ) -> (Arc<GenericClient>, Vec<Arc<GenericClient>>) {
+        let Ok(mut clients) = self.clients.lock() else {
+            return (client, vec![]);
+        };
+        if let Some(cached) = clients.iter_mut().find(|c| c.key == key) {
+            cached.last_used = self.tick();
+            return (cached.client.clone(), vec![client]);
+        }
+        clients.push(CachedClient {
+            key,
+            client: client.clone(),
+            last_used: self.tick(),
+        });
+        let mut evicted = Vec::new();
+        while clients.len() > self.max_clients {
+            let Some(lru) = clients
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| c.last_used)
+                .map(|(idx, _)| idx)
+            else {
+                break;
+            };
+            evicted.push(clients.swap_remove(lru).client);
+        }
+        (client, evicted)
+    }
+
+    /// Shuts down an evicted client, unless it is still in use elsewhere
+    async fn close(client: Arc<GenericClient>) {
+        if let Ok(client) = Arc::try_unwrap(client) {
+            if let Err(e) = client.shutdown().await {
+                tracing::debug!("Failed to shut down an evicted client: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! `float` decides how the exporters of the crate write floats as text: `copy_out()`,
+//! `ResultSet::to_json_with()` and the tables of the `repl` shell.
+//!
+//! By default, a float is written as the shortest text which reads back into the same value,
+//! e.g. `0.1`. That text shows every bit of the value, so results of the same computation
+//! which differ in their last bits, e.g. sums aggregated in a different order, are written
+//! differently, e.g. `0.30000000000000004` and `0.3`. `FloatFormat::Fixed` rounds floats
+//! to a number of decimals instead, so that exports of the same data diff cleanly between
+//! runs. Non-finite floats are written as `inf`, `-inf` and `NaN` in text, and as `null`
+//! in JSON, which has no representation for them.
+//!
+//! ```
+//!   use libsql_client::float::FloatFormat;
+//!
+//!   assert_eq!(FloatFormat::Shortest.format(0.1 + 0.2), "0.30000000000000004");
+//!   assert_eq!(FloatFormat::Fixed(6).format(0.1 + 0.2), "0.300000");
+//!   assert_eq!(FloatFormat::Fixed(6).round(0.1 + 0.2), 0.3);
+//! ```
+
+/// How floats are written as text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// The shortest text which reads back into the same value, e.g. `0.1` or `3`
+    #[default]
+    Shortest,
+    /// A fixed number of decimals, e.g. `0.100` or `3.000` with `Fixed(3)`
+    Fixed(usize),
+}
+
+impl FloatFormat {
+    /// Writes a float as text
+    pub fn format(self, value: f64) -> String {
+        match self {
+            Self::Shortest => value.to_string(),
+            Self::Fixed(decimals) => {
+                let text = format!("{value:.decimals$}");
+                // Negative values rounded to zero would differ from positive ones
+                match text.strip_prefix('-') {
+                    Some(abs) if abs.bytes().all(|b| b == b'0' || b == b'.') => abs.to_string(),
+                    _ => text,
+                }
+            }
+        }
+    }
+
+    /// Rounds a float to the value of its text, e.g. for JSON numbers, which are always
+    /// written as the shortest text of their value, without trailing zeros
+    pub fn round(self, value: f64) -> f64 {
+        match self {
+            Self::Shortest => value,
+            Self::Fixed(_) => self.format(value).parse().unwrap_or(value),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! `guardrails` bounds the cost of statements by the number of rows they read,
+//! e.g. for features which run queries supplied by users.
+//!
+//! Limits are set per statement with `Statement::max_rows_read()`, which fails the statement,
+//! and `Statement::warn_rows_read()`, which only logs a warning. Rows read are counted with
+//! the statistics reported by the server when available, see the `server_stats` module,
+//! and otherwise with the number of returned rows, which underestimates scans.
+//!
+//! Remote servers report statistics once the statement finished, so a statement exceeding
+//! its limit has already run to completion there, and its result is discarded. The local
+//! backend stops reading rows as soon as the limit is exceeded, including in batches.
+//! Other backends only check the limits of statements passed to `DatabaseClient::execute()`.
+//!
+//! ```rust,no_run
+//!   # async fn f(search: &str) -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Statement};
+//!   let db = libsql_client::new_client().await?;
+//!   let stmt = Statement::with_args("SELECT * FROM posts WHERE body LIKE ?", &[search])
+//!       .warn_rows_read(1_000)
+//!       .max_rows_read(100_000);
+//!   let posts = db.execute(stmt).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+
+use crate::ResultSet;
+
+/// Limits on the rows read by a statement
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) max_rows_read: Option<u64>,
+    pub(crate) warn_rows_read: Option<u64>,
+}
+
+impl Limits {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.max_rows_read.is_none() && self.warn_rows_read.is_none()
+    }
+
+    /// Checks the rows read by a statement against its limits,
+    /// failing or warning if it read more than allowed
+    pub(crate) fn check(&self, sql: &str, result: &ResultSet) -> Result<()> {
+        let rows_read = result
+            .server_stats()
+            .and_then(|stats| stats.rows_read)
+            .unwrap_or(result.rows.len() as u64);
+        if let Some(max) = self.max_rows_read.filter(|max| rows_read > *max) {
+            anyhow::bail!("Statement read {rows_read} rows, exceeding its limit of {max}: {sql}");
+        }
+        if let Some(warn) = self.warn_rows_read.filter(|warn| rows_read > *warn) {
+            tracing::warn!("Statement read {rows_read} rows, more than {warn}: {sql}");
+        }
+        Ok(())
+    }
+
+    /// Fails a statement which returned `rows` rows so far, if it read more than its maximum
+    #[cfg_attr(not(feature = "local_backend"), allow(dead_code))]
+    pub(crate) fn check_returned(&self, rows: usize) -> Result<()> {
+        match self.max_rows_read {
+            Some(max) if rows as u64 > max => {
+                anyhow::bail!("Statement read more than its limit of {max} rows")
+            }
+            _ => Ok(()),
+        }
+    }
+}
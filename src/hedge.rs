@@ -0,0 +1,266 @@
+//! `Hedged` cuts the tail latency of reads by sending a slow read a second time,
+//! to another replica or connection, and taking whichever response arrives first.
+//! It is only available with the `tokio` feature, which provides the timer.
+//!
+//! A read is hedged once it takes longer than a percentile of the latencies observed
+//! recently, by default the 95th, so that only the slowest reads cost a second request.
+//! Until enough latencies are observed, the maximum delay of the policy is used.
+//! The losing request is cancelled, i.e. its response is not waited for.
+//!
+//! Only statements which are known not to write, e.g. `SELECT`, are hedged, since sending
+//! a write twice could apply it twice. Batches are hedged if all their statements are reads.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{Config, DatabaseClient};
+//!   use libsql_client::hedge::{HedgePolicy, Hedged};
+//!
+//!   let primary = Config::new("https://db-ams.example.com")?;
+//!   let primary = libsql_client::new_client_from_config(primary).await?;
+//!   let replica = Config::new("https://db-fra.example.com")?;
+//!   let replica = libsql_client::new_client_from_config(replica).await?;
+//!   let db = Hedged::new(primary, replica, HedgePolicy::default().percentile(0.99));
+//!   let users = db.execute("SELECT * FROM users").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Number of latencies needed before the delay follows their percentile
+const MIN_SAMPLES: usize = 20;
+
+/// When to send a read a second time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HedgePolicy {
+    percentile: f64,
+    min_delay: Duration,
+    max_delay: Duration,
+    window: usize,
+}
+
+impl Default for HedgePolicy {
+    /// Hedges reads slower than the 95th percentile of the last 1000 reads,
+    /// waiting at least 10ms and at most 1s
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+            window: 1000,
+        }
+    }
+}
+
+impl HedgePolicy {
+    /// Sets the percentile of recent latencies after which a read is hedged, e.g. `0.95`
+    pub fn percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the shortest delay before a read is hedged, so that fast databases
+    /// do not get every read twice
+    pub fn min_delay(mut self, delay: Duration) -> Self {
+        self.min_delay = delay;
+        self
+    }
+
+    /// Sets the longest delay before a read is hedged, also used until enough
+    /// latencies are observed
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets the number of recent latencies the percentile is computed over
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(MIN_SAMPLES);
+        self
+    }
+}
+
+/// Client sending reads to `primary`, and again to `secondary` if they are slow.
+/// Writes only go to `primary`.
+pub struct Hedged<Primary: DatabaseClient, Secondary: DatabaseClient> {
+    primary: Primary,
+    secondary: Secondary,
+    policy: HedgePolicy,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl<Primary: DatabaseClient, Secondary: DatabaseClient> Hedged<Primary, Secondary> {
+    /// Creates a client hedging reads of `primary` with `secondary`
+    pub fn new(primary: Primary, secondary: Secondary, policy: HedgePolicy) -> Self {
+        Self {
+            primary,
+            secondary,
+            policy,
+            latencies: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the client receiving all statements
+    pub fn primary(&self) -> &Primary {
+        &self.primary
+    }
+
+    /// Returns the client receiving hedged reads
+    pub fn secondary(&self) -> &Secondary {
+        &self.secondary
+    }
+
+    /// Returns the current delay after which reads are hedged
+    pub fn delay(&self) -> Duration {
+        let latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        if latencies.len() < MIN_SAMPLES {
+            return self.policy.max_delay;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (self.policy.percentile * (sorted.len() - 1) as f64).round() as usize;
+        sorted[rank].clamp(self.policy.min_delay, self.policy.max_delay)
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        if latencies.len() >= self.policy.window {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// Runs `primary`, and `secondary` as well if `primary` takes longer than the delay,
+    /// returning the first success. Failures are only returned once both requests failed,
+    /// or if `primary` fails before it is hedged.
+    async fn race<T, P, S>(&self, primary: P, secondary: impl FnOnce() -> S) -> Result<T>
+    where
+        P: Future<Output = Result<T>>,
+        S: Future<Output = Result<T>>,
+    {
+        let start = Instant::now();
+        let mut primary = std::pin::pin!(primary);
+        let mut timer = std::pin::pin!(tokio::time::sleep(self.delay()));
+        let mut start_secondary = Some(secondary);
+        let mut secondary: Option<Pin<Box<S>>> = None;
+        let mut primary_error = None;
+        let mut secondary_error = None;
+        let result = std::future::poll_fn(|cx| {
+            if primary_error.is_none() {
+                if let Poll::Ready(result) = primary.as_mut().poll(cx) {
+                    match result {
+                        Ok(value) => return Poll::Ready(Ok(value)),
+                        Err(e) if secondary.is_none() => return Poll::Ready(Err(e)),
+                        Err(e) => primary_error = Some(e),
+                    }
+                }
+            }
+            if secondary.is_none() && timer.as_mut().poll(cx).is_ready() {
+                if let Some(start_secondary) = start_secondary.take() {
+                    tracing::debug!("Hedging a read after {:?}", start.elapsed());
+                    secondary = Some(Box::pin(start_secondary()));
+                }
+            }
+            if let (Some(future), true) = (secondary.as_mut(), secondary_error.is_none()) {
+                if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                    match result {
+                        Ok(value) => return Poll::Ready(Ok(value)),
+                        Err(e) => secondary_error = Some(e),
+                    }
+                }
+            }
+            match (primary_error.take(), secondary_error.take()) {
+                (Some(e), Some(_)) => Poll::Ready(Err(e)),
+                (first, second) => {
+                    primary_error = first;
+                    secondary_error = second;
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        if result.is_ok() {
+            self.record(start.elapsed());
+        }
+        result
+    }
+}
+
+/// Checks if a statement can be sent twice: it does not write nor control transactions
+fn is_hedgeable(stmt: &Statement) -> bool {
+    let keyword = crate::sql::leading_keyword(&stmt.sql);
+    let controls_transaction = matches!(
+        keyword.as_deref(),
+        Some("BEGIN" | "COMMIT" | "END" | "ROLLBACK" | "SAVEPOINT" | "RELEASE")
+    );
+    crate::sql::is_read_only(&stmt.sql) && !controls_transaction
+}
+
+#[async_trait(?Send)]
+impl<Primary: DatabaseClient, Secondary: DatabaseClient> DatabaseClient
+    for Hedged<Primary, Secondary>
+{
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        if !is_hedgeable(&stmt) {
+            return self.primary.execute(stmt).await;
+        }
+        let hedge = stmt.clone();
+        self.race(self.primary.execute(stmt), || self.secondary.execute(hedge))
+            .await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        if !stmts.iter().all(is_hedgeable) {
+            return self.primary.raw_batch(stmts).await;
+        }
+        let hedge = stmts.clone();
+        self.race(self.primary.raw_batch(stmts), || {
+            self.secondary.raw_batch(hedge)
+        })
+        .await
+    }
+
+    /// Prepares the statements on both clients, since either may answer a read
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.primary.prewarm(stmts).await?;
+        self.secondary.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.primary.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.primary.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        // Transactions are never hedged, so they only run on the primary
+        self.primary.schedule_rollback()
+    }
+
+    /// Waits for both clients, since either may answer the next read
+    async fn barrier(&self) -> Result<()> {
+        self.primary.barrier().await?;
+        self.secondary.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.primary.stats()
+    }
+}
@@ -1,15 +1,30 @@
 use crate::client::Config;
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::{BatchResult, ResultSet, Statement};
+use crate::batch::Batch;
+use crate::stats::StatsCollector;
+use crate::timings::Phase;
+use crate::{BatchResult, ClientStats, ResultSet, Statement};
 
 /// Database client. This is the main structure used to
 /// communicate with the database.
+///
+/// A client dropped without `shutdown()` closes its connection in the background if a tokio
+/// runtime is running, and reports it with a warning, along with a backtrace of where the client
+/// was created in debug builds (if backtraces are enabled with `RUST_BACKTRACE`).
 pub struct Client {
-    client: hrana_client::Client,
-    client_future: hrana_client::ConnFut,
+    /// Connection and the future completing once it is closed, taken by `shutdown()`
+    connection: Option<(hrana_client::Client, hrana_client::ConnFut)>,
     stream: hrana_client::Stream,
+    /// Whether a transaction was dropped, to roll back before the next request
+    rollback_pending: AtomicBool,
+    backtrace: Option<std::backtrace::Backtrace>,
+    stats: StatsCollector,
+    validate_batches: bool,
+    collect_timings: bool,
+    read_only: bool,
 }
 
 impl Client {
@@ -26,9 +41,14 @@ impl Client {
                 .await?;
         let stream = client.open_stream().await?;
         Ok(Self {
-            client,
-            client_future,
+            connection: Some((client, client_future)),
             stream,
+            rollback_pending: AtomicBool::new(false),
+            backtrace: cfg!(debug_assertions).then(std::backtrace::Backtrace::capture),
+            stats: StatsCollector::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
         })
     }
 
@@ -69,15 +89,72 @@ impl Client {
     }
 
     /// Creates a database client from a `Config` object.
+    /// Connection initialization statements are executed on the stream
+    /// right after it is opened.
     pub async fn from_config(config: Config) -> Result<Self> {
-        Self::new(config.url, config.auth_token.unwrap_or_default()).await
+        let init_statements = config.connection_statements();
+        let mut client = Self::new(config.url, config.auth_token.unwrap_or_default()).await?;
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        for stmt in init_statements {
+            crate::DatabaseClient::execute(&client, stmt).await?;
+        }
+        // Set after the initialization statements, which include `PRAGMA query_only = ON`
+        client.read_only = config.read_only;
+        Ok(client)
+    }
+
+    /// Returns cumulative counters describing the activity of this client
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
     }
 
-    pub async fn shutdown(self) -> Result<()> {
-        self.client.shutdown().await?;
-        self.client_future.await?;
+    pub async fn shutdown(mut self) -> Result<()> {
+        if let Some((client, client_future)) = self.connection.take() {
+            client.shutdown().await?;
+            client_future.await?;
+        }
         Ok(())
     }
+
+    /// Rolls back a transaction dropped since the previous request, see `schedule_rollback()`
+    async fn rollback_if_pending(&self) {
+        if self.rollback_pending.swap(false, Ordering::Relaxed) {
+            let rollback = hrana_client::proto::Stmt::new("ROLLBACK".to_string(), false);
+            if let Err(e) = self.stream.execute(rollback).await {
+                tracing::debug!("Failed to roll back a dropped transaction: {e}");
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let Some((client, client_future)) = self.connection.take() else {
+            return;
+        };
+        let backtrace = match &self.backtrace {
+            Some(backtrace) => backtrace.to_string(),
+            None => "unavailable".to_string(),
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(%backtrace, "Client dropped without shutdown() outside of a runtime");
+            return;
+        };
+        tracing::warn!(
+            %backtrace,
+            "Client dropped without shutdown(), closing it in the background"
+        );
+        runtime.spawn(async move {
+            if let Err(e) = client.shutdown().await {
+                tracing::debug!("Failed to shut down a dropped client: {e}");
+                return;
+            }
+            if let Err(e) = client_future.await {
+                tracing::debug!("Connection of a dropped client failed: {e}");
+            }
+        });
+    }
 }
 
 #[async_trait(?Send)]
@@ -86,33 +163,89 @@ impl crate::DatabaseClient for Client {
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> anyhow::Result<BatchResult> {
+        crate::deadline::check()?;
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        self.rollback_if_pending().await;
         let mut batch = hrana_client::proto::Batch::new();
 
-        for stmt in stmts.into_iter() {
-            let stmt: Statement = stmt.into();
+        for stmt in stmts {
             let mut hrana_stmt = hrana_client::proto::Stmt::new(stmt.sql, true);
             for param in stmt.args {
                 hrana_stmt.bind(param);
             }
             batch.step(None, hrana_stmt);
         }
-        self.stream
-            .execute_batch(batch)
+        let result =
+            crate::timings::measure_async(Phase::Network, self.stream.execute_batch(batch))
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.stats.record_batch(&result);
+        Ok(result)
+    }
+
+    async fn run_batch(&self, batch: Batch) -> Result<BatchResult> {
+        crate::deadline::check()?;
+        let stmts: Vec<Statement> = batch.steps().iter().map(|s| s.stmt.clone()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        self.rollback_if_pending().await;
+        // Initialization statements were executed when the stream was opened
+        let request = self.stream.execute_batch(batch.to_proto(&[]));
+        let result = crate::timings::measure_async(Phase::Network, request)
             .await
-            .map_err(|e| anyhow::anyhow!("{}", e))
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.stats.record_batch(&result);
+        Ok(result)
     }
 
     async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
-        let stmt: Statement = stmt.into();
-        let mut hrana_stmt = hrana_client::proto::Stmt::new(stmt.sql, true);
-        for param in stmt.args {
-            hrana_stmt.bind(param);
-        }
+        crate::deadline::check()?;
+        let mut stmt: Statement = stmt.into();
+        crate::client::check_read_only(self.read_only, std::slice::from_ref(&stmt))?;
+        let options = crate::client::ResultOptions::take(&mut stmt);
+        self.rollback_if_pending().await;
+        let to_hrana_stmt = |stmt: &Statement| {
+            let mut hrana_stmt = hrana_client::proto::Stmt::new(stmt.sql.clone(), true);
+            for param in &stmt.args {
+                hrana_stmt.bind(param.clone());
+            }
+            hrana_stmt
+        };
 
-        self.stream
-            .execute(hrana_stmt)
-            .await
-            .map(ResultSet::from)
-            .map_err(|e| anyhow::anyhow!("{}", e))
+        let execution = crate::timings::measure_async(Phase::Network, async {
+            match self.stream.execute(to_hrana_stmt(&stmt)).await {
+                // The server compiled the statement against the old schema, e.g. before a migration
+                Err(e) if crate::error::is_schema_change(&e.to_string()) => {
+                    tracing::debug!("Schema changed, retrying the statement once: {e}");
+                    self.stream.execute(to_hrana_stmt(&stmt)).await
+                }
+                result => result,
+            }
+        });
+        let execution = crate::server_stats::collect(execution);
+        let ((result, server_stats), timings) =
+            crate::timings::collect(self.collect_timings, execution).await;
+        let result = result.map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.stats.record_result(&result);
+        options.finish(result, timings, server_stats)
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.rollback_pending.store(true, Ordering::Relaxed);
+        true
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats()
     }
 }
@@ -0,0 +1,184 @@
+//! Generic HTTP backend, which takes care of encoding statements and decoding
+//! results, while leaving the actual I/O to a pluggable `HttpTransport`.
+//! See `libsql_client::transport` for implementing one.
+//!
+//! It only depends on crates which compile on `wasm32-wasi`, so WASI runtimes
+//! (wasmtime components, wasmCloud, ...) can provide their own HTTP host function.
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::batch::Batch;
+use crate::client::Config;
+use crate::stats::StatsCollector;
+use crate::timings::Phase;
+use crate::{BatchResult, ClientStats, Statement, Transaction};
+
+pub use crate::transport::{BlobEncoding, HttpResponse, HttpTransport};
+
+/// Database client. This is the main structure used to
+/// communicate with the database.
+#[derive(Debug)]
+pub struct Client<T: HttpTransport> {
+    transport: T,
+    url_for_queries: String,
+    auth: String,
+    init_statements: Vec<Statement>,
+    stats: StatsCollector,
+    validate_batches: bool,
+    collect_timings: bool,
+    read_only: bool,
+    blob_encoding: BlobEncoding,
+    /// Encoding of the last blob received, followed with `BlobEncoding::Detect`
+    detected_blob_encoding: std::sync::Mutex<Option<BlobEncoding>>,
+}
+
+impl<T: HttpTransport> Client<T> {
+    /// Creates a database client with JWT authentication.
+    ///
+    /// # Arguments
+    /// * `transport` - transport used to send requests
+    /// * `url` - URL of the database endpoint
+    /// * `token` - auth token
+    pub fn new(transport: T, url: impl Into<String>, token: impl Into<String>) -> Self {
+        let token = token.into();
+        let url = url.into();
+        // Auto-update the URL to start with https:// if no protocol was specified
+        let base_url = if !url.contains("://") {
+            format!("https://{}", &url)
+        } else {
+            url
+        };
+        let url_for_queries = if cfg!(feature = "separate_url_for_queries") {
+            format!("{base_url}/queries")
+        } else {
+            base_url
+        };
+        Self {
+            transport,
+            url_for_queries,
+            auth: format!("Bearer {token}"),
+            init_statements: vec![],
+            stats: StatsCollector::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            blob_encoding: BlobEncoding::default(),
+            detected_blob_encoding: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Creates a database client from a `Config` object.
+    /// Connection initialization statements are sent along with every request.
+    pub fn from_config(transport: T, config: Config) -> Self {
+        let init_statements = config.connection_statements();
+        let mut client = Self::new(
+            transport,
+            config.url.as_str(),
+            config.auth_token.unwrap_or_default(),
+        );
+        client.init_statements = init_statements;
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        client.read_only = config.read_only;
+        client.blob_encoding = config.blob_encoding;
+        client
+    }
+
+    /// Sets the encoding of blobs in requests, see `Config::blob_encoding()`
+    pub fn with_blob_encoding(mut self, encoding: BlobEncoding) -> Self {
+        self.blob_encoding = encoding;
+        self
+    }
+
+    /// Returns the encoding of blobs in the next request
+    pub fn blob_encoding(&self) -> BlobEncoding {
+        match self.blob_encoding {
+            BlobEncoding::Detect => self
+                .detected_blob_encoding
+                .lock()
+                .ok()
+                .and_then(|detected| *detected)
+                .unwrap_or(BlobEncoding::Base64),
+            encoding => encoding,
+        }
+    }
+
+    /// Returns the transport used by this client
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    async fn send_statements(&self, stmts: Vec<Statement>) -> Result<BatchResult> {
+        crate::deadline::check()?;
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        let (body, stmts_count) =
+            crate::pipeline::encode_request(&self.init_statements, stmts, self.blob_encoding());
+        self.stats.record_bytes_sent(body.len());
+        let headers = [("Authorization", self.auth.as_str())];
+        let request = self
+            .transport
+            .post(&self.url_for_queries, &headers, body.into_bytes());
+        let response = crate::timings::measure_async(Phase::Network, request)
+            .await?
+            .error_for_status()?;
+        self.stats.record_bytes_received(response.body.len());
+        let mut detected = None;
+        let result = crate::pipeline::decode_response_detecting(
+            &response.body,
+            stmts_count,
+            self.init_statements.len(),
+            &mut detected,
+        )?;
+        if let (BlobEncoding::Detect, Some(detected)) = (self.blob_encoding, detected) {
+            if let Ok(mut last) = self.detected_blob_encoding.lock() {
+                *last = Some(detected);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: HttpTransport> crate::DatabaseClient for Client<T> {
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let result = self
+            .send_statements(stmts.into_iter().map(|s| s.into()).collect())
+            .await?;
+        self.stats.record_batch(&result);
+        Ok(result)
+    }
+
+    async fn transaction<'a>(&'a self) -> Result<Transaction<'a, Self>> {
+        anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are only supported by WebSocket (hrana) and local backends. Use batch() instead"))
+    }
+
+    async fn execute_multi(&self, sql: &str) -> Result<Vec<crate::ResultSet>> {
+        crate::client::execute_sequentially(self, sql).await
+    }
+
+    async fn run_batch(&self, batch: Batch) -> Result<BatchResult> {
+        if batch.is_conditional() {
+            anyhow::bail!(crate::error::Unsupported::new(
+                "Conditional batches are not supported by the http backend"
+            ))
+        }
+        self.raw_batch(batch.into_statements()).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+}
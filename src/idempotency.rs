@@ -0,0 +1,335 @@
+//! Idempotency keys let a client dedupe writes which are sent more than once,
+//! e.g. when a request is retried after its response was lost.
+//!
+//! Keys of statements known to be applied are remembered client-side,
+//! and optionally persisted in a dedupe table, written in the same request
+//! as the statement itself if it succeeds. A request with an unknown outcome is only resent
+//! after the dedupe table is consulted, so statements which were already
+//! applied are skipped instead of being executed twice.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use crate::batch::{Batch, BatchBuilder};
+use crate::proto::StmtResult;
+use crate::sql::quote_ident;
+use crate::{BatchResult, Statement, Value};
+
+/// Number of applied keys remembered by a client
+const RECENT_KEYS_CAPACITY: usize = 4096;
+
+/// Bounded set of keys of statements which are known to be applied,
+/// the oldest keys are forgotten first.
+#[derive(Debug, Default)]
+pub(crate) struct RecentKeys {
+    inner: Mutex<RecentKeysInner>,
+}
+
+#[derive(Debug, Default)]
+struct RecentKeysInner {
+    order: VecDeque<String>,
+    keys: HashSet<String>,
+}
+
+impl RecentKeys {
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.inner
+            .lock()
+            .map(|inner| inner.keys.contains(key))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn insert(&self, key: &str) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        if !inner.keys.insert(key.to_string()) {
+            return;
+        }
+        inner.order.push_back(key.to_string());
+        if inner.order.len() > RECENT_KEYS_CAPACITY {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.keys.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Statement creating the dedupe table, if it does not exist yet
+pub(crate) fn create_table_statement(table: &str) -> Statement {
+    Statement::new(format!(
+        "CREATE TABLE IF NOT EXISTS {} (idempotency_key TEXT PRIMARY KEY, created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')))",
+        quote_ident(table)
+    ))
+}
+
+/// Statement returning which of the given keys are recorded in the dedupe table
+pub(crate) fn lookup_statement(table: &str, keys: &[&str]) -> Statement {
+    let placeholders = vec!["?"; keys.len()].join(", ");
+    Statement::with_args(
+        format!(
+            "SELECT idempotency_key FROM {} WHERE idempotency_key IN ({placeholders})",
+            quote_ident(table)
+        ),
+        keys,
+    )
+}
+
+/// Origin of a statement sent to the server
+enum Step {
+    /// Statement of the user, with its index in the batch
+    User(usize),
+    /// Statement recording the key of the preceding user statement, if it succeeded
+    Guard,
+}
+
+/// Batch of user statements, rewritten to skip the ones which are known to be applied
+/// and to record the keys of the other ones in the dedupe table.
+pub(crate) struct Plan {
+    stmts: Vec<Statement>,
+    skipped: Vec<bool>,
+    table: Option<String>,
+}
+
+impl Plan {
+    pub(crate) fn new(stmts: Vec<Statement>, recent: &RecentKeys, table: Option<&str>) -> Self {
+        let skipped = stmts
+            .iter()
+            .map(|stmt| {
+                stmt.idempotency_key
+                    .as_deref()
+                    .is_some_and(|key| recent.contains(key))
+            })
+            .collect();
+        Self {
+            stmts,
+            skipped,
+            table: table.map(|t| t.to_string()),
+        }
+    }
+
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for (idx, stmt) in self.stmts.iter().enumerate() {
+            if self.skipped[idx] {
+                continue;
+            }
+            steps.push(Step::User(idx));
+            if self.table.is_some() && stmt.idempotency_key.is_some() {
+                steps.push(Step::Guard);
+            }
+        }
+        steps
+    }
+
+    /// Batch to send to the server, in which the key of a user statement is only
+    /// recorded if the statement succeeded, so that a failed one can be retried
+    pub(crate) fn batch(&self) -> Result<Batch> {
+        let mut builder = BatchBuilder::new();
+        let mut last_key = None;
+        for step in self.steps() {
+            builder = match step {
+                Step::User(idx) => {
+                    last_key = self.stmts[idx].idempotency_key.clone();
+                    builder.step(self.stmts[idx].clone())
+                }
+                Step::Guard => builder.step_if_ok(Statement::with_args(
+                    format!(
+                        "INSERT OR IGNORE INTO {} (idempotency_key) VALUES (?)",
+                        quote_ident(self.table.as_deref().unwrap_or_default())
+                    ),
+                    &[last_key.clone().unwrap_or_default()],
+                )),
+            };
+        }
+        builder.build()
+    }
+
+    /// Keys of the statements which are going to be sent
+    pub(crate) fn pending_keys(&self) -> Vec<&str> {
+        self.stmts
+            .iter()
+            .zip(&self.skipped)
+            .filter(|(_, skipped)| !**skipped)
+            .filter_map(|(stmt, _)| stmt.idempotency_key.as_deref())
+            .collect()
+    }
+
+    /// Whether the batch can be resent if the outcome of a request is unknown:
+    /// every statement is either read-only, idempotent or deduped by the dedupe table
+    pub(crate) fn is_resendable(&self) -> bool {
+        self.stmts.iter().zip(&self.skipped).all(|(stmt, skipped)| {
+            *skipped
+                || stmt.idempotent
+                || (self.table.is_some() && stmt.idempotency_key.is_some())
+                || matches!(
+                    crate::sql::leading_keyword(&stmt.sql).as_deref(),
+                    Some("SELECT" | "EXPLAIN" | "BEGIN" | "END" | "COMMIT" | "ROLLBACK")
+                )
+        })
+    }
+
+    /// Marks statements whose keys are known to be applied, so that they're not resent
+    pub(crate) fn skip_applied(&mut self, recent: &RecentKeys) {
+        for (stmt, skipped) in self.stmts.iter().zip(self.skipped.iter_mut()) {
+            if let Some(key) = &stmt.idempotency_key {
+                *skipped |= recent.contains(key);
+            }
+        }
+    }
+
+    /// Maps the result of the sent statements back to the user statements,
+    /// recording the keys of the applied ones. Skipped statements get an empty result.
+    pub(crate) fn into_results(self, result: BatchResult, recent: &RecentKeys) -> BatchResult {
+        let mut step_results: Vec<Option<StmtResult>> = self
+            .skipped
+            .iter()
+            .map(|skipped| skipped.then(empty_result))
+            .collect();
+        let mut step_errors: Vec<Option<crate::proto::Error>> =
+            self.stmts.iter().map(|_| None).collect();
+        let responses = result.step_results.into_iter().zip(result.step_errors);
+        for (step, (step_result, step_error)) in self.steps().into_iter().zip(responses) {
+            if let Step::User(idx) = step {
+                let key = self.stmts[idx].idempotency_key.as_deref();
+                if let (Some(key), true) = (key, step_result.is_some()) {
+                    recent.insert(key);
+                }
+                step_results[idx] = step_result;
+                step_errors[idx] = step_error;
+            }
+        }
+        BatchResult {
+            step_results,
+            step_errors,
+        }
+    }
+}
+
+/// Parses the result of `lookup_statement()` into the recent keys
+pub(crate) fn record_applied(result: &StmtResult, recent: &RecentKeys) {
+    for row in &result.rows {
+        if let Some(Value::Text { value }) = row.first() {
+            recent.insert(value);
+        }
+    }
+}
+
+fn empty_result() -> StmtResult {
+    StmtResult {
+        cols: vec![],
+        rows: vec![],
+        affected_row_count: 0,
+        last_insert_rowid: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Condition, StepRef};
+
+    fn keyed(sql: &str, key: &str) -> Statement {
+        Statement::new(sql).idempotency_key(key)
+    }
+
+    fn error() -> crate::proto::Error {
+        crate::proto::Error {
+            message: "constraint failed".to_string(),
+        }
+    }
+
+    #[test]
+    fn guards_are_conditioned_on_their_statements() {
+        let recent = RecentKeys::default();
+        recent.insert("a");
+        let stmts = vec![
+            keyed("INSERT INTO t VALUES (1)", "a"),
+            keyed("INSERT INTO t VALUES (2)", "b"),
+            Statement::new("SELECT * FROM t"),
+        ];
+        let plan = Plan::new(stmts, &recent, Some("keys"));
+        let batch = plan.batch().unwrap();
+        let steps: Vec<_> = batch
+            .steps()
+            .iter()
+            .map(|s| (s.stmt.sql.as_str(), s.condition.clone()))
+            .collect();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], ("INSERT INTO t VALUES (2)", None));
+        assert!(steps[1].0.starts_with("INSERT OR IGNORE INTO \"keys\""));
+        assert_eq!(steps[1].1, Some(Condition::Ok(StepRef::Index(0))));
+        assert!(matches!(&batch.steps()[1].stmt.args[..], [Value::Text { value }] if value == "b"));
+        assert_eq!(steps[2], ("SELECT * FROM t", None));
+        assert_eq!(plan.pending_keys(), ["b"]);
+    }
+
+    #[test]
+    fn keys_are_not_guarded_without_a_table() {
+        let recent = RecentKeys::default();
+        let plan = Plan::new(vec![keyed("DELETE FROM t", "a")], &recent, None);
+        assert!(!plan.batch().unwrap().is_conditional());
+        assert!(!plan.is_resendable());
+    }
+
+    #[test]
+    fn results_of_skipped_failed_and_guarded_steps() {
+        let recent = RecentKeys::default();
+        recent.insert("skipped");
+        let stmts = vec![
+            keyed("INSERT INTO t VALUES (1)", "skipped"),
+            keyed("INSERT INTO t VALUES (2)", "failed"),
+            keyed("INSERT INTO t VALUES (3)", "applied"),
+            Statement::new("SELECT * FROM t"),
+        ];
+        let plan = Plan::new(stmts, &recent, Some("keys"));
+        // Steps: failed, its guard (skipped), applied, its guard, select
+        let result = BatchResult {
+            step_results: vec![
+                None,
+                None,
+                Some(empty_result()),
+                Some(empty_result()),
+                Some(empty_result()),
+            ],
+            step_errors: vec![Some(error()), None, None, None, None],
+        };
+        let result = plan.into_results(result, &recent);
+        assert_eq!(result.step_results.len(), 4);
+        assert!(result.step_results[0].is_some());
+        assert!(result.step_errors[0].is_none());
+        assert!(result.step_results[1].is_none());
+        assert_eq!(
+            result.step_errors[1].as_ref().unwrap().message,
+            "constraint failed"
+        );
+        assert!(result.step_results[2].is_some());
+        assert!(result.step_results[3].is_some());
+        assert!(recent.contains("applied"));
+        assert!(!recent.contains("failed"));
+    }
+
+    #[test]
+    fn applied_keys_are_skipped_when_resending() {
+        let recent = RecentKeys::default();
+        let mut plan = Plan::new(
+            vec![
+                keyed("INSERT INTO t VALUES (1)", "a"),
+                keyed("INSERT INTO t VALUES (2)", "b"),
+            ],
+            &recent,
+            Some("keys"),
+        );
+        assert!(plan.is_resendable());
+        let applied = StmtResult {
+            rows: vec![vec![Value::from("a")]],
+            ..empty_result()
+        };
+        record_applied(&applied, &recent);
+        plan.skip_applied(&recent);
+        assert_eq!(plan.pending_keys(), ["b"]);
+        assert_eq!(plan.batch().unwrap().steps().len(), 2);
+    }
+}
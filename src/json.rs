@@ -0,0 +1,97 @@
+//! `json` exports result sets as plain JSON, an array with an object per row keyed by
+//! column name, e.g. for returning query results from a Cloudflare Worker to a browser.
+//!
+//! JavaScript parses JSON numbers as doubles, which represent integers exactly only up to
+//! `Number.MAX_SAFE_INTEGER`, i.e. 2^53 - 1, so larger SQLite integers, e.g. snowflake IDs,
+//! would silently lose precision. `JsonIntegers` decides how such integers are exported:
+//! as strings by default, which JavaScript converts with `BigInt(value)`. Integers within
+//! the safe range are always numbers. Floats are numbers, rounded by `to_json_with()`
+//! if requested, see `libsql_client::float`, except non-finite ones which have no JSON
+//! representation and become `null`. Blobs are base64-encoded strings.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::json::JsonIntegers;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let result = db.execute("SELECT id, name FROM users").await?;
+//!   // [{"id": "1152921504606846976", "name": "alice"}]
+//!   let body = serde_json::to_string(&result.to_json(JsonIntegers::StringIfUnsafe))?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use base64::Engine;
+
+use crate::float::FloatFormat;
+use crate::{ResultSet, Value};
+
+/// Largest integer which JavaScript represents exactly, `Number.MAX_SAFE_INTEGER`
+pub const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// How integers beyond ±`MAX_SAFE_INTEGER` are exported to JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonIntegers {
+    /// As numbers, which JavaScript rounds to the nearest double
+    Number,
+    /// As strings, e.g. `"9007199254740993"`
+    #[default]
+    StringIfUnsafe,
+    /// As objects tagging the digits, e.g. `{"$bigint": "9007199254740993"}`, so that they can
+    /// be told apart from text, e.g. by a reviver passed to `JSON.parse()`
+    Tagged,
+}
+
+impl ResultSet {
+    /// Exports the rows as a JSON array of objects keyed by column name, with large integers
+    /// exported according to `integers`. If several columns have the same name, the last
+    /// one wins, so such columns should be renamed with `AS`.
+    pub fn to_json(&self, integers: JsonIntegers) -> serde_json::Value {
+        self.to_json_with(integers, FloatFormat::default())
+    }
+
+    /// Exports the rows like `to_json()`, with floats rounded according to `floats`
+    pub fn to_json_with(&self, integers: JsonIntegers, floats: FloatFormat) -> serde_json::Value {
+        self.rows
+            .iter()
+            .map(|row| {
+                let object: serde_json::Map<String, serde_json::Value> = self
+                    .columns
+                    .iter()
+                    .zip(&row.values)
+                    .map(|(column, value)| (column.clone(), json_value(value, integers, floats)))
+                    .collect();
+                serde_json::Value::Object(object)
+            })
+            .collect()
+    }
+}
+
+/// Converts a value to JSON, with large integers exported according to `integers`
+/// and floats rounded according to `floats`
+pub fn json_value(value: &Value, integers: JsonIntegers, floats: FloatFormat) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer { value } => json_integer(*value, integers),
+        // Non-finite floats have no JSON representation
+        Value::Float { value } => serde_json::Number::from_f64(floats.round(*value))
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Text { value } => value.as_str().into(),
+        Value::Blob { value } => base64::engine::general_purpose::STANDARD
+            .encode(value)
+            .into(),
+    }
+}
+
+fn json_integer(value: i64, integers: JsonIntegers) -> serde_json::Value {
+    if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&value) {
+        return value.into();
+    }
+    match integers {
+        JsonIntegers::Number => value.into(),
+        JsonIntegers::StringIfUnsafe => value.to_string().into(),
+        JsonIntegers::Tagged => serde_json::json!({ "$bigint": value.to_string() }),
+    }
+}
@@ -0,0 +1,199 @@
+//! `kv` implements a key-value store on top of a table of the database.
+//! Values are serialized as JSON, and may expire after a time to live.
+//!
+//! Expired entries are never returned, and are deleted by `purge_expired()`,
+//! or when they're overwritten.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::kv::Kv;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let kv = Kv::new(&db);
+//!   kv.create().await?;
+//!   kv.set_with_ttl("session:42", &vec!["admin"], Duration::from_secs(3600)).await?;
+//!   let roles: Option<Vec<String>> = kv.get("session:42").await?;
+//!   for (key, roles) in kv.scan::<Vec<String>>("session:", 100).await? {
+//!       println!("{key}: {roles:?}");
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing entries
+const DEFAULT_KV_TABLE: &str = "_libsql_kv";
+
+/// Key-value store, stored in the `_libsql_kv` table by default
+pub struct Kv<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Kv<'a, Client> {
+    /// Creates a store using the `_libsql_kv` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_KV_TABLE),
+        }
+    }
+
+    /// Sets the table storing entries
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Creates the table of the store, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, value TEXT NOT NULL, expires_at INTEGER)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the value of `key`, or `None` if it's missing or expired
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT value FROM {} WHERE key = ? AND (expires_at IS NULL OR expires_at > {NOW_MS})",
+                    self.table
+                ),
+                &[key],
+            ))
+            .await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            None => Ok(None),
+            Some(Value::Text { value }) => Ok(Some(serde_json::from_str(value)?)),
+            Some(value) => anyhow::bail!("Invalid value of key {key}: {value:?}"),
+        }
+    }
+
+    /// Sets the value of `key`, which never expires
+    pub async fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        self.put(key, value, None).await
+    }
+
+    /// Sets the value of `key`, which expires after `ttl`
+    pub async fn set_with_ttl<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.put(key, value, Some(ttl)).await
+    }
+
+    async fn put<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let ttl = match ttl {
+            Some(ttl) => Value::from(ttl.as_millis() as i64),
+            None => Value::Null,
+        };
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT OR REPLACE INTO {} (key, value, expires_at) VALUES (?, ?, {NOW_MS} + ?)",
+                    self.table
+                ),
+                &[Value::from(key), Value::from(serde_json::to_string(value)?), ttl],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes `key`, returning whether it existed and was not expired
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "DELETE FROM {} WHERE key = ? RETURNING expires_at IS NULL OR expires_at > {NOW_MS}",
+                    self.table
+                ),
+                &[key],
+            ))
+            .await?;
+        Ok(matches!(
+            result.rows.first().and_then(|row| row.values.first()),
+            Some(Value::Integer { value: 1 })
+        ))
+    }
+
+    /// Returns up to `limit` entries whose key starts with `prefix`, ordered by key
+    pub async fn scan<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, T)>> {
+        self.scan_after(prefix, "", limit).await
+    }
+
+    /// Returns up to `limit` entries whose key starts with `prefix` and comes after `after`,
+    /// ordered by key, e.g. for paging through entries with the last key of the previous page
+    pub async fn scan_after<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, T)>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT key, value FROM {} \
+                     WHERE key >= ?1 AND key > ?2 AND substr(key, 1, length(?1)) = ?1 \
+                       AND (expires_at IS NULL OR expires_at > {NOW_MS}) \
+                     ORDER BY key LIMIT ?3",
+                    self.table
+                ),
+                &[
+                    Value::from(prefix),
+                    Value::from(after),
+                    Value::from(limit as i64),
+                ],
+            ))
+            .await?;
+        result
+            .rows
+            .iter()
+            .map(|row| match &row.values[..] {
+                [Value::Text { value: key }, Value::Text { value }] => {
+                    Ok((key.clone(), serde_json::from_str(value)?))
+                }
+                values => anyhow::bail!("Invalid entry: {values:?}"),
+            })
+            .collect()
+    }
+
+    /// Deletes the expired entries, returning how many were deleted
+    pub async fn purge_expired(&self) -> Result<u64> {
+        let result = self
+            .client
+            .execute(format!(
+                "DELETE FROM {} WHERE expires_at <= {NOW_MS}",
+                self.table
+            ))
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
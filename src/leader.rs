@@ -0,0 +1,230 @@
+//! `leader` implements lease-based leader election stored in a table of the database,
+//! for services which need a single active worker among several instances.
+//!
+//! Each election is identified by a name. A candidate becomes the leader by taking the lease
+//! of the election, which it must renew before its time to live expires. Every change of leader
+//! increments the fencing token of the election: resources written by the leader should reject
+//! writes carrying a token lower than the highest one they've seen, so that a former leader which
+//! was paused past the expiry of its lease can't overwrite the work of its successor.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::leader::Election;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let election = Election::new(&db, "scheduler", "worker-1", Duration::from_secs(15));
+//!   if let Some(leadership) = election.try_acquire().await? {
+//!       println!("Leading with fencing token {}", leadership.fencing_token());
+//!       leadership.renew().await?;
+//!       leadership.release().await?;
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing the leases of elections
+const DEFAULT_LEASES_TABLE: &str = "_libsql_leases";
+
+/// Election of a leader among candidates sharing a database
+pub struct Election<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    name: String,
+    candidate: String,
+    ttl: Duration,
+    table: String,
+}
+
+/// Current leader of an election
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leader {
+    /// Identifier of the candidate holding the lease
+    pub candidate: String,
+    pub fencing_token: i64,
+}
+
+/// Lease held by this candidate, until it's released or it expires.
+/// Dropping it does not release the lease, which then expires after its time to live,
+/// since releasing takes a database call.
+pub struct Leadership<'a, Client: DatabaseClient + ?Sized> {
+    election: &'a Election<'a, Client>,
+    fencing_token: i64,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Election<'a, Client> {
+    /// Creates an election named `name`, in which this process runs as `candidate`.
+    /// Candidate identifiers must be unique among the processes taking part in the election,
+    /// e.g. host names.
+    pub fn new(
+        client: &'a Client,
+        name: impl Into<String>,
+        candidate: impl Into<String>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            client,
+            name: name.into(),
+            candidate: candidate.into(),
+            ttl,
+            table: quote_ident(DEFAULT_LEASES_TABLE),
+        }
+    }
+
+    /// Sets the table storing the leases of elections
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Tries to become the leader, which succeeds if the lease is free, expired,
+    /// or already held by this candidate
+    pub async fn try_acquire(&self) -> Result<Option<Leadership<'_, Client>>> {
+        self.create_table().await?;
+        let table = &self.table;
+        // The token is incremented whenever the lease changes hands, atomically
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT INTO {table} (name, candidate, fencing_token, expires_at) \
+                     VALUES (?, ?, 1, {NOW_MS} + ?) \
+                     ON CONFLICT (name) DO UPDATE SET \
+                       fencing_token = CASE WHEN candidate = excluded.candidate AND expires_at >= {NOW_MS} \
+                         THEN fencing_token ELSE fencing_token + 1 END, \
+                       candidate = excluded.candidate, expires_at = excluded.expires_at \
+                     WHERE candidate = excluded.candidate OR expires_at < {NOW_MS} \
+                     RETURNING fencing_token"
+                ),
+                &[
+                    Value::from(self.name.as_str()),
+                    Value::from(self.candidate.as_str()),
+                    Value::from(self.ttl.as_millis() as i64),
+                ],
+            ))
+            .await?;
+        let fencing_token = match result.rows.first().and_then(|row| row.values.first()) {
+            Some(Value::Integer { value }) => *value,
+            _ => return Ok(None),
+        };
+        tracing::debug!(
+            election = %self.name,
+            candidate = %self.candidate,
+            fencing_token,
+            "Leadership acquired"
+        );
+        Ok(Some(Leadership {
+            election: self,
+            fencing_token,
+        }))
+    }
+
+    /// Tries to become the leader every `interval`, until it succeeds
+    #[cfg(feature = "tokio")]
+    pub async fn campaign(&self, interval: Duration) -> Result<Leadership<'_, Client>> {
+        loop {
+            if let Some(leadership) = self.try_acquire().await? {
+                return Ok(leadership);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Returns the current leader, or `None` if the lease is free or expired
+    pub async fn leader(&self) -> Result<Option<Leader>> {
+        self.create_table().await?;
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT candidate, fencing_token FROM {} WHERE name = ? AND expires_at >= {NOW_MS}",
+                    self.table
+                ),
+                &[self.name.as_str()],
+            ))
+            .await?;
+        Ok(result.rows.first().and_then(|row| match &row.values[..] {
+            [Value::Text { value: candidate }, Value::Integer {
+                value: fencing_token,
+            }] => Some(Leader {
+                candidate: candidate.clone(),
+                fencing_token: *fencing_token,
+            }),
+            _ => None,
+        }))
+    }
+
+    async fn create_table(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, candidate TEXT NOT NULL, \
+                 fencing_token INTEGER NOT NULL, expires_at INTEGER NOT NULL)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+}
+
+impl<Client: DatabaseClient + ?Sized> Leadership<'_, Client> {
+    /// Returns the fencing token of this leadership, to be passed along with the writes
+    /// of the leader
+    pub fn fencing_token(&self) -> i64 {
+        self.fencing_token
+    }
+
+    /// Extends the lease by its time to live from now.
+    /// Fails if the lease expired and was taken over by another candidate in the meantime.
+    pub async fn renew(&self) -> Result<()> {
+        let election = self.election;
+        let result = election
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {} SET expires_at = {NOW_MS} + ? \
+                     WHERE name = ? AND candidate = ? AND fencing_token = ?",
+                    election.table
+                ),
+                &[
+                    Value::from(election.ttl.as_millis() as i64),
+                    Value::from(election.name.as_str()),
+                    Value::from(election.candidate.as_str()),
+                    Value::from(self.fencing_token),
+                ],
+            ))
+            .await?;
+        if result.rows_affected == 0 {
+            anyhow::bail!("Leadership of election {} was lost", election.name);
+        }
+        Ok(())
+    }
+
+    /// Releases the lease, unless it was already taken over by another candidate.
+    /// The lease is expired rather than deleted, so that fencing tokens keep increasing.
+    pub async fn release(self) -> Result<()> {
+        let election = self.election;
+        election
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {} SET expires_at = 0 \
+                     WHERE name = ? AND candidate = ? AND fencing_token = ?",
+                    election.table
+                ),
+                &[
+                    Value::from(election.name.as_str()),
+                    Value::from(election.candidate.as_str()),
+                    Value::from(self.fencing_token),
+                ],
+            ))
+            .await?;
+        tracing::debug!(election = %election.name, "Leadership released");
+        Ok(())
+    }
+}
@@ -12,9 +12,46 @@
 pub mod statement;
 pub use statement::Statement;
 
+pub mod error;
+pub use error::Error;
+
+pub mod deadline;
+
+pub mod redact;
+
+pub mod mask;
+
+pub mod retry;
+pub use retry::{RateLimit, RetryBudget, RetryPolicy};
+
+pub mod stats;
+pub use stats::ClientStats;
+
+pub mod timings;
+pub use timings::Timings;
+
+pub mod server_stats;
+pub use server_stats::ServerStats;
+
+pub mod guardrails;
+
 pub mod proto;
 pub use proto::{BatchResult, Col, Value};
 
+pub mod sql;
+pub use sql::{affinity_of, fingerprint, Affinity};
+
+pub mod text;
+
+pub mod time;
+
+pub mod transport;
+
+mod pipeline;
+
+#[cfg(feature = "reqwest_backend")]
+mod idempotency;
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Row {
     pub values: Vec<Value>,
@@ -22,12 +59,66 @@ pub struct Row {
     pub value_map: std::collections::HashMap<String, Value>,
 }
 
+/// Rows returned by a statement. New fields may be added in minor versions, so results
+/// are built with `new()` rather than a struct literal.
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
 pub struct ResultSet {
     pub columns: Vec<String>,
     pub rows: Vec<Row>,
     pub rows_affected: u64,
     pub last_insert_rowid: Option<i64>,
+    /// Time spent in each phase of the execution of the statement,
+    /// collected when enabled with `Config::collect_timings()`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+    /// Statistics reported by the server for the statement, see `server_stats()`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    server_stats: Option<ServerStats>,
+    /// Whether the result may be out of date, see `is_stale()`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+}
+
+impl ResultSet {
+    /// Creates a result set, e.g. returned by a mock client in tests
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::ResultSet;
+    /// // Result of an INSERT
+    /// let result = ResultSet::new(vec![], vec![], 1, Some(42));
+    /// assert_eq!(result.last_insert_rowid, Some(42));
+    /// ```
+    pub fn new(
+        columns: Vec<String>,
+        rows: Vec<Row>,
+        rows_affected: u64,
+        last_insert_rowid: Option<i64>,
+    ) -> Self {
+        Self {
+            columns,
+            rows,
+            rows_affected,
+            last_insert_rowid,
+            timings: None,
+            server_stats: None,
+            stale: false,
+        }
+    }
+
+    /// Statistics reported by the server for the statement, e.g. the number of rows it read,
+    /// if the backend and the server report them. See the `server_stats` module for details.
+    pub fn server_stats(&self) -> Option<&ServerStats> {
+        self.server_stats.as_ref()
+    }
+
+    /// Whether the result may be out of date, because it was returned by a replica or from
+    /// a cache while the primary was unreachable. See the `stale` module for details.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
 }
 
 impl std::convert::From<proto::StmtResult> for ResultSet {
@@ -54,21 +145,110 @@ impl std::convert::From<proto::StmtResult> for ResultSet {
                 }
             })
             .collect();
-        ResultSet {
+        ResultSet::new(
             columns,
             rows,
-            rows_affected: value.affected_row_count,
-            last_insert_rowid: value.last_insert_rowid,
-        }
+            value.affected_row_count,
+            value.last_insert_rowid,
+        )
     }
 }
 
+pub mod de;
+
+pub mod checksum;
+
+pub mod float;
+
+pub mod json;
+
+pub mod numeric;
+pub use numeric::Numeric;
+
+pub mod decode;
+
 pub mod client;
 pub use client::{new_client, new_client_from_config, Config, DatabaseClient};
 
+pub mod dsn;
+
+pub mod factory;
+pub use factory::ClientFactory;
+
+pub mod discovery;
+
+pub mod reload;
+
+pub mod standby;
+
+pub mod pool;
+
+pub mod priority;
+pub use priority::Priority;
+
+pub mod stale;
+
+#[cfg(feature = "tokio")]
+pub mod hedge;
+
+#[cfg(feature = "tokio")]
+pub mod drain;
+
+pub mod scoped;
+pub use scoped::SchemaPrefixed;
+
+pub mod scope;
+pub use scope::Scope;
+
+pub mod attach;
+
+pub mod writes;
+
+pub mod buffer;
+
+pub mod vars;
+
+pub mod limit;
+
+pub mod batch;
+pub use batch::BatchBuilder;
+
+pub mod bulk;
+
+#[cfg(feature = "copy")]
+pub mod copy;
+
+pub mod migrations;
+
+pub mod strict;
+
+pub mod lock;
+
+pub mod outbox;
+
+pub mod queue;
+
+pub mod leader;
+
+pub mod counter;
+
+pub mod kv;
+
+pub mod docs;
+
+pub mod dataloader;
+
+pub mod resume;
+
 pub mod transaction;
 pub use transaction::Transaction;
 
+#[cfg(feature = "test-support")]
+pub mod testing;
+
+#[cfg(feature = "test-support")]
+pub mod conformance;
+
 #[cfg(feature = "workers_backend")]
 pub mod workers;
 
@@ -84,6 +264,24 @@ pub mod spin;
 #[cfg(feature = "hrana_backend")]
 pub mod hrana;
 
+#[cfg(feature = "http_backend")]
+pub mod http;
+
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+#[cfg(feature = "sqlar")]
+pub mod sqlar;
+
+#[cfg(feature = "encryption")]
+pub mod encryption;
+
+#[cfg(feature = "codegen")]
+pub mod codegen;
+
+#[cfg(feature = "repl")]
+pub mod repl;
+
 /// A macro for passing parameters to statements without having to manually
 /// define their types.
 ///
@@ -0,0 +1,104 @@
+//! `AutoLimit` bounds the rows returned by queries which forgot to, for interactive tools
+//! like REPLs or admin UIs, where an unbounded `SELECT` on a large table could pull
+//! millions of rows.
+//!
+//! Top-level `SELECT` statements, including ones starting with common table expressions,
+//! get a `LIMIT` appended unless they have one already. `LIMIT` clauses of subqueries
+//! do not count, and other statements are left untouched.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::limit::AutoLimit;
+//!
+//!   let db = AutoLimit::new(libsql_client::new_client().await?, 1000);
+//!   // Executed as SELECT * FROM events LIMIT 1000
+//!   let events = db.execute("SELECT * FROM events").await?;
+//!   // Executed as is
+//!   let latest = db.execute("SELECT * FROM events ORDER BY id DESC LIMIT 10").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Client wrapper which appends a `LIMIT` to queries lacking one
+pub struct AutoLimit<Client: DatabaseClient> {
+    inner: Client,
+    limit: u64,
+}
+
+impl<Client: DatabaseClient> AutoLimit<Client> {
+    /// Wraps a client, limiting queries to `limit` rows unless they set their own limit
+    pub fn new(inner: Client, limit: u64) -> Self {
+        Self { inner, limit }
+    }
+
+    /// Returns the limit appended to queries
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Appends the limit to a statement if it is a query lacking one, keeping its arguments
+    pub fn rewrite(&self, stmt: impl Into<Statement>) -> Statement {
+        let mut stmt: Statement = stmt.into();
+        if let Some(sql) = crate::sql::append_limit(&stmt.sql, self.limit) {
+            tracing::debug!("Limiting the query to {} rows: {}", self.limit, stmt.sql);
+            stmt.sql = sql;
+        }
+        stmt
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for AutoLimit<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        self.inner.execute(self.rewrite(stmt)).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| self.rewrite(s)).collect();
+        self.inner.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let stmts: Vec<Statement> = stmts.iter().map(|s| self.rewrite(s.clone())).collect();
+        self.inner.prewarm(&stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
@@ -1,17 +1,81 @@
-use crate::{proto, proto::StmtResult, BatchResult, Col, Statement, Value};
+use crate::client::Config;
+use crate::decode::BlobPolicy;
+use crate::proto::v2::{DescribeCol, DescribeParam, DescribeResult};
+use crate::stats::StatsCollector;
+use crate::text::InvalidUtf8;
+use crate::timings::Phase;
+use crate::{proto, proto::StmtResult, BatchResult, ClientStats, Col, Statement, Value};
 use async_trait::async_trait;
 
 use rusqlite::types::Value as RusqliteValue;
+use rusqlite::OpenFlags;
+
+#[cfg(feature = "local_session")]
+mod session;
+#[cfg(feature = "local_session")]
+pub use session::{ChangeCapture, Conflict, ConflictKind, Resolution};
 
 /// Database client. This is the main structure used to
 /// communicate with the database.
+/// Prepared statements are cached, and the cache is flushed if a statement
+/// fails because the schema changed, e.g. after a migration.
+/// TEXT values which are not valid UTF-8 are read according to `Config::invalid_utf8()`.
 #[derive(Debug)]
 pub struct Client {
     inner: rusqlite::Connection,
+    stats: StatsCollector,
+    validate_batches: bool,
+    collect_timings: bool,
+    invalid_utf8: InvalidUtf8,
 }
 
 struct ValueWrapper(Value);
 
+/// Default capacity of the cache of prepared statements of rusqlite
+const PREPARED_CACHE_CAPACITY: usize = 16;
+
+/// URI and open flags of the database designated by a `file:` URL
+fn open_target(url: &url::Url) -> anyhow::Result<(String, OpenFlags)> {
+    if url.scheme() != "file" {
+        anyhow::bail!("Local URL needs to start with file:, got {url}");
+    }
+    let mode = url
+        .query_pairs()
+        .find(|(name, _)| name == "mode")
+        .map(|(_, mode)| mode.into_owned());
+    let mode_flags = match mode.as_deref() {
+        Some("ro") => OpenFlags::SQLITE_OPEN_READ_ONLY,
+        Some("rw") => OpenFlags::SQLITE_OPEN_READ_WRITE,
+        None | Some("rwc") => OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        Some("memory") => {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE
+                | OpenFlags::SQLITE_OPEN_MEMORY
+        }
+        Some(mode) => {
+            anyhow::bail!(
+                "Invalid mode for a local database: {mode}, expected ro, rw, rwc or memory"
+            )
+        }
+    };
+    let flags = mode_flags | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    // The url crate normalizes `file::memory:` into `file:///:memory:`,
+    // which SQLite would open as a file named `:memory:` in the root directory
+    let uri = match url.as_str().strip_prefix("file:///:memory:") {
+        Some(rest) => format!("file::memory:{rest}"),
+        None => url.to_string(),
+    };
+    Ok((uri, flags))
+}
+
+fn is_schema_change(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::SchemaChanged)
+}
+
+fn is_interrupted(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::OperationInterrupted)
+}
+
 impl From<ValueWrapper> for RusqliteValue {
     fn from(v: ValueWrapper) -> Self {
         match v.0 {
@@ -42,16 +106,73 @@ impl Client {
     /// # Arguments
     /// * `path` - path of the local database
     pub fn new(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
-        Ok(Self {
-            inner: rusqlite::Connection::open(path).map_err(|e| anyhow::anyhow!("{e}"))?,
-        })
+        let inner = rusqlite::Connection::open(path).map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(Self::with_connection(inner))
     }
 
     /// Establishes a new in-memory database and connects to it.
     pub fn in_memory() -> anyhow::Result<Self> {
-        Ok(Self {
-            inner: rusqlite::Connection::open(":memory:").map_err(|e| anyhow::anyhow!("{e}"))?,
-        })
+        let inner = rusqlite::Connection::open(":memory:").map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(Self::with_connection(inner))
+    }
+
+    /// Establishes a database client, given a `file:` URL.
+    /// The `mode` parameter sets how the database is opened, like in SQLite URIs:
+    /// `ro` (read-only), `rw` (read-write), `rwc` (read-write, created if missing,
+    /// the default) or `memory` (in-memory). Other parameters, e.g. `cache=shared`,
+    /// are interpreted by SQLite.
+    ///
+    /// # Arguments
+    /// * `url` - `file:` URL of the database, or `file::memory:` for an in-memory database
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use libsql_client::local::Client;
+    /// let db = Client::from_url("file:/tmp/example.db?mode=rwc").unwrap();
+    /// let scratch = Client::from_url("file::memory:").unwrap();
+    /// ```
+    pub fn from_url<T: TryInto<url::Url>>(url: T) -> anyhow::Result<Self>
+    where
+        <T as TryInto<url::Url>>::Error: std::fmt::Display,
+    {
+        let url = url
+            .try_into()
+            .map_err(|e| anyhow::anyhow!(format!("{e}")))?;
+        let (uri, flags) = open_target(&url)?;
+        let inner = rusqlite::Connection::open_with_flags(uri, flags)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(Self::with_connection(inner))
+    }
+
+    fn with_connection(inner: rusqlite::Connection) -> Self {
+        Self {
+            inner,
+            stats: StatsCollector::default(),
+            validate_batches: false,
+            collect_timings: false,
+            invalid_utf8: InvalidUtf8::default(),
+        }
+    }
+
+    /// Establishes a database client from a `Config` object.
+    /// Connection initialization statements, like `PRAGMA foreign_keys`,
+    /// are executed right after the database is opened.
+    pub fn from_config(config: Config) -> anyhow::Result<Self> {
+        let (uri, mut flags) = open_target(&config.url)?;
+        if config.read_only {
+            flags.remove(OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE);
+            flags.insert(OpenFlags::SQLITE_OPEN_READ_ONLY);
+        }
+        let inner = rusqlite::Connection::open_with_flags(uri, flags)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let mut client = Self::with_connection(inner);
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        client.invalid_utf8 = config.invalid_utf8;
+        let init_result = client.execute_batch(config.connection_statements())?;
+        crate::client::strip_init_results(init_result, usize::MAX)?;
+        Ok(client)
     }
 
     pub fn from_env() -> anyhow::Result<Self> {
@@ -86,56 +207,225 @@ impl Client {
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> anyhow::Result<BatchResult> {
+        // Executing statements and reading their rows happen in the same steps
+        crate::timings::measure(Phase::Server, || self.execute_batch(stmts))
+    }
+
+    /// Describes statements without executing them: their parameters and columns,
+    /// and whether they are read-only, e.g. for build-time tools generating typed query modules.
+    /// A statement which cannot be described, e.g. due to a syntax error, gets its own error.
+    ///
+    /// Unlike the other fields, `is_readonly` is not reported by SQLite but guessed from the
+    /// SQL text, erring on the side of caution: statements not known to be reads,
+    /// e.g. most pragmas, are described as writes.
+    ///
+    /// # Arguments
+    /// * `sqls` - SQL texts of the statements
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f() -> anyhow::Result<()> {
+    /// let db = libsql_client::local::Client::in_memory()?;
+    /// let sqls = vec!["SELECT :a + 1 AS b".to_string(), "EXPLAIN SELECT 1".to_string()];
+    /// let described = db.describe_all(sqls).await?;
+    /// let description = described[0].as_ref().unwrap();
+    /// assert_eq!(description.params[0].name.as_deref(), Some(":a"));
+    /// assert_eq!(description.cols[0].name, "b");
+    /// assert!(described[1].as_ref().unwrap().is_explain);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn describe_all(
+        &self,
+        sqls: Vec<String>,
+    ) -> anyhow::Result<Vec<Result<DescribeResult, proto::Error>>> {
+        Ok(sqls
+            .iter()
+            .map(|sql| {
+                self.describe(sql).map_err(|e| proto::Error {
+                    message: e.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Describes a single statement, see `describe_all()`
+    pub(crate) fn describe(&self, sql: &str) -> rusqlite::Result<DescribeResult> {
+        let stmt = self.inner.prepare(sql)?;
+        let params = (1..=stmt.parameter_count())
+            .map(|i| DescribeParam {
+                name: stmt.parameter_name(i).map(str::to_string),
+            })
+            .collect();
+        let cols = stmt
+            .columns()
+            .into_iter()
+            .map(|c| DescribeCol {
+                name: c.name().to_string(),
+                decltype: c.decl_type().map(str::to_string),
+            })
+            .collect();
+        Ok(DescribeResult {
+            params,
+            cols,
+            is_explain: stmt.is_explain() != 0,
+            // rusqlite does not expose sqlite3_stmt_readonly()
+            is_readonly: crate::sql::is_read_only(sql),
+        })
+    }
+
+    /// Executes an SQL script, e.g. a schema dump, statement by statement
+    #[cfg_attr(not(feature = "codegen"), allow(dead_code))]
+    pub(crate) fn execute_script(&self, sql: &str) -> anyhow::Result<()> {
+        self.inner
+            .execute_batch(sql)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
+
+    fn execute_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        crate::deadline::check()?;
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        crate::client::trace_batch(&stmts);
         let mut step_results = vec![];
         let mut step_errors = vec![];
-        for stmt in stmts {
-            let stmt = stmt.into();
-            let sql_string = &stmt.sql;
-            let params = rusqlite::params_from_iter(
-                stmt.args
+        'stmts: for stmt in stmts {
+            self.set_timeout(stmt.timeout);
+            let mut retried = false;
+            let (cols, rows) = loop {
+                let params = rusqlite::params_from_iter(
+                    stmt.args
+                        .iter()
+                        .cloned()
+                        .map(ValueWrapper)
+                        .map(RusqliteValue::from),
+                );
+                let mut prepared = match self.inner.prepare_cached(&stmt.sql) {
+                    Ok(prepared) => prepared,
+                    Err(e) => {
+                        step_results.push(None);
+                        step_errors.push(Some(proto::Error {
+                            message: e.to_string(),
+                        }));
+                        continue 'stmts;
+                    }
+                };
+                let cols: Vec<Col> = prepared
+                    .columns()
                     .into_iter()
-                    .map(ValueWrapper)
-                    .map(RusqliteValue::from),
-            );
-            let mut stmt = self.inner.prepare(sql_string)?;
-            let cols: Vec<Col> = stmt
-                .columns()
-                .into_iter()
-                .map(|c| Col {
-                    name: Some(c.name().to_string()),
-                })
-                .collect();
-            let mut rows = Vec::new();
-            let mut input_rows = match stmt.query(params) {
-                Ok(rows) => rows,
-                Err(e) => {
-                    step_results.push(None);
-                    step_errors.push(Some(proto::Error {
-                        message: e.to_string(),
-                    }));
-                    break;
+                    .map(|c| Col {
+                        name: Some(c.name().to_string()),
+                    })
+                    .collect();
+                let outcome = prepared.query(params).and_then(|mut input_rows| {
+                    let mut rows = Vec::new();
+                    let mut failure = None;
+                    // Stepping fails e.g. when the statement is interrupted by its timeout
+                    while let Some(row) = input_rows.next()? {
+                        if let Err(e) = stmt.limits.check_returned(rows.len() + 1) {
+                            failure = Some(e);
+                            break;
+                        }
+                        let cells = (0..cols.len())
+                            .map(|i| self.read_value(row.get_ref_unwrap(i), stmt.blob_policy))
+                            .collect::<anyhow::Result<Vec<Value>>>();
+                        match cells {
+                            Ok(cells) => rows.push(cells),
+                            Err(e) => {
+                                failure = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                    Ok((rows, failure))
+                });
+                drop(prepared);
+                match outcome {
+                    Ok((rows, None)) => break (cols, rows),
+                    Ok((_, Some(e))) => {
+                        step_results.push(None);
+                        step_errors.push(Some(proto::Error {
+                            message: e.to_string(),
+                        }));
+                        continue 'stmts;
+                    }
+                    // Cached statements were compiled against the old schema
+                    Err(e) if !retried && is_schema_change(&e) => {
+                        tracing::debug!("Schema changed, retrying with a fresh statement cache");
+                        self.inner.flush_prepared_statement_cache();
+                        retried = true;
+                    }
+                    Err(e) => {
+                        let message = match stmt.timeout {
+                            Some(timeout) if is_interrupted(&e) => {
+                                format!("Statement interrupted after exceeding its timeout of {timeout:?}")
+                            }
+                            _ => e.to_string(),
+                        };
+                        step_results.push(None);
+                        step_errors.push(Some(proto::Error { message }));
+                        continue 'stmts;
+                    }
                 }
             };
-            while let Some(row) = input_rows.next()? {
-                let cells = (0..cols.len())
-                    .map(|i| ValueWrapper::from(row.get::<usize, RusqliteValue>(i).unwrap()).0)
-                    .collect();
-                rows.push(cells)
-            }
-            // FIXME: affected_row_count and last_insert_rowid are not implemented yet
+            // The counters of the connection are left over from the last write,
+            // so they're only reported for the statements which changed rows
+            let writes_rows = !crate::sql::is_read_only(&stmt.sql)
+                && matches!(
+                    crate::sql::leading_keyword(&stmt.sql).as_deref(),
+                    Some("INSERT" | "UPDATE" | "DELETE" | "REPLACE" | "WITH")
+                );
             let stmt_result = StmtResult {
                 cols,
                 rows,
-                affected_row_count: 0,
-                last_insert_rowid: None,
+                affected_row_count: if writes_rows { self.inner.changes() } else { 0 },
+                last_insert_rowid: writes_rows.then(|| self.inner.last_insert_rowid()),
             };
             step_results.push(Some(stmt_result));
             step_errors.push(None);
         }
-        Ok(BatchResult {
+        self.set_timeout(None);
+        let result = BatchResult {
             step_results,
             step_errors,
-        })
+        };
+        self.stats.record_batch(&result);
+        Ok(result)
+    }
+
+    /// Interrupts statements running for longer than `timeout`, or removes the limit
+    fn set_timeout(&self, timeout: Option<std::time::Duration>) {
+        match timeout {
+            Some(timeout) => {
+                let deadline = std::time::Instant::now() + timeout;
+                // The handler is invoked every 1000 virtual machine instructions
+                self.inner
+                    .progress_handler(1000, Some(move || std::time::Instant::now() > deadline));
+            }
+            None => self.inner.progress_handler(0, None::<fn() -> bool>),
+        }
+    }
+
+    /// Converts a value read from a row, applying the policy for invalid UTF-8 to text
+    /// and the blob policy of the statement to blobs
+    fn read_value(
+        &self,
+        value: rusqlite::types::ValueRef<'_>,
+        blobs: BlobPolicy,
+    ) -> anyhow::Result<Value> {
+        match value {
+            rusqlite::types::ValueRef::Text(bytes) => self.invalid_utf8.decode(bytes),
+            rusqlite::types::ValueRef::Blob(bytes) => Ok(blobs.read(bytes)),
+            value => Ok(ValueWrapper::from(RusqliteValue::from(value)).0),
+        }
+    }
+
+    /// Returns cumulative counters describing the activity of this client
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
     }
 }
 
@@ -147,4 +437,36 @@ impl crate::DatabaseClient for Client {
     ) -> anyhow::Result<BatchResult> {
         self.raw_batch(stmts).await
     }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> anyhow::Result<()> {
+        // The cache must hold the statements along with the ones already in use
+        self.inner
+            .set_prepared_statement_cache_capacity(stmts.len() + PREPARED_CACHE_CAPACITY);
+        let errors = stmts.iter().enumerate().filter_map(|(idx, stmt)| {
+            let error = self.inner.prepare_cached(&stmt.sql).err()?;
+            Some((idx, stmt, error.to_string()))
+        });
+        crate::client::report_invalid(errors)
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        if !self.inner.is_autocommit() {
+            if let Err(e) = self.inner.execute_batch("ROLLBACK") {
+                tracing::debug!("Failed to roll back a dropped transaction: {e}");
+            }
+        }
+        true
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats()
+    }
 }
@@ -0,0 +1,135 @@
+//! Changeset capture with the SQLite session extension, for custom sync workflows:
+//! changes made to a set of tables are recorded, serialized as a changeset,
+//! and applied to another database, with a handler resolving conflicts.
+
+use std::panic::RefUnwindSafe;
+
+use rusqlite::session::{ChangesetItem, ConflictAction, ConflictType, Session};
+
+use super::Client;
+
+/// Kind of conflict met while applying a change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The row to update or delete exists, but its values differ from the original ones
+    Data,
+    /// The row to update or delete does not exist
+    NotFound,
+    /// The row to insert has the same primary key as an existing row
+    Conflict,
+    /// The change violates a constraint other than the primary key
+    Constraint,
+    /// Foreign key constraints are violated once the whole changeset is applied
+    ForeignKey,
+}
+
+/// Conflict met while applying a change
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    /// Kind of the conflict
+    pub kind: ConflictKind,
+    /// Table of the conflicting change, empty for foreign key conflicts
+    pub table: String,
+}
+
+/// Resolution of a conflict, returned by the conflict handler of `Client::apply_changeset()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Skips the conflicting change
+    Omit,
+    /// Replaces the existing row with the change, valid for `Data` and `Conflict` kinds
+    Replace,
+    /// Rolls back the whole changeset
+    Abort,
+}
+
+/// Recorder of the changes made to a set of tables, created with `Client::capture_changes()`.
+/// Changes are recorded until it's dropped.
+pub struct ChangeCapture<'a> {
+    session: Session<'a>,
+}
+
+impl ChangeCapture<'_> {
+    /// Returns whether no changes were recorded
+    pub fn is_empty(&self) -> bool {
+        self.session.is_empty()
+    }
+
+    /// Serializes the changes recorded so far as a changeset
+    pub fn changeset(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut changeset = Vec::new();
+        self.session
+            .changeset_strm(&mut changeset)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(changeset)
+    }
+}
+
+impl Client {
+    /// Starts recording the changes made to `tables` through this client,
+    /// or to all the tables if `tables` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use libsql_client::local::{Client, Resolution};
+    ///
+    ///   let db = Client::new("/tmp/device.db")?;
+    ///   let mut capture = db.capture_changes(&["notes"])?;
+    ///   db.execute("INSERT INTO notes (id, body) VALUES (1, 'hello')").await?;
+    ///   let changeset = capture.changeset()?;
+    ///
+    ///   let replica = Client::new("/tmp/replica.db")?;
+    ///   replica.apply_changeset(&changeset, |_conflict| Resolution::Replace)?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn capture_changes(&self, tables: &[&str]) -> anyhow::Result<ChangeCapture<'_>> {
+        let mut session = Session::new(&self.inner).map_err(|e| anyhow::anyhow!("{e}"))?;
+        if tables.is_empty() {
+            session.attach(None).map_err(|e| anyhow::anyhow!("{e}"))?;
+        }
+        for table in tables {
+            session
+                .attach(Some(table))
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+        }
+        Ok(ChangeCapture { session })
+    }
+
+    /// Applies a changeset produced by `ChangeCapture::changeset()`, atomically.
+    /// Conflicting changes are resolved by `on_conflict`.
+    pub fn apply_changeset<F>(&self, changeset: &[u8], on_conflict: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Conflict) -> Resolution + Send + RefUnwindSafe + 'static,
+    {
+        let mut input = changeset;
+        self.inner
+            .apply_strm(
+                &mut input,
+                None::<fn(&str) -> bool>,
+                move |kind: ConflictType, item: ChangesetItem| {
+                    let kind = match kind {
+                        ConflictType::SQLITE_CHANGESET_DATA => ConflictKind::Data,
+                        ConflictType::SQLITE_CHANGESET_NOTFOUND => ConflictKind::NotFound,
+                        ConflictType::SQLITE_CHANGESET_CONFLICT => ConflictKind::Conflict,
+                        ConflictType::SQLITE_CHANGESET_CONSTRAINT => ConflictKind::Constraint,
+                        ConflictType::SQLITE_CHANGESET_FOREIGN_KEY => ConflictKind::ForeignKey,
+                        _ => return ConflictAction::SQLITE_CHANGESET_ABORT,
+                    };
+                    let table = item
+                        .op()
+                        .map(|op| op.table_name().to_string())
+                        .unwrap_or_default();
+                    match on_conflict(&Conflict { kind, table }) {
+                        Resolution::Omit => ConflictAction::SQLITE_CHANGESET_OMIT,
+                        Resolution::Replace => ConflictAction::SQLITE_CHANGESET_REPLACE,
+                        Resolution::Abort => ConflictAction::SQLITE_CHANGESET_ABORT,
+                    }
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
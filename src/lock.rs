@@ -0,0 +1,156 @@
+//! `lock` implements advisory locks stored in a table of the database,
+//! e.g. for making sure that a single instance of a distributed cron job runs at a time.
+//!
+//! A lock is held by a random owner token until it's released or until its time to live
+//! expires, so that a crashed holder does not keep it forever. Expiry is based on the clock
+//! of the database, which all the contenders share.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::sql::NOW_MS;
+use crate::{DatabaseClient, Statement, Value};
+
+/// Table storing advisory locks
+const LOCKS_TABLE: &str = "_libsql_locks";
+
+/// Advisory lock held by this client, until it's released or it expires.
+/// Dropping the guard does not release the lock, which then expires after its time to live,
+/// since releasing takes a database call.
+pub struct LockGuard<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    name: String,
+    owner: String,
+    ttl: Duration,
+}
+
+/// Tries to acquire a lock, see `DatabaseClient::try_advisory_lock()`
+pub(crate) async fn try_lock<'a, Client: DatabaseClient + ?Sized>(
+    client: &'a Client,
+    name: &str,
+    ttl: Duration,
+) -> Result<Option<LockGuard<'a, Client>>> {
+    let table = crate::sql::quote_ident(LOCKS_TABLE);
+    client
+        .execute(format!(
+            "CREATE TABLE IF NOT EXISTS {table} (name TEXT PRIMARY KEY, owner TEXT NOT NULL, expires_at INTEGER NOT NULL)"
+        ))
+        .await?;
+    // The lock is taken over only if it expired, atomically
+    let result = client
+        .execute(Statement::with_args(
+            format!(
+                "INSERT INTO {table} (name, owner, expires_at) \
+                 VALUES (?, lower(hex(randomblob(16))), {NOW_MS} + ?) \
+                 ON CONFLICT (name) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at \
+                 WHERE expires_at < {NOW_MS} \
+                 RETURNING owner"
+            ),
+            &[Value::from(name), Value::from(ttl.as_millis() as i64)],
+        ))
+        .await?;
+    let owner = match result.rows.first().and_then(|row| row.values.first()) {
+        Some(Value::Text { value }) => value.clone(),
+        _ => return Ok(None),
+    };
+    tracing::debug!(name, "Advisory lock acquired");
+    Ok(Some(LockGuard {
+        client,
+        name: name.to_string(),
+        owner,
+        ttl,
+    }))
+}
+
+impl<Client: DatabaseClient + ?Sized> LockGuard<'_, Client> {
+    /// Returns the name of the lock
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Extends the lock by its time to live from now.
+    /// Fails if the lock expired and was taken over by another owner in the meantime.
+    pub async fn renew(&self) -> Result<()> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {} SET expires_at = {NOW_MS} + ? WHERE name = ? AND owner = ?",
+                    crate::sql::quote_ident(LOCKS_TABLE)
+                ),
+                &[
+                    Value::from(self.ttl.as_millis() as i64),
+                    Value::from(self.name.as_str()),
+                    Value::from(self.owner.as_str()),
+                ],
+            ))
+            .await?;
+        if result.rows_affected == 0 {
+            anyhow::bail!("Advisory lock {} was lost", self.name);
+        }
+        Ok(())
+    }
+
+    /// Releases the lock, unless it was already taken over by another owner
+    pub async fn release(self) -> Result<()> {
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "DELETE FROM {} WHERE name = ? AND owner = ?",
+                    crate::sql::quote_ident(LOCKS_TABLE)
+                ),
+                &[self.name.as_str(), self.owner.as_str()],
+            ))
+            .await?;
+        tracing::debug!(name = %self.name, "Advisory lock released");
+        Ok(())
+    }
+
+    /// Runs `work` while holding the lock, renewing it every third of its time to live.
+    /// Fails without waiting for `work` to finish if the lock is lost.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use std::time::Duration;
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   if let Some(lock) = db.try_advisory_lock("nightly-report", Duration::from_secs(30)).await? {
+    ///       lock.hold(async {
+    ///           // Runs on a single instance at a time
+    ///       })
+    ///       .await?;
+    ///       lock.release().await?;
+    ///   }
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn hold<F: std::future::Future>(&self, work: F) -> Result<F::Output> {
+        use std::future::Future;
+        use std::task::Poll;
+
+        let period = (self.ttl / 3).max(Duration::from_millis(1));
+        // Completes only once a renewal fails
+        let renewals = async {
+            loop {
+                tokio::time::sleep(period).await;
+                if let Err(e) = self.renew().await {
+                    return e;
+                }
+            }
+        };
+        let mut work = std::pin::pin!(work);
+        let mut renewals = std::pin::pin!(renewals);
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(output) = work.as_mut().poll(cx) {
+                return Poll::Ready(Ok(output));
+            }
+            renewals.as_mut().poll(cx).map(Err)
+        })
+        .await
+    }
+}
@@ -0,0 +1,230 @@
+//! `mask` hides personal data from environments which should not see it, e.g. a staging
+//! deployment reading from a replica of the production database, by masking the values of
+//! designated columns in the results returned by a client.
+//!
+//! Columns are designated by their name in results, case-insensitively, so a column selected
+//! under another name, e.g. `SELECT email AS contact`, is not masked unless that name is
+//! designated as well. `NULL` is never masked, and masked values are text, except with
+//! `Mask::Null`. Masking only applies to results: to make sure such an environment does not
+//! write to production either, combine it with `Config::read_only()`.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::mask::{Mask, Masked};
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let db = Masked::new(db)
+//!       .column("email", Mask::Email)
+//!       .column("phone", Mask::Phone)
+//!       .column("customer_id", Mask::Hash);
+//!   // ann.smith@example.com is returned as a***@example.com
+//!   let users = db.execute("SELECT email, phone FROM users").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement, Value};
+
+/// How the values of a column are masked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mask {
+    /// Keeps the first character of the local part and the domain of an email address,
+    /// e.g. `a***@example.com`
+    Email,
+    /// Keeps the last 2 digits and the formatting of a phone number, e.g. `+** * ** ** ** 78`
+    Phone,
+    /// Keeps the last characters of the value, replacing the others with `*`
+    KeepLast(usize),
+    /// Replaces the value with a hash, so that equal values still match, e.g. to join tables.
+    /// Hashes are not salted, so they do not protect values which are easy to guess.
+    Hash,
+    /// Replaces the value with a fixed text
+    Fixed(String),
+    /// Replaces the value with `NULL`
+    Null,
+}
+
+impl Mask {
+    /// Masks a value. NULLs are never masked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libsql_client::mask::Mask;
+    /// use libsql_client::Value;
+    ///
+    /// let masked = |mask: Mask, value: Value| match mask.apply(&value) {
+    ///     Value::Text { value } => value,
+    ///     value => panic!("{value:?}"),
+    /// };
+    /// assert_eq!(masked(Mask::Email, Value::from("ann@example.com")), "a***@example.com");
+    /// assert_eq!(masked(Mask::Phone, Value::from("555-0142")), "***-**42");
+    /// assert_eq!(masked(Mask::KeepLast(4), Value::from(4111111111111111i64)), "************1111");
+    /// assert!(matches!(Mask::Email.apply(&Value::Null), Value::Null));
+    /// ```
+    pub fn apply(&self, value: &Value) -> Value {
+        let text = match value {
+            Value::Null => return Value::Null,
+            Value::Integer { value } => value.to_string(),
+            Value::Float { value } => value.to_string(),
+            Value::Text { value } => value.clone(),
+            Value::Blob { value } => String::from_utf8_lossy(value).into_owned(),
+        };
+        let masked = match self {
+            Mask::Email => match text.split_once('@') {
+                Some((local, domain)) => {
+                    let first: String = local.chars().take(1).collect();
+                    format!("{first}***@{domain}")
+                }
+                None => "***".to_string(),
+            },
+            Mask::Phone => {
+                let digits = text.chars().filter(char::is_ascii_digit).count();
+                let mut seen = 0;
+                text.chars()
+                    .map(|c| {
+                        if !c.is_ascii_digit() {
+                            return c;
+                        }
+                        seen += 1;
+                        if seen + 2 > digits {
+                            c
+                        } else {
+                            '*'
+                        }
+                    })
+                    .collect()
+            }
+            Mask::KeepLast(keep) => {
+                let len = text.chars().count();
+                text.chars()
+                    .enumerate()
+                    .map(|(i, c)| if i + keep < len { '*' } else { c })
+                    .collect()
+            }
+            Mask::Hash => format!("hash:{:016x}", crate::redact::fnv1a(text.as_bytes())),
+            Mask::Fixed(fixed) => fixed.clone(),
+            Mask::Null => return Value::Null,
+        };
+        Value::Text { value: masked }
+    }
+}
+
+/// Client wrapper masking the values of designated columns in results
+pub struct Masked<Client: DatabaseClient> {
+    inner: Client,
+    columns: Vec<(String, Mask)>,
+}
+
+impl<Client: DatabaseClient> Masked<Client> {
+    /// Wraps a client, without any masked column yet
+    pub fn new(inner: Client) -> Self {
+        Self {
+            inner,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Masks the values of the result columns with the given name, in every table
+    pub fn column(mut self, name: impl Into<String>, mask: Mask) -> Self {
+        let name = name.into().to_ascii_lowercase();
+        self.columns.retain(|(column, _)| *column != name);
+        self.columns.push((name, mask));
+        self
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+
+    fn mask_of(&self, name: &str) -> Option<&Mask> {
+        let name = name.to_ascii_lowercase();
+        self.columns
+            .iter()
+            .find(|(column, _)| *column == name)
+            .map(|(_, mask)| mask)
+    }
+
+    /// Returns the masks of the given columns, or `None` if none of them is masked
+    fn masks<'c>(&self, columns: impl Iterator<Item = &'c str>) -> Option<Vec<Option<&Mask>>> {
+        let masks: Vec<Option<&Mask>> = columns.map(|name| self.mask_of(name)).collect();
+        masks.iter().any(Option::is_some).then_some(masks)
+    }
+}
+
+fn mask_row(masks: &[Option<&Mask>], values: &mut [Value]) {
+    for (value, mask) in values.iter_mut().zip(masks) {
+        if let Some(mask) = mask {
+            *value = mask.apply(value);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for Masked<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let mut result = self.inner.execute(stmt).await?;
+        let Some(masks) = self.masks(result.columns.iter().map(String::as_str)) else {
+            return Ok(result);
+        };
+        for row in result.rows.iter_mut() {
+            mask_row(&masks, &mut row.values);
+            #[cfg(feature = "mapping_names_to_values_in_rows")]
+            for (column, value) in result.columns.iter().zip(&row.values) {
+                row.value_map.insert(column.clone(), value.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let mut result = self.inner.raw_batch(stmts).await?;
+        for step in result.step_results.iter_mut().flatten() {
+            let names = step.cols.iter().map(|c| c.name.as_deref().unwrap_or(""));
+            let Some(masks) = self.masks(names) else {
+                continue;
+            };
+            for row in step.rows.iter_mut() {
+                mask_row(&masks, row);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.inner.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
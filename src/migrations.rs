@@ -0,0 +1,751 @@
+//! `migrations` helps evolving the schema of a database: `diff()` compares
+//! the current schema with the desired one and produces candidate statements
+//! to migrate from the former to the latter, e.g. for code generation or CLI tools.
+//!
+//! The candidates are meant to be reviewed: changes which lose data are flagged
+//! as destructive, and changes which SQLite cannot apply with `ALTER TABLE`,
+//! e.g. a new type of an existing column, are reported as warnings instead,
+//! since they require rebuilding the table.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::migrations::{diff, Schema};
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let current = Schema::introspect(&db).await?;
+//!   let desired = Schema::parse(&std::fs::read_to_string("schema.sql")?)?;
+//!   let plan = diff(&current, &desired);
+//!   for step in &plan.steps {
+//!       let marker = if step.destructive { "-- DESTRUCTIVE\n" } else { "" };
+//!       println!("{marker}{};", step.sql);
+//!   }
+//!   for warning in &plan.warnings {
+//!       eprintln!("warning: {warning}");
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+//!
+//! `assert_schema()` checks at startup that the schema of a database is the one the
+//! application expects, e.g. the schema script its structs were generated from with the
+//! `codegen` module, or a snapshot saved with `Schema::to_sql()`, and fails with a readable
+//! list of differences otherwise, instead of queries failing later on:
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::migrations::Schema;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let expected = Schema::parse(&std::fs::read_to_string("schema.sql")?)?;
+//!   db.assert_schema(&expected).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+//!
+//! `Migrator` applies versioned migrations, recording a checksum of each of them
+//! so that a migration which was modified after being applied is detected,
+//! instead of environments silently drifting apart.
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, tokenize, unquote, Token};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table recording applied migrations
+const DEFAULT_MIGRATIONS_TABLE: &str = "_libsql_migrations";
+
+/// Kind of a schema object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl ObjectKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::Table => "TABLE",
+            Self::Index => "INDEX",
+            Self::View => "VIEW",
+            Self::Trigger => "TRIGGER",
+        }
+    }
+}
+
+/// Object of a schema, along with the statement creating it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaObject {
+    pub kind: ObjectKind,
+    /// Name of the object, unquoted
+    pub name: String,
+    /// `CREATE` statement of the object
+    pub sql: String,
+}
+
+/// Set of tables, indexes, views and triggers of a database
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    pub objects: Vec<SchemaObject>,
+}
+
+impl Schema {
+    /// Parses the `CREATE` statements of a schema script.
+    /// Other statements, e.g. inserting seed data, are ignored.
+    pub fn parse(ddl: &str) -> Result<Self> {
+        let mut objects = Vec::new();
+        for sql in crate::sql::split_statements(ddl) {
+            if crate::sql::leading_keyword(sql).as_deref() != Some("CREATE") {
+                continue;
+            }
+            let (kind, name) = object_header(sql)
+                .ok_or_else(|| anyhow::anyhow!("Cannot parse schema statement: {sql}"))?;
+            objects.push(SchemaObject {
+                kind,
+                name,
+                sql: sql.to_string(),
+            });
+        }
+        Ok(Self { objects })
+    }
+
+    /// Reads the schema of the main database of a client, from `sqlite_schema`.
+    /// Internal objects, e.g. indexes created for UNIQUE constraints, are skipped.
+    pub async fn introspect<C: DatabaseClient + ?Sized>(client: &C) -> Result<Self> {
+        let result = client
+            .execute(
+                "SELECT type, name, sql FROM sqlite_schema \
+                 WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' ORDER BY rowid",
+            )
+            .await?;
+        let objects = result
+            .rows
+            .iter()
+            .filter_map(|row| {
+                let text = |i: usize| match row.values.get(i) {
+                    Some(Value::Text { value }) => Some(value.clone()),
+                    _ => None,
+                };
+                let kind = match text(0)?.as_str() {
+                    "table" => ObjectKind::Table,
+                    "index" => ObjectKind::Index,
+                    "view" => ObjectKind::View,
+                    "trigger" => ObjectKind::Trigger,
+                    _ => return None,
+                };
+                Some(SchemaObject {
+                    kind,
+                    name: text(1)?,
+                    sql: text(2)?,
+                })
+            })
+            .collect();
+        Ok(Self { objects })
+    }
+
+    /// Returns the `CREATE` statements of the schema as a script, e.g. to save a snapshot
+    /// of a schema which `parse()` reads back
+    pub fn to_sql(&self) -> String {
+        self.objects
+            .iter()
+            .map(|object| format!("{};\n", object.sql.trim_end().trim_end_matches(';')))
+            .collect()
+    }
+
+    fn find(&self, kind: ObjectKind, name: &str) -> Option<&SchemaObject> {
+        self.objects
+            .iter()
+            .find(|o| o.kind == kind && o.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Candidate statement of a migration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub sql: String,
+    /// Whether the statement loses data, i.e. drops a table or a column
+    pub destructive: bool,
+}
+
+/// Candidate statements migrating a schema to another, see `diff()`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// Statements to execute, in order
+    pub steps: Vec<Step>,
+    /// Changes which could not be expressed as statements and need manual work
+    pub warnings: Vec<String>,
+}
+
+impl MigrationPlan {
+    /// Returns whether the schemas are the same
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty() && self.warnings.is_empty()
+    }
+
+    /// Returns whether any of the steps loses data
+    pub fn is_destructive(&self) -> bool {
+        self.steps.iter().any(|step| step.destructive)
+    }
+
+    fn push(&mut self, sql: String, destructive: bool) {
+        self.steps.push(Step { sql, destructive });
+    }
+}
+
+/// Produces candidate statements migrating the `current` schema to the `desired` one.
+/// Objects are matched by kind and name, and compared by the text of their statements,
+/// regardless of whitespace, comments and keyword case:
+/// * new objects are created, and removed ones are dropped,
+/// * indexes, views and triggers which changed are dropped and created again,
+/// * columns added to a table are added with `ALTER TABLE ... ADD COLUMN`,
+///   and removed ones are dropped with `ALTER TABLE ... DROP COLUMN`,
+/// * other changes of a table, e.g. of a column type or a constraint, are warnings.
+pub fn diff(current: &Schema, desired: &Schema) -> MigrationPlan {
+    let mut plan = MigrationPlan::default();
+    let changed = |object: &SchemaObject, other: &Schema| {
+        other
+            .find(object.kind, &object.name)
+            .is_none_or(|o| normalize(&o.sql) != normalize(&object.sql))
+    };
+
+    // Dependent objects go first, so that they don't refer to dropped tables and columns
+    for object in current.objects.iter().rev() {
+        if object.kind != ObjectKind::Table && changed(object, desired) {
+            let sql = format!(
+                "DROP {} {}",
+                object.kind.keyword(),
+                quote_ident(&object.name)
+            );
+            plan.push(sql, false);
+        }
+    }
+    for object in &desired.objects {
+        if object.kind != ObjectKind::Table {
+            continue;
+        }
+        match current.find(ObjectKind::Table, &object.name) {
+            None => plan.push(object.sql.clone(), false),
+            Some(existing) if normalize(&existing.sql) != normalize(&object.sql) => {
+                diff_table(&mut plan, existing, object)
+            }
+            Some(_) => (),
+        }
+    }
+    for object in current.objects.iter().rev() {
+        if object.kind == ObjectKind::Table && desired.find(object.kind, &object.name).is_none() {
+            plan.push(format!("DROP TABLE {}", quote_ident(&object.name)), true);
+        }
+    }
+    for object in &desired.objects {
+        if object.kind != ObjectKind::Table && changed(object, current) {
+            plan.push(object.sql.clone(), false);
+        }
+    }
+    plan
+}
+
+/// Checks that the schema of the main database of a client contains the objects of the
+/// `expected` schema, failing with the list of differences otherwise. Objects are compared
+/// like in `diff()`, and columns of tables are compared one by one. Objects which are not
+/// expected are ignored, e.g. the tables of the application unrelated to the expected schema,
+/// or the ones created by the crate, but columns which are not expected are differences.
+pub async fn assert_schema<C: DatabaseClient + ?Sized>(
+    client: &C,
+    expected: &Schema,
+) -> Result<()> {
+    let live = Schema::introspect(client).await?;
+    let differences = differences(&live, expected);
+    if !differences.is_empty() {
+        anyhow::bail!(
+            "The schema of the database differs from the expected one:\n  {}",
+            differences.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Describes how the objects of the `expected` schema differ in the `live` one
+fn differences(live: &Schema, expected: &Schema) -> Vec<String> {
+    let mut differences = Vec::new();
+    for object in &expected.objects {
+        let kind = object.kind.keyword().to_ascii_lowercase();
+        let Some(existing) = live.find(object.kind, &object.name) else {
+            differences.push(format!("missing {kind} {}", object.name));
+            continue;
+        };
+        if normalize(&existing.sql) == normalize(&object.sql) {
+            continue;
+        }
+        let tables = match object.kind {
+            ObjectKind::Table => table_definition(&existing.sql).zip(table_definition(&object.sql)),
+            _ => None,
+        };
+        let Some((old, new)) = tables else {
+            differences.push(format!(
+                "{kind} {} is `{}` instead of `{}`",
+                object.name, existing.sql, object.sql
+            ));
+            continue;
+        };
+        for column in &new.columns {
+            match old.column(&column.name) {
+                None => differences.push(format!(
+                    "table {}: missing column `{}`",
+                    object.name, column.definition
+                )),
+                Some(current)
+                    if normalize(&current.definition) != normalize(&column.definition) =>
+                {
+                    differences.push(format!(
+                        "table {}: column {} is `{}` instead of `{}`",
+                        object.name, column.name, current.definition, column.definition
+                    ))
+                }
+                Some(_) => (),
+            }
+        }
+        for column in &old.columns {
+            if new.column(&column.name).is_none() {
+                differences.push(format!(
+                    "table {}: unexpected column `{}`",
+                    object.name, column.definition
+                ));
+            }
+        }
+        if old.constraints != new.constraints || old.options != new.options {
+            differences.push(format!(
+                "table {}: constraints or options differ from `{}`",
+                object.name, object.sql
+            ));
+        }
+    }
+    differences
+}
+
+/// Adds the steps migrating a table whose statement changed
+fn diff_table(plan: &mut MigrationPlan, current: &SchemaObject, desired: &SchemaObject) {
+    let table = quote_ident(&desired.name);
+    let (Some(old), Some(new)) = (
+        table_definition(&current.sql),
+        table_definition(&desired.sql),
+    ) else {
+        plan.warnings.push(format!(
+            "Table {} changed in a way which requires rebuilding it",
+            desired.name
+        ));
+        return;
+    };
+    if old.constraints != new.constraints || old.options != new.options {
+        plan.warnings.push(format!(
+            "Constraints or options of table {} changed, which requires rebuilding it",
+            desired.name
+        ));
+    }
+    for column in &new.columns {
+        match old.column(&column.name) {
+            None => {
+                let definition = normalize(&column.definition);
+                if ["PRIMARY KEY", "UNIQUE"]
+                    .iter()
+                    .any(|c| definition.contains(c))
+                    || (definition.contains("NOT NULL") && !definition.contains("DEFAULT"))
+                {
+                    plan.warnings.push(format!(
+                        "Column {} of table {} cannot be added with ALTER TABLE: \
+                         it requires a default value and no PRIMARY KEY or UNIQUE constraint",
+                        column.name, desired.name
+                    ));
+                }
+                plan.push(
+                    format!("ALTER TABLE {table} ADD COLUMN {}", column.definition),
+                    false,
+                );
+            }
+            Some(existing) if normalize(&existing.definition) != normalize(&column.definition) => {
+                plan.warnings.push(format!(
+                    "Column {} of table {} changed from `{}` to `{}`, which requires rebuilding the table",
+                    column.name, desired.name, existing.definition, column.definition
+                ));
+            }
+            Some(_) => (),
+        }
+    }
+    for column in &old.columns {
+        if new.column(&column.name).is_none() {
+            plan.push(
+                format!(
+                    "ALTER TABLE {table} DROP COLUMN {}",
+                    quote_ident(&column.name)
+                ),
+                true,
+            );
+        }
+    }
+}
+
+/// Returns the kind and unquoted name of the object created by a `CREATE` statement
+fn object_header(sql: &str) -> Option<(ObjectKind, String)> {
+    let tokens = tokenize(sql);
+    let (idx, kind) = tokens
+        .iter()
+        .enumerate()
+        .take(5)
+        .find_map(|(i, t)| match t {
+            Token::Word(w) => {
+                let kind = match w.to_ascii_uppercase().as_str() {
+                    "TABLE" => ObjectKind::Table,
+                    "INDEX" => ObjectKind::Index,
+                    "VIEW" => ObjectKind::View,
+                    "TRIGGER" => ObjectKind::Trigger,
+                    _ => return None,
+                };
+                Some((i, kind))
+            }
+            _ => None,
+        })?;
+    let is_word = |i: usize, w: &str| matches!(tokens.get(i), Some(Token::Word(t)) if t.eq_ignore_ascii_case(w));
+    let mut i = idx + 1;
+    if is_word(i, "IF") {
+        i += if is_word(i + 1, "NOT") { 3 } else { 2 };
+    }
+    // Skips the schema of a qualified name
+    if tokens.get(i + 1) == Some(&Token::Punct(".")) {
+        i += 2;
+    }
+    match tokens.get(i)? {
+        Token::Word(name) | Token::QuotedIdent(name) | Token::String(name) => {
+            Some((kind, unquote(name.trim_matches('\''))))
+        }
+        _ => None,
+    }
+}
+
+/// Column of a table, as declared in its `CREATE TABLE` statement
+struct ColumnDefinition {
+    name: String,
+    /// Whole column definition, e.g. `email TEXT NOT NULL`
+    definition: String,
+}
+
+/// Parts of a `CREATE TABLE` statement
+struct TableDefinition {
+    columns: Vec<ColumnDefinition>,
+    /// Table constraints, normalized
+    constraints: Vec<String>,
+    /// Table options after the parenthesis, e.g. `STRICT`, normalized
+    options: String,
+}
+
+impl TableDefinition {
+    fn column(&self, name: &str) -> Option<&ColumnDefinition> {
+        self.columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Splits a `CREATE TABLE` statement into column definitions, table constraints and options,
+/// or returns `None` for other forms, e.g. `CREATE TABLE ... AS SELECT`
+fn table_definition(sql: &str) -> Option<TableDefinition> {
+    let tokens = tokenize(sql);
+    let offset = |t: &str| t.as_ptr() as usize - sql.as_ptr() as usize;
+    let open = tokens.iter().position(|t| *t == Token::Punct("("))?;
+    let mut definitions = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    let mut close = None;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => {
+                depth -= 1;
+                if depth == 0 {
+                    definitions.push(&tokens[start..i]);
+                    close = Some(i);
+                    break;
+                }
+            }
+            Token::Punct(",") if depth == 1 => {
+                definitions.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    let close = close?;
+    let text = |tokens: &[Token]| match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => {
+            sql[offset(first.text())..offset(last.text()) + last.text().len()].to_string()
+        }
+        _ => String::new(),
+    };
+    let mut definition = TableDefinition {
+        columns: Vec::new(),
+        constraints: Vec::new(),
+        options: normalize(&text(&tokens[close + 1..])),
+    };
+    for tokens in definitions.into_iter().filter(|t| !t.is_empty()) {
+        let is_constraint = matches!(tokens[0], Token::Word(w) if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|k| w.eq_ignore_ascii_case(k)));
+        if is_constraint {
+            definition.constraints.push(normalize(&text(tokens)));
+        } else {
+            definition.columns.push(ColumnDefinition {
+                name: unquote(tokens[0].text()),
+                definition: text(tokens),
+            });
+        }
+    }
+    Some(definition)
+}
+
+/// Normalizes a statement for comparisons: keywords are uppercased,
+/// identifiers are unquoted, and whitespace and comments are removed.
+/// `IF NOT EXISTS` is removed as well, like in the statements stored by SQLite.
+fn normalize(sql: &str) -> String {
+    let mut parts: Vec<String> = tokenize(sql)
+        .into_iter()
+        .filter(|t| *t != Token::Punct(";"))
+        .map(|token| match token {
+            Token::Word(w) => w.to_ascii_uppercase(),
+            Token::QuotedIdent(q) => unquote(q).to_ascii_uppercase(),
+            token => token.text().to_string(),
+        })
+        .collect();
+    if parts.first().map(|p| p.as_str()) == Some("CREATE") {
+        if let Some(i) = (0..parts.len().min(8))
+            .find(|&i| parts[i..].starts_with(&["IF", "NOT", "EXISTS"].map(String::from)))
+        {
+            parts.drain(i..i + 3);
+        }
+    }
+    parts.join(" ")
+}
+
+/// Versioned migration script, applied at most once by `Migrator`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    /// Statements of the migration, separated by semicolons
+    pub sql: String,
+}
+
+impl Migration {
+    /// Creates a migration with the given version, name and statements
+    pub fn new(version: i64, name: impl Into<String>, sql: impl Into<String>) -> Self {
+        Self {
+            version,
+            name: name.into(),
+            sql: sql.into(),
+        }
+    }
+
+    /// Reads the migrations of a directory, from files named `{version}_{name}.sql`,
+    /// e.g. `0001_create_users.sql`, ordered by version
+    pub fn from_directory(dir: impl AsRef<std::path::Path>) -> Result<Vec<Self>> {
+        let mut migrations = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("sql") {
+                continue;
+            }
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            let (version, name) = stem.split_once('_').unwrap_or((stem, ""));
+            let version = version.parse().map_err(|_| {
+                anyhow::anyhow!("Migration file {path:?} does not start with a version number")
+            })?;
+            migrations.push(Self::new(version, name, std::fs::read_to_string(&path)?));
+        }
+        migrations.sort_by_key(|m| m.version);
+        Ok(migrations)
+    }
+
+    /// Checksum of the statements of the migration, a hex-encoded 64-bit FNV-1a hash.
+    /// Line endings are normalized, so that checking out files on another platform
+    /// does not change it.
+    pub fn checksum(&self) -> String {
+        let hash = self
+            .sql
+            .replace("\r\n", "\n")
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |hash, b| {
+                (hash ^ b as u64).wrapping_mul(0x100000001b3)
+            });
+        format!("{hash:016x}")
+    }
+}
+
+/// Runner of migrations, recording the applied ones in a table of the database.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   use libsql_client::migrations::{Migration, Migrator};
+///
+///   let db = libsql_client::new_client().await?;
+///   let migrations = Migration::from_directory("migrations")?;
+///   // Fails if an applied migration was modified since
+///   let applied = Migrator::new(&db).run(&migrations).await?;
+///   println!("Applied migrations {applied:?}");
+///   # Ok(())
+///   # }
+/// ```
+pub struct Migrator<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Migrator<'a, Client> {
+    /// Creates a runner recording applied migrations in the `_libsql_migrations` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_MIGRATIONS_TABLE),
+        }
+    }
+
+    /// Sets the table recording applied migrations
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Applies the migrations which were not applied yet, in order of version,
+    /// each one in its own transaction along with its record.
+    /// Returns the versions of the migrations applied.
+    ///
+    /// Nothing is applied if the checksum of a migration applied before changed,
+    /// unless the change is accepted with `repair()` first.
+    pub async fn run(&self, migrations: &[Migration]) -> Result<Vec<i64>> {
+        let applied = self.applied().await?;
+        let changed: Vec<String> = changed(migrations, &applied)
+            .map(|(m, recorded)| {
+                format!(
+                    "{} ({}): recorded checksum {recorded}, now {}",
+                    m.version,
+                    m.name,
+                    m.checksum()
+                )
+            })
+            .collect();
+        if !changed.is_empty() {
+            anyhow::bail!(
+                "Migrations changed since they were applied: {}. \
+                 Restore them, or accept the changes with Migrator::repair()",
+                changed.join(", ")
+            );
+        }
+        let mut pending: Vec<&Migration> = migrations
+            .iter()
+            .filter(|m| !applied.iter().any(|(version, _)| *version == m.version))
+            .collect();
+        pending.sort_by_key(|m| m.version);
+        let mut versions = Vec::new();
+        for migration in pending {
+            tracing::info!(
+                version = migration.version,
+                name = %migration.name,
+                "Applying migration"
+            );
+            let mut stmts: Vec<Statement> = crate::sql::split_statements(&migration.sql)
+                .into_iter()
+                .map(Statement::new)
+                .collect();
+            stmts.push(Statement::with_args(
+                format!(
+                    "INSERT INTO {} (version, name, checksum) VALUES (?, ?, ?)",
+                    self.table
+                ),
+                &[
+                    Value::from(migration.version),
+                    Value::from(migration.name.as_str()),
+                    Value::from(migration.checksum()),
+                ],
+            ));
+            self.client.batch(stmts).await.map_err(|e| {
+                anyhow::anyhow!(
+                    "Migration {} ({}) failed: {e}",
+                    migration.version,
+                    migration.name
+                )
+            })?;
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    }
+
+    /// Accepts changes of applied migrations, recording their current checksums
+    /// without executing them again. Returns the versions of the migrations repaired.
+    pub async fn repair(&self, migrations: &[Migration]) -> Result<Vec<i64>> {
+        let applied = self.applied().await?;
+        let mut versions = Vec::new();
+        for (migration, recorded) in changed(migrations, &applied) {
+            tracing::warn!(
+                version = migration.version,
+                name = %migration.name,
+                recorded,
+                "Repairing the checksum of a changed migration"
+            );
+            self.client
+                .execute(Statement::with_args(
+                    format!("UPDATE {} SET checksum = ? WHERE version = ?", self.table),
+                    &[
+                        Value::from(migration.checksum()),
+                        Value::from(migration.version),
+                    ],
+                ))
+                .await?;
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    }
+
+    /// Returns the versions and checksums of applied migrations,
+    /// creating the table recording them if needed
+    async fn applied(&self) -> Result<Vec<(i64, String)>> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (version INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+                 checksum TEXT NOT NULL, applied_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')))",
+                self.table
+            ))
+            .await?;
+        let result = self
+            .client
+            .execute(format!("SELECT version, checksum FROM {}", self.table))
+            .await?;
+        Ok(result
+            .rows
+            .iter()
+            .filter_map(|row| match &row.values[..] {
+                [Value::Integer { value }, Value::Text { value: checksum }] => {
+                    Some((*value, checksum.clone()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Returns the migrations whose checksum differs from the recorded one,
+/// along with the recorded checksum
+fn changed<'m>(
+    migrations: &'m [Migration],
+    applied: &'m [(i64, String)],
+) -> impl Iterator<Item = (&'m Migration, &'m str)> {
+    migrations.iter().filter_map(move |m| {
+        applied
+            .iter()
+            .find(|(version, _)| *version == m.version)
+            .filter(|(_, checksum)| *checksum != m.checksum())
+            .map(|(_, checksum)| (m, checksum.as_str()))
+    })
+}
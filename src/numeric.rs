@@ -0,0 +1,212 @@
+//! `Numeric` holds a number as its exact decimal text, e.g. amounts of money stored
+//! in TEXT columns to avoid the rounding of REAL ones, so that reading them does not
+//! lose digits through `f64`.
+//!
+//! A `Numeric` is read from text values which are numeric literals, and from integers
+//! and floats, whose text is the shortest one reading back into the same value.
+//! It can be a field of a struct deserialized with `DatabaseClient::query_as()`, and
+//! converted into `rust_decimal::Decimal` or `num_bigint::BigInt` with the `rust_decimal`
+//! and `num-bigint` features.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Numeric};
+//!   #[derive(serde::Deserialize)]
+//!   struct Invoice {
+//!       id: i64,
+//!       total: Numeric,
+//!   }
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let invoices: Vec<Invoice> = db.query_as("SELECT id, total FROM invoices").await?;
+//!   for invoice in invoices {
+//!       println!("{}: {}", invoice.id, invoice.total);
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+
+use crate::Value;
+
+/// Number kept as its exact decimal text, e.g. `-12.500` or `1e30`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Numeric(String);
+
+impl Numeric {
+    /// Returns the text of the number, as read
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the number has neither a fractional part nor an exponent
+    pub fn is_integer(&self) -> bool {
+        !self.0.contains(['.', 'e', 'E'])
+    }
+
+    /// Converts the number into an integer, if it is one and fits in 64 bits
+    pub fn to_i64(&self) -> Option<i64> {
+        self.0.parse().ok()
+    }
+
+    /// Converts the number into a float, possibly rounding it
+    pub fn to_f64(&self) -> f64 {
+        // The text was validated as a numeric literal
+        self.0.parse().unwrap_or(f64::NAN)
+    }
+
+    /// Reads a number from an integer, a float or a numeric literal in a text value
+    pub fn from_value(value: &Value) -> Result<Numeric> {
+        match value {
+            Value::Integer { value } => Ok(Numeric(value.to_string())),
+            Value::Float { value } if value.is_finite() => Ok(Numeric(value.to_string())),
+            Value::Text { value } => value.parse(),
+            _ => anyhow::bail!(
+                "Cannot read a {} value as a number",
+                crate::de::type_name(value)
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for Numeric {
+    type Err = anyhow::Error;
+
+    /// Parses a numeric literal: an optional sign, digits with an optional decimal point,
+    /// and an optional exponent. Surrounding whitespace is ignored.
+    fn from_str(text: &str) -> Result<Numeric> {
+        let text = text.trim();
+        if !is_numeric_literal(text) {
+            anyhow::bail!("{text:?} is not a numeric literal");
+        }
+        Ok(Numeric(text.to_string()))
+    }
+}
+
+impl std::fmt::Display for Numeric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<i64> for Numeric {
+    fn from(value: i64) -> Numeric {
+        Numeric(value.to_string())
+    }
+}
+
+/// Numbers are bound as text, to keep their digits
+impl From<Numeric> for Value {
+    fn from(value: Numeric) -> Value {
+        Value::Text { value: value.0 }
+    }
+}
+
+impl TryFrom<&Value> for Numeric {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Value) -> Result<Numeric> {
+        Numeric::from_value(value)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Numeric {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NumericVisitor;
+
+        impl serde::de::Visitor<'_> for NumericVisitor {
+            type Value = Numeric;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a number or a numeric literal")
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Numeric, E> {
+                Ok(Numeric(value.to_string()))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Numeric, E> {
+                Ok(Numeric(value.to_string()))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Numeric, E> {
+                Numeric::from_value(&Value::Float { value }).map_err(E::custom)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Numeric, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(NumericVisitor)
+    }
+}
+
+impl serde::Serialize for Numeric {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<&Numeric> for rust_decimal::Decimal {
+    type Error = anyhow::Error;
+
+    /// Converts the number exactly, failing if it has too many digits
+    fn try_from(value: &Numeric) -> Result<rust_decimal::Decimal> {
+        let decimal = if value.0.contains(['e', 'E']) {
+            rust_decimal::Decimal::from_scientific(&value.0)
+        } else {
+            rust_decimal::Decimal::from_str_exact(&value.0)
+        };
+        decimal.map_err(|e| anyhow::anyhow!("Cannot convert {} into a decimal: {e}", value.0))
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl TryFrom<&Numeric> for num_bigint::BigInt {
+    type Error = anyhow::Error;
+
+    /// Converts the number, failing if it is not an integer
+    fn try_from(value: &Numeric) -> Result<num_bigint::BigInt> {
+        let digits = value.0.strip_prefix('+').unwrap_or(&value.0);
+        digits
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Cannot convert {} into an integer: {e}", value.0))
+    }
+}
+
+/// Checks the grammar of SQLite numeric literals, without hexadecimal integers
+pub(crate) fn is_numeric_literal(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        i += 1;
+    }
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+            *i += 1;
+        }
+        *i - start
+    };
+    let mut mantissa = digits(&mut i);
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        mantissa += digits(&mut i);
+    }
+    if mantissa == 0 {
+        return false;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    i == bytes.len()
+}
@@ -0,0 +1,266 @@
+//! `outbox` implements the transactional outbox pattern: messages are written in the same
+//! transaction as the business data they describe, and published afterwards by a poller,
+//! so that a message is published if and only if its transaction committed.
+//!
+//! The poller claims batches of messages for a lease period: a message which is neither
+//! marked as processed nor failed before its lease expires, e.g. because the poller crashed,
+//! can be claimed again. Publishing is therefore at-least-once.
+//!
+//! ```rust,no_run
+//!   # async fn publish(topic: &str, payload: &str) -> anyhow::Result<()> { Ok(()) }
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{args, DatabaseClient, Statement};
+//!   use libsql_client::outbox::Outbox;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let outbox = Outbox::new(&db);
+//!   outbox.create().await?;
+//!
+//!   // Writer
+//!   db.batch([
+//!       Statement::with_args("INSERT INTO orders (id, total) VALUES (?, ?)", args!(42, 99.5)),
+//!       outbox.message("order_created", &serde_json::json!({ "id": 42 }))?,
+//!   ])
+//!   .await?;
+//!
+//!   // Poller
+//!   for message in outbox.claim(100, Duration::from_secs(30)).await? {
+//!       match publish(&message.topic, &message.payload).await {
+//!           Ok(()) => outbox.mark_processed(&message).await?,
+//!           Err(e) => outbox.mark_failed(&message, &e.to_string(), Duration::from_secs(10)).await?,
+//!       }
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing outbox messages
+const DEFAULT_OUTBOX_TABLE: &str = "_libsql_outbox";
+
+/// Default number of attempts after which a message is not claimed anymore
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+
+/// Message claimed from the outbox
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub topic: String,
+    /// Payload of the message, serialized as JSON
+    pub payload: String,
+    /// Number of times the message was claimed, including this one
+    pub attempts: u32,
+    /// Token proving the claim, which expires with its lease
+    pub claim_token: String,
+}
+
+impl OutboxMessage {
+    /// Deserializes the payload of the message
+    pub fn payload<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_str(&self.payload)?)
+    }
+}
+
+/// Outbox stored in a table of the database, `_libsql_outbox` by default
+pub struct Outbox<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+    max_attempts: u32,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Outbox<'a, Client> {
+    /// Creates an outbox stored in the `_libsql_outbox` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_OUTBOX_TABLE),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Sets the table storing outbox messages
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Sets the number of attempts after which a failing message is not claimed anymore,
+    /// and stays in the table for inspection. 10 by default.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Creates the table of the outbox, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .batch([
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (\
+                     id INTEGER PRIMARY KEY AUTOINCREMENT, \
+                     topic TEXT NOT NULL, \
+                     payload TEXT NOT NULL, \
+                     created_at INTEGER NOT NULL DEFAULT ({NOW_MS}), \
+                     available_at INTEGER NOT NULL DEFAULT ({NOW_MS}), \
+                     attempts INTEGER NOT NULL DEFAULT 0, \
+                     claim_token TEXT, \
+                     claimed_until INTEGER, \
+                     processed_at INTEGER, \
+                     last_error TEXT)",
+                    self.table
+                ),
+                format!(
+                    "CREATE INDEX IF NOT EXISTS {} ON {} (processed_at, available_at)",
+                    quote_ident(&format!("{}_pending", crate::sql::unquote(&self.table))),
+                    self.table
+                ),
+            ])
+            .await?;
+        Ok(())
+    }
+
+    /// Returns a statement writing a message, to be executed within the transaction
+    /// or batch which writes the business data
+    pub fn message(&self, topic: &str, payload: &impl serde::Serialize) -> Result<Statement> {
+        let payload = serde_json::to_string(payload)?;
+        Ok(Statement::with_args(
+            format!("INSERT INTO {} (topic, payload) VALUES (?, ?)", self.table),
+            &[topic, payload.as_str()],
+        ))
+    }
+
+    /// Claims up to `limit` messages available for publishing, oldest first,
+    /// for the duration of `lease`
+    pub async fn claim(&self, limit: usize, lease: Duration) -> Result<Vec<OutboxMessage>> {
+        let table = &self.table;
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {table} SET claim_token = lower(hex(randomblob(16))), \
+                     claimed_until = {NOW_MS} + ?, attempts = attempts + 1 \
+                     WHERE id IN (SELECT id FROM {table} \
+                       WHERE processed_at IS NULL AND available_at <= {NOW_MS} AND attempts < ? \
+                       AND (claimed_until IS NULL OR claimed_until < {NOW_MS}) \
+                       ORDER BY id LIMIT ?) \
+                     RETURNING id, topic, payload, attempts, claim_token"
+                ),
+                &[
+                    Value::from(lease.as_millis() as i64),
+                    Value::from(self.max_attempts as i64),
+                    Value::from(limit as i64),
+                ],
+            ))
+            .await?;
+        let mut messages: Vec<OutboxMessage> = result
+            .rows
+            .iter()
+            .filter_map(|row| match &row.values[..] {
+                [Value::Integer { value: id }, Value::Text { value: topic }, Value::Text { value: payload }, Value::Integer { value: attempts }, Value::Text { value: claim_token }] => {
+                    Some(OutboxMessage {
+                        id: *id,
+                        topic: topic.clone(),
+                        payload: payload.clone(),
+                        attempts: *attempts as u32,
+                        claim_token: claim_token.clone(),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        // RETURNING does not guarantee the order of rows
+        messages.sort_by_key(|m| m.id);
+        Ok(messages)
+    }
+
+    /// Marks a claimed message as processed.
+    /// Fails if its lease expired and it was claimed again in the meantime.
+    pub async fn mark_processed(&self, message: &OutboxMessage) -> Result<()> {
+        self.finish(
+            message,
+            format!(
+                "UPDATE {} SET processed_at = {NOW_MS}, claim_token = NULL, claimed_until = NULL \
+                 WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![],
+        )
+        .await
+    }
+
+    /// Marks a claimed message as failed, making it available again after `retry_after`,
+    /// unless it reached the maximum number of attempts.
+    /// Fails if its lease expired and it was claimed again in the meantime.
+    pub async fn mark_failed(
+        &self,
+        message: &OutboxMessage,
+        error: &str,
+        retry_after: Duration,
+    ) -> Result<()> {
+        if message.attempts >= self.max_attempts {
+            tracing::warn!(
+                id = message.id,
+                topic = %message.topic,
+                error,
+                "Outbox message reached the maximum number of attempts"
+            );
+        }
+        self.finish(
+            message,
+            format!(
+                "UPDATE {} SET available_at = {NOW_MS} + ?, last_error = ?, \
+                 claim_token = NULL, claimed_until = NULL \
+                 WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![
+                Value::from(retry_after.as_millis() as i64),
+                Value::from(error),
+            ],
+        )
+        .await
+    }
+
+    /// Deletes messages processed for longer than `age`, returning how many were deleted
+    pub async fn purge_processed(&self, age: Duration) -> Result<u64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "DELETE FROM {} WHERE processed_at IS NOT NULL AND processed_at < {NOW_MS} - ?",
+                    self.table
+                ),
+                &[age.as_millis() as i64],
+            ))
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    async fn finish(
+        &self,
+        message: &OutboxMessage,
+        sql: String,
+        mut args: Vec<Value>,
+    ) -> Result<()> {
+        args.push(Value::from(message.id));
+        args.push(Value::from(message.claim_token.as_str()));
+        let result = self
+            .client
+            .execute(Statement::with_args(sql, &args))
+            .await?;
+        if result.rows_affected == 0 {
+            anyhow::bail!(
+                "Claim of outbox message {} expired before it was finished",
+                message.id
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! `parquet` exports query results as Parquet files, e.g. for shipping them
+//! to a data lake without an intermediate ETL service. It requires the `parquet` feature.
+//!
+//! SQLite columns have no fixed type, so the Arrow type of each column is inferred
+//! from the values of the first result set: INTEGER columns become `Int64`,
+//! REAL columns, or a mix of INTEGER and REAL, become `Float64`, TEXT columns become
+//! `Utf8` and BLOB columns become `Binary`. Any other mix of types, as well as columns
+//! with only NULL values, become `Utf8`. All columns are nullable.
+//!
+//! When the declared types of the columns are known, e.g. from `describe_all()`, the types
+//! can instead follow the affinity of the columns with `ParquetWriter::with_decltypes()`,
+//! see `libsql_client::affinity_of()`, so that they do not depend on the values of the first
+//! result set. Columns of `NUMERIC` affinity, and columns without a declared type, are still
+//! inferred from their values.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use arrow_array::{ArrayRef, BinaryArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use base64::Engine;
+use parquet::arrow::ArrowWriter;
+
+use crate::sql::Affinity;
+use crate::{ResultSet, Value};
+
+impl ResultSet {
+    /// Writes the result set as a Parquet file, returning the underlying writer.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   let db = libsql_client::new_client().await?;
+    ///   let result = db.execute("SELECT * FROM events").await?;
+    ///   result.to_parquet(std::fs::File::create("/tmp/events.parquet")?)?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn to_parquet<W: Write + Send>(&self, writer: W) -> Result<W> {
+        let mut writer = ParquetWriter::new(writer, self)?;
+        writer.write(self)?;
+        writer.finish()
+    }
+}
+
+/// Writer streaming result sets into a single Parquet file, one row group per result set,
+/// e.g. for exporting a table page by page without holding all of it in memory.
+/// The schema is inferred from the first result set, and the following ones
+/// must have the same columns, with values which fit in the inferred types.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::{args, DatabaseClient, Statement, Value};
+///   use libsql_client::parquet::ParquetWriter;
+///
+///   let db = libsql_client::new_client().await?;
+///   let page = |after: i64| {
+///       Statement::with_args("SELECT * FROM events WHERE id > ? ORDER BY id LIMIT 10000", args!(after))
+///   };
+///   let mut result = db.execute(page(0)).await?;
+///   let mut writer = ParquetWriter::new(std::fs::File::create("/tmp/events.parquet")?, &result)?;
+///   while let Some(Value::Integer { value: last_id }) = result.rows.last().map(|r| &r.values[0]) {
+///       let last_id = *last_id;
+///       writer.write(&result)?;
+///       result = db.execute(page(last_id)).await?;
+///   }
+///   writer.finish()?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct ParquetWriter<W: Write + Send> {
+    schema: SchemaRef,
+    writer: ArrowWriter<W>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    /// Creates a writer with a schema inferred from `first`, which is not written yet
+    pub fn new(writer: W, first: &ResultSet) -> Result<Self> {
+        Self::with_decltypes(writer, first, &[])
+    }
+
+    /// Creates a writer with a schema following the declared types of the columns,
+    /// in the order of the columns of `first`, which is not written yet. Columns whose
+    /// declared type is missing or of `NUMERIC` affinity are inferred from `first`.
+    /// Values must fit in the declared types, e.g. an `INTEGER` column cannot hold text.
+    pub fn with_decltypes(
+        writer: W,
+        first: &ResultSet,
+        decltypes: &[Option<&str>],
+    ) -> Result<Self> {
+        let fields: Vec<Field> = first
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let declared = decltypes.get(i).copied().flatten().and_then(declared_type);
+                let data_type = declared.unwrap_or_else(|| {
+                    infer_type(first.rows.iter().filter_map(|row| row.values.get(i)))
+                });
+                Field::new(name, data_type, true)
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+        Ok(Self { schema, writer })
+    }
+
+    /// Writes the rows of a result set
+    pub fn write(&mut self, result: &ResultSet) -> Result<()> {
+        if result.columns.len() != self.schema.fields().len() {
+            anyhow::bail!(
+                "Result set has {} columns, expected {}",
+                result.columns.len(),
+                self.schema.fields().len()
+            );
+        }
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let values = result.rows.iter().map(|row| row.values.get(i));
+                build_array(field, values)
+            })
+            .collect::<Result<Vec<ArrayRef>>>()?;
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+        // Every result set is a separate row group, so that memory use stays bounded
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the footer of the file, returning the underlying writer
+    pub fn finish(self) -> Result<W> {
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+/// Returns the Arrow type of a column from the affinity of its declared type,
+/// unless its values may be of several types
+fn declared_type(decltype: &str) -> Option<DataType> {
+    match crate::sql::affinity_of(decltype) {
+        Affinity::Integer => Some(DataType::Int64),
+        Affinity::Real => Some(DataType::Float64),
+        Affinity::Text => Some(DataType::Utf8),
+        Affinity::Blob if !decltype.trim().is_empty() => Some(DataType::Binary),
+        Affinity::Blob | Affinity::Numeric => None,
+    }
+}
+
+/// Infers the Arrow type of a column from its values
+fn infer_type<'a>(values: impl Iterator<Item = &'a Value>) -> DataType {
+    let mut inferred = None;
+    for value in values {
+        let data_type = match value {
+            Value::Null => continue,
+            Value::Integer { .. } => DataType::Int64,
+            Value::Float { .. } => DataType::Float64,
+            Value::Text { .. } => DataType::Utf8,
+            Value::Blob { .. } => DataType::Binary,
+        };
+        inferred = Some(match (inferred, data_type) {
+            (None, data_type) => data_type,
+            (Some(a), b) if a == b => a,
+            (Some(DataType::Int64), DataType::Float64)
+            | (Some(DataType::Float64), DataType::Int64) => DataType::Float64,
+            _ => DataType::Utf8,
+        });
+    }
+    inferred.unwrap_or(DataType::Utf8)
+}
+
+fn build_array<'a>(
+    field: &Field,
+    values: impl Iterator<Item = Option<&'a Value>>,
+) -> Result<ArrayRef> {
+    let mismatch = |value: &Value| {
+        anyhow!(
+            "Column {} has value {value:?}, which does not fit its type {}",
+            field.name(),
+            field.data_type()
+        )
+    };
+    let values = values.map(|value| value.filter(|v| !matches!(v, Value::Null)));
+    let array: ArrayRef = match field.data_type() {
+        DataType::Int64 => Arc::new(
+            values
+                .map(|value| match value {
+                    None => Ok(None),
+                    Some(Value::Integer { value }) => Ok(Some(*value)),
+                    Some(value) => Err(mismatch(value)),
+                })
+                .collect::<Result<Int64Array>>()?,
+        ),
+        DataType::Float64 => Arc::new(
+            values
+                .map(|value| match value {
+                    None => Ok(None),
+                    Some(Value::Integer { value }) => Ok(Some(*value as f64)),
+                    Some(Value::Float { value }) => Ok(Some(*value)),
+                    Some(value) => Err(mismatch(value)),
+                })
+                .collect::<Result<Float64Array>>()?,
+        ),
+        DataType::Binary => Arc::new(
+            values
+                .map(|value| match value {
+                    None => Ok(None),
+                    Some(Value::Blob { value }) => Ok(Some(value.as_slice())),
+                    Some(Value::Text { value }) => Ok(Some(value.as_bytes())),
+                    Some(value) => Err(mismatch(value)),
+                })
+                .collect::<Result<BinaryArray>>()?,
+        ),
+        _ => Arc::new(
+            values
+                .map(|value| value.map(to_text))
+                .collect::<StringArray>(),
+        ),
+    };
+    Ok(array)
+}
+
+/// Converts a value of a column of mixed types to text, with blobs encoded in base64
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer { value } => value.to_string(),
+        Value::Float { value } => value.to_string(),
+        Value::Text { value } => value.clone(),
+        Value::Blob { value } => base64::engine::general_purpose::STANDARD.encode(value),
+    }
+}
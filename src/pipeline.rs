@@ -0,0 +1,609 @@
+//! Encoding of statements into Hrana-over-HTTP requests and decoding of responses,
+//! shared by all HTTP-flavored backends so that they agree on every corner case,
+//! e.g. how nulls and blobs are represented.
+
+#![cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+
+use crate::server_stats::ServerStats;
+use crate::timings::Phase;
+use crate::transport::BlobEncoding;
+use crate::{proto, BatchResult, Col, Statement, Value};
+
+/// Encodes a value as a statement parameter: integers and floats as numbers,
+/// text as strings and blobs as `{"base64": ...}` objects.
+/// Floats which are not finite have no JSON encoding, so they're sent as nulls.
+#[cfg_attr(not(feature = "test-support"), allow(dead_code))]
+pub(crate) fn encode_value(value: &Value) -> serde_json::Value {
+    encode_value_as(value, BlobEncoding::Base64)
+}
+
+/// Encodes a value as a statement parameter, with blobs in the given encoding
+pub(crate) fn encode_value_as(value: &Value, blobs: BlobEncoding) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer { value } => serde_json::json!(value),
+        Value::Float { value } => serde_json::Number::from_f64(*value)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Text { value } => serde_json::json!(value),
+        Value::Blob { value } => match blobs {
+            BlobEncoding::Base64 | BlobEncoding::Detect => serde_json::json!({
+                "base64": base64::engine::general_purpose::STANDARD.encode(value)
+            }),
+            BlobEncoding::Base64Url => serde_json::json!({
+                "base64url": base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value)
+            }),
+            BlobEncoding::Hex => {
+                let hex: String = value.iter().map(|byte| format!("{byte:02x}")).collect();
+                serde_json::json!({ "hex": hex })
+            }
+        },
+    }
+}
+
+/// Encodes a statement, as a plain string if it has no parameters
+pub(crate) fn encode_statement(stmt: &Statement) -> serde_json::Value {
+    encode_statement_as(stmt, BlobEncoding::Base64)
+}
+
+/// Encodes a statement, with blobs among its parameters in the given encoding
+pub(crate) fn encode_statement_as(stmt: &Statement, blobs: BlobEncoding) -> serde_json::Value {
+    if stmt.args.is_empty() {
+        serde_json::json!(stmt.sql)
+    } else {
+        serde_json::json!({
+            "q": stmt.sql,
+            "params": stmt.args.iter().map(|arg| encode_value_as(arg, blobs)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Encodes statements into the body of a request, returning the number of statements
+pub(crate) fn encode_statements(
+    stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    blobs: BlobEncoding,
+) -> (String, usize) {
+    let stmts: Vec<serde_json::Value> = stmts
+        .into_iter()
+        .map(|stmt| encode_statement_as(&stmt.into(), blobs))
+        .collect();
+    let stmts_count = stmts.len();
+    let body = serde_json::json!({ "statements": stmts }).to_string();
+    (body, stmts_count)
+}
+
+/// Encodes statements prepended with connection initialization statements
+/// into the body of a request, returning the number of statements
+pub(crate) fn encode_request(
+    init: &[Statement],
+    stmts: Vec<Statement>,
+    blobs: BlobEncoding,
+) -> (String, usize) {
+    crate::timings::measure(Phase::Serialize, || {
+        encode_statements(init.iter().cloned().chain(stmts), blobs)
+    })
+}
+
+/// Decodes the body of a successful response, dropping the results
+/// of connection initialization statements
+#[cfg_attr(
+    not(any(feature = "reqwest_backend", feature = "spin_backend")),
+    allow(dead_code)
+)]
+pub(crate) fn decode_response(
+    body: &[u8],
+    stmts_count: usize,
+    init_count: usize,
+) -> Result<BatchResult> {
+    decode_response_detecting(body, stmts_count, init_count, &mut None)
+}
+
+/// Decodes the body of a successful response like `decode_response()`, setting `blobs`
+/// to the encoding of the blobs it contains, if any
+pub(crate) fn decode_response_detecting(
+    body: &[u8],
+    stmts_count: usize,
+    init_count: usize,
+    blobs: &mut Option<BlobEncoding>,
+) -> Result<BatchResult> {
+    crate::timings::measure(Phase::Decode, || {
+        let response_json: serde_json::Value = serde_json::from_slice(body)?;
+        let stats = decode_server_stats(&response_json);
+        let result = decode_batch_result(response_json, stmts_count, blobs)?;
+        crate::server_stats::record(stats.into_iter().skip(init_count).collect());
+        crate::client::strip_init_results(result, init_count)
+    })
+}
+
+/// Decodes the statistics reported by the server for each statement of a response
+fn decode_server_stats(response_json: &serde_json::Value) -> Vec<Option<ServerStats>> {
+    let Some(results) = response_json.as_array() else {
+        return Vec::new();
+    };
+    results
+        .iter()
+        .map(|result| match result.get("results") {
+            Some(serde_json::Value::Object(obj)) => ServerStats::from_json(obj),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_columns(columns: Vec<serde_json::Value>, result_idx: usize) -> Result<Vec<Col>> {
+    let mut result = Vec::with_capacity(columns.len());
+    for (idx, column) in columns.into_iter().enumerate() {
+        match column {
+            serde_json::Value::String(column) => result.push(Col { name: Some(column) }),
+            _ => {
+                return Err(anyhow!(format!(
+                    "Result {result_idx} column name {idx} not a string",
+                )))
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Decodes a single value of a result row
+#[cfg_attr(not(feature = "test-support"), allow(dead_code))]
+pub(crate) fn decode_value(cell: serde_json::Value) -> Result<Value> {
+    parse_value(cell, 0, 0, 0, &mut None)
+}
+
+/// Decodes a blob encoded as `{"base64": ...}`, `{"base64url": ...}` or `{"hex": ...}`,
+/// returning its encoding along with it
+fn parse_blob(
+    obj: &serde_json::Map<String, serde_json::Value>,
+) -> Option<(BlobEncoding, Result<Vec<u8>>)> {
+    let (encoding, encoded) = [
+        ("base64", BlobEncoding::Base64),
+        ("base64url", BlobEncoding::Base64Url),
+        ("hex", BlobEncoding::Hex),
+    ]
+    .into_iter()
+    .find_map(|(key, encoding)| Some((encoding, obj.get(key)?.as_str()?)))?;
+    let decoded = match encoding {
+        BlobEncoding::Hex => parse_hex(encoded),
+        BlobEncoding::Base64Url => base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .map_err(|e| e.into()),
+        _ => base64::engine::general_purpose::STANDARD_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .map_err(|e| e.into()),
+    };
+    Some((encoding, decoded))
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    // from_str_radix() alone would accept a sign, e.g. "+1"
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid hex string of length {}", hex.len()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+fn parse_value(
+    cell: serde_json::Value,
+    result_idx: usize,
+    row_idx: usize,
+    cell_idx: usize,
+    blobs: &mut Option<BlobEncoding>,
+) -> Result<Value> {
+    match cell {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Number(v) => match v.as_i64() {
+            Some(v) => Ok(Value::Integer{value: v} ),
+            None => match v.as_f64() {
+                Some(v) => Ok(Value::Float{value: v}),
+                None => Err(anyhow!(
+                    "Result {result_idx} row {row_idx} cell {cell_idx} had unknown number value: {v}",
+                )),
+            },
+        },
+        serde_json::Value::String(v) => Ok(Value::Text{value: v}),
+        serde_json::Value::Object(obj) => match parse_blob(&obj) {
+            Some((encoding, decoded)) => {
+                *blobs = Some(encoding);
+                Ok(Value::Blob {
+                    value: decoded.map_err(|e| anyhow!(
+                        "Result {result_idx} row {row_idx} cell {cell_idx} had invalid {encoding:?} blob: {e}",
+                    ))?,
+                })
+            }
+            None => Err(anyhow!(
+                "Result {result_idx} row {row_idx} cell {cell_idx} had unknown type",
+            )),
+        },
+        _ => Err(anyhow!(
+            "Result {result_idx} row {row_idx} cell {cell_idx} had unknown type",
+        )),
+    }
+}
+
+fn parse_rows(
+    rows: Vec<serde_json::Value>,
+    cols_len: usize,
+    result_idx: usize,
+    blobs: &mut Option<BlobEncoding>,
+) -> Result<Vec<Vec<Value>>> {
+    let mut result = Vec::with_capacity(rows.len());
+    for (idx, row) in rows.into_iter().enumerate() {
+        match row {
+            serde_json::Value::Array(row) => {
+                if row.len() != cols_len {
+                    return Err(anyhow!(
+                        "Result {result_idx} row {idx} had wrong number of cells",
+                    ));
+                }
+                let mut cells: Vec<Value> = Vec::with_capacity(cols_len);
+                for (cell_idx, value) in row.into_iter().enumerate() {
+                    cells.push(parse_value(value, result_idx, idx, cell_idx, blobs)?);
+                }
+                result.push(cells)
+            }
+            _ => return Err(anyhow!("Result {result_idx} row {idx} was not an array",)),
+        }
+    }
+    Ok(result)
+}
+
+fn parse_query_result(
+    result: serde_json::Value,
+    idx: usize,
+    blobs: &mut Option<BlobEncoding>,
+) -> Result<(Option<proto::StmtResult>, Option<proto::Error>)> {
+    match result {
+        serde_json::Value::Object(obj) => {
+            if let Some(err) = obj.get("error") {
+                return match err {
+                    serde_json::Value::Object(obj) => match obj.get("message") {
+                        Some(serde_json::Value::String(msg)) => Ok((
+                            None,
+                            Some(proto::Error {
+                                message: msg.clone(),
+                            }),
+                        )),
+                        _ => Err(anyhow!("Result {idx} error message was not a string",)),
+                    },
+                    _ => Err(anyhow!("Result {idx} results was not an object",)),
+                };
+            }
+
+            let results = obj.get("results");
+            match results {
+                Some(serde_json::Value::Object(obj)) => {
+                    let columns = obj
+                        .get("columns")
+                        .ok_or_else(|| anyhow!(format!("Result {idx} had no columns")))?;
+                    let rows = obj
+                        .get("rows")
+                        .ok_or_else(|| anyhow!(format!("Result {idx} had no rows")))?;
+                    match (rows, columns) {
+                        (serde_json::Value::Array(rows), serde_json::Value::Array(columns)) => {
+                            let cols = parse_columns(columns.to_vec(), idx)?;
+                            let rows = parse_rows(rows.to_vec(), columns.len(), idx, blobs)?;
+                            // FIXME: affected_row_count and last_insert_rowid are not implemented yet
+                            let result_set = proto::StmtResult {
+                                cols,
+                                rows,
+                                affected_row_count: 0,
+                                last_insert_rowid: None,
+                            };
+                            Ok((Some(result_set), None))
+                        }
+                        _ => Err(anyhow!(
+                            "Result {idx} had rows or columns that were not an array",
+                        )),
+                    }
+                }
+                Some(_) => Err(anyhow!("Result {idx} was not an object",)),
+                None => Err(anyhow!("Result {idx} did not contain results or error",)),
+            }
+        }
+        _ => Err(anyhow!("Result {idx} was not an object",)),
+    }
+}
+
+pub(crate) fn decode_batch_result(
+    response_json: serde_json::Value,
+    stmts_count: usize,
+    blobs: &mut Option<BlobEncoding>,
+) -> Result<BatchResult> {
+    match response_json {
+        serde_json::Value::Array(results) => {
+            if results.len() != stmts_count {
+                return Err(anyhow!(
+                    "Response array did not contain expected {stmts_count} results"
+                ));
+            }
+
+            let mut step_results: Vec<Option<proto::StmtResult>> = Vec::with_capacity(stmts_count);
+            let mut step_errors: Vec<Option<proto::Error>> = Vec::with_capacity(stmts_count);
+            for (idx, result) in results.into_iter().enumerate() {
+                let (step_result, step_error) =
+                    parse_query_result(result, idx, blobs).map_err(|e| anyhow!("{e}"))?;
+                step_results.push(step_result);
+                step_errors.push(step_error);
+            }
+
+            Ok(BatchResult {
+                step_results,
+                step_errors,
+            })
+        }
+        e => Err(anyhow!("Error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(value: &Value) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn blob(obj: serde_json::Value) -> Option<(BlobEncoding, Result<Vec<u8>>)> {
+        parse_blob(obj.as_object().unwrap())
+    }
+
+    #[test]
+    fn encode_value_as_round_trip() {
+        let values = [
+            Value::Null,
+            Value::Integer { value: 0 },
+            Value::Integer { value: i64::MIN },
+            Value::Integer { value: i64::MAX },
+            Value::Float { value: 1.5 },
+            Value::Float { value: -2.0 },
+            Value::Text {
+                value: String::new(),
+            },
+            Value::Text {
+                value: "zażółć {\"base64\": \"\"}".to_string(),
+            },
+            Value::Blob { value: vec![] },
+            Value::Blob {
+                value: vec![0, 1, 0xfe, 0xff],
+            },
+            Value::Blob {
+                value: (0..=255).collect(),
+            },
+        ];
+        for encoding in [
+            BlobEncoding::Base64,
+            BlobEncoding::Base64Url,
+            BlobEncoding::Hex,
+            BlobEncoding::Detect,
+        ] {
+            for value in &values {
+                let encoded = encode_value_as(value, encoding);
+                let decoded = parse_value(encoded, 0, 0, 0, &mut None).unwrap();
+                assert_eq!(json(&decoded), json(value), "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn encode_value_as_blob_encodings() {
+        let value = Value::Blob {
+            value: vec![0xfb, 0xff],
+        };
+        assert_eq!(
+            encode_value_as(&value, BlobEncoding::Base64),
+            serde_json::json!({ "base64": "+/8=" })
+        );
+        assert_eq!(
+            encode_value_as(&value, BlobEncoding::Base64Url),
+            serde_json::json!({ "base64url": "-_8" })
+        );
+        assert_eq!(
+            encode_value_as(&value, BlobEncoding::Hex),
+            serde_json::json!({ "hex": "fbff" })
+        );
+        assert_eq!(
+            encode_value_as(&value, BlobEncoding::Detect),
+            serde_json::json!({ "base64": "+/8=" })
+        );
+    }
+
+    #[test]
+    fn encode_value_as_non_finite_floats() {
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let encoded = encode_value_as(&Value::Float { value }, BlobEncoding::Base64);
+            assert_eq!(encoded, serde_json::Value::Null);
+        }
+    }
+
+    #[test]
+    fn encode_statement_without_args() {
+        let stmt = Statement::new("SELECT 1");
+        assert_eq!(encode_statement(&stmt), serde_json::json!("SELECT 1"));
+        let stmt = Statement::with_args("SELECT ?", &[Value::Integer { value: 1 }]);
+        assert_eq!(
+            encode_statement(&stmt),
+            serde_json::json!({ "q": "SELECT ?", "params": [1] })
+        );
+    }
+
+    #[test]
+    fn parse_blob_encodings() {
+        let cases = [
+            (
+                serde_json::json!({ "base64": "AAE=" }),
+                BlobEncoding::Base64,
+            ),
+            (serde_json::json!({ "base64": "AAE" }), BlobEncoding::Base64),
+            (
+                serde_json::json!({ "base64url": "AAE" }),
+                BlobEncoding::Base64Url,
+            ),
+            (
+                serde_json::json!({ "base64url": "AAE=" }),
+                BlobEncoding::Base64Url,
+            ),
+            (serde_json::json!({ "hex": "0001" }), BlobEncoding::Hex),
+        ];
+        for (obj, expected) in cases {
+            let (encoding, decoded) = blob(obj.clone()).unwrap();
+            assert_eq!(encoding, expected, "{obj}");
+            assert_eq!(decoded.unwrap(), vec![0, 1], "{obj}");
+        }
+    }
+
+    #[test]
+    fn parse_blob_invalid() {
+        assert!(blob(serde_json::json!({})).is_none());
+        assert!(blob(serde_json::json!({ "base85": "AAE" })).is_none());
+        assert!(blob(serde_json::json!({ "base64": 1 })).is_none());
+        let (_, decoded) = blob(serde_json::json!({ "base64": "A!E=" })).unwrap();
+        assert!(decoded.is_err());
+        let (_, decoded) = blob(serde_json::json!({ "base64url": "+/8" })).unwrap();
+        assert!(decoded.is_err());
+        let (_, decoded) = blob(serde_json::json!({ "hex": "0g" })).unwrap();
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn parse_hex_valid() {
+        assert_eq!(parse_hex("").unwrap(), Vec::<u8>::new());
+        assert_eq!(parse_hex("00ff10").unwrap(), vec![0x00, 0xff, 0x10]);
+        assert_eq!(parse_hex("ABcd").unwrap(), vec![0xab, 0xcd]);
+    }
+
+    #[test]
+    fn parse_hex_odd_length() {
+        assert!(parse_hex("0").is_err());
+        assert!(parse_hex("abc").is_err());
+    }
+
+    #[test]
+    fn parse_hex_non_hex() {
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("0x").is_err());
+        assert!(parse_hex("+1").is_err());
+        assert!(parse_hex(" 1").is_err());
+        // Two bytes, but a single character which must not be split
+        assert!(parse_hex("é").is_err());
+    }
+
+    #[test]
+    fn parse_value_detects_blob_encoding() {
+        let mut blobs = None;
+        parse_value(serde_json::json!(1), 0, 0, 0, &mut blobs).unwrap();
+        assert_eq!(blobs, None);
+        parse_value(serde_json::json!({ "hex": "00" }), 0, 0, 0, &mut blobs).unwrap();
+        assert_eq!(blobs, Some(BlobEncoding::Hex));
+    }
+
+    #[test]
+    fn parse_value_invalid() {
+        for cell in [
+            serde_json::json!(true),
+            serde_json::json!([1]),
+            serde_json::json!({ "value": 1 }),
+            serde_json::json!({ "hex": "0" }),
+        ] {
+            assert!(
+                parse_value(cell.clone(), 0, 0, 0, &mut None).is_err(),
+                "{cell}"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_batch_result_results_and_errors() {
+        let response = serde_json::json!([
+            { "results": { "columns": ["a", "b"], "rows": [[1, "x"], [null, { "base64": "AA==" }]] } },
+            { "error": { "message": "no such table: t" } },
+            { "results": { "columns": [], "rows": [] } },
+        ]);
+        let result = decode_batch_result(response, 3, &mut None).unwrap();
+        assert_eq!(result.step_results.len(), 3);
+        assert_eq!(result.step_errors.len(), 3);
+
+        let first = result.step_results[0].as_ref().unwrap();
+        let names: Vec<_> = first.cols.iter().map(|c| c.name.as_deref()).collect();
+        assert_eq!(names, [Some("a"), Some("b")]);
+        assert_eq!(
+            serde_json::to_value(&first.rows).unwrap(),
+            serde_json::to_value(vec![
+                vec![Value::Integer { value: 1 }, Value::from("x")],
+                vec![Value::Null, Value::Blob { value: vec![0] }],
+            ])
+            .unwrap()
+        );
+        assert!(result.step_errors[0].is_none());
+
+        assert!(result.step_results[1].is_none());
+        assert_eq!(
+            result.step_errors[1].as_ref().unwrap().message,
+            "no such table: t"
+        );
+
+        assert!(result.step_results[2].as_ref().unwrap().rows.is_empty());
+        assert!(result.step_errors[2].is_none());
+    }
+
+    #[test]
+    fn decode_batch_result_mismatched_lengths() {
+        let response = serde_json::json!([{ "results": { "columns": [], "rows": [] } }]);
+        assert!(decode_batch_result(response.clone(), 0, &mut None).is_err());
+        assert!(decode_batch_result(response.clone(), 2, &mut None).is_err());
+        assert!(decode_batch_result(response, 1, &mut None).is_ok());
+
+        let row_too_short =
+            serde_json::json!([{ "results": { "columns": ["a", "b"], "rows": [[1]] } }]);
+        assert!(decode_batch_result(row_too_short, 1, &mut None).is_err());
+        let row_too_long =
+            serde_json::json!([{ "results": { "columns": ["a"], "rows": [[1, 2]] } }]);
+        assert!(decode_batch_result(row_too_long, 1, &mut None).is_err());
+    }
+
+    #[test]
+    fn decode_batch_result_invalid() {
+        let invalid = [
+            serde_json::json!({ "results": [] }),
+            serde_json::json!([1]),
+            serde_json::json!([{}]),
+            serde_json::json!([{ "error": "message" }]),
+            serde_json::json!([{ "error": { "message": 1 } }]),
+            serde_json::json!([{ "results": { "rows": [] } }]),
+            serde_json::json!([{ "results": { "columns": [] } }]),
+            serde_json::json!([{ "results": { "columns": [1], "rows": [] } }]),
+            serde_json::json!([{ "results": { "columns": ["a"], "rows": [1] } }]),
+        ];
+        for response in invalid {
+            let count = response.as_array().map_or(0, Vec::len);
+            assert!(
+                decode_batch_result(response.clone(), count, &mut None).is_err(),
+                "{response}"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_response_strips_init_results() {
+        let body = serde_json::json!([
+            { "results": { "columns": [], "rows": [] } },
+            { "results": { "columns": ["a"], "rows": [[1]] } },
+        ])
+        .to_string();
+        let result = decode_response(body.as_bytes(), 2, 1).unwrap();
+        assert_eq!(result.step_results.len(), 1);
+        assert_eq!(result.step_results[0].as_ref().unwrap().rows.len(), 1);
+    }
+}
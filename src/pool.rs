@@ -0,0 +1,453 @@
+//! `Pool` keeps a set of connections to a database, checked out by tasks one at a time,
+//! e.g. to run interactive transactions concurrently over the local or hrana backends,
+//! where a connection carries the state of its transaction.
+//!
+//! A transaction started with `Pool::transaction()` pins its connection until it is
+//! committed, rolled back or dropped, and the connection is then returned to the pool.
+//! A transaction dropped without finishing is rolled back by the client if it supports
+//! `DatabaseClient::schedule_rollback()`, and otherwise before its connection is handed
+//! out again.
+//!
+//! Tasks holding a pinned connection often need another one, e.g. to read outside of
+//! their transaction. If every connection were pinned, they would wait for each other
+//! forever, so at most `Pool::max_pinned()` connections may be pinned at the same time,
+//! by default all but one, and further transactions wait for a pinned connection to be
+//! returned.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::pool::Pool;
+//!
+//!   let pool = Pool::new(8, || Box::pin(libsql_client::new_client()));
+//!   let tx = pool.transaction().await?;
+//!   tx.execute("UPDATE accounts SET balance = balance - 10 WHERE id = 1").await?;
+//!   tx.execute("UPDATE accounts SET balance = balance + 10 WHERE id = 2").await?;
+//!   tx.commit().await?;
+//!
+//!   let db = pool.get().await?;
+//!   db.execute("SELECT * FROM accounts").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Poll, Waker};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::sql::TransactionControl;
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+type Connect<Client> = Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<Client>>>>>;
+
+/// Pool of connections, established on demand up to a maximum size
+pub struct Pool<Client: DatabaseClient> {
+    connect: Connect<Client>,
+    max_size: usize,
+    max_pinned: usize,
+    state: Mutex<PoolState<Client>>,
+}
+
+struct PoolState<Client> {
+    idle: Vec<Idle<Client>>,
+    open: usize,
+    pinned: usize,
+    waiters: Vec<Waker>,
+}
+
+/// Connection waiting in the pool
+struct Idle<Client> {
+    client: Client,
+    /// Whether it may be in the middle of a transaction which was never finished
+    dirty: bool,
+}
+
+/// Numbers of connections of a pool
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    /// Connections established, whether checked out or idle
+    pub open: usize,
+    /// Connections waiting in the pool
+    pub idle: usize,
+    /// Connections pinned by transactions
+    pub pinned: usize,
+}
+
+impl<Client: DatabaseClient> Pool<Client> {
+    /// Creates a pool of at most `max_size` connections, established with `connect`.
+    /// No connection is established until one is checked out.
+    pub fn new<F>(max_size: usize, connect: F) -> Self
+    where
+        F: Fn() -> Pin<Box<dyn Future<Output = Result<Client>>>> + 'static,
+    {
+        let max_size = max_size.max(1);
+        Self {
+            connect: Box::new(connect),
+            max_size,
+            max_pinned: max_size.saturating_sub(1).max(1),
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+                pinned: 0,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Sets how many connections may be pinned by transactions at the same time,
+    /// at most the size of the pool. Keeping it below the size lets tasks running
+    /// a transaction check out another connection without risking a deadlock.
+    pub fn max_pinned(mut self, max_pinned: usize) -> Self {
+        self.max_pinned = max_pinned.clamp(1, self.max_size);
+        self
+    }
+
+    /// Checks out a connection, waiting for one to be returned if all are in use
+    pub async fn get(&self) -> Result<Pooled<'_, Client>> {
+        let client = self.checkout(false).await?;
+        Ok(Pooled {
+            pool: self,
+            client: Some(client),
+            pinned: false,
+            dirty: Cell::new(false),
+        })
+    }
+
+    /// Checks out a connection and begins a transaction on it, which pins the connection
+    /// until the transaction finishes. It waits if `max_pinned()` connections are pinned already.
+    pub async fn transaction(&self) -> Result<PooledTransaction<'_, Client>> {
+        let client = self.checkout(true).await?;
+        // Dirty from the start, since the transaction may begin even if this is cancelled
+        let mut conn = Pooled {
+            pool: self,
+            client: Some(client),
+            pinned: true,
+            dirty: Cell::new(true),
+        };
+        if let Err(e) = conn.execute("BEGIN").await {
+            // The connection may be broken, so it is not returned to the pool
+            conn.discard();
+            return Err(e);
+        }
+        Ok(PooledTransaction { conn })
+    }
+
+    /// Returns the numbers of connections of the pool
+    pub fn status(&self) -> PoolStatus {
+        let state = self.state();
+        PoolStatus {
+            open: state.open,
+            idle: state.idle.len(),
+            pinned: state.pinned,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, PoolState<Client>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reserves a connection, either an idle one or a slot for a new one,
+    /// then returns it in a clean state
+    async fn checkout(&self, pin: bool) -> Result<Client> {
+        let idle = std::future::poll_fn(|cx| {
+            let mut state = self.state();
+            let pinnable = !pin || state.pinned < self.max_pinned;
+            if pinnable && (!state.idle.is_empty() || state.open < self.max_size) {
+                state.pinned += pin as usize;
+                let idle = state.idle.pop();
+                state.open += idle.is_none() as usize;
+                return Poll::Ready(idle);
+            }
+            if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                state.waiters.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await;
+        // Frees the slot if the connection cannot be established or the checkout is cancelled
+        let reservation = Reservation {
+            pool: self,
+            pinned: pin,
+        };
+        let client = match idle {
+            Some(Idle {
+                client,
+                dirty: false,
+            }) => Ok(client),
+            Some(Idle {
+                client,
+                dirty: true,
+            }) => match self.clean(&client).await {
+                Ok(()) => Ok(client),
+                Err(e) => {
+                    tracing::debug!("Replacing a pooled connection which failed to roll back: {e}");
+                    (self.connect)().await
+                }
+            },
+            None => (self.connect)().await,
+        }?;
+        reservation.complete();
+        Ok(client)
+    }
+
+    /// Rolls back the transaction a connection was left in
+    async fn clean(&self, client: &Client) -> Result<()> {
+        client.execute("ROLLBACK").await?;
+        tracing::warn!("Rolled back a transaction which was dropped without finishing");
+        Ok(())
+    }
+
+    /// Returns a connection to the pool, or frees its slot if it is discarded,
+    /// and wakes the tasks waiting for one
+    fn release(&self, idle: Option<Idle<Client>>, pinned: bool) {
+        let waiters = {
+            let mut state = self.state();
+            state.pinned -= pinned as usize;
+            match idle {
+                Some(idle) => state.idle.push(idle),
+                None => state.open -= 1,
+            }
+            std::mem::take(&mut state.waiters)
+        };
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
+
+/// Slot of a pool reserved for a connection being checked out, freed once dropped
+/// unless the checkout completes
+struct Reservation<'a, Client: DatabaseClient> {
+    pool: &'a Pool<Client>,
+    pinned: bool,
+}
+
+impl<Client: DatabaseClient> Reservation<'_, Client> {
+    /// Keeps the slot, which the checked out connection now holds
+    fn complete(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<Client: DatabaseClient> Drop for Reservation<'_, Client> {
+    fn drop(&mut self) {
+        self.pool.release(None, self.pinned);
+    }
+}
+
+/// Connection checked out from a pool, which returns it once dropped
+pub struct Pooled<'a, Client: DatabaseClient> {
+    pool: &'a Pool<Client>,
+    client: Option<Client>,
+    pinned: bool,
+    /// Whether a transaction begun on the connection may still be open
+    dirty: Cell<bool>,
+}
+
+impl<Client: DatabaseClient> Pooled<'_, Client> {
+    /// Closes the connection instead of returning it, freeing its slot in the pool
+    fn discard(&mut self) {
+        if self.client.take().is_some() {
+            self.pool.release(None, self.pinned);
+        }
+    }
+}
+
+impl<Client: DatabaseClient> std::ops::Deref for Pooled<'_, Client> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        // The connection is only taken when the checkout ends
+        self.client
+            .as_ref()
+            .expect("pooled connection was returned")
+    }
+}
+
+impl<Client: DatabaseClient> Drop for Pooled<'_, Client> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            // Clients which roll back the transaction themselves are clean already
+            let dirty = self.dirty.get() && !client.schedule_rollback();
+            self.pool.release(Some(Idle { client, dirty }), self.pinned);
+        }
+    }
+}
+
+/// Transaction pinning a pooled connection, which is returned to the pool
+/// once the transaction is committed, rolled back or dropped
+pub struct PooledTransaction<'a, Client: DatabaseClient> {
+    conn: Pooled<'a, Client>,
+}
+
+impl<Client: DatabaseClient> PooledTransaction<'_, Client> {
+    /// Commits the transaction and returns the connection to the pool.
+    /// If committing fails, the transaction is rolled back before the connection is reused.
+    pub async fn commit(self) -> Result<()> {
+        self.execute("COMMIT").await?;
+        Ok(())
+    }
+
+    /// Rolls back the transaction and returns the connection to the pool
+    pub async fn rollback(self) -> Result<()> {
+        self.execute("ROLLBACK").await?;
+        Ok(())
+    }
+
+    /// Tracks whether the transaction is still open after `stmts` succeeded,
+    /// e.g. if it was committed with an explicit `COMMIT` statement
+    fn track<'s>(&self, stmts: impl IntoIterator<Item = &'s Statement>) {
+        for stmt in stmts {
+            match crate::sql::transaction_control(&stmt.sql) {
+                Some(TransactionControl::Begin) => self.conn.dirty.set(true),
+                Some(TransactionControl::Finish) => self.conn.dirty.set(false),
+                None => (),
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for PooledTransaction<'_, Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let result = self.conn.execute(stmt.clone()).await?;
+        self.track([&stmt]);
+        Ok(result)
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        let result = self.conn.raw_batch(stmts.clone()).await?;
+        // Only the statements which succeeded affect the transaction state
+        self.track(
+            stmts
+                .iter()
+                .zip(&result.step_results)
+                .filter(|(_, r)| r.is_some())
+                .map(|(stmt, _)| stmt),
+        );
+        Ok(result)
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.conn.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.conn.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        // The client rolls the transaction back, so the connection is clean once returned
+        let scheduled = self.conn.schedule_rollback();
+        if scheduled {
+            self.conn.dirty.set(false);
+        }
+        scheduled
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.conn.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.conn.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+
+    use super::*;
+
+    fn local_pool(max_size: usize) -> Pool<crate::local::Client> {
+        Pool::new(max_size, || {
+            Box::pin(async { crate::local::Client::new("file::memory:?cache=shared") })
+        })
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused() {
+        let pool = local_pool(2);
+        pool.get().await.unwrap().execute("SELECT 1").await.unwrap();
+        let tx = pool.transaction().await.unwrap();
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                open: 1,
+                idle: 0,
+                pinned: 1
+            }
+        );
+        tx.commit().await.unwrap();
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                open: 1,
+                idle: 1,
+                pinned: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_transaction_is_rolled_back() {
+        let pool = local_pool(1);
+        let tx = pool.transaction().await.unwrap();
+        tx.execute("CREATE TABLE t (x)").await.unwrap();
+        drop(tx);
+        // A transaction still open on the connection would fail to begin
+        let tx = pool.transaction().await.unwrap();
+        assert!(tx.execute("SELECT * FROM t").await.is_err());
+        tx.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn explicit_commit_finishes_the_transaction() {
+        let pool = local_pool(1);
+        let tx = pool.transaction().await.unwrap();
+        tx.execute("CREATE TABLE t (x)").await.unwrap();
+        tx.execute("COMMIT").await.unwrap();
+        assert!(!tx.conn.dirty.get());
+        drop(tx);
+        pool.get()
+            .await
+            .unwrap()
+            .execute("SELECT * FROM t")
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn cancelled_checkout_frees_its_slot() {
+        let pool: Pool<crate::local::Client> = Pool::new(1, || Box::pin(std::future::pending()));
+        for _ in 0..3 {
+            let mut checkout = pin!(pool.transaction());
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(checkout.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(pool.status().open, 1);
+        }
+        assert_eq!(pool.status(), PoolStatus::default());
+    }
+
+    #[tokio::test]
+    async fn failed_connection_frees_its_slot() {
+        let pool: Pool<crate::local::Client> =
+            Pool::new(1, || Box::pin(async { anyhow::bail!("unreachable") }));
+        assert!(pool.get().await.is_err());
+        assert!(pool.transaction().await.is_err());
+        assert_eq!(pool.status(), PoolStatus::default());
+    }
+}
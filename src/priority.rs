@@ -0,0 +1,252 @@
+//! `priority` schedules statements sharing a client by their priority, so that user-facing
+//! queries are not stuck behind background jobs when requests have to wait.
+//!
+//! Statements are tagged with `Statement::priority()`, and batches take the highest priority
+//! of their statements. Priorities only matter where statements queue up: in
+//! `ConcurrencyLimit`, which caps the number of requests in flight, and in the write queue of
+//! `writes::SerializedWrites`. Waiting statements are let through from the highest priority
+//! to the lowest, and in arrival order within a priority. Low priority statements may keep
+//! waiting as long as others of higher priority come in.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Priority, Statement};
+//!   use libsql_client::priority::ConcurrencyLimit;
+//!
+//!   let db = ConcurrencyLimit::new(libsql_client::new_client().await?, 4);
+//!   let report = Statement::new("SELECT category, SUM(amount) FROM orders GROUP BY category")
+//!       .priority(Priority::Low);
+//!   let page = Statement::new("SELECT * FROM orders WHERE id = 7").priority(Priority::High);
+//!   let (report, page) = tokio::join!(db.execute(report), db.execute(page));
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::sync::Mutex;
+use std::task::{Poll, Waker};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Priority of a statement, when statements wait for their turn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Background work, e.g. maintenance or reports
+    Low,
+    /// Priority of statements which do not set one
+    #[default]
+    Normal,
+    /// User-facing queries
+    High,
+}
+
+impl Priority {
+    /// Highest priority of a set of statements
+    pub(crate) fn of(stmts: &[Statement]) -> Priority {
+        stmts
+            .iter()
+            .map(|stmt| stmt.priority)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Client wrapper which caps the number of requests in flight, letting waiting
+/// requests through by priority
+pub struct ConcurrencyLimit<Client: DatabaseClient> {
+    inner: Client,
+    permits: Permits,
+}
+
+impl<Client: DatabaseClient> ConcurrencyLimit<Client> {
+    /// Wraps a client, allowing at most `max_in_flight` requests at the same time
+    pub fn new(inner: Client, max_in_flight: usize) -> Self {
+        Self {
+            inner,
+            permits: Permits::new(max_in_flight.max(1)),
+        }
+    }
+
+    /// Returns the number of requests waiting for their turn
+    pub fn waiting(&self) -> usize {
+        self.permits.state().waiters.len()
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for ConcurrencyLimit<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let _permit = self.permits.acquire(stmt.priority).await;
+        self.inner.execute(stmt).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        let _permit = self.permits.acquire(Priority::of(&stmts)).await;
+        self.inner.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let _permit = self.permits.acquire(Priority::of(stmts)).await;
+        self.inner.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
+
+/// Asynchronous semaphore, which hands its permits to waiters
+/// by priority, then in arrival order. Waiters which are cancelled
+/// simply stop competing for permits.
+pub(crate) struct Permits {
+    state: Mutex<PermitState>,
+}
+
+struct PermitState {
+    available: usize,
+    waiters: Vec<Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    priority: Priority,
+    waker: Option<Waker>,
+}
+
+impl PermitState {
+    /// The waiter which gets the next permit
+    fn first(&self) -> Option<u64> {
+        self.waiters
+            .iter()
+            .max_by_key(|w| (w.priority, std::cmp::Reverse(w.id)))
+            .map(|w| w.id)
+    }
+
+    /// Wakers of all waiters if a permit is available, so that the first one takes it
+    fn wakers(&mut self) -> Vec<Waker> {
+        if self.available == 0 {
+            return Vec::new();
+        }
+        self.waiters
+            .iter_mut()
+            .filter_map(|waiter| waiter.waker.take())
+            .collect()
+    }
+}
+
+impl Permits {
+    pub(crate) fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(PermitState {
+                available: permits,
+                waiters: Vec::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, PermitState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) async fn acquire(&self, priority: Priority) -> Permit<'_> {
+        let id = {
+            let mut state = self.state();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push(Waiter {
+                id,
+                priority,
+                waker: None,
+            });
+            id
+        };
+        let waiting = Waiting { permits: self, id };
+        std::future::poll_fn(|cx| {
+            let mut state = self.state();
+            if state.available > 0 && state.first() == Some(id) {
+                state.available -= 1;
+                state.waiters.retain(|w| w.id != id);
+                return Poll::Ready(());
+            }
+            if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == id) {
+                waiter.waker = Some(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await;
+        drop(waiting);
+        Permit { permits: self }
+    }
+}
+
+/// Registration of a waiter, withdrawn if it is cancelled
+struct Waiting<'a> {
+    permits: &'a Permits,
+    id: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.permits.state();
+            let len = state.waiters.len();
+            state.waiters.retain(|w| w.id != self.id);
+            // A cancelled waiter may have been the first one, holding up the others
+            if state.waiters.len() == len {
+                return;
+            }
+            state.wakers()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+pub(crate) struct Permit<'a> {
+    permits: &'a Permits,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.permits.state();
+            state.available += 1;
+            state.wakers()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
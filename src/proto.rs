@@ -1,12 +1,25 @@
 //! `proto` contains libSQL/sqld/hrana wire protocol.
+//!
+//! Types of each version of the Hrana protocol are available in a versioned module,
+//! `v1`, `v2` and `v3`, regardless of the enabled backends. The types at the root
+//! of the module are the ones of `v1`, used by the client API.
 
-#[cfg(feature = "hrana_backend")]
-pub use hrana_client::proto::{
-    Batch, BatchReq, BatchResp, BatchResult, ClientMsg, Col, Error, ExecuteReq, ExecuteResp,
-    OpenStreamReq, Request, Response, ServerMsg, Stmt, StmtResult, Value,
-};
-#[cfg(not(feature = "hrana_backend"))]
+// Protocol types always come from hrana-client-proto, regardless of the enabled backends,
+// so that downstream code compiles with any combination of features. The dependency
+// is kept on the version used by hrana-client, so that the hrana backend passes the types
+// of hrana-client through without conversions.
 pub use hrana_client_proto::{
-    Batch, BatchReq, BatchResp, BatchResult, ClientMsg, Col, Error, ExecuteReq, ExecuteResp,
-    OpenStreamReq, Request, Response, ServerMsg, Stmt, StmtResult, Value,
+    Batch, BatchCond, BatchReq, BatchResp, BatchResult, ClientMsg, Col, Error, ExecuteReq,
+    ExecuteResp, OpenStreamReq, Request, Response, ServerMsg, Stmt, StmtResult, Value,
 };
+
+/// Hrana v1 protocol, spoken over WebSockets
+pub mod v1 {
+    pub use super::{
+        Batch, BatchCond, BatchReq, BatchResp, BatchResult, ClientMsg, Col, Error, ExecuteReq,
+        ExecuteResp, OpenStreamReq, Request, Response, ServerMsg, Stmt, StmtResult, Value,
+    };
+}
+
+pub mod v2;
+pub mod v3;
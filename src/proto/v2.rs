@@ -0,0 +1,248 @@
+//! Hrana v2 protocol, which adds Hrana over HTTP (`/v2/pipeline`),
+//! stored SQL texts, `sequence` and `describe` requests on top of v1.
+//! Values are shared with `v1`. Batches, statement results and errors have their own types,
+//! which are both serialized and deserialized, e.g. by proxies or test servers.
+
+use serde::{Deserialize, Serialize};
+
+pub use super::v1::Value;
+
+/// Body of a request to the `/v2/pipeline` endpoint
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PipelineReqBody {
+    /// Baton returned by the previous request of the stream, `None` opens a new stream
+    pub baton: Option<String>,
+    pub requests: Vec<StreamRequest>,
+}
+
+/// Body of a response from the `/v2/pipeline` endpoint
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PipelineRespBody {
+    /// Baton for the next request of the stream, `None` if the stream was closed
+    pub baton: Option<String>,
+    /// URL which should be used for the next request of the stream, if it changed
+    pub base_url: Option<String>,
+    pub results: Vec<StreamResult>,
+}
+
+/// Statement of Hrana v2, which may refer to a stored SQL text instead of including it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Stmt {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sql: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sql_id: Option<i32>,
+    #[serde(default)]
+    pub args: Vec<Value>,
+    #[serde(default)]
+    pub named_args: Vec<NamedArg>,
+    #[serde(default)]
+    pub want_rows: bool,
+}
+
+impl Stmt {
+    /// Creates a statement with the given SQL text
+    pub fn new(sql: impl Into<String>, want_rows: bool) -> Self {
+        Self {
+            sql: Some(sql.into()),
+            want_rows,
+            ..Default::default()
+        }
+    }
+}
+
+/// Argument bound to a named parameter, e.g. `:name`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NamedArg {
+    pub name: String,
+    pub value: Value,
+}
+
+/// Statements executed in a single request, each one under an optional condition
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Batch {
+    pub steps: Vec<BatchStep>,
+}
+
+/// Statement of a batch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchStep {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<BatchCond>,
+    pub stmt: Stmt,
+}
+
+/// Condition of a batch step, on the outcome of previous steps, referred to by index
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchCond {
+    Ok { step: u32 },
+    Error { step: u32 },
+    Not { cond: Box<BatchCond> },
+    And { conds: Vec<BatchCond> },
+    Or { conds: Vec<BatchCond> },
+}
+
+/// Outcome of each step of a batch: a result if it succeeded, an error if it failed,
+/// and neither if it was skipped
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub step_results: Vec<Option<StmtResult>>,
+    pub step_errors: Vec<Option<Error>>,
+}
+
+/// Result of a statement
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StmtResult {
+    pub cols: Vec<Col>,
+    pub rows: Vec<Vec<Value>>,
+    pub affected_row_count: u64,
+    /// Rowid of the last inserted row, as a decimal string
+    pub last_insert_rowid: Option<String>,
+}
+
+/// Column returned by a statement
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Col {
+    pub name: Option<String>,
+    /// Declared type of the column, if it is a table column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decltype: Option<String>,
+}
+
+/// Error returned by the server
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Error {
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<StmtResult> for super::StmtResult {
+    fn from(result: StmtResult) -> Self {
+        Self {
+            cols: result
+                .cols
+                .into_iter()
+                .map(|col| super::Col { name: col.name })
+                .collect(),
+            rows: result.rows,
+            affected_row_count: result.affected_row_count,
+            last_insert_rowid: result
+                .last_insert_rowid
+                .and_then(|rowid| rowid.parse().ok()),
+        }
+    }
+}
+
+impl From<BatchResult> for super::BatchResult {
+    fn from(result: BatchResult) -> Self {
+        Self {
+            step_results: result
+                .step_results
+                .into_iter()
+                .map(|r| r.map(Into::into))
+                .collect(),
+            step_errors: result
+                .step_errors
+                .into_iter()
+                .map(|e| e.map(Into::into))
+                .collect(),
+        }
+    }
+}
+
+impl From<Error> for super::Error {
+    fn from(error: Error) -> Self {
+        Self {
+            message: error.message,
+        }
+    }
+}
+
+/// Request executed within a stream
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamRequest {
+    Close,
+    Execute {
+        stmt: Stmt,
+    },
+    Batch {
+        batch: Batch,
+    },
+    /// Executes a sequence of statements separated by semicolons, without returning rows
+    Sequence {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql_id: Option<i32>,
+    },
+    /// Analyzes a statement without executing it
+    Describe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql_id: Option<i32>,
+    },
+    /// Stores an SQL text on the server, so that statements can refer to it by `sql_id`
+    StoreSql {
+        sql_id: i32,
+        sql: String,
+    },
+    CloseSql {
+        sql_id: i32,
+    },
+}
+
+/// Result of a single request of a pipeline
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamResult {
+    Ok { response: StreamResponse },
+    Error { error: Error },
+}
+
+/// Response to a `StreamRequest`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamResponse {
+    Close,
+    Execute { result: StmtResult },
+    Batch { result: BatchResult },
+    Sequence,
+    Describe { result: DescribeResult },
+    StoreSql,
+    CloseSql,
+}
+
+/// Result of a `describe` request
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DescribeResult {
+    pub params: Vec<DescribeParam>,
+    pub cols: Vec<DescribeCol>,
+    pub is_explain: bool,
+    pub is_readonly: bool,
+}
+
+/// Parameter of a described statement, with its name if it is a named parameter
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DescribeParam {
+    pub name: Option<String>,
+}
+
+/// Column returned by a described statement
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DescribeCol {
+    pub name: String,
+    /// Declared type of the column, if it is a table column
+    pub decltype: Option<String>,
+}
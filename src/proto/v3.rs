@@ -0,0 +1,114 @@
+//! Hrana v3 protocol, which adds cursors (`/v3/cursor`), which stream the results
+//! of a batch row by row, and the `get_autocommit` request on top of v2.
+
+use serde::{Deserialize, Serialize};
+
+pub use super::v2::{
+    Batch, BatchCond, BatchResult, BatchStep, Col, DescribeCol, DescribeParam, DescribeResult,
+    Error, NamedArg, PipelineRespBody, Stmt, StmtResult, Value,
+};
+
+/// Body of a request to the `/v3/pipeline` endpoint
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PipelineReqBody {
+    /// Baton returned by the previous request of the stream, `None` opens a new stream
+    pub baton: Option<String>,
+    pub requests: Vec<StreamRequest>,
+}
+
+/// Request executed within a stream
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamRequest {
+    Close,
+    Execute {
+        stmt: Stmt,
+    },
+    Batch {
+        batch: Batch,
+    },
+    Sequence {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql_id: Option<i32>,
+    },
+    Describe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sql_id: Option<i32>,
+    },
+    StoreSql {
+        sql_id: i32,
+        sql: String,
+    },
+    CloseSql {
+        sql_id: i32,
+    },
+    /// Checks whether the stream is in autocommit mode, i.e. outside of a transaction
+    GetAutocommit,
+}
+
+/// Result of a single request of a pipeline
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamResult {
+    Ok { response: StreamResponse },
+    Error { error: Error },
+}
+
+/// Response to a `StreamRequest`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamResponse {
+    Close,
+    Execute { result: StmtResult },
+    Batch { result: BatchResult },
+    Sequence,
+    Describe { result: DescribeResult },
+    StoreSql,
+    CloseSql,
+    GetAutocommit { is_autocommit: bool },
+}
+
+/// Body of a request to the `/v3/cursor` endpoint
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CursorReqBody {
+    /// Baton returned by the previous request of the stream, `None` opens a new stream
+    pub baton: Option<String>,
+    pub batch: Batch,
+}
+
+/// First line of a response from the `/v3/cursor` endpoint,
+/// followed by `CursorEntry` lines
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CursorRespBody {
+    pub baton: Option<String>,
+    pub base_url: Option<String>,
+}
+
+/// Entry of a cursor, streamed as a separate line of the response
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CursorEntry {
+    StepBegin {
+        step: u32,
+        cols: Vec<Col>,
+    },
+    StepEnd {
+        affected_row_count: u64,
+        last_insert_rowid: Option<String>,
+    },
+    StepError {
+        step: u32,
+        error: Error,
+    },
+    Row {
+        row: Vec<Value>,
+    },
+    /// The whole batch failed, no more entries follow
+    Error {
+        error: Error,
+    },
+}
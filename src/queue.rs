@@ -0,0 +1,407 @@
+//! `queue` implements durable job queues stored in a table of the database,
+//! for running background jobs without extra infrastructure.
+//!
+//! A claimed job is invisible to other workers for a visibility timeout. A job which is
+//! neither acknowledged nor failed before the timeout expires, e.g. because its worker crashed,
+//! becomes visible again. Jobs failing too many times are moved to a dead-letter queue,
+//! where they are kept for inspection until they're retried or deleted.
+//!
+//! ```rust,no_run
+//!   # async fn run(payload: &str) -> anyhow::Result<()> { Ok(()) }
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use libsql_client::queue::Queue;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let queue = Queue::new(&db, "emails");
+//!   queue.create().await?;
+//!   queue.enqueue(&serde_json::json!({ "to": "john@example.com" })).await?;
+//!
+//!   while let Some(job) = queue.claim(Duration::from_secs(60)).await? {
+//!       match run(&job.payload).await {
+//!           Ok(()) => queue.ack(&job).await?,
+//!           Err(e) => queue.fail(&job, &e.to_string(), Duration::from_secs(5)).await?,
+//!       }
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing jobs, shared by all the queues
+const DEFAULT_JOBS_TABLE: &str = "_libsql_jobs";
+
+/// Default number of attempts after which a failing job is dead-lettered
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Job claimed from a queue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub id: i64,
+    /// Payload of the job, serialized as JSON
+    pub payload: String,
+    /// Number of times the job was claimed, including this one
+    pub attempts: u32,
+    /// Token proving the claim, which expires with the visibility timeout
+    pub claim_token: String,
+}
+
+impl Job {
+    /// Deserializes the payload of the job
+    pub fn payload<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_str(&self.payload)?)
+    }
+}
+
+/// Job moved to the dead-letter queue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadJob {
+    pub id: i64,
+    /// Payload of the job, serialized as JSON
+    pub payload: String,
+    pub attempts: u32,
+    /// Error reported by the last failed attempt
+    pub last_error: Option<String>,
+}
+
+/// Named job queue, stored in the `_libsql_jobs` table by default
+pub struct Queue<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    name: String,
+    table: String,
+    max_attempts: u32,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Queue<'a, Client> {
+    /// Creates a handle to the queue `name`
+    pub fn new(client: &'a Client, name: impl Into<String>) -> Self {
+        Self {
+            client,
+            name: name.into(),
+            table: quote_ident(DEFAULT_JOBS_TABLE),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Sets the table storing jobs
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Sets the number of failed attempts after which a job is dead-lettered. 5 by default.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Returns the name of the queue
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Creates the table storing jobs, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .batch([
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (\
+                     id INTEGER PRIMARY KEY AUTOINCREMENT, \
+                     queue TEXT NOT NULL, \
+                     payload TEXT NOT NULL, \
+                     enqueued_at INTEGER NOT NULL DEFAULT ({NOW_MS}), \
+                     visible_at INTEGER NOT NULL DEFAULT ({NOW_MS}), \
+                     attempts INTEGER NOT NULL DEFAULT 0, \
+                     claim_token TEXT, \
+                     dead INTEGER NOT NULL DEFAULT 0, \
+                     last_error TEXT)",
+                    self.table
+                ),
+                format!(
+                    "CREATE INDEX IF NOT EXISTS {} ON {} (queue, dead, visible_at)",
+                    quote_ident(&format!("{}_visible", crate::sql::unquote(&self.table))),
+                    self.table
+                ),
+            ])
+            .await?;
+        Ok(())
+    }
+
+    /// Returns a statement enqueuing a job, e.g. to be executed within the transaction
+    /// or batch which writes the data the job is about
+    pub fn enqueue_statement(&self, payload: &impl serde::Serialize) -> Result<Statement> {
+        self.enqueue_delayed_statement(payload, Duration::ZERO)
+    }
+
+    /// Returns a statement enqueuing a job which becomes visible after `delay`
+    pub fn enqueue_delayed_statement(
+        &self,
+        payload: &impl serde::Serialize,
+        delay: Duration,
+    ) -> Result<Statement> {
+        let payload = serde_json::to_string(payload)?;
+        Ok(Statement::with_args(
+            format!(
+                "INSERT INTO {} (queue, payload, visible_at) VALUES (?, ?, {NOW_MS} + ?)",
+                self.table
+            ),
+            &[
+                Value::from(self.name.as_str()),
+                Value::from(payload),
+                Value::from(delay.as_millis() as i64),
+            ],
+        ))
+    }
+
+    /// Enqueues a job, returning its id
+    pub async fn enqueue(&self, payload: &impl serde::Serialize) -> Result<i64> {
+        let result = self
+            .client
+            .execute(self.enqueue_statement(payload)?)
+            .await?;
+        result
+            .last_insert_rowid
+            .ok_or_else(|| anyhow::anyhow!("Enqueued job has no id"))
+    }
+
+    /// Enqueues a job which becomes visible after `delay`, returning its id
+    pub async fn enqueue_delayed(
+        &self,
+        payload: &impl serde::Serialize,
+        delay: Duration,
+    ) -> Result<i64> {
+        let result = self
+            .client
+            .execute(self.enqueue_delayed_statement(payload, delay)?)
+            .await?;
+        result
+            .last_insert_rowid
+            .ok_or_else(|| anyhow::anyhow!("Enqueued job has no id"))
+    }
+
+    /// Claims the oldest visible job, hiding it from other workers for `visibility_timeout`
+    pub async fn claim(&self, visibility_timeout: Duration) -> Result<Option<Job>> {
+        Ok(self.claim_many(1, visibility_timeout).await?.pop())
+    }
+
+    /// Claims up to `limit` visible jobs, oldest first,
+    /// hiding them from other workers for `visibility_timeout`
+    pub async fn claim_many(&self, limit: usize, visibility_timeout: Duration) -> Result<Vec<Job>> {
+        let table = &self.table;
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {table} SET claim_token = lower(hex(randomblob(16))), \
+                     visible_at = {NOW_MS} + ?, attempts = attempts + 1 \
+                     WHERE id IN (SELECT id FROM {table} \
+                       WHERE queue = ? AND dead = 0 AND visible_at <= {NOW_MS} \
+                       ORDER BY id LIMIT ?) \
+                     RETURNING id, payload, attempts, claim_token"
+                ),
+                &[
+                    Value::from(visibility_timeout.as_millis() as i64),
+                    Value::from(self.name.as_str()),
+                    Value::from(limit as i64),
+                ],
+            ))
+            .await?;
+        let mut jobs: Vec<Job> = result
+            .rows
+            .iter()
+            .filter_map(|row| match &row.values[..] {
+                [Value::Integer { value: id }, Value::Text { value: payload }, Value::Integer { value: attempts }, Value::Text { value: claim_token }] => {
+                    Some(Job {
+                        id: *id,
+                        payload: payload.clone(),
+                        attempts: *attempts as u32,
+                        claim_token: claim_token.clone(),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        // RETURNING does not guarantee the order of rows
+        jobs.sort_by_key(|job| job.id);
+        Ok(jobs)
+    }
+
+    /// Extends the visibility timeout of a claimed job, for jobs running longer than expected.
+    /// Fails if the timeout expired and the job was claimed again in the meantime.
+    pub async fn extend(&self, job: &Job, visibility_timeout: Duration) -> Result<()> {
+        self.finish(
+            job,
+            format!(
+                "UPDATE {} SET visible_at = {NOW_MS} + ? WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![Value::from(visibility_timeout.as_millis() as i64)],
+        )
+        .await
+    }
+
+    /// Acknowledges a claimed job, deleting it from the queue.
+    /// Fails if the timeout expired and the job was claimed again in the meantime.
+    pub async fn ack(&self, job: &Job) -> Result<()> {
+        self.finish(
+            job,
+            format!(
+                "DELETE FROM {} WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![],
+        )
+        .await
+    }
+
+    /// Reports a failed attempt of a claimed job, which becomes visible again after `retry_after`,
+    /// or is dead-lettered once it reached the maximum number of attempts.
+    /// Fails if the timeout expired and the job was claimed again in the meantime.
+    pub async fn fail(&self, job: &Job, error: &str, retry_after: Duration) -> Result<()> {
+        let dead = job.attempts >= self.max_attempts;
+        if dead {
+            tracing::warn!(
+                queue = %self.name,
+                id = job.id,
+                error,
+                "Job moved to the dead-letter queue"
+            );
+        }
+        self.finish(
+            job,
+            format!(
+                "UPDATE {} SET visible_at = {NOW_MS} + ?, last_error = ?, dead = ?, claim_token = NULL \
+                 WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![
+                Value::from(retry_after.as_millis() as i64),
+                Value::from(error),
+                Value::from(dead as i64),
+            ],
+        )
+        .await
+    }
+
+    /// Moves a claimed job to the dead-letter queue right away, e.g. if its payload is invalid.
+    /// Fails if the timeout expired and the job was claimed again in the meantime.
+    pub async fn dead_letter(&self, job: &Job, error: &str) -> Result<()> {
+        self.finish(
+            job,
+            format!(
+                "UPDATE {} SET last_error = ?, dead = 1, claim_token = NULL \
+                 WHERE id = ? AND claim_token = ?",
+                self.table
+            ),
+            vec![Value::from(error)],
+        )
+        .await
+    }
+
+    /// Lists the jobs of the dead-letter queue, oldest first
+    pub async fn dead_jobs(&self) -> Result<Vec<DeadJob>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT id, payload, attempts, last_error FROM {} WHERE queue = ? AND dead = 1 ORDER BY id",
+                    self.table
+                ),
+                &[self.name.as_str()],
+            ))
+            .await?;
+        Ok(result
+            .rows
+            .iter()
+            .filter_map(|row| match &row.values[..] {
+                [Value::Integer { value: id }, Value::Text { value: payload }, Value::Integer { value: attempts }, last_error] => {
+                    Some(DeadJob {
+                        id: *id,
+                        payload: payload.clone(),
+                        attempts: *attempts as u32,
+                        last_error: match last_error {
+                            Value::Text { value } => Some(value.clone()),
+                            _ => None,
+                        },
+                    })
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Moves a job of the dead-letter queue back to the queue, with its attempts reset,
+    /// returning whether it existed
+    pub async fn retry_dead(&self, id: i64) -> Result<bool> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "UPDATE {} SET dead = 0, attempts = 0, visible_at = {NOW_MS} \
+                     WHERE id = ? AND queue = ? AND dead = 1",
+                    self.table
+                ),
+                &[Value::from(id), Value::from(self.name.as_str())],
+            ))
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Deletes the jobs of the dead-letter queue, returning how many were deleted
+    pub async fn purge_dead(&self) -> Result<u64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE queue = ? AND dead = 1", self.table),
+                &[self.name.as_str()],
+            ))
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// Returns the number of jobs waiting in the queue, claimed or not, excluding dead ones
+    pub async fn len(&self) -> Result<u64> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!(
+                    "SELECT count(*) FROM {} WHERE queue = ? AND dead = 0",
+                    self.table
+                ),
+                &[self.name.as_str()],
+            ))
+            .await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(Value::Integer { value }) => Ok(*value as u64),
+            value => anyhow::bail!("Unexpected job count: {value:?}"),
+        }
+    }
+
+    /// Returns whether no jobs are waiting in the queue
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    async fn finish(&self, job: &Job, sql: String, mut args: Vec<Value>) -> Result<()> {
+        args.push(Value::from(job.id));
+        args.push(Value::from(job.claim_token.as_str()));
+        let result = self
+            .client
+            .execute(Statement::with_args(sql, &args))
+            .await?;
+        if result.rows_affected == 0 {
+            anyhow::bail!(
+                "Claim of job {} in queue {} expired before it was finished",
+                job.id,
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
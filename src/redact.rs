@@ -0,0 +1,87 @@
+//! `redact` controls how the arguments of statements are rendered wherever they may end up
+//! in logs: the `Debug` and `Display` representations of `Statement`, which errors and
+//! applications' own log lines are built from, and the statements traced by the client.
+//!
+//! The policy is process-wide, so that it also covers statements formatted outside
+//! of the client. Arguments are shown as is by default.
+//!
+//! ```rust
+//!   use libsql_client::redact::{self, Redaction};
+//!   use libsql_client::Statement;
+//!
+//!   redact::set_policy(Redaction::Full);
+//!   let stmt = Statement::with_args("SELECT * FROM users WHERE email = ?", &["john@example.com"]);
+//!   assert!(!format!("{stmt:?}").contains("john@example.com"));
+//!   # redact::set_policy(Redaction::None);
+//! ```
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::Value;
+
+static POLICY: AtomicU8 = AtomicU8::new(Redaction::None as u8);
+
+/// Rendering of statement arguments in logs and errors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Redaction {
+    /// Arguments are shown as is
+    #[default]
+    None = 0,
+    /// Arguments are replaced with a hash of their value, so that equal values can be
+    /// correlated across log lines without being revealed. Hashes are not salted, so they
+    /// do not protect values which are easy to guess, e.g. small integers.
+    Hash = 1,
+    /// Arguments are replaced with `[redacted]`
+    Full = 2,
+}
+
+impl Redaction {
+    /// Renders a value according to the policy. NULLs are never redacted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libsql_client::redact::Redaction;
+    /// use libsql_client::Value;
+    ///
+    /// assert_eq!(Redaction::None.apply(&Value::from("secret")), "\"secret\"");
+    /// assert_eq!(Redaction::Full.apply(&Value::from("secret")), "\"[redacted]\"");
+    /// assert!(Redaction::Hash.apply(&Value::from("secret")).starts_with("\"hash:"));
+    /// ```
+    pub fn apply(self, value: &Value) -> String {
+        let rendered = serde_json::json!(value)["value"].to_string();
+        match (self, value) {
+            (Redaction::None, _) | (_, Value::Null) => rendered,
+            (Redaction::Hash, _) => format!("\"hash:{:016x}\"", fnv1a(rendered.as_bytes())),
+            (Redaction::Full, _) => "\"[redacted]\"".to_string(),
+        }
+    }
+}
+
+/// Sets the process-wide policy for rendering statement arguments
+pub fn set_policy(policy: Redaction) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the process-wide policy for rendering statement arguments
+pub fn policy() -> Redaction {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => Redaction::Hash,
+        2 => Redaction::Full,
+        _ => Redaction::None,
+    }
+}
+
+/// Renders arguments according to the process-wide policy
+pub(crate) fn render_args(args: &[Value]) -> Vec<String> {
+    let policy = policy();
+    args.iter().map(|arg| policy.apply(arg)).collect()
+}
+
+/// 64-bit FNV-1a hash, stable across processes and versions
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
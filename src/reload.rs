@@ -0,0 +1,179 @@
+//! `reload` applies a new configuration to a running client, e.g. a rotated auth token or
+//! new timeouts read by a configuration watcher, without restarting the service.
+//!
+//! `Reloadable::update_config()` establishes a client from the new configuration, then
+//! sends the following statements to it. Statements in flight complete on the previous
+//! client, which is closed once the last of them returns, and a transaction started before
+//! the update keeps using the previous client until it ends. If the new client cannot be
+//! established, e.g. because the new URL is unreachable, the previous one is kept.
+//!
+//! Other settings are reloaded where they live: the endpoints of replicas are resolved again
+//! by a `discovery::Resolver`, e.g. `FileResolver` reading a file rewritten by the watcher,
+//! and the log level is a setting of the `tracing` subscriber installed by the application,
+//! e.g. with the `reload` layer of `tracing-subscriber`.
+//!
+//! ```rust,no_run
+//!   # async fn f(new_token: String) -> anyhow::Result<()> {
+//!   # use libsql_client::{Config, DatabaseClient};
+//!   use libsql_client::reload::Reloadable;
+//!
+//!   let config = Config::new("libsql://example.turso.io")?.with_auth_token("<token>");
+//!   let db = Reloadable::new(config).await?;
+//!   db.execute("SELECT 1").await?;
+//!   // Once the token was rotated
+//!   let config = Config::new("libsql://example.turso.io")?.with_auth_token(new_token);
+//!   db.update_config(config).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::client::GenericClient;
+use crate::sql::TransactionControl;
+use crate::{BatchResult, ClientStats, Config, DatabaseClient, ResultSet, Statement};
+
+struct Clients {
+    /// Client receiving new statements
+    current: Arc<GenericClient>,
+    /// Client of the transaction in progress, if any
+    pinned: Option<Arc<GenericClient>>,
+    /// Number of configurations applied since the client was created
+    generation: u64,
+}
+
+/// Client whose configuration can be replaced while it is in use
+pub struct Reloadable {
+    clients: Mutex<Clients>,
+}
+
+impl Reloadable {
+    /// Establishes a client from the given configuration
+    pub async fn new(config: Config) -> Result<Self> {
+        let client = crate::new_client_from_config(config).await?;
+        Ok(Self::from_client(client))
+    }
+
+    /// Wraps an established client
+    // Arc is Send and Sync whenever GenericClient is, which depends on the enabled backends
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn from_client(client: GenericClient) -> Self {
+        Self {
+            clients: Mutex::new(Clients {
+                current: Arc::new(client),
+                pinned: None,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Establishes a client from `config` and sends the following statements to it.
+    /// Statements in flight and a transaction in progress complete on the previous client.
+    /// Fails, keeping the previous client, if the new one cannot be established.
+    pub async fn update_config(&self, config: Config) -> Result<()> {
+        let url = config.url.clone();
+        #[allow(clippy::arc_with_non_send_sync)]
+        let client = Arc::new(crate::new_client_from_config(config).await?);
+        let previous = {
+            let mut clients = self.lock();
+            clients.generation += 1;
+            std::mem::replace(&mut clients.current, client)
+        };
+        tracing::info!("Applied the configuration of {url}");
+        Self::close(previous).await;
+        Ok(())
+    }
+
+    /// Returns the number of configurations applied with `update_config()`
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Returns the client receiving new statements
+    pub fn current(&self) -> Arc<GenericClient> {
+        self.lock().current.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Clients> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the client which executes `stmts`, pinning it for the rest of a transaction
+    fn client_for(&self, stmts: &[Statement]) -> Arc<GenericClient> {
+        let mut clients = self.lock();
+        let client = clients
+            .pinned
+            .clone()
+            .unwrap_or_else(|| clients.current.clone());
+        for stmt in stmts {
+            match crate::sql::transaction_control(&stmt.sql) {
+                Some(TransactionControl::Begin) => clients.pinned = Some(client.clone()),
+                Some(TransactionControl::Finish) => clients.pinned = None,
+                None => {}
+            }
+        }
+        client
+    }
+
+    /// Shuts down a replaced client, unless statements are still in flight on it,
+    /// in which case it is closed when the last of them returns
+    async fn close(client: Arc<GenericClient>) {
+        if let Ok(client) = Arc::try_unwrap(client) {
+            if let Err(e) = client.shutdown().await {
+                tracing::debug!("Failed to shut down a replaced client: {e}");
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl DatabaseClient for Reloadable {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        self.client_for(std::slice::from_ref(&stmt))
+            .execute(stmt)
+            .await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        self.client_for(&stmts).raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.current().prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.current().validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.current().collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        let client = {
+            let mut clients = self.lock();
+            clients
+                .pinned
+                .take()
+                .unwrap_or_else(|| clients.current.clone())
+        };
+        client.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.current().barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.current().stats()
+    }
+}
@@ -0,0 +1,281 @@
+//! `repl` is the engine of an interactive SQL shell over any `DatabaseClient`,
+//! for command-line tools which would otherwise rebuild the same shell.
+//! It is only available with the `repl` feature.
+//!
+//! The engine is fed lines and returns what to print, leaving reading input,
+//! line editing and history to the tool. Lines accumulate until they form complete
+//! statements, terminated by a semicolon, and the rows they return are rendered as tables,
+//! with floats written as set with `Repl::float_format()`.
+//! Lines starting with a dot are commands for the shell:
+//!
+//! | Command | Effect |
+//! |---|---|
+//! | `.tables [PATTERN]` | Lists tables and views, optionally matching a `LIKE` pattern |
+//! | `.schema [PATTERN]` | Shows the `CREATE` statements of matching tables, or of all objects |
+//! | `.timer on\|off` | Shows the execution time of each statement |
+//! | `.help` | Lists the commands |
+//! | `.quit`, `.exit` | Exits the shell |
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   use std::io::{BufRead, Write};
+//!   use libsql_client::repl::{Outcome, Repl};
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let mut repl = Repl::new(&db);
+//!   let mut lines = std::io::stdin().lock().lines();
+//!   loop {
+//!       print!("{}", repl.prompt());
+//!       std::io::stdout().flush()?;
+//!       let Some(line) = lines.next() else { break };
+//!       match repl.eval_line(&line?).await {
+//!           Outcome::Incomplete => {}
+//!           Outcome::Output(text) => print!("{text}"),
+//!           Outcome::Error(message) => eprintln!("Error: {message}"),
+//!           Outcome::Exit => break,
+//!       }
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::fmt::Write as _;
+
+use anyhow::Result;
+
+use crate::float::FloatFormat;
+use crate::{DatabaseClient, ResultSet, Statement, Value};
+
+const HELP: &str = "\
+.tables [PATTERN]   List tables and views, optionally matching a LIKE pattern
+.schema [PATTERN]   Show the CREATE statements of matching tables, or of all objects
+.timer on|off       Show the execution time of each statement
+.help               Show this message
+.quit, .exit        Exit the shell
+";
+
+/// Result of evaluating a line
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The line does not complete a statement, more lines are expected
+    Incomplete,
+    /// Text to print, e.g. rendered rows, possibly empty
+    Output(String),
+    /// A statement or command failed. The output of the statements
+    /// preceding it on the same line is lost.
+    Error(String),
+    /// The shell was asked to exit
+    Exit,
+}
+
+/// Interactive shell, evaluating lines of input against a database
+pub struct Repl<'a, Client: DatabaseClient + ?Sized> {
+    db: &'a Client,
+    buffer: String,
+    timer: bool,
+    floats: FloatFormat,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Repl<'a, Client> {
+    /// Creates a shell executing statements on `db`
+    pub fn new(db: &'a Client) -> Self {
+        Self {
+            db,
+            buffer: String::new(),
+            timer: false,
+            floats: FloatFormat::default(),
+        }
+    }
+
+    /// Sets how floats are written in tables, the shortest text reading back into
+    /// the same value by default
+    pub fn float_format(mut self, floats: FloatFormat) -> Self {
+        self.floats = floats;
+        self
+    }
+
+    /// Returns the prompt to show before reading the next line,
+    /// which differs while a statement is incomplete
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            "libsql> "
+        } else {
+            "   ...> "
+        }
+    }
+
+    /// Whether lines read so far form an incomplete statement
+    pub fn is_incomplete(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Drops the incomplete statement, e.g. when the user presses Ctrl-C
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Evaluates a line of input: a command, or a part of one or more statements,
+    /// which are executed once terminated
+    pub async fn eval_line(&mut self, line: &str) -> Outcome {
+        if self.buffer.is_empty() && line.trim_start().starts_with('.') {
+            return self.command(line.trim()).await;
+        }
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+        if self.buffer.trim().is_empty() {
+            self.buffer.clear();
+            return Outcome::Output(String::new());
+        }
+        if !crate::sql::is_complete(&self.buffer) {
+            return Outcome::Incomplete;
+        }
+        let sql = std::mem::take(&mut self.buffer);
+        match self.run(&sql).await {
+            Ok(output) => Outcome::Output(output),
+            Err(e) => Outcome::Error(e.to_string()),
+        }
+    }
+
+    /// Executes the statements of a complete input, rendering their results
+    async fn run(&self, sql: &str) -> Result<String> {
+        let mut output = String::new();
+        for stmt in crate::sql::split_statements(sql) {
+            let start = std::time::Instant::now();
+            let result = self.db.execute(stmt).await?;
+            if !result.columns.is_empty() {
+                output.push_str(&render_table_with(&result, self.floats));
+            }
+            if self.timer {
+                writeln!(output, "Run Time: {:.3?}", start.elapsed())?;
+            }
+        }
+        Ok(output)
+    }
+
+    async fn command(&mut self, line: &str) -> Outcome {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let result = match (command, args.as_slice()) {
+            (".quit" | ".exit", []) => return Outcome::Exit,
+            (".help", []) => Ok(HELP.to_string()),
+            (".timer", ["on"]) => {
+                self.timer = true;
+                Ok(String::new())
+            }
+            (".timer", ["off"]) => {
+                self.timer = false;
+                Ok(String::new())
+            }
+            (".tables", []) => self.tables(None).await,
+            (".tables", [pattern]) => self.tables(Some(*pattern)).await,
+            (".schema", []) => self.schema(None).await,
+            (".schema", [pattern]) => self.schema(Some(*pattern)).await,
+            (".quit" | ".exit" | ".help" | ".timer" | ".tables" | ".schema", _) => Err(
+                anyhow::anyhow!("Invalid arguments for {command}, see .help"),
+            ),
+            _ => Err(anyhow::anyhow!("Unknown command {command}, see .help")),
+        };
+        match result {
+            Ok(output) => Outcome::Output(output),
+            Err(e) => Outcome::Error(e.to_string()),
+        }
+    }
+
+    async fn tables(&self, pattern: Option<&str>) -> Result<String> {
+        let sql = "SELECT name FROM sqlite_master \
+            WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' AND name LIKE ? \
+            ORDER BY name";
+        let result = self
+            .db
+            .execute(Statement::with_args(sql, &[pattern.unwrap_or("%")]))
+            .await?;
+        let mut output = String::new();
+        for row in &result.rows {
+            writeln!(output, "{}", render_value(&row.values[0], self.floats))?;
+        }
+        Ok(output)
+    }
+
+    async fn schema(&self, pattern: Option<&str>) -> Result<String> {
+        let sql = "SELECT sql FROM sqlite_master \
+            WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' AND tbl_name LIKE ? \
+            ORDER BY tbl_name, type DESC, name";
+        let result = self
+            .db
+            .execute(Statement::with_args(sql, &[pattern.unwrap_or("%")]))
+            .await?;
+        let mut output = String::new();
+        for row in &result.rows {
+            writeln!(output, "{};", render_value(&row.values[0], self.floats))?;
+        }
+        Ok(output)
+    }
+}
+
+/// Renders the rows of a result as a table, followed by the number of rows
+pub fn render_table(result: &ResultSet) -> String {
+    render_table_with(result, FloatFormat::default())
+}
+
+/// Renders the rows of a result as a table like `render_table()`, with floats written
+/// according to `floats`
+pub fn render_table_with(result: &ResultSet, floats: FloatFormat) -> String {
+    let rows: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.values.iter().map(|v| render_value(v, floats)).collect())
+        .collect();
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| c.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let separator: String = widths
+        .iter()
+        .map(|width| format!("+{}", "-".repeat(width + 2)))
+        .collect::<String>()
+        + "+\n";
+    let line = |cells: &mut dyn Iterator<Item = &str>| {
+        let mut line = String::new();
+        for (cell, width) in cells.zip(&widths) {
+            let padding = width - cell.chars().count();
+            line.push_str(&format!("| {cell}{} ", " ".repeat(padding)));
+        }
+        line + "|\n"
+    };
+
+    let mut table = separator.clone();
+    table.push_str(&line(&mut result.columns.iter().map(String::as_str)));
+    table.push_str(&separator);
+    for row in &rows {
+        table.push_str(&line(&mut row.iter().map(String::as_str)));
+    }
+    if !rows.is_empty() {
+        table.push_str(&separator);
+    }
+    let count = rows.len();
+    table.push_str(&format!(
+        "({count} {})\n",
+        if count == 1 { "row" } else { "rows" }
+    ));
+    table
+}
+
+/// Renders a single value like the SQLite shell, with blobs in hex
+fn render_value(value: &Value, floats: FloatFormat) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer { value } => value.to_string(),
+        Value::Float { value } => floats.format(*value),
+        Value::Text { value } => value.clone(),
+        Value::Blob { value } => {
+            let hex: String = value.iter().map(|b| format!("{b:02x}")).collect();
+            format!("x'{hex}'")
+        }
+    }
+}
@@ -2,7 +2,173 @@ use crate::client::Config;
 use async_trait::async_trait;
 use base64::Engine;
 
-use crate::{BatchResult, Statement, Transaction};
+use crate::batch::Batch;
+use crate::idempotency::{self, RecentKeys};
+use crate::proto::{
+    self,
+    v2::{
+        DescribeResult, PipelineReqBody, PipelineRespBody, StreamRequest, StreamResponse,
+        StreamResult,
+    },
+};
+use crate::stats::StatsCollector;
+use crate::timings::Phase;
+use crate::transport::{HttpResponse, HttpTransport};
+use crate::{
+    BatchResult, ClientStats, RateLimit, RetryBudget, RetryPolicy, Statement, Transaction,
+};
+
+/// Maximum number of statements described by a single pipeline request
+const DESCRIBE_PIPELINE_LEN: usize = 128;
+
+/// `HttpTransport` implemented with reqwest
+#[derive(Clone, Debug, Default)]
+pub struct Transport {
+    pool: std::sync::Arc<std::sync::Mutex<Pool>>,
+    options: TransportOptions,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TransportOptions {
+    connect_timeout: Option<std::time::Duration>,
+    read_timeout: Option<std::time::Duration>,
+    dns_refresh: Option<std::time::Duration>,
+}
+
+impl TransportOptions {
+    fn build(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        // Probes connections after `read_timeout` of silence, including idle pooled ones
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.tcp_keepalive(timeout);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Client holding the pooled connections, and when it was built
+#[derive(Debug, Default)]
+struct Pool {
+    client: reqwest::Client,
+    built_at: Option<std::time::Instant>,
+}
+
+impl Transport {
+    /// Creates a transport which gives up establishing a connection after `timeout`
+    pub fn with_connect_timeout(timeout: std::time::Duration) -> anyhow::Result<Self> {
+        Self::with_timeouts(Some(timeout), None)
+    }
+
+    /// Creates a transport which gives up establishing a connection after `connect_timeout`,
+    /// and considers a connection dead once it stays silent for `read_timeout`, see
+    /// `Config::read_timeout()`
+    pub fn with_timeouts(
+        connect_timeout: Option<std::time::Duration>,
+        read_timeout: Option<std::time::Duration>,
+    ) -> anyhow::Result<Self> {
+        let options = TransportOptions {
+            connect_timeout,
+            read_timeout,
+            dns_refresh: None,
+        };
+        let pool = Pool {
+            client: options.build()?,
+            built_at: None,
+        };
+        Ok(Self {
+            pool: std::sync::Arc::new(std::sync::Mutex::new(pool)),
+            options,
+        })
+    }
+
+    /// Drops the pooled connections every `interval`, so that the hostname of the server
+    /// is resolved again, see `Config::dns_refresh()`
+    pub fn with_dns_refresh(mut self, interval: std::time::Duration) -> Self {
+        self.options.dns_refresh = Some(interval);
+        self
+    }
+
+    /// Returns the client sending requests, replacing it once its connections are due
+    /// to be dropped. Requests in flight keep using the previous one until they complete.
+    fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut pool = self.pool.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(interval) = self.options.dns_refresh else {
+            return Ok(pool.client.clone());
+        };
+        let now = crate::timings::now();
+        match (pool.built_at, now) {
+            (Some(built_at), Some(now)) if now.duration_since(built_at) >= interval => {
+                tracing::debug!("Dropping pooled connections to resolve the server again");
+                pool.client = self.options.build()?;
+                pool.built_at = Some(now);
+            }
+            (None, _) => pool.built_at = now,
+            _ => {}
+        }
+        Ok(pool.client.clone())
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpTransport for Transport {
+    async fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<HttpResponse> {
+        let mut request = self.client()?.post(url).body(body);
+        if let Some(remaining) = crate::deadline::check()? {
+            request = request.timeout(remaining);
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let silent = |read_timeout: std::time::Duration| {
+            anyhow::anyhow!(
+                "Connection to the server was silent for {read_timeout:?} \
+                 while reading a response, and is considered dead"
+            )
+        };
+        // The headers are only sent once the server executed the statements
+        let mut response = match self.options.read_timeout {
+            None => request.send().await?,
+            Some(read_timeout) => tokio::time::timeout(read_timeout, request.send())
+                .await
+                .map_err(|_| silent(read_timeout))??,
+        };
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = match self.options.read_timeout {
+            None => response.bytes().await?.to_vec(),
+            Some(read_timeout) => {
+                let mut body = Vec::new();
+                loop {
+                    match tokio::time::timeout(read_timeout, response.chunk()).await {
+                        Ok(chunk) => match chunk? {
+                            Some(chunk) => body.extend_from_slice(&chunk),
+                            None => break,
+                        },
+                        Err(_) => return Err(silent(read_timeout)),
+                    }
+                }
+                body
+            }
+        };
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
 
 /// Database client. This is the main structure used to
 /// communicate with the database.
@@ -11,6 +177,17 @@ pub struct Client {
     base_url: String,
     url_for_queries: String,
     auth: String,
+    init_statements: Vec<Statement>,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    rate_limit: std::sync::Arc<std::sync::Mutex<Option<RateLimit>>>,
+    stats: std::sync::Arc<StatsCollector>,
+    validate_batches: bool,
+    collect_timings: bool,
+    read_only: bool,
+    idempotency_table: Option<String>,
+    recent_keys: std::sync::Arc<RecentKeys>,
+    transport: Transport,
 }
 
 impl Client {
@@ -37,6 +214,17 @@ impl Client {
             base_url,
             url_for_queries,
             auth: format!("Bearer {token}"),
+            init_statements: vec![],
+            retry_policy: RetryPolicy::default(),
+            retry_budget: None,
+            rate_limit: Default::default(),
+            stats: Default::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            idempotency_table: None,
+            recent_keys: Default::default(),
+            transport: Transport::default(),
         }
     }
 
@@ -72,12 +260,57 @@ impl Client {
                 "Basic {}",
                 base64::engine::general_purpose::STANDARD.encode(format!("{username}:{pass}"))
             ),
+            init_statements: vec![],
+            retry_policy: RetryPolicy::default(),
+            retry_budget: None,
+            rate_limit: Default::default(),
+            stats: Default::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            idempotency_table: None,
+            recent_keys: Default::default(),
+            transport: Transport::default(),
         }
     }
 
-    /// Establishes  a database client from a `Config` object
+    /// Establishes  a database client from a `Config` object.
+    /// HTTP requests are not bound to a single connection, so connection
+    /// initialization statements are sent along with every request,
+    /// including the creation of the idempotency table, if configured.
     pub fn from_config(config: Config) -> anyhow::Result<Self> {
-        Ok(Self::new(config.url, config.auth_token.unwrap_or_default()))
+        let mut init_statements = config.connection_statements();
+        if let Some(table) = &config.idempotency_table {
+            init_statements.push(idempotency::create_table_statement(table));
+        }
+        let mut client = Self::new(config.url, config.auth_token.unwrap_or_default());
+        client.init_statements = init_statements;
+        client.retry_policy = config.retry_policy;
+        client.retry_budget = config.retry_budget;
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        client.read_only = config.read_only;
+        client.idempotency_table = config.idempotency_table;
+        if config.connect_timeout.is_some() || config.read_timeout.is_some() {
+            client.transport =
+                Transport::with_timeouts(config.connect_timeout, config.read_timeout)?;
+        }
+        if let Some(interval) = config.dns_refresh {
+            client.transport = client.transport.with_dns_refresh(interval);
+        }
+        Ok(client)
+    }
+
+    /// Returns cumulative counters describing the activity of this client.
+    /// Clones of a client share the same counters.
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the rate limiting state reported by the server in its latest response,
+    /// or `None` if the server does not report it.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.lock().ok().and_then(|r| r.clone())
     }
 
     /// Establishes a database client, given a `Url`
@@ -138,45 +371,314 @@ impl Client {
     }
 }
 
+impl Client {
+    /// Sends a single request, returning the response body on success
+    async fn send(&self, transport: &Transport, body: &str) -> anyhow::Result<Vec<u8>> {
+        self.stats.record_bytes_sent(body.len());
+        let headers = [("Authorization", self.auth.as_str())];
+        let request = transport.post(&self.url_for_queries, &headers, body.as_bytes().to_vec());
+        let response = match crate::timings::measure_async(Phase::Network, request).await {
+            Ok(resp) if resp.status == 200 => resp,
+            // Retry with the legacy route: "/"
+            resp => {
+                if cfg!(feature = "separate_url_for_queries") {
+                    let request =
+                        transport.post(&self.base_url, &headers, body.as_bytes().to_vec());
+                    crate::timings::measure_async(Phase::Network, request).await?
+                } else {
+                    resp?
+                }
+            }
+        };
+        self.update_rate_limit(&response);
+        let response = response.error_for_status()?;
+        self.stats.record_bytes_received(response.body.len());
+        Ok(response.body)
+    }
+
+    /// Sends statements prepended with connection initialization statements,
+    /// returning the results of the statements
+    async fn send_statements(
+        &self,
+        transport: &Transport,
+        stmts: Vec<Statement>,
+    ) -> anyhow::Result<BatchResult> {
+        let (body, stmts_count) = crate::pipeline::encode_request(
+            &self.init_statements,
+            stmts,
+            crate::transport::BlobEncoding::Base64,
+        );
+        let resp = self.send(transport, &body).await?;
+        crate::pipeline::decode_response(&resp, stmts_count, self.init_statements.len())
+    }
+
+    /// Sends a conditional batch after the connection initialization statements,
+    /// which only the Hrana pipeline endpoint supports, returning the results of its steps
+    async fn send_batch(&self, batch: &Batch) -> anyhow::Result<BatchResult> {
+        let request = serde_json::to_value(PipelineReqBody {
+            baton: None,
+            requests: vec![
+                StreamRequest::Batch {
+                    batch: batch.to_v2(&self.init_statements),
+                },
+                StreamRequest::Close,
+            ],
+        })?;
+        let response: PipelineRespBody = serde_json::from_value(self.raw_pipeline(request).await?)?;
+        let result = match response.results.into_iter().next() {
+            Some(StreamResult::Ok {
+                response: StreamResponse::Batch { result },
+            }) => result,
+            Some(StreamResult::Error { error }) => anyhow::bail!(error.message),
+            other => anyhow::bail!("Unexpected response to a batch request: {other:?}"),
+        };
+        crate::client::strip_init_results(result.into(), self.init_statements.len())
+    }
+
+    /// Checks which of the pending idempotency keys were recorded by a request
+    /// whose outcome is unknown, so that their statements are not sent again
+    async fn recover_applied_keys(
+        &self,
+        transport: &Transport,
+        plan: &mut idempotency::Plan,
+    ) -> anyhow::Result<()> {
+        let (Some(table), keys) = (&self.idempotency_table, plan.pending_keys()) else {
+            return Ok(());
+        };
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let lookup = idempotency::lookup_statement(table, &keys);
+        let result = self.send_statements(transport, vec![lookup]).await?;
+        if let Some(Some(error)) = result.step_errors.first() {
+            anyhow::bail!("Failed to look up idempotency keys: {}", error.message);
+        }
+        if let Some(Some(applied)) = result.step_results.first() {
+            idempotency::record_applied(applied, &self.recent_keys);
+        }
+        plan.skip_applied(&self.recent_keys);
+        Ok(())
+    }
+
+    /// Sends a raw Hrana-over-HTTP pipeline request to the `/v2/pipeline` endpoint
+    /// and returns the parsed response. It's an escape hatch for using server
+    /// features which are not covered by the typed API yet.
+    ///
+    /// Typed requests and responses are available in `proto::v2`.
+    ///
+    /// # Arguments
+    /// * `request` - pipeline request, with a `baton` and a list of `requests`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # async fn f() -> anyhow::Result<()> {
+    /// # let db = libsql_client::reqwest::Client::from_url("https://localhost:8080")?;
+    /// let response = db
+    ///     .raw_pipeline(serde_json::json!({
+    ///         "baton": null,
+    ///         "requests": [
+    ///             {"type": "execute", "stmt": {"sql": "SELECT 1", "want_rows": true}},
+    ///             {"type": "close"},
+    ///         ],
+    ///     }))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn raw_pipeline(
+        &self,
+        request: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let body = request.to_string();
+        self.stats.record_bytes_sent(body.len());
+        let url = format!("{}/v2/pipeline", self.base_url.trim_end_matches('/'));
+        let headers = [("Authorization", self.auth.as_str())];
+        let response = self
+            .transport
+            .post(&url, &headers, body.into_bytes())
+            .await?;
+        self.update_rate_limit(&response);
+        let response = response.error_for_status()?;
+        self.stats.record_bytes_received(response.body.len());
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    /// Describes statements without executing them: their parameters and columns,
+    /// and whether they are read-only, e.g. for build-time tools generating typed query modules.
+    /// Statements are described in pipelines of up to 128 requests. A statement which
+    /// cannot be described, e.g. due to a syntax error, gets its own error.
+    ///
+    /// # Arguments
+    /// * `sqls` - SQL texts of the statements
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # async fn f() -> anyhow::Result<()> {
+    /// # let db = libsql_client::reqwest::Client::from_url("https://localhost:8080")?;
+    /// let described = db
+    ///     .describe_all(vec!["SELECT id, name FROM users WHERE id = :id".to_string()])
+    ///     .await?;
+    /// for description in described {
+    ///     match description {
+    ///         Ok(d) => println!("{} params, {} columns", d.params.len(), d.cols.len()),
+    ///         Err(e) => println!("invalid statement: {}", e.message),
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn describe_all(
+        &self,
+        sqls: Vec<String>,
+    ) -> anyhow::Result<Vec<Result<DescribeResult, proto::Error>>> {
+        let mut described = Vec::with_capacity(sqls.len());
+        for chunk in sqls.chunks(DESCRIBE_PIPELINE_LEN) {
+            let mut requests: Vec<StreamRequest> = chunk
+                .iter()
+                .map(|sql| StreamRequest::Describe {
+                    sql: Some(sql.clone()),
+                    sql_id: None,
+                })
+                .collect();
+            requests.push(StreamRequest::Close);
+            let request = serde_json::to_value(PipelineReqBody {
+                baton: None,
+                requests,
+            })?;
+            let response: PipelineRespBody =
+                serde_json::from_value(self.raw_pipeline(request).await?)?;
+            if response.results.len() < chunk.len() {
+                anyhow::bail!(
+                    "Expected {} describe results, got {}",
+                    chunk.len(),
+                    response.results.len()
+                );
+            }
+            for result in response.results.into_iter().take(chunk.len()) {
+                described.push(match result {
+                    StreamResult::Ok {
+                        response: StreamResponse::Describe { result },
+                    } => Ok(result),
+                    StreamResult::Ok { response } => {
+                        anyhow::bail!("Unexpected response to a describe request: {response:?}")
+                    }
+                    StreamResult::Error { error } => Err(error.into()),
+                });
+            }
+        }
+        Ok(described)
+    }
+
+    fn update_rate_limit(&self, response: &HttpResponse) {
+        let rate_limit = RateLimit::from_headers(|name| response.header(name));
+        if let Some(rate_limit) = &rate_limit {
+            tracing::debug!(
+                limit = ?rate_limit.limit,
+                remaining = ?rate_limit.remaining,
+                reset = ?rate_limit.reset,
+                "Rate limit reported by the server"
+            );
+        }
+        if let Ok(mut current) = self.rate_limit.lock() {
+            *current = rate_limit;
+        }
+    }
+}
+
 #[async_trait(?Send)]
 impl crate::DatabaseClient for Client {
     async fn raw_batch(
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> anyhow::Result<BatchResult> {
-        let (body, stmts_count) = crate::client::statements_to_string(stmts);
-        let client = reqwest::Client::new();
-        let response = match client
-            .post(&self.url_for_queries)
-            .body(body.clone())
-            .header("Authorization", &self.auth)
-            .send()
-            .await
-        {
-            Ok(resp) if resp.status() == reqwest::StatusCode::OK => resp,
-            // Retry with the legacy route: "/"
-            resp => {
-                if cfg!(feature = "separate_url_for_queries") {
-                    client
-                        .post(&self.base_url)
-                        .body(body)
-                        .header("Authorization", &self.auth)
-                        .send()
-                        .await?
-                } else {
-                    anyhow::bail!("{}", resp?.status());
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        let mut plan =
+            idempotency::Plan::new(stmts, &self.recent_keys, self.idempotency_table.as_deref());
+        let transport = &self.transport;
+        let mut attempt = 0;
+        let result = loop {
+            let batch = plan.batch()?;
+            let stmts: Vec<Statement> = batch.steps().iter().map(|s| s.stmt.clone()).collect();
+            crate::client::trace_batch(&stmts);
+            // Keys are recorded on the condition that their statements succeeded
+            let sent = if batch.is_conditional() {
+                self.send_batch(&batch).await
+            } else {
+                self.send_statements(transport, stmts).await
+            };
+            let err = match sent {
+                Ok(result) => break result,
+                Err(err) => err,
+            };
+            // The server may have applied a request whose response was lost,
+            // so it is only resent if it does not contain non-idempotent writes
+            let lost = err.downcast_ref::<reqwest::Error>().is_some() && plan.is_resendable();
+            let delay = match err.downcast_ref::<crate::Error>() {
+                Some(e) => self.retry_policy.delay(attempt, e),
+                None if lost => self.retry_policy.backoff(attempt),
+                None => None,
+            };
+            // Retrying is pointless if the deadline of the task would pass in the meantime
+            let remaining = crate::deadline::remaining();
+            let delay = delay.filter(|delay| remaining.is_none_or(|r| *delay < r));
+            // Retries of all requests are bounded together, to not amplify an outage
+            let delay = delay.filter(|_| match &self.retry_budget {
+                Some(budget) if !budget.try_acquire() => {
+                    tracing::debug!("Retry budget exhausted, not retrying");
+                    self.stats.record_retry_throttled();
+                    false
+                }
+                _ => true,
+            });
+            match delay {
+                Some(delay) => {
+                    tracing::debug!("Request failed ({err}), retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    crate::timings::record(Phase::Queue, delay);
+                    self.stats.record_retry();
+                    attempt += 1;
+                    if lost {
+                        self.recover_applied_keys(transport, &mut plan).await?;
+                    }
                 }
+                None => return Err(err),
             }
         };
-        if response.status() != reqwest::StatusCode::OK {
-            anyhow::bail!("{}", response.status());
-        }
-        let resp: String = response.text().await?;
-        let response_json: serde_json::Value = serde_json::from_str(&resp)?;
-        crate::client::http_json_to_batch_result(response_json, stmts_count)
+        let result = plan.into_results(result, &self.recent_keys);
+        self.stats.record_batch(&result);
+        Ok(result)
     }
 
     async fn transaction<'a>(&'a self) -> anyhow::Result<Transaction<'a, Self>> {
-        anyhow::bail!("Interactive transactions are only supported by WebSocket (hrana) and local backends. Use batch() instead")
+        anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are only supported by WebSocket (hrana) and local backends. Use batch() instead"))
+    }
+
+    async fn run_batch(&self, batch: Batch) -> anyhow::Result<BatchResult> {
+        // Plain batches keep the retries and idempotency of raw_batch()
+        if !batch.is_conditional() {
+            return self.raw_batch(batch.into_statements()).await;
+        }
+        crate::deadline::check()?;
+        let stmts: Vec<Statement> = batch.steps().iter().map(|s| s.stmt.clone()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        let result = self.send_batch(&batch).await?;
+        self.stats.record_batch(&result);
+        Ok(result)
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats()
     }
 }
@@ -0,0 +1,153 @@
+//! `resume` lets a later web request continue fetching the rows of a query where a previous
+//! one left off, e.g. for "load more" buttons and paginated APIs, with an opaque token
+//! handed to the client in between.
+//!
+//! Cursors of the Hrana protocol belong to the stream which opened them, which does not outlive
+//! the request, so the position is kept in the token instead: the values of the key columns
+//! of the last row returned, which the query resumes after, e.g. with `WHERE id > ?`.
+//! The query should be ordered by these columns, so that resuming neither skips nor repeats rows.
+//!
+//! A token is only accepted for the query which issued it, and for `Resumer::max_age()`
+//! after it was issued. Tokens are not signed: a client may forge the position of a token,
+//! so queries must still only return rows the client may see. In WebAssembly outside of WASI,
+//! where no clock is available, tokens do not expire.
+//!
+//! ```rust,no_run
+//!   # async fn f(token: Option<&str>) -> anyhow::Result<Option<String>> {
+//!   # use libsql_client::{DatabaseClient, Statement, Value};
+//!   use libsql_client::resume::Resumer;
+//!   use std::time::Duration;
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let resumer = Resumer::new(Duration::from_secs(15 * 60));
+//!   let sql = "SELECT id, title FROM posts WHERE id > ? ORDER BY id LIMIT 50";
+//!   let after = match token {
+//!       Some(token) => resumer.resume(sql, token)?,
+//!       None => vec![Value::from(0i64)],
+//!   };
+//!   let page = db.execute(Statement::with_args(sql, &after)).await?;
+//!   // Sent along with the page, to fetch the next one
+//!   let next = resumer.issue(sql, &page, &["id"])?;
+//!   # Ok(next)
+//!   # }
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use base64::Engine;
+
+use crate::{ResultSet, Value};
+
+/// Version of the layout of tokens
+const VERSION: u8 = 1;
+
+/// Contents of a token, before encoding
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Position {
+    #[serde(rename = "v")]
+    version: u8,
+    /// Hash of the fingerprint of the query
+    #[serde(rename = "q")]
+    query: String,
+    /// Values of the key columns of the last row returned
+    #[serde(rename = "a")]
+    after: Vec<Value>,
+    /// Time the token was issued, in milliseconds since the unix epoch, if known
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    issued_at: Option<u64>,
+}
+
+/// Issuer and verifier of resumption tokens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resumer {
+    max_age: Duration,
+}
+
+impl Resumer {
+    /// Creates a resumer accepting tokens for `max_age` after they were issued
+    pub fn new(max_age: Duration) -> Self {
+        Self { max_age }
+    }
+
+    /// Returns how long tokens are accepted after they were issued
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// Issues a token resuming `sql` after the last row of `page`, identified by the values
+    /// of `key_columns`. Returns `None` if the page is empty, since there is nothing left.
+    pub fn issue(
+        &self,
+        sql: &str,
+        page: &ResultSet,
+        key_columns: &[&str],
+    ) -> Result<Option<String>> {
+        let Some(last) = page.rows.last() else {
+            return Ok(None);
+        };
+        let after = key_columns
+            .iter()
+            .map(|column| {
+                let index = page
+                    .columns
+                    .iter()
+                    .position(|c| c.eq_ignore_ascii_case(column))
+                    .ok_or_else(|| anyhow::anyhow!("The page has no column `{column}`"))?;
+                Ok(last.values[index].clone())
+            })
+            .collect::<Result<Vec<Value>>>()?;
+        let position = Position {
+            version: VERSION,
+            query: query_hash(sql),
+            after,
+            issued_at: unix_now().map(|now| now.as_millis() as u64),
+        };
+        let json = serde_json::to_vec(&position)?;
+        Ok(Some(
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json),
+        ))
+    }
+
+    /// Returns the values of the key columns of the last row returned before a token was
+    /// issued, to bind as arguments of `sql`. Fails if the token was issued for another
+    /// query, has expired, or is not a token at all.
+    pub fn resume(&self, sql: &str, token: &str) -> Result<Vec<Value>> {
+        let invalid = || anyhow::anyhow!("Invalid resumption token");
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| invalid())?;
+        let position: Position = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if position.version != VERSION {
+            return Err(invalid());
+        }
+        if position.query != query_hash(sql) {
+            anyhow::bail!("Resumption token was issued for another query");
+        }
+        if let (Some(issued_at), Some(now)) = (position.issued_at, unix_now()) {
+            let age = now.saturating_sub(Duration::from_millis(issued_at));
+            if age > self.max_age {
+                anyhow::bail!("Resumption token expired {:?} ago", age - self.max_age);
+            }
+        }
+        Ok(position.after)
+    }
+}
+
+/// Identifies a query by its fingerprint, so that formatting does not invalidate tokens
+fn query_hash(sql: &str) -> String {
+    format!(
+        "{:016x}",
+        crate::redact::fnv1a(crate::fingerprint(sql).as_bytes())
+    )
+}
+
+/// Returns the time since the unix epoch, unless the clock is not available,
+/// i.e. in WebAssembly outside of WASI
+fn unix_now() -> Option<Duration> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        SystemTime::now().duration_since(UNIX_EPOCH).ok()
+    }
+}
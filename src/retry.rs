@@ -0,0 +1,341 @@
+//! `RetryPolicy` controls how requests rejected by the server are retried,
+//! `RetryBudget` bounds the rate of retries shared by all requests of a client,
+//! and `RateLimit` describes the rate limiting state reported by the server.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// Policy for retrying requests which the server rejected without processing them,
+/// i.e. responses with HTTP status 429 (Too Many Requests) or 503 (Service Unavailable).
+/// A `Retry-After` header sent by the server takes precedence over the backoff
+/// computed from `base_delay`.
+/// Requests which may have been received, e.g. after a connection reset, are retried as well
+/// by the reqwest backend if none of their statements would be applied twice: they only read,
+/// are marked with `Statement::idempotent()`, or are deduped with an idempotency key.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a single request
+    pub max_retries: u32,
+    /// Delay before the first retry if the server did not send `Retry-After`.
+    /// It doubles with each subsequent attempt.
+    pub base_delay: Duration,
+    /// Upper bound for a single delay, including the ones requested by the server
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Returns how long to wait before retrying after `error`,
+    /// or `None` if the request should not be retried.
+    ///
+    /// # Arguments
+    /// * `attempt` - number of retries already performed
+    /// * `error` - error returned by the last attempt
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let http = match error {
+            Error::RateLimited(e) => e,
+            Error::Server(e) if e.status == 503 => e,
+            _ => return None,
+        };
+        let backoff = self.backoff(attempt)?;
+        Some(http.retry_after.unwrap_or(backoff).min(self.max_delay))
+    }
+
+    /// Returns the exponential backoff before the next retry,
+    /// or `None` if no retries are left.
+    pub(crate) fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.min(16)));
+        Some(backoff.min(self.max_delay))
+    }
+}
+
+/// Token bucket bounding the retries of all requests sent by a client, so that during
+/// a widespread outage, the requests in flight do not each retry `max_retries` times and
+/// multiply the load on a server which is trying to recover.
+///
+/// Every retry takes a token from the bucket, which holds at most `capacity` tokens and
+/// regains `refill_per_second` of them every second. A request whose retry finds the bucket
+/// empty fails with its last error instead, which is counted in `ClientStats::retries_throttled`.
+/// The bucket starts full. Clones of a budget share their tokens, so the same budget may be
+/// set on the configs of several clients to bound their retries together.
+///
+/// # Examples
+///
+/// ```
+/// # use libsql_client::{Config, RetryBudget};
+/// // Bursts of up to 20 retries, then at most 2 retries per second
+/// let config = Config::new("https://example.turso.io")
+///     .unwrap()
+///     .retry_budget(RetryBudget::new(20, 2.0));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryBudget {
+    capacity: f64,
+    refill_per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RetryBudget {
+    /// Creates a budget of `capacity` retries, regaining `refill_per_second` retries every second
+    pub fn new(capacity: u32, refill_per_second: f64) -> Self {
+        let capacity = f64::from(capacity);
+        Self {
+            capacity,
+            refill_per_second: refill_per_second.max(0.0),
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the number of retries which may currently be performed
+    pub fn available(&self) -> u32 {
+        let Ok(mut bucket) = self.bucket.lock() else {
+            return 0;
+        };
+        self.refill(&mut bucket);
+        bucket.tokens as u32
+    }
+
+    /// Takes a token for a retry, returning `false` if the budget is exhausted
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn try_acquire(&self) -> bool {
+        let Ok(mut bucket) = self.bucket.lock() else {
+            return false;
+        };
+        self.refill(&mut bucket);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.refilled_at = now;
+    }
+}
+
+/// Rate limiting state reported by the server in `X-RateLimit-*` response headers
+#[derive(Clone, Debug, Default)]
+pub struct RateLimit {
+    /// Number of requests allowed in the current window (`X-RateLimit-Limit`)
+    pub limit: Option<u64>,
+    /// Number of requests left in the current window (`X-RateLimit-Remaining`)
+    pub remaining: Option<u64>,
+    /// Time until the current window resets (`X-RateLimit-Reset`)
+    pub reset: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Builds the rate limit state from header lookups,
+    /// returning `None` if the server did not report any of them.
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn from_headers<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        let number = |name: &str| header(name).and_then(|v| v.trim().parse::<u64>().ok());
+        let rate_limit = Self {
+            limit: number("x-ratelimit-limit"),
+            remaining: number("x-ratelimit-remaining"),
+            reset: number("x-ratelimit-reset").map(Duration::from_secs),
+        };
+        if rate_limit.limit.is_none()
+            && rate_limit.remaining.is_none()
+            && rate_limit.reset.is_none()
+        {
+            None
+        } else {
+            Some(rate_limit)
+        }
+    }
+}
+
+/// Parses a `Retry-After` header value, which is either a number of seconds
+/// or an HTTP date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`.
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_date(value)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(at.saturating_sub(now))
+}
+
+/// Parses an IMF-fixdate (RFC 7231) into a duration since the Unix epoch.
+/// The header comes from the server, so out of range fields are rejected
+/// rather than trusted.
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+fn parse_http_date(value: &str) -> Option<Duration> {
+    // e.g. "Wed, 21 Oct 2015 07:28:00 GMT"
+    let mut parts = value.split_whitespace().skip(1);
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some() || parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    // A leap second may be reported as 60
+    if !(1970..=9999).contains(&year)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let secs = days_since_epoch(year, month, day)
+        .checked_mul(86400)?
+        .checked_add(hour * 3600 + minute * 60 + second)?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Number of days between 1970-01-01 and the given date of the Gregorian calendar
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let is_leap = |y: u64| (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400);
+    // Leap years from year 1 up to and including `y`
+    let leap_years_until = |y: u64| y / 4 - y / 100 + y / 400;
+    const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap_days = leap_years_until(year - 1) - leap_years_until(1969);
+    let mut days = (year - 1970) * 365 + leap_days + DAYS_BEFORE_MONTH[(month - 1) as usize];
+    if month > 2 && is_leap(year) {
+        days += 1;
+    }
+    days + day - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-1"), None);
+        assert_eq!(parse_retry_after("1.5"), None);
+        assert_eq!(parse_retry_after(""), None);
+    }
+
+    #[test]
+    fn retry_after_date() {
+        // Dates in the past mean retrying right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let wait = parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT").unwrap();
+        assert!(wait > Duration::from_secs(86400 * 365 * 7000));
+    }
+
+    #[test]
+    fn http_dates() {
+        let secs = |value| parse_http_date(value).map(|d| d.as_secs());
+        assert_eq!(secs("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(secs("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(secs("Tue, 29 Feb 2000 12:00:00 GMT"), Some(951825600));
+        assert_eq!(secs("Mon, 01 Mar 2100 00:00:00 GMT"), Some(4107542400));
+        assert_eq!(secs("Sat, 31 Dec 2016 23:59:60 GMT"), Some(1483228800));
+        assert_eq!(secs("Fri, 31 Dec 9999 23:59:59 GMT"), Some(253402300799));
+    }
+
+    #[test]
+    fn invalid_http_dates() {
+        for value in [
+            "Wed, 21 Oct 2015 07:28:00",
+            "Wed, 21 Oct 2015 07:28:00 UTC",
+            "Wed, 21 Oct 2015 07:28 GMT",
+            "Wed, 21 Oct 2015 07:28:00:00 GMT",
+            "Wed, 21 Oct 2015 07:28:00 GMT extra",
+            "Wed, 21 Okt 2015 07:28:00 GMT",
+            "Wed, 00 Oct 2015 07:28:00 GMT",
+            "Wed, 32 Oct 2015 07:28:00 GMT",
+            "Wed, 21 Oct 1969 07:28:00 GMT",
+            "Wed, 21 Oct 10000 07:28:00 GMT",
+            "Wed, 21 Oct 99999999999 07:28:00 GMT",
+            "Wed, 21 Oct 2015 24:00:00 GMT",
+            "Wed, 21 Oct 2015 07:60:00 GMT",
+            "Wed, 21 Oct 2015 07:28:61 GMT",
+            "Wed, 21 Oct 2015 18446744073709551615:00:00 GMT",
+            "Wed, 21 Oct 2015 -1:00:00 GMT",
+        ] {
+            assert_eq!(parse_http_date(value), None, "{value}");
+        }
+    }
+}
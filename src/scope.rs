@@ -0,0 +1,227 @@
+//! `Scope` runs a unit of work against a client and cleans up after it,
+//! created with `DatabaseClient::scope()`.
+//!
+//! Long-running services may otherwise leak a transaction on a stateful connection,
+//! e.g. when a handler returns early with `?` or panics between `BEGIN` and `COMMIT`,
+//! leaving the connection inside a transaction for every statement sent after it.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+
+use crate::sql::TransactionControl;
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Client handed to the closure of `DatabaseClient::scope()`.
+/// It tracks transactions started through it, with `transaction()` or a `BEGIN`
+/// statement, so that they can be rolled back when the scope exits.
+pub struct Scope<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    in_transaction: Rc<Cell<bool>>,
+}
+
+impl<Client: DatabaseClient + ?Sized> Scope<'_, Client> {
+    /// Returns whether a transaction started in the scope is still open
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction.get()
+    }
+
+    fn track<'s>(&self, stmts: impl IntoIterator<Item = &'s Statement>) {
+        for stmt in stmts {
+            match crate::sql::transaction_control(&stmt.sql) {
+                Some(TransactionControl::Begin) => self.in_transaction.set(true),
+                Some(TransactionControl::Finish) => self.in_transaction.set(false),
+                None => (),
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient + ?Sized> DatabaseClient for Scope<'_, Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let result = self.client.execute(stmt.clone()).await;
+        if result.is_ok() {
+            self.track([&stmt]);
+        }
+        result
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        let result = self.client.raw_batch(stmts.clone()).await?;
+        // Only the statements which succeeded affect the transaction state
+        self.track(
+            stmts
+                .iter()
+                .zip(&result.step_results)
+                .filter(|(_, r)| r.is_some())
+                .map(|(stmt, _)| stmt),
+        );
+        Ok(result)
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.client.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.client.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        // The client rolls the transaction back, which the scope must not do again
+        let scheduled = self.client.schedule_rollback();
+        if scheduled {
+            self.in_transaction.set(false);
+        }
+        scheduled
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.client.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.client.stats()
+    }
+}
+
+/// Future which catches panics of the wrapped future
+struct CatchUnwind<F: Future> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.inner;
+        match std::panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+/// Rolls back the transaction of a scope which was dropped before it finished,
+/// e.g. cancelled by a timeout, like a dropped `Transaction`
+struct CancelGuard<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    in_transaction: Rc<Cell<bool>>,
+    finished: bool,
+}
+
+impl<Client: DatabaseClient + ?Sized> Drop for CancelGuard<'_, Client> {
+    fn drop(&mut self) {
+        if self.finished || !self.in_transaction.get() {
+            return;
+        }
+        self.in_transaction.set(false);
+        if self.client.schedule_rollback() {
+            tracing::debug!("Scope cancelled within a transaction, it is rolled back");
+        } else {
+            tracing::warn!(
+                "Scope cancelled within a transaction, it stays open until the connection is closed"
+            );
+        }
+    }
+}
+
+/// Runs `f` with a scope over `client`, rolling back a transaction left open
+/// when `f` returns or panics. Panics are propagated after the rollback.
+pub(crate) async fn run<'a, Client, F, Fut, T>(client: &'a Client, f: F) -> anyhow::Result<T>
+where
+    Client: DatabaseClient + ?Sized,
+    F: FnOnce(Scope<'a, Client>) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let in_transaction = Rc::new(Cell::new(false));
+    let mut guard = CancelGuard {
+        client,
+        in_transaction: in_transaction.clone(),
+        finished: false,
+    };
+    let scope = Scope {
+        client,
+        in_transaction: in_transaction.clone(),
+    };
+    let outcome = CatchUnwind {
+        inner: Box::pin(f(scope)),
+    }
+    .await;
+    if in_transaction.get() {
+        tracing::debug!("Rolling back a transaction left open by a scope");
+        let rollback = client.execute("ROLLBACK").await;
+        in_transaction.set(false);
+        // A failed rollback is only reported if the scope itself succeeded
+        if let (Err(e), Ok(Ok(_))) = (rollback, &outcome) {
+            guard.finished = true;
+            return Err(e);
+        }
+    }
+    guard.finished = true;
+    match outcome {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    use crate::DatabaseClient;
+
+    #[tokio::test]
+    async fn dropped_transaction_is_rolled_back_once() {
+        let db = crate::local::Client::in_memory().unwrap();
+        db.execute("CREATE TABLE t (x)").await.unwrap();
+        let result = db
+            .scope(|scope| async move {
+                let tx = scope.transaction().await?;
+                tx.execute("INSERT INTO t VALUES (1)").await?;
+                assert!(scope.in_transaction());
+                drop(tx);
+                assert!(!scope.in_transaction());
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(db.count("SELECT * FROM t").await.unwrap(), 0);
+        // The connection is not left inside a transaction
+        db.execute("BEGIN").await.unwrap();
+        db.execute("ROLLBACK").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_scope_rolls_back() {
+        let db = crate::local::Client::in_memory().unwrap();
+        db.execute("CREATE TABLE t (x)").await.unwrap();
+        {
+            let scope = pin!(db.scope(|scope| async move {
+                scope.execute("BEGIN").await?;
+                scope.execute("INSERT INTO t VALUES (1)").await?;
+                std::future::pending::<()>().await;
+                Ok(())
+            }));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(matches!(scope.poll(&mut cx), Poll::Pending));
+        }
+        assert_eq!(db.count("SELECT * FROM t").await.unwrap(), 0);
+        db.execute("BEGIN").await.unwrap();
+        db.execute("ROLLBACK").await.unwrap();
+    }
+}
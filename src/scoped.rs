@@ -0,0 +1,73 @@
+//! `SchemaPrefixed` scopes a client to tables sharing a name prefix,
+//! e.g. for sharding schemes with a set of tables per tenant.
+
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Client wrapper which rewrites unqualified table names of every statement
+/// to start with a prefix, created with `DatabaseClient::with_schema_prefix()`.
+/// Names of indexes, triggers and columns qualified with a table name are
+/// rewritten as well, while schema-qualified tables, e.g. `main.users`,
+/// and internal `sqlite_*` tables are left untouched.
+pub struct SchemaPrefixed<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    prefix: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> SchemaPrefixed<'a, Client> {
+    /// Creates a wrapper rewriting table names of `client` to start with `prefix`
+    pub fn new(client: &'a Client, prefix: impl Into<String>) -> Self {
+        Self {
+            client,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Returns the prefix of table names
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Rewrites a statement, keeping its arguments
+    pub fn rewrite(&self, stmt: impl Into<Statement>) -> Statement {
+        let mut stmt = stmt.into();
+        stmt.sql = crate::sql::prefix_tables(&stmt.sql, &self.prefix);
+        stmt
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient + ?Sized> DatabaseClient for SchemaPrefixed<'_, Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        self.client.execute(self.rewrite(stmt)).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| self.rewrite(s)).collect();
+        self.client.raw_batch(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.client.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.client.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.client.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.client.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.client.stats()
+    }
+}
@@ -0,0 +1,97 @@
+//! `server_stats` exposes the statistics which sqld reports for each statement it executes,
+//! e.g. for alerting on statements which read too many rows.
+//!
+//! They are reported by the HTTP API of sqld, used by the `reqwest`, `spin` and generic HTTP
+//! backends, and attached to the `ResultSet` returned by `DatabaseClient::execute()`.
+//! Servers which do not report them, and the other backends, leave `ResultSet::server_stats()`
+//! empty.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   let db = libsql_client::new_client().await?;
+//!   let result = db.execute("SELECT * FROM users WHERE name LIKE '%a%'").await?;
+//!   if let Some(rows_read) = result.server_stats().and_then(|stats| stats.rows_read) {
+//!       if rows_read > 10_000 {
+//!           tracing::warn!("Scanned {rows_read} rows to find {} users", result.rows.len());
+//!       }
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Duration;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Vec<Option<ServerStats>>>> = const { RefCell::new(None) };
+}
+
+/// Statistics reported by the server for a statement.
+/// Each of them is only set if the server reported it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ServerStats {
+    /// Number of rows the statement read, including the ones scanned and not returned
+    pub rows_read: Option<u64>,
+    /// Number of rows the statement wrote, including the ones written to indexes
+    pub rows_written: Option<u64>,
+    /// Time the server spent executing the statement
+    pub duration: Option<Duration>,
+}
+
+impl ServerStats {
+    /// Parses the statistics of a result object of the HTTP API, if it has any
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn from_json(result: &serde_json::Map<String, serde_json::Value>) -> Option<Self> {
+        let stats = Self {
+            rows_read: result.get("rows_read").and_then(|v| v.as_u64()),
+            rows_written: result.get("rows_written").and_then(|v| v.as_u64()),
+            duration: result
+                .get("query_duration_ms")
+                .and_then(|v| v.as_f64())
+                .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok()),
+        };
+        (stats != Self::default()).then_some(stats)
+    }
+}
+
+/// Runs `future`, returning the statistics of the first statement of the last response
+/// it decoded, i.e. of the statement of `DatabaseClient::execute()`
+pub(crate) async fn collect<F: Future>(future: F) -> (F::Output, Option<ServerStats>) {
+    let mut future = std::pin::pin!(future);
+    let mut stats = Some(Vec::new());
+    let output = std::future::poll_fn(|cx| {
+        let outer = CURRENT.with(|current| current.replace(stats.take()));
+        let poll = future.as_mut().poll(cx);
+        stats = CURRENT.with(|current| current.replace(outer));
+        poll
+    })
+    .await;
+    let stats = stats.unwrap_or_default().into_iter().next().flatten();
+    (output, stats)
+}
+
+/// Records the statistics of the statements of a decoded response, if they are being collected
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn record(stats: Vec<Option<ServerStats>>) {
+    CURRENT.with(|current| {
+        if let Some(current) = current.borrow_mut().as_mut() {
+            *current = stats;
+        }
+    })
+}
@@ -1,10 +1,57 @@
+use crate::batch::Batch;
 use crate::client::Config;
-use crate::proto;
-// use async_trait::async_trait;
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use base64::Engine;
+use futures_util::StreamExt;
+use spin_sdk::http::{IncomingResponse, Method, Request};
 
-use crate::{BatchResult, ResultSet, Statement};
+use crate::stats::StatsCollector;
+use crate::timings::Phase;
+use crate::transaction::Transaction;
+use crate::transport::{HttpResponse, HttpTransport};
+use crate::{BatchResult, ClientStats, Statement};
+
+/// `HttpTransport` implemented with Spin's outbound HTTP.
+/// The response body is consumed as a stream, so that large result sets
+/// are not buffered twice by the SDK.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transport;
+
+#[async_trait(?Send)]
+impl HttpTransport for Transport {
+    async fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<HttpResponse> {
+        let mut req = Request::builder();
+        req.method(Method::Post).uri(url);
+        for (name, value) in headers {
+            req.header(*name, *value);
+        }
+        let response: IncomingResponse = spin_sdk::http::send(req.body(body).build()).await?;
+        let status = response.status();
+        let headers = response
+            .headers()
+            .entries()
+            .into_iter()
+            .map(|(name, value)| (name, String::from_utf8_lossy(&value).into_owned()))
+            .collect();
+        let mut stream = response.take_body_stream();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| anyhow!("Failed to read the response body: {e:?}"))?;
+            body.extend_from_slice(&chunk);
+        }
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
 
 /// Database client. This is the main structure used to
 /// communicate with the database.
@@ -13,6 +60,12 @@ pub struct Client {
     base_url: String,
     url_for_queries: String,
     auth: String,
+    init_statements: Vec<Statement>,
+    stats: std::sync::Arc<StatsCollector>,
+    validate_batches: bool,
+    collect_timings: bool,
+    read_only: bool,
+    max_requests: Option<u64>,
 }
 
 impl Client {
@@ -39,6 +92,12 @@ impl Client {
             base_url,
             url_for_queries,
             auth: format!("Bearer {token}"),
+            init_statements: vec![],
+            stats: Default::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            max_requests: None,
         }
     }
 
@@ -74,12 +133,26 @@ impl Client {
                 "Basic {}",
                 base64::engine::general_purpose::STANDARD.encode(format!("{username}:{pass}"))
             ),
+            init_statements: vec![],
+            stats: Default::default(),
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            max_requests: None,
         }
     }
 
     /// Creates a database client from a `Config` object.
+    /// Connection initialization statements are sent along with every request.
     pub fn from_config(config: Config) -> Self {
-        Self::new(config.url, config.auth_token.unwrap_or_default())
+        let init_statements = config.connection_statements();
+        let mut client = Self::new(config.url, config.auth_token.unwrap_or_default());
+        client.init_statements = init_statements;
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        client.read_only = config.read_only;
+        client.max_requests = config.max_requests;
+        client
     }
 
     /// Creates a database client, given a `Url`
@@ -117,103 +190,85 @@ impl Client {
         Ok(Client::from_credentials(url.as_str(), username, password))
     }
 
-    pub fn raw_batch(
-        &self,
-        stmts: impl IntoIterator<Item = impl Into<Statement>>,
-    ) -> Result<BatchResult> {
-        // FIXME: serialize and deserialize with existing routines from sqld
-        let mut body = "{\"statements\": [".to_string();
-        let mut stmts_count = 0;
-        for stmt in stmts {
-            body += &format!("{},", stmt.into());
-            stmts_count += 1;
-        }
-        if stmts_count > 0 {
-            body.pop();
-        }
-        body += "]}";
-
-        let req = http::Request::builder()
-            .uri(&self.url_for_queries)
-            .header("Authorization", &self.auth)
-            .method("POST")
-            .body(Some(bytes::Bytes::copy_from_slice(body.as_bytes())))?;
+    /// Sends statements to the server, prepending the connection initialization statements
+    async fn send_statements(&self, stmts: Vec<Statement>) -> Result<BatchResult> {
+        crate::deadline::check()?;
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        self.stats.start_request(self.max_requests)?;
+        let (body, stmts_count) = crate::pipeline::encode_request(
+            &self.init_statements,
+            stmts,
+            crate::transport::BlobEncoding::Base64,
+        );
+        self.stats.record_bytes_sent(body.len());
 
         // NOTICE: legacy base_url parameter is not used in Spin backend
         let _ = &self.base_url;
 
-        let response = spin_sdk::outbound_http::send_request(req);
-        let resp: String =
-            std::str::from_utf8(&response?.into_body().unwrap_or_default())?.to_string();
-        let response_json: serde_json::Value = serde_json::from_str(&resp)?;
-        crate::client::http_json_to_batch_result(response_json, stmts_count)
+        let request = Transport.post(
+            &self.url_for_queries,
+            &[("Authorization", &self.auth)],
+            body.into_bytes(),
+        );
+        let response = crate::timings::measure_async(Phase::Network, request).await?;
+        self.stats.record_bytes_received(response.body.len());
+        let response = response.error_for_status()?;
+        crate::pipeline::decode_response(&response.body, stmts_count, self.init_statements.len())
+    }
+
+    /// Returns cumulative counters describing the activity of this client.
+    /// Clones of a client share the same counters.
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns how many requests may still be sent under the limit set with
+    /// `Config::max_requests()`, shared by clones of the client, if any
+    pub fn requests_remaining(&self) -> Option<u64> {
+        self.stats.requests_remaining(self.max_requests)
     }
 }
 
-/* FIXME: the code below provides async API. Spin does not support async Rust yet,
-**        so let's cook a sync API instead
 #[async_trait(?Send)]
 impl crate::DatabaseClient for Client {
     async fn raw_batch(
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> Result<BatchResult> {
-        self.raw_batch(stmts).map_err(|e| anyhow!("{e}"))
+        let result = self
+            .send_statements(stmts.into_iter().map(|s| s.into()).collect())
+            .await?;
+        self.stats.record_batch(&result);
+        Ok(result)
     }
 
     async fn transaction<'a>(&'a self) -> Result<Transaction<'a, Self>> {
-        anyhow::bail!("Interactive transactions are only supported by WebSocket (hrana) and local backends. Use batch() instead")
+        anyhow::bail!(crate::error::Unsupported::new("Interactive transactions are only supported by WebSocket (hrana) and local backends. Use batch() instead"))
     }
-}
-*/
 
-impl Client {
-    /// Executes a single SQL statement.
-    pub fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
-        let results = self.raw_batch(std::iter::once(stmt))?;
-        match (results.step_results.first(), results.step_errors.first()) {
-            (Some(Some(result)), Some(None)) => Ok(ResultSet::from(result.clone())),
-            (Some(None), Some(Some(err))) => Err(anyhow!(err.message.clone())),
-            _ => unreachable!(),
-        }
+    async fn execute_multi(&self, sql: &str) -> Result<Vec<crate::ResultSet>> {
+        crate::client::execute_sequentially(self, sql).await
     }
 
-    /// Executes a batch of SQL statements, wrapped in "BEGIN", "END", transaction-style.
-    /// Each statement is going to run in its own transaction,
-    /// unless they're wrapped in BEGIN and END
-    ///
-    /// # Arguments
-    /// * `stmts` - SQL statements
-    pub fn batch(
-        &self,
-        stmts: impl IntoIterator<Item = impl Into<Statement>>,
-    ) -> Result<Vec<ResultSet>> {
-        let batch_results = self.raw_batch(
-            std::iter::once(Statement::new("BEGIN"))
-                .chain(stmts.into_iter().map(|s| s.into()))
-                .chain(std::iter::once(Statement::new("END"))),
-        )?;
-        let step_error: Option<proto::Error> = batch_results
-            .step_errors
-            .into_iter()
-            .skip(1)
-            .find(|e| e.is_some())
-            .flatten();
-        if let Some(error) = step_error {
-            return Err(anyhow!(error.message));
+    async fn run_batch(&self, batch: Batch) -> Result<BatchResult> {
+        if batch.is_conditional() {
+            anyhow::bail!(crate::error::Unsupported::new(
+                "Conditional batches are not supported by the spin backend"
+            ))
         }
-        let mut step_results: Vec<Result<ResultSet>> = batch_results
-            .step_results
-            .into_iter()
-            .skip(1) // BEGIN is not counted in the result, it's implicitly ignored
-            .map(|maybe_rs| {
-                maybe_rs
-                    .map(ResultSet::from)
-                    .ok_or_else(|| anyhow!("Unexpected missing result set"))
-            })
-            .collect();
-        step_results.pop(); // END is not counted in the result, it's implicitly ignored
-                            // Collect all the results into a single Result
-        step_results.into_iter().collect::<Result<Vec<ResultSet>>>()
+        self.raw_batch(batch.into_statements()).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats()
     }
 }
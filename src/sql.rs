@@ -0,0 +1,1210 @@
+//! `sql` contains lightweight SQL text utilities which do not require a database,
+//! e.g. normalizing statements into a stable fingerprint.
+
+/// SQL expression of the current time of the database, in milliseconds since the unix epoch.
+/// Helpers coordinating several clients use it instead of their local clocks, which may differ.
+pub(crate) const NOW_MS: &str = "CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)";
+
+/// Lexical token of an SQL statement
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token<'a> {
+    /// Keyword or unquoted identifier
+    Word(&'a str),
+    /// Quoted identifier, e.g. `"name"`, `[name]` or `` `name` ``
+    QuotedIdent(&'a str),
+    /// String literal, including the quotes
+    String(&'a str),
+    /// Numeric literal
+    Number(&'a str),
+    /// Blob literal, e.g. `x'00ff'`
+    Blob(&'a str),
+    /// Parameter placeholder, e.g. `?`, `?1`, `:name`, `@name` or `$name`
+    Param(&'a str),
+    /// Operator or punctuation
+    Punct(&'a str),
+}
+
+impl<'a> Token<'a> {
+    /// Returns the text of the token, as written in the statement
+    pub(crate) fn text(&self) -> &'a str {
+        match *self {
+            Token::Word(t)
+            | Token::QuotedIdent(t)
+            | Token::String(t)
+            | Token::Number(t)
+            | Token::Blob(t)
+            | Token::Param(t)
+            | Token::Punct(t) => t,
+        }
+    }
+}
+
+/// Splits an SQL string into tokens, skipping whitespace and comments
+pub(crate) fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        match c {
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i = (i + 2).min(bytes.len());
+                continue;
+            }
+            b'\'' => {
+                i = skip_quoted(bytes, i, b'\'');
+                tokens.push(Token::String(&sql[start..i]));
+            }
+            b'"' | b'`' => {
+                i = skip_quoted(bytes, i, c);
+                tokens.push(Token::QuotedIdent(&sql[start..i]));
+            }
+            b'[' => {
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+                tokens.push(Token::QuotedIdent(&sql[start..i]));
+            }
+            b'x' | b'X' if bytes.get(i + 1) == Some(&b'\'') => {
+                i = skip_quoted(bytes, i + 1, b'\'');
+                tokens.push(Token::Blob(&sql[start..i]));
+            }
+            c if c.is_ascii_digit()
+                || (c == b'.' && bytes.get(i + 1).is_some_and(|c| c.is_ascii_digit())) =>
+            {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric()
+                        || bytes[i] == b'.'
+                        || ((bytes[i] == b'+' || bytes[i] == b'-')
+                            && matches!(bytes[i - 1], b'e' | b'E')
+                            && !sql[start..i].starts_with("0x")))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Number(&sql[start..i]));
+            }
+            b'?' | b':' | b'@' | b'$' => {
+                i += 1;
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                if c != b'?' && i == start + 1 {
+                    tokens.push(Token::Punct(&sql[start..i]));
+                } else {
+                    tokens.push(Token::Param(&sql[start..i]));
+                }
+            }
+            c if is_ident_byte(c) => {
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                tokens.push(Token::Word(&sql[start..i]));
+            }
+            _ => {
+                let two = sql.get(i..i + 2).unwrap_or_default();
+                i += if matches!(two, "<=" | ">=" | "<>" | "!=" | "==" | "||" | "<<" | ">>") {
+                    2
+                } else {
+                    // Advance by the whole character, so that slicing stays on UTF-8 boundaries
+                    sql[i..].chars().next().map_or(1, char::len_utf8)
+                };
+                tokens.push(Token::Punct(&sql[start..i]));
+            }
+        }
+    }
+    tokens
+}
+
+fn is_ident_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Returns the index right after the closing quote, handling doubled quotes as escapes
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Returns the number of parameters expected by an SQL statement, following SQLite rules:
+/// `?NNN` has index NNN, `?` takes the next index, and a named parameter takes the next
+/// index the first time it appears and reuses it afterwards.
+pub(crate) fn parameter_count(sql: &str) -> usize {
+    let mut count = 0;
+    let mut named: Vec<&str> = Vec::new();
+    for token in tokenize(sql) {
+        let Token::Param(param) = token else {
+            continue;
+        };
+        if param == "?" {
+            count += 1;
+        } else if let Some(index) = param.strip_prefix('?') {
+            count = count.max(index.parse().unwrap_or(0));
+        } else if !named.contains(&param) {
+            named.push(param);
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Returns the first keyword of an SQL statement, uppercased
+pub(crate) fn leading_keyword(sql: &str) -> Option<String> {
+    tokenize(sql).into_iter().find_map(|token| match token {
+        Token::Word(w) => Some(w.to_ascii_uppercase()),
+        _ => None,
+    })
+}
+
+/// Splits an SQL script into statements at semicolons, which are ignored within literals,
+/// comments and the body of `CREATE TRIGGER`. Empty statements are skipped,
+/// and comments before and after each statement are not included.
+pub(crate) fn split_statements(sql: &str) -> Vec<&str> {
+    scan_statements(sql).0
+}
+
+/// Checks if an SQL string ends with a complete statement, i.e. a semicolon which is
+/// not within a literal, a comment or the body of `CREATE TRIGGER`, e.g. to tell
+/// when an interactive shell has read a whole statement
+#[cfg_attr(not(feature = "repl"), allow(dead_code))]
+pub(crate) fn is_complete(sql: &str) -> bool {
+    let (statements, terminated) = scan_statements(sql);
+    terminated && !statements.is_empty()
+}
+
+/// Splits an SQL string into statements, also returning whether the last one is terminated
+fn scan_statements(sql: &str) -> (Vec<&str>, bool) {
+    let offset = |t: &str| t.as_ptr() as usize - sql.as_ptr() as usize;
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut end = 0;
+    let mut leading: Vec<String> = Vec::new();
+    let mut in_trigger_body = false;
+    let mut case_depth = 0;
+    let mut terminated = false;
+    for token in tokenize(sql) {
+        let text = token.text();
+        if token == Token::Punct(";") && !in_trigger_body {
+            if let Some(start) = start.take() {
+                statements.push(&sql[start..end]);
+            }
+            leading.clear();
+            terminated = true;
+            continue;
+        }
+        terminated = false;
+        start.get_or_insert(offset(text));
+        end = offset(text) + text.len();
+        let Token::Word(word) = token else {
+            continue;
+        };
+        let word = word.to_ascii_uppercase();
+        if leading.len() < 4 {
+            leading.push(word.clone());
+        }
+        let is_trigger = leading[0] == "CREATE" && leading[1..].iter().any(|w| w == "TRIGGER");
+        if !is_trigger {
+            continue;
+        }
+        // CASE expressions within the body end with END as well
+        match word.as_str() {
+            "BEGIN" => in_trigger_body = true,
+            "CASE" if in_trigger_body => case_depth += 1,
+            "END" if case_depth > 0 => case_depth -= 1,
+            "END" => in_trigger_body = false,
+            _ => (),
+        }
+    }
+    if let Some(start) = start {
+        statements.push(&sql[start..end]);
+    }
+    (statements, terminated)
+}
+
+/// Type affinity of a column, which determines how SQLite converts the values stored in it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Affinity {
+    /// Declared types containing `INT`
+    Integer,
+    /// Declared types containing `CHAR`, `CLOB` or `TEXT`
+    Text,
+    /// Declared types containing `BLOB`, and columns without a declared type
+    Blob,
+    /// Declared types containing `REAL`, `FLOA` or `DOUB`
+    Real,
+    /// Any other declared type, e.g. `NUMERIC`, `DECIMAL` or `BOOLEAN`
+    Numeric,
+}
+
+/// Returns the affinity of a column from its declared type, following the rules of
+/// https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+///
+/// # Examples
+///
+/// ```
+/// use libsql_client::{affinity_of, Affinity};
+///
+/// assert_eq!(affinity_of("VARCHAR(255)"), Affinity::Text);
+/// assert_eq!(affinity_of("BIGINT"), Affinity::Integer);
+/// assert_eq!(affinity_of("DECIMAL(10, 2)"), Affinity::Numeric);
+/// ```
+pub fn affinity_of(decltype: &str) -> Affinity {
+    let decltype = decltype.to_ascii_uppercase();
+    if decltype.contains("INT") {
+        Affinity::Integer
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|t| decltype.contains(t))
+    {
+        Affinity::Text
+    } else if decltype.contains("BLOB") || decltype.trim().is_empty() {
+        Affinity::Blob
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|t| decltype.contains(t))
+    {
+        Affinity::Real
+    } else {
+        Affinity::Numeric
+    }
+}
+
+/// Effect of a statement on the transaction state of a connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TransactionControl {
+    /// `BEGIN`
+    Begin,
+    /// `COMMIT`, `END` or `ROLLBACK`, but not `ROLLBACK TO` a savepoint
+    Finish,
+}
+
+/// Returns how a statement starts or finishes a transaction, if it does
+pub(crate) fn transaction_control(sql: &str) -> Option<TransactionControl> {
+    let words: Vec<String> = tokenize(sql)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Word(w) => Some(w.to_ascii_uppercase()),
+            _ => None,
+        })
+        .collect();
+    match words.first().map(|w| w.as_str()) {
+        Some("BEGIN") => Some(TransactionControl::Begin),
+        Some("COMMIT" | "END") => Some(TransactionControl::Finish),
+        Some("ROLLBACK") if !words.iter().any(|w| w == "TO") => Some(TransactionControl::Finish),
+        _ => None,
+    }
+}
+
+/// Pragmas taking an argument which only read, e.g. `PRAGMA table_info(users)`
+const READ_ONLY_PRAGMA_CALLS: &[&str] = &[
+    "foreign_key_check",
+    "foreign_key_list",
+    "index_info",
+    "index_list",
+    "index_xinfo",
+    "integrity_check",
+    "quick_check",
+    "table_info",
+    "table_list",
+    "table_xinfo",
+];
+
+/// Pragmas without an argument which may write, e.g. `PRAGMA optimize` running `ANALYZE`
+const WRITING_PRAGMAS: &[&str] = &["incremental_vacuum", "optimize", "wal_checkpoint"];
+
+/// Checks if a statement cannot write to the database. It errs on the side of caution:
+/// statements which are not known to be reads, e.g. `PRAGMA name = value`
+/// or `PRAGMA name(value)`, count as writes.
+pub(crate) fn is_read_only(sql: &str) -> bool {
+    let tokens = tokenize(sql);
+    let is_word = |token: &Token, words: &[&str]| matches!(token, Token::Word(w) if words.iter().any(|word| w.eq_ignore_ascii_case(word)));
+    let Some(first) = tokens.first() else {
+        return true;
+    };
+    if is_word(first, &["SELECT", "VALUES", "EXPLAIN"]) {
+        true
+    } else if is_word(first, &["WITH"]) {
+        // The statement following common table expressions may be a write
+        !tokens
+            .iter()
+            .any(|token| is_word(token, &["INSERT", "UPDATE", "DELETE", "REPLACE"]))
+    } else if is_word(first, &["PRAGMA"]) {
+        // PRAGMA [schema.]name, PRAGMA [schema.]name(arg) or PRAGMA [schema.]name = value
+        let name_at = match tokens.get(2) {
+            Some(Token::Punct(".")) => 3,
+            _ => 1,
+        };
+        let Some(Token::Word(name)) = tokens.get(name_at) else {
+            return false;
+        };
+        let listed = |names: &[&str]| names.iter().any(|n| name.eq_ignore_ascii_case(n));
+        match &tokens[name_at + 1..] {
+            [] | [Token::Punct(";")] => !listed(WRITING_PRAGMAS),
+            [Token::Punct("("), ..] => listed(READ_ONLY_PRAGMA_CALLS),
+            _ => false,
+        }
+    } else {
+        is_word(
+            first,
+            &["BEGIN", "COMMIT", "END", "ROLLBACK", "SAVEPOINT", "RELEASE"],
+        )
+    }
+}
+
+/// Rewrites a query into `SELECT COUNT(*) FROM (query)`, counting its rows without fetching
+/// them. Only a single statement returning rows without writing can be counted, e.g. `SELECT`.
+pub(crate) fn count_query(sql: &str) -> anyhow::Result<String> {
+    let stmt = single_query(sql, "count the rows of")?;
+    Ok(format!("SELECT COUNT(*) FROM ({stmt})"))
+}
+
+/// Rewrites a query into `SELECT EXISTS (SELECT 1 FROM (query) LIMIT 1)`, checking if it
+/// returns any row while reading at most one. The same queries as `count_query()` are accepted.
+pub(crate) fn exists_query(sql: &str) -> anyhow::Result<String> {
+    let stmt = single_query(sql, "check the rows of")?;
+    Ok(format!("SELECT EXISTS (SELECT 1 FROM ({stmt}) LIMIT 1)"))
+}
+
+/// Extracts the single statement of an SQL string, which must return rows without writing
+fn single_query<'a>(sql: &'a str, action: &str) -> anyhow::Result<&'a str> {
+    let stmt = match split_statements(sql)[..] {
+        [stmt] => stmt,
+        [] => anyhow::bail!("Cannot {action} an empty statement"),
+        _ => anyhow::bail!("Cannot {action} several statements: {sql}"),
+    };
+    let returns_rows = matches!(
+        leading_keyword(stmt).as_deref(),
+        Some("SELECT" | "VALUES" | "WITH")
+    );
+    if !returns_rows || !is_read_only(stmt) {
+        anyhow::bail!("Can only {action} queries, not {stmt}");
+    }
+    Ok(stmt)
+}
+
+/// Normalizes an SQL statement into a stable identity of the query:
+/// literals and parameters are replaced with `?`, keywords are uppercased,
+/// comments are removed and whitespace is collapsed. Lists of values, e.g.
+/// `IN (1, 2, 3)` or multi-row `VALUES`, are collapsed into a single element,
+/// so that they don't affect the fingerprint.
+///
+/// The fingerprint is a stable identity of a query, suitable for keeping
+/// the cardinality of metrics labels low or aggregating slow queries.
+///
+/// # Examples
+///
+/// ```
+/// let fp = libsql_client::fingerprint("select * from users  where id = 42 and name = 'ann'");
+/// assert_eq!(fp, "SELECT * FROM USERS WHERE ID = ? AND NAME = ?");
+/// assert_eq!(
+///     libsql_client::fingerprint("SELECT * FROM t WHERE id IN (1, 2, 3)"),
+///     libsql_client::fingerprint("SELECT * FROM t WHERE id IN (?)"),
+/// );
+/// ```
+pub fn fingerprint(sql: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for token in tokenize(sql) {
+        let part = match token {
+            Token::Word(w) => w.to_ascii_uppercase(),
+            Token::QuotedIdent(q) => q.to_string(),
+            Token::Number(_) if ends_with_unary_sign(&parts) => {
+                // The sign is a part of the literal
+                parts.pop();
+                "?".to_string()
+            }
+            Token::String(_) | Token::Number(_) | Token::Blob(_) | Token::Param(_) => {
+                "?".to_string()
+            }
+            Token::Punct(p) => p.to_string(),
+        };
+        parts.push(part);
+        collapse_lists(&mut parts);
+    }
+    let mut out = String::with_capacity(sql.len());
+    for (i, part) in parts.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| parts[i].as_str());
+        let glue = matches!(part.as_str(), "," | ")" | ";" | ".")
+            || matches!(prev, Some("(" | ".") | None);
+        if i > 0 && !glue {
+            out.push(' ');
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Checks if the last part is a `+` or `-` sign which cannot be a binary operator,
+/// i.e. it is the first token or follows another operator or punctuation
+fn ends_with_unary_sign(parts: &[String]) -> bool {
+    let n = parts.len();
+    if n == 0 || !matches!(parts[n - 1].as_str(), "-" | "+") {
+        return false;
+    }
+    match n.checked_sub(2).map(|i| parts[i].as_str()) {
+        None => true,
+        Some(prev) => prev.starts_with([
+            '(', ',', '=', '<', '>', '!', '|', '*', '/', '%', '+', '-', '&', '~',
+        ]),
+    }
+}
+
+/// Collapses `?, ?` into `?` and `(...), (...)` into `(...)` at the end of `parts`
+fn collapse_lists(parts: &mut Vec<String>) {
+    let n = parts.len();
+    if n >= 3 && parts[n - 1] == "?" && parts[n - 2] == "," && parts[n - 3] == "?" {
+        parts.truncate(n - 2);
+        return;
+    }
+    if n < 2 || parts[n - 1] != ")" {
+        return;
+    }
+    // Find the opening parenthesis of the group which was just closed
+    let Some(open) = matching_open(parts, n - 1) else {
+        return;
+    };
+    if open < 2 || parts[open - 1] != "," || parts[open - 2] != ")" {
+        return;
+    }
+    let Some(prev_open) = matching_open(parts, open - 2) else {
+        return;
+    };
+    if parts[prev_open..open - 1] == parts[open..] {
+        parts.truncate(open - 1);
+    }
+}
+
+fn matching_open(parts: &[String], close: usize) -> Option<usize> {
+    let mut depth = 0;
+    for i in (0..=close).rev() {
+        match parts[i].as_str() {
+            ")" => depth += 1,
+            "(" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reference to a table in an SQL statement
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TableRef<'a> {
+    /// Schema name if the reference is qualified, e.g. `main` in `main.users`
+    pub(crate) schema: Option<&'a str>,
+    /// Table name, as written in the statement
+    pub(crate) name: &'a str,
+    /// Byte range of the table name in the statement
+    pub(crate) span: std::ops::Range<usize>,
+    /// Keyword which introduced the reference, uppercased, e.g. `FROM` or `INTO`
+    pub(crate) keyword: String,
+}
+
+/// Classifies the tables referenced by an SQL statement: the ones it reads from,
+/// writes to, creates, alters or references in constraints.
+/// Names of common table expressions and table-valued functions are not included.
+/// It's a lightweight lexical classifier rather than a full parser, so exotic
+/// syntax may go unnoticed.
+pub(crate) fn table_refs(sql: &str) -> Vec<TableRef<'_>> {
+    let tokens = tokenize(sql);
+    let word = |i: usize| match tokens.get(i) {
+        Some(Token::Word(w)) => Some(w.to_ascii_uppercase()),
+        _ => None,
+    };
+    let is_name = |i: usize| matches!(tokens.get(i), Some(Token::Word(_) | Token::QuotedIdent(_)));
+    let is_punct = |i: usize, p: &str| tokens.get(i) == Some(&Token::Punct(p));
+    let text = |i: usize| match tokens[i] {
+        Token::Word(t) | Token::QuotedIdent(t) => t,
+        _ => "",
+    };
+    let offset = |t: &str| t.as_ptr() as usize - sql.as_ptr() as usize;
+
+    // `ON` introduces a table in CREATE INDEX and CREATE TRIGGER, and an expression in joins
+    let mut on_table = word(0).as_deref() == Some("CREATE")
+        && (1..4).any(|i| matches!(word(i).as_deref(), Some("INDEX" | "TRIGGER")));
+    let ctes = cte_names(&tokens);
+    let mut refs = Vec::new();
+    for i in 0..tokens.len() {
+        let keyword = word(i);
+        let start = match keyword.as_deref() {
+            Some("FROM") if word(i.wrapping_sub(1)).as_deref() == Some("DISTINCT") => None,
+            Some("FROM" | "JOIN" | "INTO" | "REFERENCES") => Some(i + 1),
+            Some("UPDATE") if word(i + 1).as_deref() == Some("OR") => Some(i + 3),
+            Some("UPDATE") if word(i + 1).as_deref() != Some("SET") => Some(i + 1),
+            Some("TABLE" | "VIEW") => match (word(i + 1).as_deref(), word(i + 2).as_deref()) {
+                (Some("IF"), Some("NOT")) => Some(i + 4),
+                (Some("IF"), _) => Some(i + 3),
+                _ => Some(i + 1),
+            },
+            Some("ON") if on_table => {
+                on_table = false;
+                Some(i + 1)
+            }
+            Some("TO") if word(i.wrapping_sub(1)).as_deref() == Some("RENAME") => Some(i + 1),
+            _ => None,
+        };
+        let Some(mut j) = start else {
+            continue;
+        };
+        let is_from = keyword.as_deref() == Some("FROM");
+        while is_name(j) {
+            let (schema, name_idx) = if is_punct(j + 1, ".") && is_name(j + 2) {
+                (Some(text(j)), j + 2)
+            } else {
+                (None, j)
+            };
+            let name = text(name_idx);
+            j = name_idx + 1;
+            let is_function =
+                matches!(keyword.as_deref(), Some("FROM" | "JOIN")) && is_punct(j, "(");
+            let is_cte = schema.is_none()
+                && ctes
+                    .iter()
+                    .any(|c| unquote(c).eq_ignore_ascii_case(&unquote(name)));
+            if !is_function && !is_cte {
+                let start = offset(name);
+                refs.push(TableRef {
+                    schema,
+                    name,
+                    span: start..start + name.len(),
+                    keyword: keyword.clone().unwrap_or_default(),
+                });
+            }
+            if !is_from {
+                break;
+            }
+            // Skip the alias and continue with the next table of a `FROM a, b` list
+            if word(j).as_deref() == Some("AS") {
+                j += 2;
+            } else if is_name(j) && !word(j).is_some_and(|w| is_clause_keyword(&w)) {
+                j += 1;
+            }
+            if !is_punct(j, ",") {
+                break;
+            }
+            j += 1;
+        }
+    }
+    refs
+}
+
+/// Names of common table expressions, i.e. `name` in `WITH name AS (...)`
+fn cte_names<'a>(tokens: &[Token<'a>]) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    for i in 2..tokens.len() {
+        let is_as = matches!(tokens[i - 1], Token::Word(w) if w.eq_ignore_ascii_case("AS"));
+        if tokens[i] != Token::Punct("(") || !is_as {
+            continue;
+        }
+        let mut name_idx = i - 2;
+        // Column list, e.g. `WITH name(a, b) AS (...)`
+        if tokens[name_idx] == Token::Punct(")") {
+            let Some(open) = (0..name_idx)
+                .rev()
+                .find(|&k| tokens[k] == Token::Punct("("))
+            else {
+                continue;
+            };
+            let Some(idx) = open.checked_sub(1) else {
+                continue;
+            };
+            name_idx = idx;
+        }
+        if let Token::Word(name) | Token::QuotedIdent(name) = tokens[name_idx] {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Keywords which may follow a table name, so they cannot be its alias
+fn is_clause_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "WHERE"
+            | "GROUP"
+            | "ORDER"
+            | "LIMIT"
+            | "HAVING"
+            | "WINDOW"
+            | "JOIN"
+            | "INNER"
+            | "LEFT"
+            | "RIGHT"
+            | "FULL"
+            | "CROSS"
+            | "NATURAL"
+            | "OUTER"
+            | "ON"
+            | "USING"
+            | "UNION"
+            | "EXCEPT"
+            | "INTERSECT"
+            | "SET"
+            | "VALUES"
+            | "SELECT"
+            | "DEFAULT"
+            | "RETURNING"
+            | "INDEXED"
+            | "NOT"
+    )
+}
+
+/// Quotes an identifier, escaping the quotes it contains
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Strips the quotes of a quoted identifier, unescaping doubled quotes
+pub(crate) fn unquote(ident: &str) -> String {
+    let bytes = ident.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(b'"'), Some(b'"')) if ident.len() >= 2 => {
+            ident[1..ident.len() - 1].replace("\"\"", "\"")
+        }
+        (Some(b'`'), Some(b'`')) if ident.len() >= 2 => {
+            ident[1..ident.len() - 1].replace("``", "`")
+        }
+        (Some(b'['), Some(b']')) if ident.len() >= 2 => ident[1..ident.len() - 1].to_string(),
+        _ => ident.to_string(),
+    }
+}
+
+/// Rewrites unqualified table names of an SQL statement to start with `prefix`,
+/// including table names used to qualify columns, e.g. `users.name`,
+/// and names of indexes and triggers, which would otherwise collide.
+/// Internal `sqlite_*` tables are left untouched.
+pub(crate) fn prefix_tables(sql: &str, prefix: &str) -> String {
+    let refs: Vec<TableRef> = table_refs(sql)
+        .into_iter()
+        .filter(|r| {
+            r.schema.is_none() && !unquote(r.name).to_ascii_lowercase().starts_with("sqlite_")
+        })
+        .collect();
+    let names: Vec<String> = refs
+        .iter()
+        .map(|r| unquote(r.name).to_ascii_lowercase())
+        .collect();
+    let mut spans: Vec<std::ops::Range<usize>> = refs.iter().map(|r| r.span.clone()).collect();
+    // Column qualifiers, which are followed but not preceded by a dot
+    let tokens = tokenize(sql);
+    for (i, token) in tokens.iter().enumerate() {
+        let (Token::Word(name) | Token::QuotedIdent(name)) = token else {
+            continue;
+        };
+        let followed_by_dot = tokens.get(i + 1) == Some(&Token::Punct("."));
+        let preceded_by_dot = i > 0 && tokens[i - 1] == Token::Punct(".");
+        if followed_by_dot
+            && !preceded_by_dot
+            && names.contains(&unquote(name).to_ascii_lowercase())
+        {
+            let start = name.as_ptr() as usize - sql.as_ptr() as usize;
+            if !spans.iter().any(|s| s.start == start) {
+                spans.push(start..start + name.len());
+            }
+        }
+    }
+    // Names of indexes and triggers are unique per database as well
+    spans.extend(object_names(sql, &tokens));
+    replace_spans(sql, spans, |ident| prefixed_ident(ident, prefix))
+}
+
+/// Qualifies unqualified table names of an SQL statement with `schema`, e.g. an alias
+/// of an attached database, along with names of indexes, triggers and views it creates.
+/// References which SQLite requires to be unqualified, i.e. foreign key targets,
+/// tables of CREATE INDEX, new names in ALTER TABLE RENAME and the bodies of views
+/// and triggers, are left untouched.
+pub(crate) fn qualify_tables(sql: &str, schema: &str) -> String {
+    let tokens = tokenize(sql);
+    let creates = |kind: &str| {
+        matches!(tokens.first(), Some(Token::Word(w)) if w.eq_ignore_ascii_case("CREATE"))
+            && tokens
+                .iter()
+                .take(4)
+                .any(|t| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case(kind)))
+    };
+    let (creates_view, creates_trigger) = (creates("VIEW"), creates("TRIGGER"));
+    let mut spans: Vec<std::ops::Range<usize>> = table_refs(sql)
+        .into_iter()
+        .filter(|r| r.schema.is_none() && !matches!(r.keyword.as_str(), "REFERENCES" | "TO" | "ON"))
+        .filter(|r| !creates_trigger && (!creates_view || r.keyword == "VIEW"))
+        .map(|r| r.span)
+        .collect();
+    spans.extend(object_names(sql, &tokens));
+    let schema = if schema.bytes().all(is_ident_byte) {
+        schema.to_string()
+    } else {
+        format!("\"{}\"", schema.replace('"', "\"\""))
+    };
+    replace_spans(sql, spans, |ident| format!("{schema}.{ident}"))
+}
+
+/// Byte ranges of unqualified names of indexes and triggers, which are unique per database
+fn object_names(sql: &str, tokens: &[Token]) -> Vec<std::ops::Range<usize>> {
+    let word = |k: usize| match tokens.get(k) {
+        Some(Token::Word(w)) => Some(w.to_ascii_uppercase()),
+        _ => None,
+    };
+    let mut spans = Vec::new();
+    for i in 0..tokens.len() {
+        if !matches!(word(i).as_deref(), Some("INDEX" | "TRIGGER")) {
+            continue;
+        }
+        let k = match (word(i + 1).as_deref(), word(i + 2).as_deref()) {
+            (Some("IF"), Some("NOT")) => i + 4,
+            (Some("IF"), _) => i + 3,
+            _ => i + 1,
+        };
+        let qualified = tokens.get(k + 1) == Some(&Token::Punct("."));
+        if let (Some(Token::Word(name) | Token::QuotedIdent(name)), false) =
+            (tokens.get(k), qualified)
+        {
+            let start = name.as_ptr() as usize - sql.as_ptr() as usize;
+            spans.push(start..start + name.len());
+        }
+    }
+    spans
+}
+
+/// Replaces the given byte ranges of `sql` with the result of `replace`
+fn replace_spans(
+    sql: &str,
+    mut spans: Vec<std::ops::Range<usize>>,
+    replace: impl Fn(&str) -> String,
+) -> String {
+    if spans.is_empty() {
+        return sql.to_string();
+    }
+    spans.sort_by_key(|s| s.start);
+    spans.dedup_by_key(|s| s.start);
+    let mut out = String::with_capacity(sql.len() + spans.len() * 16);
+    let mut last = 0;
+    for span in spans {
+        out.push_str(&sql[last..span.start]);
+        out.push_str(&replace(&sql[span.clone()]));
+        last = span.end;
+    }
+    out.push_str(&sql[last..]);
+    out
+}
+
+/// Prepends `prefix` to an identifier, keeping its quoting style
+fn prefixed_ident(ident: &str, prefix: &str) -> String {
+    let quoted = matches!(ident.as_bytes().first(), Some(b'"' | b'`' | b'['));
+    if !quoted && prefix.bytes().all(is_ident_byte) {
+        return format!("{prefix}{ident}");
+    }
+    let name = format!("{prefix}{}", unquote(ident));
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Placeholder of a session variable in an SQL statement, e.g. `{{tenant_id}}`
+pub(crate) struct VarRef<'a> {
+    pub(crate) name: &'a str,
+    /// Byte range of the whole placeholder, braces included
+    pub(crate) span: std::ops::Range<usize>,
+    /// Number of anonymous `?` parameters preceding the placeholder
+    pub(crate) position: usize,
+}
+
+/// Finds placeholders of session variables outside of literals and comments.
+/// Fails if the statement also has numbered or named parameters, whose indexes
+/// would be shifted by binding the variables.
+pub(crate) fn var_refs(sql: &str) -> Result<Vec<VarRef<'_>>, String> {
+    let tokens = tokenize(sql);
+    let offset = |text: &str| text.as_ptr() as usize - sql.as_ptr() as usize;
+    let mut refs = Vec::new();
+    let mut position = 0;
+    let mut other_params = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i..] {
+            [Token::Punct("{"), Token::Punct("{"), Token::Word(name), Token::Punct("}"), end @ Token::Punct("}"), ..] =>
+            {
+                let start = offset(tokens[i].text());
+                refs.push(VarRef {
+                    name,
+                    span: start..offset(end.text()) + 1,
+                    position,
+                });
+                i += 5;
+                continue;
+            }
+            [Token::Param("?"), ..] => position += 1,
+            [Token::Param(_), ..] => other_params = true,
+            _ => {}
+        }
+        i += 1;
+    }
+    if other_params && !refs.is_empty() {
+        return Err(
+            "Session variables cannot be combined with numbered or named parameters".to_string(),
+        );
+    }
+    Ok(refs)
+}
+
+/// Appends `LIMIT limit` to a top-level SELECT which does not limit its rows already,
+/// returning `None` for other statements
+pub(crate) fn append_limit(sql: &str, limit: u64) -> Option<String> {
+    let tokens = tokenize(sql);
+    let is_word =
+        |token: &Token, word: &str| matches!(token, Token::Word(w) if w.eq_ignore_ascii_case(word));
+    match tokens.first() {
+        Some(first) if is_word(first, "SELECT") => {}
+        // Common table expressions may be followed by a write
+        Some(first) if is_word(first, "WITH") && is_read_only(sql) => {}
+        _ => return None,
+    }
+    let mut depth = 0usize;
+    for token in &tokens {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth = depth.saturating_sub(1),
+            token if depth == 0 && is_word(token, "LIMIT") => return None,
+            _ => {}
+        }
+    }
+    // Inserted after the last token rather than at the end, which may be within a comment
+    let last = tokens.iter().rev().find(|t| **t != Token::Punct(";"))?;
+    let end = last.text().as_ptr() as usize - sql.as_ptr() as usize + last.text().len();
+    Some(format!("{} LIMIT {limit}{}", &sql[..end], &sql[end..]))
+}
+
+/// Appends `ORDER BY keys` to a top-level query which does not order its rows already,
+/// before its `LIMIT` clause if any, returning `None` for other statements
+pub(crate) fn append_order_by(sql: &str, keys: &[String]) -> Option<String> {
+    if keys.is_empty() {
+        return None;
+    }
+    let tokens = tokenize(sql);
+    let is_word =
+        |token: &Token, word: &str| matches!(token, Token::Word(w) if w.eq_ignore_ascii_case(word));
+    match tokens.first() {
+        Some(first) if is_word(first, "SELECT") || is_word(first, "VALUES") => {}
+        Some(first) if is_word(first, "WITH") && is_read_only(sql) => {}
+        _ => return None,
+    }
+    let mut depth = 0usize;
+    let mut limit = None;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth = depth.saturating_sub(1),
+            token if depth == 0 && is_word(token, "ORDER") => return None,
+            token if depth == 0 && is_word(token, "LIMIT") => {
+                limit = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let order_by = keys
+        .iter()
+        .map(|key| quote_ident(key))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let offset = |token: &Token| token.text().as_ptr() as usize - sql.as_ptr() as usize;
+    match limit {
+        Some(i) => {
+            let start = offset(&tokens[i]);
+            Some(format!(
+                "{}ORDER BY {order_by} {}",
+                &sql[..start],
+                &sql[start..]
+            ))
+        }
+        None => {
+            let last = tokens.iter().rev().find(|t| **t != Token::Punct(";"))?;
+            let end = offset(last) + last.text().len();
+            Some(format!(
+                "{} ORDER BY {order_by}{}",
+                &sql[..end],
+                &sql[end..]
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints() {
+        assert_eq!(
+            fingerprint("select *  from t -- comment\n where a = 'x' and b = x'00' and c = ?1"),
+            "SELECT * FROM T WHERE A = ? AND B = ? AND C = ?"
+        );
+        assert_eq!(
+            fingerprint("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')"),
+            fingerprint("INSERT INTO t VALUES (?, ?)")
+        );
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE x = -1 AND y = 2 - 1"),
+            "SELECT * FROM T WHERE X = ? AND Y = ? - ?"
+        );
+        assert_eq!(
+            fingerprint("SELECT \"Mixed Case\" FROM t"),
+            "SELECT \"Mixed Case\" FROM T"
+        );
+    }
+
+    #[test]
+    fn parameter_counts() {
+        assert_eq!(parameter_count("SELECT 1"), 0);
+        assert_eq!(parameter_count("SELECT ?, ?"), 2);
+        assert_eq!(parameter_count("SELECT ?3, ?"), 4);
+        assert_eq!(parameter_count("SELECT :a, :b, :a, @c"), 3);
+        assert_eq!(parameter_count("SELECT '?', \"?\" -- ?"), 0);
+    }
+
+    #[test]
+    fn leading_keywords() {
+        assert_eq!(leading_keyword("  select 1").as_deref(), Some("SELECT"));
+        assert_eq!(
+            leading_keyword("/* c */ -- c\n begin").as_deref(),
+            Some("BEGIN")
+        );
+        assert_eq!(leading_keyword("(SELECT 1)").as_deref(), Some("SELECT"));
+        assert_eq!(leading_keyword("-- only a comment"), None);
+    }
+
+    #[test]
+    fn prefixed_tables() {
+        assert_eq!(
+            prefix_tables("SELECT users.name FROM users JOIN main.t ON 1", "app_"),
+            "SELECT app_users.name FROM app_users JOIN main.t ON 1"
+        );
+        assert_eq!(
+            prefix_tables("CREATE INDEX idx ON \"users\" (name)", "app_"),
+            "CREATE INDEX app_idx ON \"app_users\" (name)"
+        );
+        assert_eq!(
+            prefix_tables("SELECT * FROM sqlite_master", "app_"),
+            "SELECT * FROM sqlite_master"
+        );
+        assert_eq!(
+            prefix_tables(
+                "WITH recent AS (SELECT * FROM logs) SELECT * FROM recent",
+                "p_"
+            ),
+            "WITH recent AS (SELECT * FROM p_logs) SELECT * FROM recent"
+        );
+    }
+
+    #[test]
+    fn table_references() {
+        let names = |sql| {
+            table_refs(sql)
+                .into_iter()
+                .map(|r| (r.keyword, r.name))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("SELECT * FROM a, b AS x JOIN c ON x.id = c.id WHERE a.id IN (SELECT id FROM d)"),
+            [
+                ("FROM".to_string(), "a"),
+                ("FROM".to_string(), "b"),
+                ("JOIN".to_string(), "c"),
+                ("FROM".to_string(), "d"),
+            ]
+        );
+        assert_eq!(
+            names("INSERT INTO t SELECT * FROM json_each(?)"),
+            [("INTO".to_string(), "t")]
+        );
+        assert_eq!(
+            names("UPDATE OR IGNORE t SET x = 1"),
+            [("UPDATE".to_string(), "t")]
+        );
+    }
+
+    #[test]
+    fn qualified_tables() {
+        assert_eq!(
+            qualify_tables(
+                "SELECT * FROM users WHERE id IN (SELECT id FROM main.t)",
+                "t0"
+            ),
+            "SELECT * FROM t0.users WHERE id IN (SELECT id FROM main.t)"
+        );
+        assert_eq!(
+            qualify_tables(
+                "CREATE TABLE posts (user_id REFERENCES users(id))",
+                "tenant1"
+            ),
+            "CREATE TABLE tenant1.posts (user_id REFERENCES users(id))"
+        );
+        assert_eq!(
+            qualify_tables("CREATE INDEX idx ON posts (user_id)", "t0"),
+            "CREATE INDEX t0.idx ON posts (user_id)"
+        );
+        assert_eq!(
+            qualify_tables("SELECT * FROM t", "my schema"),
+            "SELECT * FROM \"my schema\".t"
+        );
+    }
+
+    #[test]
+    fn transaction_controls() {
+        use TransactionControl::*;
+        assert_eq!(transaction_control("begin immediate"), Some(Begin));
+        assert_eq!(transaction_control("COMMIT"), Some(Finish));
+        assert_eq!(transaction_control("END TRANSACTION"), Some(Finish));
+        assert_eq!(transaction_control("ROLLBACK"), Some(Finish));
+        assert_eq!(transaction_control("ROLLBACK TO sp"), None);
+        assert_eq!(transaction_control("SAVEPOINT sp"), None);
+        assert_eq!(transaction_control("SELECT 1"), None);
+    }
+
+    #[test]
+    fn split_scripts() {
+        assert_eq!(
+            split_statements("SELECT 1; ; SELECT ';' -- ;\n; /* ; */ SELECT 2"),
+            ["SELECT 1", "SELECT ';'", "SELECT 2"]
+        );
+        let trigger = "CREATE TRIGGER t AFTER INSERT ON a BEGIN \
+                       UPDATE b SET x = CASE WHEN 1 THEN 2 END; DELETE FROM c; END";
+        assert_eq!(
+            split_statements(&format!("{trigger}; SELECT 1;")),
+            [trigger, "SELECT 1"]
+        );
+        assert!(split_statements(" -- nothing\n").is_empty());
+    }
+
+    #[test]
+    fn read_only_statements() {
+        for sql in [
+            "SELECT * FROM t",
+            "values (1)",
+            "EXPLAIN DELETE FROM t",
+            "WITH x AS (SELECT 1) SELECT * FROM x",
+            "BEGIN",
+            "PRAGMA user_version",
+            "PRAGMA main.journal_mode;",
+            "PRAGMA table_info(users)",
+            "PRAGMA main.index_list('users')",
+            "",
+        ] {
+            assert!(is_read_only(sql), "{sql}");
+        }
+        for sql in [
+            "INSERT INTO t VALUES (1)",
+            "WITH x AS (SELECT 1) DELETE FROM t",
+            "CREATE TABLE t (x)",
+            "PRAGMA user_version = 7",
+            "PRAGMA user_version(7)",
+            "PRAGMA foreign_keys(OFF)",
+            "PRAGMA main.journal_mode(WAL)",
+            "PRAGMA optimize",
+            "PRAGMA wal_checkpoint(TRUNCATE)",
+            "PRAGMA",
+        ] {
+            assert!(!is_read_only(sql), "{sql}");
+        }
+    }
+
+    #[test]
+    fn session_variables() {
+        let sql = "SELECT ? FROM t WHERE a = {{tenant}} AND b = '{{x}}'";
+        let refs = var_refs(sql).unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].name, "tenant");
+        assert_eq!(refs[0].position, 1);
+        assert_eq!(&sql[refs[0].span.clone()], "{{tenant}}");
+        assert!(var_refs("SELECT :a, {{tenant}}").is_err());
+        assert_eq!(var_refs("SELECT :a").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn appended_limits() {
+        assert_eq!(
+            append_limit("SELECT * FROM t; -- all", 10).as_deref(),
+            Some("SELECT * FROM t LIMIT 10; -- all")
+        );
+        assert_eq!(
+            append_limit("SELECT * FROM (SELECT * FROM t LIMIT 5)", 10).as_deref(),
+            Some("SELECT * FROM (SELECT * FROM t LIMIT 5) LIMIT 10")
+        );
+        assert_eq!(append_limit("SELECT * FROM t LIMIT 5", 10), None);
+        assert_eq!(append_limit("WITH x AS (SELECT 1) DELETE FROM t", 10), None);
+        assert_eq!(append_limit("DELETE FROM t", 10), None);
+    }
+
+    #[test]
+    fn complete_statements() {
+        assert!(is_complete("SELECT 1;"));
+        assert!(!is_complete("SELECT 1"));
+        assert!(!is_complete("SELECT ';"));
+        assert!(!is_complete(
+            "CREATE TRIGGER t AFTER INSERT ON a BEGIN DELETE FROM b;"
+        ));
+        assert!(!is_complete(";"));
+    }
+
+    #[test]
+    fn count_and_exists_queries() {
+        assert_eq!(
+            count_query("SELECT * FROM t;").unwrap(),
+            "SELECT COUNT(*) FROM (SELECT * FROM t)"
+        );
+        assert_eq!(
+            exists_query("VALUES (1)").unwrap(),
+            "SELECT EXISTS (SELECT 1 FROM (VALUES (1)) LIMIT 1)"
+        );
+        for sql in [
+            "",
+            "SELECT 1; SELECT 2",
+            "DELETE FROM t RETURNING *",
+            "PRAGMA user_version",
+        ] {
+            assert!(count_query(sql).is_err(), "{sql}");
+            assert!(exists_query(sql).is_err(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn appended_order_by() {
+        let keys = ["id".to_string(), "a\"b".to_string()];
+        assert_eq!(
+            append_order_by("SELECT * FROM t", &keys).as_deref(),
+            Some("SELECT * FROM t ORDER BY \"id\", \"a\"\"b\"")
+        );
+        assert_eq!(
+            append_order_by("SELECT * FROM t LIMIT 5;", &keys[..1]).as_deref(),
+            Some("SELECT * FROM t ORDER BY \"id\" LIMIT 5;")
+        );
+        assert_eq!(append_order_by("SELECT * FROM t ORDER BY x", &keys), None);
+        assert_eq!(append_order_by("SELECT * FROM t", &[]), None);
+        assert_eq!(append_order_by("UPDATE t SET x = 1", &keys), None);
+    }
+}
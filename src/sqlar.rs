@@ -0,0 +1,231 @@
+//! `sqlar` reads and writes [SQLite archives](https://sqlite.org/sqlar.html),
+//! i.e. files stored in an `sqlar` table, for apps using the database as a small blob store.
+//! It requires the `sqlar` feature.
+//!
+//! Archives are compatible with the `sqlite3 -A` command line tool: file contents are
+//! compressed with zlib, unless compression does not make them smaller.
+
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::{DatabaseClient, Statement, Value};
+
+/// Permissions of files inserted without an explicit mode, i.e. a regular file with `rw-r--r--`
+const DEFAULT_MODE: u32 = 0o100644;
+
+/// File stored in an archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Path of the file within the archive
+    pub name: String,
+    /// Unix file type and permissions
+    pub mode: u32,
+    /// Modification time, in seconds since the unix epoch
+    pub mtime: i64,
+    /// Size of the file before compression, in bytes
+    pub size: u64,
+}
+
+/// SQLite archive stored in a table of the database, `sqlar` by default.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   use libsql_client::sqlar::Archive;
+///
+///   let db = libsql_client::new_client().await?;
+///   let archive = Archive::new(&db);
+///   archive.create().await?;
+///   archive.insert("avatars/john.png", &std::fs::read("john.png")?).await?;
+///   for entry in archive.list().await? {
+///       println!("{} ({} bytes)", entry.name, entry.size);
+///   }
+///   let png = archive.extract("avatars/john.png").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct Archive<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Archive<'a, Client> {
+    /// Creates an archive stored in the `sqlar` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: crate::sql::quote_ident("sqlar"),
+        }
+    }
+
+    /// Sets the table storing the archive
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = crate::sql::quote_ident(table);
+        self
+    }
+
+    /// Creates the table of the archive, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, mode INT, mtime INT, sz INT, data BLOB)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts a regular file, replacing the one with the same name if any.
+    /// Its modification time is the current time of the database.
+    pub async fn insert(&self, name: &str, data: &[u8]) -> Result<()> {
+        let data = compress(data)?;
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT OR REPLACE INTO {} (name, mode, mtime, sz, data) VALUES (?, ?, CAST(strftime('%s', 'now') AS INTEGER), ?, ?)",
+                    self.table
+                ),
+                &[
+                    Value::from(name),
+                    Value::from(DEFAULT_MODE as i64),
+                    Value::from(data.size as i64),
+                    Value::Blob { value: data.bytes },
+                ],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts a file with the mode and modification time of `entry`,
+    /// replacing the one with the same name if any. The size of `entry` is ignored.
+    pub async fn insert_entry(&self, entry: &Entry, data: &[u8]) -> Result<()> {
+        let data = compress(data)?;
+        self.client
+            .execute(Statement::with_args(
+                format!(
+                    "INSERT OR REPLACE INTO {} (name, mode, mtime, sz, data) VALUES (?, ?, ?, ?, ?)",
+                    self.table
+                ),
+                &[
+                    Value::from(entry.name.as_str()),
+                    Value::from(entry.mode as i64),
+                    Value::from(entry.mtime),
+                    Value::from(data.size as i64),
+                    Value::Blob { value: data.bytes },
+                ],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the contents of a file, decompressed, or `None` if it does not exist
+    pub async fn extract(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("SELECT sz, data FROM {} WHERE name = ?", self.table),
+                &[name],
+            ))
+            .await?;
+        let Some(row) = result.rows.first() else {
+            return Ok(None);
+        };
+        let size = match row.values.first() {
+            Some(Value::Integer { value }) => *value as u64,
+            _ => 0,
+        };
+        let data = match row.values.get(1) {
+            Some(Value::Blob { value }) => value.as_slice(),
+            Some(Value::Text { value }) => value.as_bytes(),
+            // Directories have no contents
+            _ => return Ok(Some(Vec::new())),
+        };
+        decompress(data, size).map(Some)
+    }
+
+    /// Lists the files of the archive, ordered by name
+    pub async fn list(&self) -> Result<Vec<Entry>> {
+        let result = self
+            .client
+            .execute(format!(
+                "SELECT name, mode, mtime, sz FROM {} ORDER BY name",
+                self.table
+            ))
+            .await?;
+        result
+            .rows
+            .iter()
+            .map(|row| {
+                let integer = |i: usize| match row.values.get(i) {
+                    Some(Value::Integer { value }) => *value,
+                    _ => 0,
+                };
+                let name = match row.values.first() {
+                    Some(Value::Text { value }) => value.clone(),
+                    value => return Err(anyhow!("Invalid archive entry name: {value:?}")),
+                };
+                Ok(Entry {
+                    name,
+                    mode: integer(1) as u32,
+                    mtime: integer(2),
+                    size: integer(3) as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Removes a file, returning whether it existed
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE name = ?", self.table),
+                &[name],
+            ))
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+}
+
+/// Contents of a file as stored in an archive
+struct Stored {
+    size: u64,
+    bytes: Vec<u8>,
+}
+
+/// Compresses data with zlib, unless it does not make it smaller
+fn compress(data: &[u8]) -> Result<Stored> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let bytes = if compressed.len() < data.len() {
+        compressed
+    } else {
+        data.to_vec()
+    };
+    Ok(Stored {
+        size: data.len() as u64,
+        bytes,
+    })
+}
+
+/// Decompresses data of `size` bytes, which is stored as is if it has the same length
+fn decompress(data: &[u8], size: u64) -> Result<Vec<u8>> {
+    if data.len() as u64 == size {
+        return Ok(data.to_vec());
+    }
+    let mut out = Vec::with_capacity(size as usize);
+    ZlibDecoder::new(data).read_to_end(&mut out)?;
+    if out.len() as u64 != size {
+        anyhow::bail!(
+            "Archive entry has {} bytes once decompressed, expected {size}",
+            out.len()
+        );
+    }
+    Ok(out)
+}
@@ -0,0 +1,257 @@
+//! `StaleOnOutage` keeps read-mostly applications up while their primary database is
+//! unreachable, by answering reads with stale data instead of failing them.
+//!
+//! Results of reads are kept in memory as they succeed. If the primary then fails with
+//! an error meaning it cannot be reached, e.g. a connection failure or an HTTP 5xx status,
+//! the read is sent to the replica if there is one, and otherwise answered with its last
+//! result, no older than `StalePolicy::max_staleness()`. Either way, the result is flagged
+//! with `ResultSet::is_stale()`, since a replica may lag behind the primary. Errors reported
+//! by the database itself, e.g. a syntax error, are returned as they are.
+//!
+//! Only statements which are known not to write, e.g. `SELECT`, are answered with stale data.
+//! Writes and batches always go to the primary. In WebAssembly outside of WASI, where
+//! no clock is available, the age of cached results is unknown and they do not expire.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::stale::{StaleOnOutage, StalePolicy};
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let db = StaleOnOutage::new(db, StalePolicy::default());
+//!   let menu = db.execute("SELECT * FROM menu_items").await?;
+//!   if menu.is_stale() {
+//!       println!("The menu may be out of date");
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Which stale results may be returned during an outage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StalePolicy {
+    max_entries: usize,
+    max_staleness: Duration,
+}
+
+impl Default for StalePolicy {
+    /// Keeps the results of the last 1000 distinct reads, for up to an hour
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_staleness: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl StalePolicy {
+    /// Sets the number of distinct reads whose results are kept, the oldest ones being
+    /// dropped first. With 0, only the replica, if any, answers during an outage.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the age after which a cached result is no longer returned
+    pub fn max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+}
+
+struct Cached {
+    result: ResultSet,
+    stored_at: Option<Instant>,
+    /// Order in which results were stored, to drop the oldest one
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Cached>,
+    next_tick: u64,
+}
+
+/// Client answering reads with stale data while `primary` is unreachable,
+/// from `replica` if there is one and from the last results of `primary` otherwise
+pub struct StaleOnOutage<Primary: DatabaseClient, Replica: DatabaseClient = Primary> {
+    primary: Primary,
+    replica: Option<Replica>,
+    policy: StalePolicy,
+    cache: Mutex<Cache>,
+}
+
+impl<Primary: DatabaseClient> StaleOnOutage<Primary> {
+    /// Wraps a client, answering reads with its last results during an outage
+    pub fn new(primary: Primary, policy: StalePolicy) -> Self {
+        Self::build(primary, None, policy)
+    }
+}
+
+impl<Primary: DatabaseClient, Replica: DatabaseClient> StaleOnOutage<Primary, Replica> {
+    /// Wraps a client, answering reads with `replica` during an outage,
+    /// then with the last results of `primary` if `replica` fails as well
+    pub fn with_replica(primary: Primary, replica: Replica, policy: StalePolicy) -> Self {
+        Self::build(primary, Some(replica), policy)
+    }
+
+    fn build(primary: Primary, replica: Option<Replica>, policy: StalePolicy) -> Self {
+        Self {
+            primary,
+            replica,
+            policy,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Returns the client receiving all statements
+    pub fn primary(&self) -> &Primary {
+        &self.primary
+    }
+
+    /// Returns the client answering reads during an outage, if any
+    pub fn replica(&self) -> Option<&Replica> {
+        self.replica.as_ref()
+    }
+
+    /// Drops all cached results, e.g. after a write which makes them misleading
+    pub fn clear(&self) {
+        self.cache().entries.clear();
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn store(&self, key: String, result: &ResultSet) {
+        if self.policy.max_entries == 0 {
+            return;
+        }
+        let mut cache = self.cache();
+        let tick = cache.next_tick;
+        cache.next_tick += 1;
+        cache.entries.insert(
+            key,
+            Cached {
+                result: result.clone(),
+                stored_at: crate::timings::now(),
+                tick,
+            },
+        );
+        if cache.entries.len() > self.policy.max_entries {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.tick)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn cached(&self, key: &str) -> Option<ResultSet> {
+        let cache = self.cache();
+        let cached = cache.entries.get(key)?;
+        let expired = cached
+            .stored_at
+            .is_some_and(|stored_at| stored_at.elapsed() > self.policy.max_staleness);
+        (!expired).then(|| cached.result.clone())
+    }
+}
+
+/// Checks if a statement may be answered with stale data: it does not write
+/// nor control transactions
+fn is_cacheable(stmt: &Statement) -> bool {
+    crate::sql::is_read_only(&stmt.sql) && crate::sql::transaction_control(&stmt.sql).is_none()
+}
+
+/// Identifies a read by its SQL and arguments
+fn cache_key(stmt: &Statement) -> String {
+    let args = serde_json::to_string(&stmt.args).unwrap_or_default();
+    format!("{}\0{args}", stmt.sql)
+}
+
+fn stale(result: ResultSet) -> ResultSet {
+    ResultSet {
+        stale: true,
+        ..result
+    }
+}
+
+#[async_trait(?Send)]
+impl<Primary: DatabaseClient, Replica: DatabaseClient> DatabaseClient
+    for StaleOnOutage<Primary, Replica>
+{
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        if !is_cacheable(&stmt) {
+            return self.primary.execute(stmt).await;
+        }
+        let key = cache_key(&stmt);
+        let outage = match self.primary.execute(stmt.clone()).await {
+            Ok(result) => {
+                self.store(key, &result);
+                return Ok(result);
+            }
+            Err(e) if crate::error::is_outage(&e) => e,
+            Err(e) => return Err(e),
+        };
+        if let Some(replica) = &self.replica {
+            match replica.execute(stmt).await {
+                Ok(result) => {
+                    tracing::warn!("Primary is unreachable, read from the replica: {outage}");
+                    return Ok(stale(result));
+                }
+                Err(e) => tracing::debug!("Replica failed to answer during an outage: {e}"),
+            }
+        }
+        match self.cached(&key) {
+            Some(result) => {
+                tracing::warn!("Primary is unreachable, returning a cached result: {outage}");
+                Ok(stale(result))
+            }
+            None => Err(outage),
+        }
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        self.primary.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.primary.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.primary.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.primary.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.primary.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.primary.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.primary.stats()
+    }
+}
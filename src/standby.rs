@@ -0,0 +1,274 @@
+//! `WarmStandby` keeps a standby endpoint connected but unused, so that failing over to it
+//! during an incident does not wait for a connection to be established.
+//!
+//! All statements go to the primary until it suffers an outage, e.g. a connection failure
+//! or an HTTP 5xx status, after which they go to the standby until `WarmStandby::fail_back()`
+//! is called. The read which hit the outage is sent again to the standby. A write is not,
+//! since it may have been applied, so its error is returned, unless it is marked with
+//! `Statement::idempotent()`. A transaction in progress on the primary is lost.
+//!
+//! The idle endpoint, i.e. the standby, or the primary after a failover, receives a cheap
+//! `SELECT 1` every `WarmStandby::keepalive_every()`, sent alongside a statement, so that
+//! its connections are neither closed by the server nor by a proxy in between. A service
+//! which may stay idle longer calls `WarmStandby::keepalive()` from a timer. In WebAssembly
+//! outside of WASI, where no clock is available, keepalives are only sent by `keepalive()`.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{Config, DatabaseClient};
+//!   use libsql_client::standby::WarmStandby;
+//!   use std::time::Duration;
+//!
+//!   let primary = Config::new("libsql://db-ams.example.com")?;
+//!   let primary = libsql_client::new_client_from_config(primary).await?;
+//!   let standby = Config::new("libsql://db-fra.example.com")?;
+//!   let standby = libsql_client::new_client_from_config(standby).await?;
+//!   let db = WarmStandby::new(primary, standby).keepalive_every(Duration::from_secs(20));
+//!   db.execute("SELECT * FROM users").await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement};
+
+/// Client sending statements to `primary`, failing over to an idle but connected `standby`
+pub struct WarmStandby<Primary: DatabaseClient, Standby: DatabaseClient> {
+    primary: Primary,
+    standby: Standby,
+    keepalive_every: Duration,
+    last_keepalive: Mutex<Option<Instant>>,
+    failed_over: AtomicBool,
+}
+
+impl<Primary: DatabaseClient, Standby: DatabaseClient> WarmStandby<Primary, Standby> {
+    /// Creates a client of `primary`, keeping `standby` connected with a keepalive
+    /// every 30 seconds
+    pub fn new(primary: Primary, standby: Standby) -> Self {
+        Self {
+            primary,
+            standby,
+            keepalive_every: Duration::from_secs(30),
+            last_keepalive: Mutex::new(None),
+            failed_over: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets how often the idle endpoint receives a keepalive. It should be shorter than
+    /// the time after which the server or a proxy closes idle connections.
+    pub fn keepalive_every(mut self, interval: Duration) -> Self {
+        self.keepalive_every = interval;
+        self
+    }
+
+    /// Returns the client of the primary
+    pub fn primary(&self) -> &Primary {
+        &self.primary
+    }
+
+    /// Returns the client of the standby
+    pub fn standby(&self) -> &Standby {
+        &self.standby
+    }
+
+    /// Checks if statements currently go to the standby
+    pub fn is_failed_over(&self) -> bool {
+        self.failed_over.load(Ordering::Relaxed)
+    }
+
+    /// Sends the following statements to the standby, e.g. ahead of a planned maintenance
+    /// of the primary
+    pub fn fail_over(&self) {
+        if !self.failed_over.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Failing over to the standby");
+        }
+    }
+
+    /// Sends the following statements to the primary again, once it recovered
+    pub fn fail_back(&self) {
+        if self.failed_over.swap(false, Ordering::Relaxed) {
+            tracing::info!("Failing back to the primary");
+        }
+    }
+
+    /// Sends a keepalive to the idle endpoint, i.e. the standby, or the primary
+    /// after a failover
+    pub async fn keepalive(&self) -> Result<()> {
+        *self.lock() = crate::timings::now();
+        if self.is_failed_over() {
+            self.primary.execute("SELECT 1").await?;
+        } else {
+            self.standby.execute("SELECT 1").await?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.last_keepalive
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Checks if a keepalive is due, in which case the caller sends it
+    fn keepalive_due(&self) -> bool {
+        let Some(now) = crate::timings::now() else {
+            return false;
+        };
+        let mut last = self.lock();
+        match *last {
+            Some(at) if now.duration_since(at) < self.keepalive_every => false,
+            _ => {
+                *last = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Runs `work`, together with a keepalive if one is due. Keepalives which fail
+    /// are only logged, the next one is sent at the next interval.
+    async fn with_keepalive<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        if !self.keepalive_due() {
+            return work.await;
+        }
+        let mut work = std::pin::pin!(work);
+        let mut keepalive = std::pin::pin!(async {
+            let idle = if self.is_failed_over() {
+                self.primary.execute("SELECT 1").await
+            } else {
+                self.standby.execute("SELECT 1").await
+            };
+            if let Err(e) = idle {
+                tracing::debug!("Keepalive of the idle endpoint failed: {e}");
+            }
+        });
+        let mut kept_alive = false;
+        let mut output = None;
+        std::future::poll_fn(|cx| {
+            if !kept_alive {
+                kept_alive = keepalive.as_mut().poll(cx).is_ready();
+            }
+            if output.is_none() {
+                if let Poll::Ready(result) = work.as_mut().poll(cx) {
+                    output = Some(result);
+                }
+            }
+            match (kept_alive, output.take()) {
+                (true, Some(result)) => Poll::Ready(result),
+                (_, result) => {
+                    output = result;
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Checks if `result` is an outage of the primary, in which case it fails over
+    fn fails_over<T>(&self, result: &Result<T>) -> bool {
+        match result {
+            Err(e) if crate::error::is_outage(e) => {
+                tracing::warn!("Primary is unreachable: {e}");
+                self.fail_over();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks if a statement can be sent again after an outage: it does not write, or is
+/// idempotent, and does not control transactions
+fn is_retryable(stmt: &Statement) -> bool {
+    (stmt.idempotent || crate::sql::is_read_only(&stmt.sql))
+        && crate::sql::transaction_control(&stmt.sql).is_none()
+}
+
+#[async_trait(?Send)]
+impl<Primary: DatabaseClient, Standby: DatabaseClient> DatabaseClient
+    for WarmStandby<Primary, Standby>
+{
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        if self.is_failed_over() {
+            return self.with_keepalive(self.standby.execute(stmt)).await;
+        }
+        let retry = is_retryable(&stmt).then(|| stmt.clone());
+        let result = self.with_keepalive(self.primary.execute(stmt)).await;
+        match (self.fails_over(&result), retry) {
+            (true, Some(stmt)) => self.standby.execute(stmt).await,
+            _ => result,
+        }
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        if self.is_failed_over() {
+            return self.with_keepalive(self.standby.raw_batch(stmts)).await;
+        }
+        let retry = stmts.iter().all(is_retryable).then(|| stmts.clone());
+        let result = self.with_keepalive(self.primary.raw_batch(stmts)).await;
+        match (self.fails_over(&result), retry) {
+            (true, Some(stmts)) => self.standby.raw_batch(stmts).await,
+            _ => result,
+        }
+    }
+
+    /// Prepares the statements on both endpoints, so that they are ready after a failover
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        self.primary.prewarm(stmts).await?;
+        self.standby.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        if self.is_failed_over() {
+            self.standby.validates_batches()
+        } else {
+            self.primary.validates_batches()
+        }
+    }
+
+    fn collects_timings(&self) -> bool {
+        if self.is_failed_over() {
+            self.standby.collects_timings()
+        } else {
+            self.primary.collects_timings()
+        }
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        // The transaction may have begun on the primary before failing over
+        let primary = self.primary.schedule_rollback();
+        if self.is_failed_over() {
+            self.standby.schedule_rollback()
+        } else {
+            primary
+        }
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        if self.is_failed_over() {
+            self.standby.barrier().await
+        } else {
+            self.primary.barrier().await
+        }
+    }
+
+    fn stats(&self) -> ClientStats {
+        if self.is_failed_over() {
+            self.standby.stats()
+        } else {
+            self.primary.stats()
+        }
+    }
+}
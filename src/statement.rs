@@ -1,12 +1,25 @@
 //! `Statement` represents an SQL statement,
 //! which can be later sent to a database.
 
-use crate::Value;
+use crate::decode::{BlobPolicy, Decode};
+use crate::guardrails::Limits;
+use crate::{Priority, ResultSet, Value};
 
-/// SQL statement, possibly with bound parameters
+/// SQL statement, possibly with bound parameters.
+/// Its `Debug` and `Display` representations render the parameters
+/// according to the policy set with `redact::set_policy()`.
+#[derive(Clone)]
 pub struct Statement {
     pub(crate) sql: String,
     pub(crate) args: Vec<Value>,
+    pub(crate) idempotency_key: Option<String>,
+    pub(crate) idempotent: bool,
+    pub(crate) timeout: Option<std::time::Duration>,
+    pub(crate) limits: Limits,
+    pub(crate) priority: Priority,
+    pub(crate) decodes: Vec<(String, Decode)>,
+    pub(crate) blob_policy: BlobPolicy,
+    pub(crate) order_keys: Vec<String>,
 }
 
 impl Statement {
@@ -21,6 +34,14 @@ impl Statement {
         Self {
             sql: q.into(),
             args: vec![],
+            idempotency_key: None,
+            idempotent: false,
+            timeout: None,
+            limits: Limits::default(),
+            priority: Priority::Normal,
+            decodes: Vec::new(),
+            blob_policy: BlobPolicy::Full,
+            order_keys: Vec::new(),
         }
     }
 
@@ -35,8 +56,172 @@ impl Statement {
         Self {
             sql: q.into(),
             args: params.iter().map(|p| p.clone().into()).collect(),
+            idempotency_key: None,
+            idempotent: false,
+            timeout: None,
+            limits: Limits::default(),
+            priority: Priority::Normal,
+            decodes: Vec::new(),
+            blob_policy: BlobPolicy::Full,
+            order_keys: Vec::new(),
         }
     }
+
+    /// Attaches an idempotency key to a write statement. The client uses it to avoid
+    /// applying the statement twice when a request is retried, see `Config::idempotency_table()`.
+    /// Keys should be unique per logical write, e.g. derived from a request ID.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::with_args("INSERT INTO payments VALUES (?, ?)", &[7, 100])
+    ///     .idempotency_key("payment-7");
+    /// ```
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Statement {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Marks the statement as idempotent: applying it twice has the same effect as applying it
+    /// once, e.g. `UPDATE users SET name = ? WHERE id = ?`, but not `UPDATE counters SET
+    /// value = value + 1`. Such a statement may be sent again after its request failed in a way
+    /// which leaves unknown whether the server received it, e.g. a connection reset. Writes
+    /// which are not marked are only sent again if they have an idempotency key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::with_args("DELETE FROM sessions WHERE id = ?", &[7])
+    ///     .idempotent();
+    /// ```
+    pub fn idempotent(mut self) -> Statement {
+        self.idempotent = true;
+        self
+    }
+
+    /// Sets a limit on the execution time of the statement, after which
+    /// it is interrupted by the database instead of running to completion.
+    /// The local backend enforces it with a progress handler, while remote servers
+    /// do not support per-statement timeouts yet, so there it is ignored;
+    /// see `libsql_client::deadline` for bounding requests client-side.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::new("SELECT * FROM events ORDER BY payload")
+    ///     .timeout(std::time::Duration::from_secs(5));
+    /// ```
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Statement {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails the statement if it reads more than `limit` rows, as reported by the server
+    /// or else counted from the returned rows. See `libsql_client::guardrails` for how
+    /// each backend enforces it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::new("SELECT * FROM events WHERE payload LIKE '%x%'")
+    ///     .max_rows_read(10_000);
+    /// ```
+    pub fn max_rows_read(mut self, limit: u64) -> Statement {
+        self.limits.max_rows_read = Some(limit);
+        self
+    }
+
+    /// Logs a warning if the statement reads more than `limit` rows, like `max_rows_read()`
+    /// but without failing it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::new("SELECT * FROM events WHERE payload LIKE '%x%'")
+    ///     .warn_rows_read(1_000);
+    /// ```
+    pub fn warn_rows_read(mut self, limit: u64) -> Statement {
+        self.limits.warn_rows_read = Some(limit);
+        self
+    }
+
+    /// Sets the priority of the statement over others sharing the client while they wait
+    /// for their turn, see `libsql_client::priority`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::new("DELETE FROM sessions WHERE expired")
+    ///     .priority(libsql_client::Priority::Low);
+    /// ```
+    pub fn priority(mut self, priority: Priority) -> Statement {
+        self.priority = priority;
+        self
+    }
+
+    /// Decodes the values of a column into the given type, instead of the types they
+    /// were stored with. See `libsql_client::decode` for the conversions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libsql_client::decode::Decode;
+    ///
+    /// let stmt = libsql_client::Statement::new("SELECT id, amount FROM payments")
+    ///     .decode_column("amount", Decode::Real);
+    /// ```
+    pub fn decode_column(mut self, column: impl Into<String>, decode: Decode) -> Statement {
+        self.decodes.push((column.into(), decode));
+        self
+    }
+
+    /// Replaces the blobs returned by the statement with their length or a prefix, e.g. for
+    /// previewing a table without reading its blobs whole. See `libsql_client::decode` for
+    /// which backends avoid reading the rest of the blobs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libsql_client::decode::BlobPolicy;
+    ///
+    /// let stmt = libsql_client::Statement::new("SELECT name, contents FROM attachments")
+    ///     .blob_policy(BlobPolicy::SizeOnly);
+    /// ```
+    pub fn blob_policy(mut self, policy: BlobPolicy) -> Statement {
+        self.blob_policy = policy;
+        self
+    }
+
+    /// Orders the rows by the given columns if the query does not order them already,
+    /// appending an `ORDER BY` clause, so that pages and diffs of the results do not depend
+    /// on the query plan. The columns should identify the rows, e.g. a primary key, and must
+    /// be returned by the query: executing it fails otherwise. Like `decode_column()`,
+    /// the columns are checked by `DatabaseClient::execute()`, not in batches.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stmt = libsql_client::Statement::new("SELECT id, name FROM users LIMIT 10")
+    ///     .with_stable_order(&["id"]);
+    /// assert_eq!(stmt.to_string(), r#""SELECT id, name FROM users ORDER BY \"id\" LIMIT 10""#);
+    /// ```
+    pub fn with_stable_order(mut self, keys: &[&str]) -> Statement {
+        self.order_keys = keys.iter().map(|key| key.to_string()).collect();
+        if let Some(sql) = crate::sql::append_order_by(&self.sql, &self.order_keys) {
+            self.sql = sql;
+        }
+        self
+    }
+}
+
+/// Checks that the columns set with `Statement::with_stable_order()` are returned
+pub(crate) fn check_order_keys(keys: &[String], result: &ResultSet) -> anyhow::Result<()> {
+    for key in keys {
+        if !result.columns.iter().any(|c| c.eq_ignore_ascii_case(key)) {
+            anyhow::bail!("Cannot order by column {key}, which is not returned by the statement");
+        }
+    }
+    Ok(())
 }
 
 impl From<String> for Statement {
@@ -44,6 +229,14 @@ impl From<String> for Statement {
         Statement {
             sql: q,
             args: vec![],
+            idempotency_key: None,
+            idempotent: false,
+            timeout: None,
+            limits: Limits::default(),
+            priority: Priority::Normal,
+            decodes: Vec::new(),
+            blob_policy: BlobPolicy::Full,
+            order_keys: Vec::new(),
         }
     }
 }
@@ -65,11 +258,7 @@ impl std::fmt::Display for Statement {
         if self.args.is_empty() {
             write!(f, "{}", serde_json::json!(self.sql))
         } else {
-            let params: Vec<String> = self
-                .args
-                .iter()
-                .map(|p| serde_json::json!(p)["value"].to_string())
-                .collect();
+            let params = crate::redact::render_args(&self.args);
             write!(
                 f,
                 "{{\"q\": {}, \"params\": [{}]}}",
@@ -79,3 +268,21 @@ impl std::fmt::Display for Statement {
         }
     }
 }
+
+impl std::fmt::Debug for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = crate::redact::render_args(&self.args);
+        f.debug_struct("Statement")
+            .field("sql", &self.sql)
+            .field("args", &format_args!("[{}]", args.join(", ")))
+            .field("idempotency_key", &self.idempotency_key)
+            .field("idempotent", &self.idempotent)
+            .field("timeout", &self.timeout)
+            .field("limits", &self.limits)
+            .field("priority", &self.priority)
+            .field("decodes", &self.decodes)
+            .field("blob_policy", &self.blob_policy)
+            .field("order_keys", &self.order_keys)
+            .finish()
+    }
+}
@@ -0,0 +1,167 @@
+//! `ClientStats` exposes cumulative counters describing the activity of a client.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{proto::StmtResult, BatchResult};
+
+/// Cumulative counters describing the activity of a client,
+/// obtained with `DatabaseClient::stats()`.
+/// Counters which do not apply to a given backend stay at 0,
+/// e.g. the local backend does not send any bytes over the network.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ClientStats {
+    /// Number of statements executed, including failed ones
+    pub statements_executed: u64,
+    /// Number of rows returned by the executed statements
+    pub rows_fetched: u64,
+    /// Number of bytes sent to the server
+    pub bytes_sent: u64,
+    /// Number of bytes received from the server
+    pub bytes_received: u64,
+    /// Number of times a connection to the server was re-established
+    pub reconnects: u64,
+    /// Number of requests retried after the server rejected them
+    pub retries: u64,
+    /// Number of results served from a cache instead of the server
+    pub cache_hits: u64,
+    /// Number of requests sent by the workers and spin backends, which count towards the
+    /// subrequest limit of an invocation. See `Config::max_requests()`.
+    #[serde(default)]
+    pub requests_sent: u64,
+    /// Number of retries given up because the `RetryBudget` of the client was exhausted
+    #[serde(default)]
+    pub retries_throttled: u64,
+}
+
+/// Thread-safe collector of `ClientStats`, owned by a backend
+#[derive(Debug, Default)]
+pub(crate) struct StatsCollector {
+    statements_executed: AtomicU64,
+    rows_fetched: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    reconnects: AtomicU64,
+    retries: AtomicU64,
+    cache_hits: AtomicU64,
+    requests_sent: AtomicU64,
+    retries_throttled: AtomicU64,
+}
+
+impl StatsCollector {
+    /// Records all executed steps of a batch
+    pub(crate) fn record_batch(&self, result: &BatchResult) {
+        let executed = result
+            .step_results
+            .iter()
+            .zip(result.step_errors.iter())
+            .filter(|(r, e)| r.is_some() || e.is_some())
+            .count();
+        let rows: usize = result
+            .step_results
+            .iter()
+            .flatten()
+            .map(|r| r.rows.len())
+            .sum();
+        self.statements_executed
+            .fetch_add(executed as u64, Ordering::Relaxed);
+        self.rows_fetched.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Records a single executed statement
+    #[cfg_attr(
+        not(any(feature = "hrana_backend", feature = "workers_backend")),
+        allow(dead_code)
+    )]
+    pub(crate) fn record_result(&self, result: &StmtResult) {
+        self.statements_executed.fetch_add(1, Ordering::Relaxed);
+        self.rows_fetched
+            .fetch_add(result.rows.len() as u64, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn record_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn record_bytes_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    pub(crate) fn record_retry_throttled(&self) {
+        self.retries_throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "workers_backend"), allow(dead_code))]
+    pub(crate) fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request about to be sent, failing without counting it
+    /// if the client already sent `max` requests
+    #[cfg_attr(
+        not(any(feature = "workers_backend", feature = "spin_backend")),
+        allow(dead_code)
+    )]
+    pub(crate) fn start_request(&self, max: Option<u64>) -> anyhow::Result<()> {
+        let counted = self.requests_sent.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |sent| match max {
+                Some(max) if sent >= max => None,
+                _ => Some(sent + 1),
+            },
+        );
+        match (counted, max) {
+            (Err(_), Some(max)) => anyhow::bail!(
+                "Refusing to send more than {max} requests, the limit set with Config::max_requests()"
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns how many requests may still be sent under a limit of `max` requests
+    #[cfg_attr(
+        not(any(feature = "workers_backend", feature = "spin_backend")),
+        allow(dead_code)
+    )]
+    pub(crate) fn requests_remaining(&self, max: Option<u64>) -> Option<u64> {
+        max.map(|max| max.saturating_sub(self.requests_sent.load(Ordering::Relaxed)))
+    }
+
+    /// Returns the current values of all counters
+    pub(crate) fn snapshot(&self) -> ClientStats {
+        ClientStats {
+            statements_executed: self.statements_executed.load(Ordering::Relaxed),
+            rows_fetched: self.rows_fetched.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            requests_sent: self.requests_sent.load(Ordering::Relaxed),
+            retries_throttled: self.retries_throttled.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! `strict` describes `STRICT` tables, whose columns only hold values of their declared type,
+//! generating their `CREATE TABLE` statement and checking values client-side before they are
+//! inserted, so that type mix-ups fail early with the name of the column.
+//!
+//! The checks are stricter than SQLite, which converts text holding a number into an
+//! `INTEGER` or `REAL` column: only values of the declared type are accepted, except
+//! integers in `REAL` columns, which are converted without loss. `ANY` columns accept
+//! every value, and `NULL` is accepted unless the column is `NOT NULL` or part of the primary
+//! key. An `INTEGER` primary key of a single column is an alias of the rowid, which
+//! is assigned when the value is `NULL` or missing.
+//!
+//! `StrictTable::save()` inserts a row or updates the existing one, returning the row as
+//! stored. Rows conflict on the primary key by default, or on the columns of another unique
+//! constraint set with `StrictTable::conflict_target()`, e.g. an email address.
+//! `StrictTable::find()`, `StrictTable::update()` and `StrictTable::delete()` address rows
+//! by their primary key, given as a single value or as a tuple for composite keys.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Value};
+//!   use libsql_client::strict::{StrictTable, StrictType};
+//!
+//!   let db = libsql_client::new_client().await?;
+//!   let orders = StrictTable::new("orders")
+//!       .column("id", StrictType::Integer)
+//!       .not_null("customer", StrictType::Text)
+//!       .not_null("total", StrictType::Real)
+//!       .column("notes", StrictType::Any)
+//!       .primary_key(&["id"]);
+//!   orders.create(&db).await?;
+//!   let insert = orders.insert(&[("customer", "ann".into()), ("total", Value::from(12))])?;
+//!   db.execute(insert).await?;
+//!   // Fails before reaching the database: column total is REAL, not TEXT
+//!   assert!(orders.insert(&[("customer", "bob".into()), ("total", "12.50".into())]).is_err());
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+
+use crate::sql::quote_ident;
+use crate::{DatabaseClient, Statement, Value};
+
+/// Column types allowed in `STRICT` tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StrictType {
+    Int,
+    Integer,
+    Real,
+    Text,
+    Blob,
+    Any,
+}
+
+impl StrictType {
+    /// Returns the type as written in the `CREATE TABLE` statement
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            StrictType::Int => "INT",
+            StrictType::Integer => "INTEGER",
+            StrictType::Real => "REAL",
+            StrictType::Text => "TEXT",
+            StrictType::Blob => "BLOB",
+            StrictType::Any => "ANY",
+        }
+    }
+
+    /// Checks if a value which is not `NULL` can be stored in a column of this type
+    fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (StrictType::Any, _)
+                | (StrictType::Int | StrictType::Integer, Value::Integer { .. })
+                | (
+                    StrictType::Real,
+                    Value::Integer { .. } | Value::Float { .. }
+                )
+                | (StrictType::Text, Value::Text { .. })
+                | (StrictType::Blob, Value::Blob { .. })
+        )
+    }
+}
+
+/// Values of a primary key: a single value, or a tuple with a value per column
+/// of a composite key, in the order of `StrictTable::primary_key()`
+pub trait Key {
+    /// Returns the values of the key, in order
+    fn into_values(self) -> Vec<Value>;
+}
+
+impl Key for Value {
+    fn into_values(self) -> Vec<Value> {
+        vec![self]
+    }
+}
+
+impl Key for i64 {
+    fn into_values(self) -> Vec<Value> {
+        vec![self.into()]
+    }
+}
+
+impl Key for &str {
+    fn into_values(self) -> Vec<Value> {
+        vec![self.into()]
+    }
+}
+
+impl Key for String {
+    fn into_values(self) -> Vec<Value> {
+        vec![self.into()]
+    }
+}
+
+macro_rules! tuple_key {
+    ($($name:ident)+) => {
+        impl<$($name: Into<Value>),+> Key for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<Value> {
+                let ($($name,)+) = self;
+                vec![$($name.into()),+]
+            }
+        }
+    };
+}
+
+tuple_key!(A B);
+tuple_key!(A B C);
+tuple_key!(A B C D);
+
+/// Column of a `STRICT` table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrictColumn {
+    pub name: String,
+    pub ty: StrictType,
+    pub not_null: bool,
+}
+
+/// Description of a `STRICT` table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrictTable {
+    name: String,
+    columns: Vec<StrictColumn>,
+    primary_key: Vec<String>,
+    conflict_target: Vec<String>,
+}
+
+impl StrictTable {
+    /// Describes a table without columns
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            columns: Vec::new(),
+            primary_key: Vec::new(),
+            conflict_target: Vec::new(),
+        }
+    }
+
+    /// Adds a column which may be `NULL`
+    pub fn column(mut self, name: impl Into<String>, ty: StrictType) -> Self {
+        self.columns.push(StrictColumn {
+            name: name.into(),
+            ty,
+            not_null: false,
+        });
+        self
+    }
+
+    /// Adds a `NOT NULL` column
+    pub fn not_null(mut self, name: impl Into<String>, ty: StrictType) -> Self {
+        self.columns.push(StrictColumn {
+            name: name.into(),
+            ty,
+            not_null: true,
+        });
+        self
+    }
+
+    /// Sets the columns of the primary key
+    pub fn primary_key(mut self, columns: &[&str]) -> Self {
+        self.primary_key = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Sets the columns of the unique constraint on which `save()` detects existing rows,
+    /// instead of the primary key
+    pub fn conflict_target(mut self, columns: &[&str]) -> Self {
+        self.conflict_target = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Returns the name of the table
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the columns of the table, in order
+    pub fn columns(&self) -> &[StrictColumn] {
+        &self.columns
+    }
+
+    /// Returns the `CREATE TABLE IF NOT EXISTS ... STRICT` statement of the table
+    pub fn create_sql(&self) -> String {
+        let mut definitions: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let not_null = if column.not_null { " NOT NULL" } else { "" };
+                format!(
+                    "{} {}{not_null}",
+                    quote_ident(&column.name),
+                    column.ty.as_sql()
+                )
+            })
+            .collect();
+        if !self.primary_key.is_empty() {
+            let columns: Vec<String> = self.primary_key.iter().map(|c| quote_ident(c)).collect();
+            definitions.push(format!("PRIMARY KEY ({})", columns.join(", ")));
+        }
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}) STRICT",
+            quote_ident(&self.name),
+            definitions.join(", ")
+        )
+    }
+
+    /// Creates the table, if it does not exist yet
+    pub async fn create(&self, db: &(impl DatabaseClient + ?Sized)) -> Result<()> {
+        if self.columns.is_empty() {
+            anyhow::bail!("Table {} has no columns", self.name);
+        }
+        db.execute(self.create_sql()).await?;
+        Ok(())
+    }
+
+    /// Checks that a value can be stored in a column
+    pub fn check(&self, column: &str, value: &Value) -> Result<()> {
+        let Some(col) = self.column_named(column) else {
+            anyhow::bail!("Table {} has no column {column}", self.name);
+        };
+        if matches!(value, Value::Null) {
+            if self.requires_value(col) {
+                anyhow::bail!("Column {column} of table {} cannot be NULL", self.name);
+            }
+            return Ok(());
+        }
+        if !col.ty.accepts(value) {
+            anyhow::bail!(
+                "Column {column} of table {} is {}, not {}",
+                self.name,
+                col.ty.as_sql(),
+                crate::de::type_name(value)
+            );
+        }
+        Ok(())
+    }
+
+    /// Builds an `INSERT` statement for a row, given as pairs of columns and values,
+    /// after checking every value and that no required column is missing
+    pub fn insert(&self, values: &[(&str, Value)]) -> Result<Statement> {
+        for (column, value) in values {
+            self.check(column, value)?;
+        }
+        if let Some(missing) = self.columns.iter().find(|col| {
+            self.requires_value(col) && !values.iter().any(|(name, _)| *name == col.name)
+        }) {
+            anyhow::bail!(
+                "Column {} of table {} cannot be NULL",
+                missing.name,
+                self.name
+            );
+        }
+        let columns: Vec<String> = values.iter().map(|(name, _)| quote_ident(name)).collect();
+        let sql = if values.is_empty() {
+            format!("INSERT INTO {} DEFAULT VALUES", quote_ident(&self.name))
+        } else {
+            format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote_ident(&self.name),
+                columns.join(", "),
+                vec!["?"; values.len()].join(", ")
+            )
+        };
+        let args: Vec<Value> = values.iter().map(|(_, value)| value.clone()).collect();
+        Ok(Statement::with_args(sql, &args))
+    }
+
+    /// Builds an `INSERT ... ON CONFLICT DO UPDATE ... RETURNING *` statement for a row,
+    /// given as pairs of columns and values, which updates the columns of the row given
+    /// in `values` if it conflicts with an existing one
+    pub fn save_stmt(&self, values: &[(&str, Value)]) -> Result<Statement> {
+        let target = if self.conflict_target.is_empty() {
+            &self.primary_key
+        } else {
+            &self.conflict_target
+        };
+        if target.is_empty() {
+            anyhow::bail!(
+                "Table {} has neither a primary key nor a conflict target",
+                self.name
+            );
+        }
+        if let Some(column) = target
+            .iter()
+            .find(|column| self.column_named(column).is_none())
+        {
+            anyhow::bail!("Table {} has no column {column}", self.name);
+        }
+        if values.is_empty() {
+            anyhow::bail!("Cannot save a row of table {} without values", self.name);
+        }
+        let mut stmt = self.insert(values)?;
+        let mut updated: Vec<&str> = values
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| !target.iter().any(|column| column == name))
+            .collect();
+        if updated.is_empty() {
+            // Updating a column to its own value still returns the existing row
+            updated.push(&target[0]);
+        }
+        let target: Vec<String> = target.iter().map(|c| quote_ident(c)).collect();
+        let assignments: Vec<String> = updated
+            .iter()
+            .map(|name| {
+                let column = quote_ident(name);
+                format!("{column} = excluded.{column}")
+            })
+            .collect();
+        stmt.sql = format!(
+            "{} ON CONFLICT ({}) DO UPDATE SET {} RETURNING *",
+            stmt.sql,
+            target.join(", "),
+            assignments.join(", ")
+        );
+        Ok(stmt)
+    }
+
+    /// Inserts a row or updates the existing one, see `save_stmt()`,
+    /// and returns the row as stored, deserialized into `T`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f(users: libsql_client::strict::StrictTable) -> anyhow::Result<()> {
+    ///   #[derive(serde::Deserialize)]
+    ///   struct User {
+    ///       id: i64,
+    ///       email: String,
+    ///       name: String,
+    ///   }
+    ///   let db = libsql_client::new_client().await?;
+    ///   let users = users.conflict_target(&["email"]);
+    ///   let user: User = users
+    ///       .save(&db, &[("email", "ann@example.com".into()), ("name", "Ann".into())])
+    ///       .await?;
+    ///   println!("Saved user {}", user.id);
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub async fn save<T: serde::de::DeserializeOwned>(
+        &self,
+        db: &(impl DatabaseClient + ?Sized),
+        values: &[(&str, Value)],
+    ) -> Result<T> {
+        let stmt = self.save_stmt(values)?;
+        let mut rows: Vec<T> = db.query_as(stmt).await?;
+        match rows.pop() {
+            Some(row) => Ok(row),
+            None => anyhow::bail!("Saving a row of table {} returned no row", self.name),
+        }
+    }
+
+    /// Returns the row with the given primary key, deserialized into `T`, if it exists
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   use libsql_client::strict::{StrictTable, StrictType};
+    ///
+    ///   #[derive(serde::Deserialize)]
+    ///   struct Membership {
+    ///       team: String,
+    ///       user_id: i64,
+    ///       role: String,
+    ///   }
+    ///   let db = libsql_client::new_client().await?;
+    ///   let memberships = StrictTable::new("memberships")
+    ///       .not_null("team", StrictType::Text)
+    ///       .not_null("user_id", StrictType::Integer)
+    ///       .not_null("role", StrictType::Text)
+    ///       .primary_key(&["team", "user_id"]);
+    ///   let membership: Option<Membership> = memberships.find(&db, ("acme", 42i64)).await?;
+    ///   memberships.update(&db, ("acme", 42i64), &[("role", "admin".into())]).await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub async fn find<T: serde::de::DeserializeOwned>(
+        &self,
+        db: &(impl DatabaseClient + ?Sized),
+        key: impl Key,
+    ) -> Result<Option<T>> {
+        let (condition, args) = self.key_condition(key)?;
+        let sql = format!(
+            "SELECT * FROM {} WHERE {condition}",
+            quote_ident(&self.name)
+        );
+        let mut rows: Vec<T> = db.query_as(Statement::with_args(sql, &args)).await?;
+        Ok(rows.pop())
+    }
+
+    /// Updates the columns of the row with the given primary key, given as pairs
+    /// of columns and values, after checking every value. Returns whether the row exists.
+    pub async fn update(
+        &self,
+        db: &(impl DatabaseClient + ?Sized),
+        key: impl Key,
+        values: &[(&str, Value)],
+    ) -> Result<bool> {
+        if values.is_empty() {
+            anyhow::bail!("Cannot update a row of table {} without values", self.name);
+        }
+        for (column, value) in values {
+            self.check(column, value)?;
+        }
+        let (condition, key_args) = self.key_condition(key)?;
+        let assignments: Vec<String> = values
+            .iter()
+            .map(|(name, _)| format!("{} = ?", quote_ident(name)))
+            .collect();
+        let sql = format!(
+            "UPDATE {} SET {} WHERE {condition}",
+            quote_ident(&self.name),
+            assignments.join(", ")
+        );
+        let args: Vec<Value> = values
+            .iter()
+            .map(|(_, value)| value.clone())
+            .chain(key_args)
+            .collect();
+        let result = db.execute(Statement::with_args(sql, &args)).await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Deletes the row with the given primary key. Returns whether the row existed.
+    pub async fn delete(&self, db: &(impl DatabaseClient + ?Sized), key: impl Key) -> Result<bool> {
+        let (condition, args) = self.key_condition(key)?;
+        let sql = format!("DELETE FROM {} WHERE {condition}", quote_ident(&self.name));
+        let result = db.execute(Statement::with_args(sql, &args)).await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Returns the condition matching the row with the given primary key, and its arguments
+    fn key_condition(&self, key: impl Key) -> Result<(String, Vec<Value>)> {
+        if self.primary_key.is_empty() {
+            anyhow::bail!("Table {} has no primary key", self.name);
+        }
+        let values = key.into_values();
+        if values.len() != self.primary_key.len() {
+            anyhow::bail!(
+                "The primary key of table {} has {} columns, not {}",
+                self.name,
+                self.primary_key.len(),
+                values.len()
+            );
+        }
+        let conditions: Vec<String> = self
+            .primary_key
+            .iter()
+            .map(|column| format!("{} = ?", quote_ident(column)))
+            .collect();
+        Ok((conditions.join(" AND "), values))
+    }
+
+    fn column_named(&self, column: &str) -> Option<&StrictColumn> {
+        self.columns.iter().find(|col| col.name == column)
+    }
+
+    /// Whether a column needs a value: it is `NOT NULL` or part of the primary key,
+    /// and is not an alias of the rowid
+    fn requires_value(&self, col: &StrictColumn) -> bool {
+        let rowid_alias = col.ty == StrictType::Integer && self.primary_key == [col.name.as_str()];
+        (col.not_null || self.primary_key.contains(&col.name)) && !rowid_alias
+    }
+}
@@ -0,0 +1,566 @@
+//! `testing` contains hooks for simulating failures of any backend,
+//! which let applications test their retry and failover behavior deterministically.
+//! It is only available with the `test-support` feature.
+//!
+//! `Chaos` builds on top of it to add random latency and errors to a client
+//! in staging environments, see its documentation for details.
+//!
+//! `arbitrary_value()` and the round-trip assertions let codecs built on top of
+//! the crate (custom types, new transports) reuse its own correctness checks.
+//!
+//! A `FaultInjector` wraps a client and applies scripted faults to the requests
+//! passing through it, in order. Simulated latency advances a `TestClock`
+//! instead of sleeping, so tests run instantly and always observe the same timings.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::testing::{Fault, FaultInjector};
+//!   use std::time::Duration;
+//!
+//!   let db = FaultInjector::new(libsql_client::local::Client::in_memory()?);
+//!   db.inject(Fault::Latency(Duration::from_secs(2)));
+//!   db.inject(Fault::ServerError { status: 503, message: "overloaded".into() });
+//!   db.inject(Fault::NetworkFailure);
+//!
+//!   db.execute("SELECT 1").await?; // succeeds, after 2 simulated seconds
+//!   assert!(db.execute("SELECT 1").await.is_err()); // HTTP 503
+//!   assert!(db.execute("SELECT 1").await.is_err()); // connection lost
+//!   db.execute("SELECT 1").await?; // no faults left
+//!   assert_eq!(db.clock().now(), Duration::from_secs(2));
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement, Value};
+
+/// Simulated clock, which only moves forward when it is advanced explicitly.
+/// Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct TestClock {
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl TestClock {
+    /// Creates a clock starting at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the simulated time elapsed since the clock was created
+    pub fn now(&self) -> Duration {
+        self.elapsed.lock().map(|e| *e).unwrap_or_default()
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, by: Duration) {
+        if let Ok(mut elapsed) = self.elapsed.lock() {
+            *elapsed += by;
+        }
+    }
+}
+
+/// Fault applied to a single request
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// The request fails with a `NetworkFailure` error, without reaching the backend
+    NetworkFailure,
+    /// The request reaches the backend, but its response is lost
+    /// and it fails with a `NetworkFailure` error
+    LostResponse,
+    /// The request succeeds after advancing the clock by the given duration
+    Latency(Duration),
+    /// The request fails with the typed `Error` the server would return
+    /// for the given HTTP status, without reaching the backend
+    ServerError { status: u16, message: String },
+}
+
+/// Error returned for requests failed with `Fault::NetworkFailure`
+/// or `Fault::LostResponse`
+#[derive(Clone, Debug)]
+pub struct NetworkFailure;
+
+impl std::fmt::Display for NetworkFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Simulated network failure")
+    }
+}
+
+impl std::error::Error for NetworkFailure {}
+
+/// Client wrapper which applies scripted faults to the requests sent through it
+pub struct FaultInjector<Client: DatabaseClient> {
+    inner: Client,
+    faults: RefCell<VecDeque<Fault>>,
+    clock: TestClock,
+    requests: RefCell<u64>,
+}
+
+impl<Client: DatabaseClient> FaultInjector<Client> {
+    /// Wraps a client, with no faults scheduled
+    pub fn new(inner: Client) -> Self {
+        Self::with_clock(inner, TestClock::new())
+    }
+
+    /// Wraps a client, advancing the given clock on simulated latency
+    pub fn with_clock(inner: Client, clock: TestClock) -> Self {
+        Self {
+            inner,
+            faults: RefCell::new(VecDeque::new()),
+            clock,
+            requests: RefCell::new(0),
+        }
+    }
+
+    /// Schedules a fault for the next request without a fault scheduled yet
+    pub fn inject(&self, fault: Fault) {
+        self.faults.borrow_mut().push_back(fault);
+    }
+
+    /// Schedules a fault for each of the next `count` requests
+    pub fn inject_n(&self, fault: Fault, count: usize) {
+        self.faults
+            .borrow_mut()
+            .extend(std::iter::repeat_n(fault, count));
+    }
+
+    /// Cancels all scheduled faults
+    pub fn clear(&self) {
+        self.faults.borrow_mut().clear();
+    }
+
+    /// Returns the number of faults which were not applied yet
+    pub fn pending(&self) -> usize {
+        self.faults.borrow().len()
+    }
+
+    /// Returns the number of requests sent through this client, including failed ones
+    pub fn requests(&self) -> u64 {
+        *self.requests.borrow()
+    }
+
+    /// Returns the clock advanced by simulated latency
+    pub fn clock(&self) -> &TestClock {
+        &self.clock
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+
+    /// Applies the next fault to a request, returning whether the response is lost
+    fn before_request(&self) -> anyhow::Result<bool> {
+        *self.requests.borrow_mut() += 1;
+        let fault = self.faults.borrow_mut().pop_front();
+        match fault {
+            None => Ok(false),
+            Some(Fault::NetworkFailure) => Err(NetworkFailure.into()),
+            Some(Fault::LostResponse) => Ok(true),
+            Some(Fault::Latency(latency)) => {
+                self.clock.advance(latency);
+                Ok(false)
+            }
+            Some(Fault::ServerError { status, message }) => {
+                let body = serde_json::json!({ "message": message }).to_string();
+                Err(crate::Error::from_http_response(status, &body).into())
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for FaultInjector<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        let lost = self.before_request()?;
+        let result = self.inner.execute(stmt).await;
+        if lost {
+            return Err(NetworkFailure.into());
+        }
+        result
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        let lost = self.before_request()?;
+        let result = self.inner.raw_batch(stmts).await;
+        if lost {
+            return Err(NetworkFailure.into());
+        }
+        result
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
+
+/// Configuration of `Chaos`: random latency and error rate applied to requests
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChaosConfig {
+    /// Minimum latency added to each request
+    pub min_latency: Duration,
+    /// Maximum latency added to each request
+    pub max_latency: Duration,
+    /// Probability of a request failing, between 0 and 1
+    pub error_rate: f64,
+    /// Seed of the random generator, which makes a sequence of faults reproducible
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            error_rate: 0.0,
+            seed: None,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Reads the configuration from the `LIBSQL_CLIENT_CHAOS` variable, e.g.
+    /// `LIBSQL_CLIENT_CHAOS="latency_ms=20-200,error_rate=0.05,seed=42"`.
+    /// Returns `None` if the variable is not set, which disables chaos.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match std::env::var("LIBSQL_CLIENT_CHAOS") {
+            Ok(spec) => Self::parse(&spec).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Parses a comma-separated list of `latency_ms=MIN-MAX` (or `latency_ms=N`),
+    /// `error_rate=P` and `seed=N` settings
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for setting in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid chaos setting: {setting}"))?;
+            match name.trim() {
+                "latency_ms" => {
+                    let (min, max) = value.split_once('-').unwrap_or((value, value));
+                    config.min_latency = Duration::from_millis(min.trim().parse()?);
+                    config.max_latency = Duration::from_millis(max.trim().parse()?);
+                    if config.min_latency > config.max_latency {
+                        anyhow::bail!("Invalid chaos latency range: {value}");
+                    }
+                }
+                "error_rate" => {
+                    config.error_rate = value.trim().parse()?;
+                    if !(0.0..=1.0).contains(&config.error_rate) {
+                        anyhow::bail!("Chaos error rate must be between 0 and 1: {value}");
+                    }
+                }
+                "seed" => config.seed = Some(value.trim().parse()?),
+                other => anyhow::bail!("Unknown chaos setting: {other}"),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Client wrapper which adds random latency and failures to requests,
+/// meant for validating the resilience of an application in staging environments.
+/// Failures are simulated with a `FaultInjector` - half of them as HTTP 503 errors,
+/// the other half as network failures.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f() -> anyhow::Result<()> {
+///   # use libsql_client::DatabaseClient;
+///   use libsql_client::testing::Chaos;
+///
+///   // A no-op wrapper unless LIBSQL_CLIENT_CHAOS is set
+///   let db = Chaos::from_env(libsql_client::new_client().await?)?;
+///   db.execute("SELECT 1").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct Chaos<Client: DatabaseClient> {
+    inner: FaultInjector<Client>,
+    config: Option<ChaosConfig>,
+    rng: RefCell<TestRng>,
+}
+
+impl<Client: DatabaseClient> Chaos<Client> {
+    /// Wraps a client with the given chaos configuration
+    pub fn new(inner: Client, config: ChaosConfig) -> Self {
+        Self::with_config(inner, Some(config))
+    }
+
+    /// Wraps a client, configured with `ChaosConfig::from_env()`
+    pub fn from_env(inner: Client) -> anyhow::Result<Self> {
+        Ok(Self::with_config(inner, ChaosConfig::from_env()?))
+    }
+
+    fn with_config(inner: Client, config: Option<ChaosConfig>) -> Self {
+        let seed = config.as_ref().and_then(|c| c.seed).unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        if let Some(config) = &config {
+            tracing::debug!(?config, "Chaos enabled");
+        }
+        Self {
+            inner: FaultInjector::new(inner),
+            config,
+            rng: RefCell::new(TestRng::new(seed)),
+        }
+    }
+
+    /// Returns whether chaos is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner.into_inner()
+    }
+
+    /// Sleeps for a random latency and schedules a random fault for the next request
+    async fn before_request(&self) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let spread = config.max_latency - config.min_latency;
+        let latency = config.min_latency + spread.mul_f64(self.rng.borrow_mut().next_f64());
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        if self.rng.borrow_mut().next_f64() < config.error_rate {
+            let fault = if self.rng.borrow_mut().next_f64() < 0.5 {
+                Fault::ServerError {
+                    status: 503,
+                    message: "Simulated server error".to_string(),
+                }
+            } else {
+                Fault::NetworkFailure
+            };
+            tracing::debug!(?fault, "Chaos injected a fault");
+            self.inner.inject(fault);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for Chaos<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        self.before_request().await;
+        self.inner.execute(stmt).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        self.before_request().await;
+        self.inner.raw_batch(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
+
+/// Deterministic pseudo-random number generator (splitmix64), for reproducible tests
+#[derive(Clone, Debug)]
+pub struct TestRng {
+    state: u64,
+}
+
+impl TestRng {
+    /// Creates a generator; the same seed always yields the same sequence
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns a uniformly distributed 64-bit number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a uniformly distributed number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a uniformly distributed number in [0, bound)
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound.max(1) as u64) as usize
+    }
+}
+
+/// Generates an arbitrary value, biased towards edge cases: extreme and zero numbers,
+/// negative zero, empty strings and blobs, non-ASCII text, quotes and NUL characters.
+/// Floats are always finite, since NaN and infinities have no portable encoding.
+///
+/// # Examples
+///
+/// ```rust
+///   use libsql_client::testing::{arbitrary_value, assert_json_round_trip, TestRng};
+///
+///   let mut rng = TestRng::new(42);
+///   for _ in 0..1000 {
+///       assert_json_round_trip(&arbitrary_value(&mut rng));
+///   }
+/// ```
+pub fn arbitrary_value(rng: &mut TestRng) -> Value {
+    const INTEGERS: [i64; 6] = [0, 1, -1, i64::MIN, i64::MAX, 1 << 53];
+    const FLOATS: [f64; 7] = [0.0, -0.0, 1.5, f64::MIN, f64::MAX, f64::EPSILON, 1e-310];
+    const TEXTS: [&str; 6] = ["", "\"'\\", "\0", "zażółć gęślą jaźń", "🦀", "NULL"];
+    let edge_case = rng.below(4) == 0;
+    match rng.below(5) {
+        0 => Value::Null,
+        1 if edge_case => Value::Integer {
+            value: INTEGERS[rng.below(INTEGERS.len())],
+        },
+        1 => Value::Integer {
+            value: rng.next_u64() as i64,
+        },
+        2 if edge_case => Value::Float {
+            value: FLOATS[rng.below(FLOATS.len())],
+        },
+        2 => {
+            let value = f64::from_bits(rng.next_u64());
+            Value::Float {
+                value: if value.is_finite() {
+                    value
+                } else {
+                    rng.next_f64()
+                },
+            }
+        }
+        3 if edge_case => Value::Text {
+            value: TEXTS[rng.below(TEXTS.len())].to_string(),
+        },
+        3 => {
+            let len = rng.below(32);
+            let value = (0..len)
+                .filter_map(|_| char::from_u32(rng.below(0x800) as u32))
+                .collect();
+            Value::Text { value }
+        }
+        _ => {
+            let len = if edge_case { 0 } else { rng.below(64) };
+            Value::Blob {
+                value: (0..len).map(|_| rng.next_u64() as u8).collect(),
+            }
+        }
+    }
+}
+
+/// Returns whether two values are identical; floats are compared bitwise,
+/// so that negative zero is told apart from zero
+pub fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Null, Value::Null) => true,
+        (Value::Integer { value: a }, Value::Integer { value: b }) => a == b,
+        (Value::Float { value: a }, Value::Float { value: b }) => a.to_bits() == b.to_bits(),
+        (Value::Text { value: a }, Value::Text { value: b }) => a == b,
+        (Value::Blob { value: a }, Value::Blob { value: b }) => a == b,
+        _ => false,
+    }
+}
+
+/// Asserts that a value survives being encoded and decoded by a codec.
+///
+/// # Panics
+/// Panics if decoding fails or yields a different value.
+///
+/// # Examples
+///
+/// ```rust
+///   use libsql_client::testing::{arbitrary_value, assert_round_trip, TestRng};
+///   use libsql_client::Value;
+///
+///   let mut rng = TestRng::new(7);
+///   for _ in 0..1000 {
+///       assert_round_trip(
+///           &arbitrary_value(&mut rng),
+///           |value| serde_json::to_vec(value).unwrap(),
+///           |bytes| Ok(serde_json::from_slice::<Value>(&bytes)?),
+///       );
+///   }
+/// ```
+pub fn assert_round_trip<T>(
+    value: &Value,
+    encode: impl FnOnce(&Value) -> T,
+    decode: impl FnOnce(T) -> anyhow::Result<Value>,
+) {
+    let decoded = match decode(encode(value)) {
+        Ok(decoded) => decoded,
+        Err(e) => panic!("Failed to decode {value:?}: {e}"),
+    };
+    assert!(
+        same_value(value, &decoded),
+        "Value changed in a round trip: {value:?} was decoded as {decoded:?}"
+    );
+}
+
+/// Asserts that a value survives the JSON encoding of the Hrana protocol
+pub fn assert_json_round_trip(value: &Value) {
+    assert_round_trip(value, serde_json::to_string::<Value>, |json| {
+        Ok(serde_json::from_str(&json?)?)
+    })
+}
+
+/// Asserts that a value survives the encoding used by HTTP backends
+/// for statement parameters and results
+pub fn assert_http_round_trip(value: &Value) {
+    assert_round_trip(
+        value,
+        crate::pipeline::encode_value,
+        crate::pipeline::decode_value,
+    )
+}
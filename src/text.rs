@@ -0,0 +1,73 @@
+//! Handling of TEXT values which are not valid UTF-8.
+//!
+//! SQLite does not validate the encoding of text, so a TEXT column may contain
+//! arbitrary bytes, e.g. written through a BLOB cast or by another client.
+//! `InvalidUtf8` decides what the client does when it reads such a value.
+//!
+//! Remote backends receive results as JSON, which the server always encodes
+//! as valid UTF-8, so the policy only applies to backends reading raw values,
+//! i.e. the local backend.
+
+use anyhow::Result;
+
+use crate::Value;
+
+/// Policy for reading TEXT values which are not valid UTF-8
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Replace invalid sequences with U+FFFD REPLACEMENT CHARACTER
+    #[default]
+    Lossy,
+    /// Fail the statement
+    Error,
+    /// Return the raw bytes as a blob
+    Blob,
+}
+
+impl InvalidUtf8 {
+    /// Converts the raw bytes of a TEXT value according to the policy
+    #[cfg_attr(not(feature = "local_backend"), allow(dead_code))]
+    pub(crate) fn decode(self, bytes: &[u8]) -> Result<Value> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(Value::Text {
+                value: text.to_string(),
+            }),
+            Err(e) => match self {
+                Self::Lossy => Ok(Value::Text {
+                    value: String::from_utf8_lossy(bytes).into_owned(),
+                }),
+                Self::Error => Err(anyhow::anyhow!("TEXT value is not valid UTF-8: {e}")),
+                Self::Blob => Ok(Value::Blob {
+                    value: bytes.to_vec(),
+                }),
+            },
+        }
+    }
+}
+
+/// Access to the raw bytes of values
+pub trait ValueExt {
+    /// Returns the bytes of a TEXT or BLOB value, which is how a TEXT value read
+    /// with `InvalidUtf8::Blob` can be accessed regardless of its encoding
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libsql_client::text::ValueExt;
+    /// use libsql_client::Value;
+    ///
+    /// let value = Value::Text { value: "libSQL".into() };
+    /// assert_eq!(value.as_bytes(), Some("libSQL".as_bytes()));
+    /// ```
+    fn as_bytes(&self) -> Option<&[u8]>;
+}
+
+impl ValueExt for Value {
+    fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Text { value } => Some(value.as_bytes()),
+            Value::Blob { value } => Some(value),
+            _ => None,
+        }
+    }
+}
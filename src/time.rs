@@ -0,0 +1,141 @@
+//! `time` contains helpers for storing timestamps consistently and decoding them
+//! into `chrono::DateTime<FixedOffset>`. Encoding and decoding require the `chrono` feature.
+//!
+//! SQLite has no timestamp type: dates are stored as text, e.g. `CURRENT_TIMESTAMP`
+//! yields `YYYY-MM-DD HH:MM:SS` in UTC with no offset, or as unix epoch numbers.
+//! Decoding accepts all of these representations, while encoding follows
+//! the `TimestampFormat` chosen in `Config`, so that stored values compare correctly.
+
+/// Representation of timestamps written by `TimestampFormat::encode()`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// UTC ISO-8601 text with millisecond precision, e.g. `2023-04-01T12:30:00.000Z`.
+    /// It sorts chronologically and is understood by SQLite date functions.
+    #[default]
+    Iso8601,
+    /// Integer number of seconds since the unix epoch
+    UnixEpoch,
+}
+
+#[cfg(feature = "chrono")]
+mod codec {
+    use anyhow::{anyhow, Result};
+    use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+    use super::TimestampFormat;
+    use crate::Value;
+
+    /// Formats of timestamps without an offset, which SQLite assumes to be in UTC
+    const NAIVE_FORMATS: [&str; 4] = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ];
+
+    /// Formats of timestamps with an offset, other than RFC 3339
+    const OFFSET_FORMATS: [&str; 4] = [
+        "%Y-%m-%d %H:%M:%S%.f%:z",
+        "%Y-%m-%d %H:%M:%S%.f %:z",
+        "%Y-%m-%d %H:%M%:z",
+        "%Y-%m-%dT%H:%M%:z",
+    ];
+
+    impl TimestampFormat {
+        /// Encodes a timestamp as a value, converted to UTC
+        pub fn encode<Tz: TimeZone>(self, timestamp: DateTime<Tz>) -> Value {
+            let timestamp = timestamp.with_timezone(&Utc);
+            match self {
+                Self::Iso8601 => Value::Text {
+                    value: timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                },
+                Self::UnixEpoch => Value::Integer {
+                    value: timestamp.timestamp(),
+                },
+            }
+        }
+    }
+
+    /// Decodes a timestamp from a value:
+    /// * INTEGER and REAL values are seconds since the unix epoch
+    /// * TEXT values are RFC 3339, or one of the formats produced by SQLite date functions,
+    ///   with either a space or `T` as the separator, optional fractional seconds
+    ///   and an optional offset. Timestamps without an offset are in UTC,
+    ///   and a date alone stands for midnight.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{Config, DatabaseClient, Statement};
+    ///   use libsql_client::time::{parse_timestamp, TimestampFormat};
+    ///
+    ///   let config = Config::new("file:////tmp/example.db")?.timestamp_format(TimestampFormat::UnixEpoch);
+    ///   let format = config.timestamp_format;
+    ///   let db = libsql_client::new_client_from_config(config).await?;
+    ///   db.execute(Statement::with_args(
+    ///       "INSERT INTO events(created_at) VALUES (?)",
+    ///       &[format.encode(chrono::Utc::now())],
+    ///   ))
+    ///   .await?;
+    ///
+    ///   let result = db.execute("SELECT created_at, CURRENT_TIMESTAMP FROM events").await?;
+    ///   for row in &result.rows {
+    ///       let created_at = parse_timestamp(&row.values[0])?;
+    ///       let now = parse_timestamp(&row.values[1])?;
+    ///   }
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn parse_timestamp(value: &Value) -> Result<DateTime<FixedOffset>> {
+        let timestamp = match value {
+            Value::Integer { value } => DateTime::from_timestamp(*value, 0),
+            Value::Float { value } if value.is_finite() => {
+                let secs = value.floor();
+                let nanos = ((value - secs) * 1e9).round().min(999_999_999.0);
+                DateTime::from_timestamp(secs as i64, nanos as u32)
+            }
+            Value::Text { value } => return parse_text(value.trim()),
+            _ => anyhow::bail!("Cannot decode a timestamp from {value:?}"),
+        };
+        timestamp
+            .map(|t| t.fixed_offset())
+            .ok_or_else(|| anyhow!("Timestamp {value:?} is out of range"))
+    }
+
+    fn parse_text(text: &str) -> Result<DateTime<FixedOffset>> {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+            return Ok(timestamp);
+        }
+        // A trailing Z is allowed in SQLite, regardless of the separator
+        if let Some(utc) = text.strip_suffix(['Z', 'z']) {
+            return parse_naive(utc.trim_end()).ok_or_else(|| invalid(text));
+        }
+        if let Some(timestamp) = OFFSET_FORMATS
+            .iter()
+            .find_map(|format| DateTime::parse_from_str(text, format).ok())
+        {
+            return Ok(timestamp);
+        }
+        parse_naive(text).ok_or_else(|| invalid(text))
+    }
+
+    fn parse_naive(text: &str) -> Option<DateTime<FixedOffset>> {
+        let naive = NAIVE_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })?;
+        Some(naive.and_utc().fixed_offset())
+    }
+
+    fn invalid(text: &str) -> anyhow::Error {
+        anyhow!("Unrecognized timestamp format: {text:?}")
+    }
+}
+
+#[cfg(feature = "chrono")]
+pub use codec::parse_timestamp;
@@ -0,0 +1,178 @@
+//! `timings` breaks down the latency of statements into the phases of their execution,
+//! for attributing slow statements to the client, the network or the server.
+//!
+//! Timings are collected when enabled with `Config::collect_timings()`, and attached to the
+//! `ResultSet` returned by `DatabaseClient::execute()`. Like deadlines, the timings being
+//! collected are carried by the task context of the statement, so concurrent statements
+//! do not mix their measurements.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{Config, DatabaseClient};
+//!   let config = Config::new("https://example.turso.io")?.collect_timings(true);
+//!   let db = libsql_client::new_client_from_config(config).await?;
+//!   let result = db.execute("SELECT * FROM users").await?;
+//!   if let Some(timings) = result.timings {
+//!       println!("{:?} on the network, {:?} in total", timings.network, timings.total);
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Timings>> = const { RefCell::new(None) };
+}
+
+/// Time spent in each phase of the execution of a statement.
+/// Phases which do not apply to a backend stay at zero, e.g. the local backend
+/// does not use the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Timings {
+    /// Time spent waiting before the request could be sent, e.g. before retries
+    pub queue: Duration,
+    /// Time spent encoding the request
+    pub serialize: Duration,
+    /// Time spent sending the request and waiting for the response
+    pub network: Duration,
+    /// Time spent executing the statement, if known: the local backend measures it,
+    /// remote servers do not report it
+    pub server: Option<Duration>,
+    /// Time spent decoding the response
+    pub decode: Duration,
+    /// Total time spent executing the statement
+    pub total: Duration,
+}
+
+/// Phase of the execution of a statement
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Phase {
+    #[cfg_attr(not(feature = "reqwest_backend"), allow(dead_code))]
+    Queue,
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    Serialize,
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend",
+            feature = "hrana_backend",
+            feature = "workers_backend"
+        )),
+        allow(dead_code)
+    )]
+    Network,
+    #[cfg_attr(not(feature = "local_backend"), allow(dead_code))]
+    Server,
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    Decode,
+}
+
+/// Runs `future`, collecting the timings of the statements it executes if `enabled`.
+/// Timings are not collected if the clock is not available,
+/// i.e. in WebAssembly outside of WASI.
+pub(crate) async fn collect<F: Future>(enabled: bool, future: F) -> (F::Output, Option<Timings>) {
+    let start = match now() {
+        Some(start) if enabled => start,
+        _ => return (future.await, None),
+    };
+    let mut future = std::pin::pin!(future);
+    let mut timings = Some(Timings::default());
+    let output = std::future::poll_fn(|cx| {
+        let outer = CURRENT.with(|current| current.replace(timings.take()));
+        let poll = future.as_mut().poll(cx);
+        timings = CURRENT.with(|current| current.replace(outer));
+        poll
+    })
+    .await;
+    let mut timings = timings.unwrap_or_default();
+    timings.total = start.elapsed();
+    (output, Some(timings))
+}
+
+/// Adds `elapsed` to a phase of the timings being collected, if any
+pub(crate) fn record(phase: Phase, elapsed: Duration) {
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        let Some(timings) = current.as_mut() else {
+            return;
+        };
+        match phase {
+            Phase::Queue => timings.queue += elapsed,
+            Phase::Serialize => timings.serialize += elapsed,
+            Phase::Network => timings.network += elapsed,
+            Phase::Server => *timings.server.get_or_insert(Duration::ZERO) += elapsed,
+            Phase::Decode => timings.decode += elapsed,
+        }
+    })
+}
+
+/// Runs `f`, adding the time it takes to a phase of the timings being collected, if any
+#[cfg_attr(
+    not(any(
+        feature = "local_backend",
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn measure<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = collecting().then(now).flatten();
+    let output = f();
+    if let Some(start) = start {
+        record(phase, start.elapsed());
+    }
+    output
+}
+
+/// Runs `future`, adding the time it takes to a phase of the timings being collected, if any
+#[cfg_attr(
+    not(any(
+        feature = "reqwest_backend",
+        feature = "http_backend",
+        feature = "spin_backend",
+        feature = "hrana_backend",
+        feature = "workers_backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn measure_async<F: Future>(phase: Phase, future: F) -> F::Output {
+    let start = collecting().then(now).flatten();
+    let output = future.await;
+    if let Some(start) = start {
+        record(phase, start.elapsed());
+    }
+    output
+}
+
+fn collecting() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Returns the current time, unless the clock is not available,
+/// i.e. in WebAssembly outside of WASI
+pub(crate) fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
@@ -1,17 +1,236 @@
 //! `Transaction` is a structure representing an interactive transaction.
+//!
+//! A transaction dropped without `commit()` or `rollback()`, e.g. while a panic unwinds,
+//! is rolled back by clients which keep a connection, see `DatabaseClient::schedule_rollback()`,
+//! and otherwise stays open on the connection. Such leaks are reported with a warning, along
+//! with a backtrace of where the transaction was created in debug builds (if backtraces are
+//! enabled with `RUST_BACKTRACE`).
+//! Transactions held for longer than `warn_after()` are reported as well, and a transaction
+//! created `with_deadline()` is rolled back once its deadline passes.
 
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::deadline::DeadlineExceeded;
 use crate::{DatabaseClient, ResultSet, Statement};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+/// Threshold for reporting long-running transactions in milliseconds, 0 if disabled
+static WARN_AFTER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of transactions dropped without being committed or rolled back
+static LEAKED: AtomicU64 = AtomicU64::new(0);
+
+/// Reports transactions held for longer than `duration`, or disables the reports with `None`.
+/// The setting applies to all transactions created afterwards.
+pub fn warn_after(duration: Option<Duration>) {
+    let ms = duration.map_or(0, |d| (d.as_millis() as u64).max(1));
+    WARN_AFTER_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Returns the number of transactions dropped without being committed or rolled back
+pub fn leaked_count() -> u64 {
+    LEAKED.load(Ordering::Relaxed)
+}
 
 pub struct Transaction<'a, Client: DatabaseClient + ?Sized> {
     client: &'a Client,
+    deferred: RefCell<Vec<Statement>>,
+    finished: Cell<bool>,
+    tracker: Tracker,
+    /// Whether foreign key constraints are only checked on commit
+    defers_foreign_keys: bool,
+    /// Time after which the transaction is rolled back, see `with_deadline()`
+    deadline: Option<Instant>,
+    /// Whether the transaction was rolled back after its deadline, failing further calls
+    expired: Cell<bool>,
+}
+
+/// Bookkeeping for detecting leaked and long-running transactions
+struct Tracker {
+    backtrace: Option<std::backtrace::Backtrace>,
+    /// Creation time and threshold, only tracked if `warn_after()` is set,
+    /// since the clock is not available on every platform
+    deadline: Option<(Instant, Duration)>,
+    warned: Cell<bool>,
+}
+
+impl Tracker {
+    fn new() -> Self {
+        let backtrace = cfg!(debug_assertions).then(std::backtrace::Backtrace::capture);
+        let ms = WARN_AFTER_MS.load(Ordering::Relaxed);
+        let deadline = (ms > 0).then(|| (Instant::now(), Duration::from_millis(ms)));
+        Self {
+            backtrace,
+            deadline,
+            warned: Cell::new(false),
+        }
+    }
+
+    fn backtrace(&self) -> String {
+        match &self.backtrace {
+            Some(backtrace) => backtrace.to_string(),
+            None => "unavailable".to_string(),
+        }
+    }
+
+    /// Warns once if the transaction has been open for longer than the threshold
+    fn check_duration(&self) {
+        let Some((created, threshold)) = self.deadline else {
+            return;
+        };
+        let elapsed = created.elapsed();
+        if elapsed > threshold && !self.warned.replace(true) {
+            tracing::warn!(
+                ?elapsed,
+                backtrace = %self.backtrace(),
+                "Transaction held for longer than {threshold:?}"
+            );
+        }
+    }
 }
 
 impl<'a, Client: DatabaseClient + ?Sized> Transaction<'a, Client> {
     /// Creates a new transaction.
     pub async fn new(client: &'a Client) -> Result<Transaction<'a, Client>> {
         client.raw_batch(vec![Statement::new("BEGIN")]).await?;
-        Ok(Self { client })
+        Ok(Self::started(client, false))
+    }
+
+    /// Creates a new transaction which checks foreign key constraints when it is committed,
+    /// rather than after each statement, with `PRAGMA defer_foreign_keys`. It lets bulk loads
+    /// insert rows referring to rows which are inserted later.
+    ///
+    /// Deferring is turned off when the transaction finishes, including when it fails:
+    /// a commit rejected because of violated constraints rolls the transaction back.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::{DatabaseClient, Transaction};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let tx = Transaction::with_deferred_foreign_keys(&db).await?;
+    ///   tx.execute("INSERT INTO employees (id, manager_id) VALUES (1, 2)").await?;
+    ///   tx.execute("INSERT INTO employees (id, manager_id) VALUES (2, NULL)").await?;
+    ///   tx.commit().await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub async fn with_deferred_foreign_keys(client: &'a Client) -> Result<Transaction<'a, Client>> {
+        let result = client
+            .raw_batch(vec![
+                Statement::new("BEGIN"),
+                Statement::new("PRAGMA defer_foreign_keys = ON"),
+            ])
+            .await?;
+        let mut errors = result.step_errors.into_iter();
+        if let Some(error) = errors.next().flatten() {
+            anyhow::bail!("Failed to begin the transaction: {}", error.message);
+        }
+        let tx = Self::started(client, true);
+        if let Some(error) = errors.next().flatten() {
+            tx.abort().await;
+            anyhow::bail!("Failed to defer foreign key constraints: {}", error.message);
+        }
+        Ok(tx)
+    }
+
+    fn started(client: &'a Client, defers_foreign_keys: bool) -> Self {
+        Self {
+            client,
+            deferred: RefCell::new(Vec::new()),
+            finished: Cell::new(false),
+            tracker: Tracker::new(),
+            defers_foreign_keys,
+            deadline: None,
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Rolls back the transaction once `deadline` passes, so that application code stuck
+    /// while holding it does not keep it open on the server for long. Statements in flight
+    /// are bounded by the time remaining, see `libsql_client::deadline`, and the first call
+    /// after the deadline rolls the transaction back instead of running. From then on,
+    /// the transaction fails every call with an error which downcasts to `DeadlineExceeded`,
+    /// except `rollback()`. No timer runs in the background, so a transaction left idle
+    /// is rolled back by its next call, or when it is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use libsql_client::DatabaseClient;
+    ///   use std::time::{Duration, Instant};
+    ///
+    ///   let db = libsql_client::new_client().await?;
+    ///   let tx = db
+    ///       .transaction()
+    ///       .await?
+    ///       .with_deadline(Instant::now() + Duration::from_secs(5));
+    ///   tx.execute("UPDATE accounts SET balance = balance - 10 WHERE id = 1").await?;
+    ///   tx.commit().await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Checks if the transaction was rolled back after its deadline, rolling it back first
+    /// if the deadline just passed. The clock is not available on every platform, where
+    /// the deadline only bounds statements in flight.
+    async fn check_deadline(&self) -> Result<()> {
+        let passed = match (self.deadline, crate::timings::now()) {
+            (Some(deadline), Some(now)) => now >= deadline,
+            _ => false,
+        };
+        if passed {
+            self.expire().await;
+        }
+        if self.expired.get() {
+            return Err(anyhow::Error::new(DeadlineExceeded)
+                .context("Transaction was rolled back after its deadline passed"));
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction after its deadline, leaving it to the client
+    /// if the rollback fails
+    async fn expire(&self) {
+        self.deferred.take();
+        if self.expired.replace(true) {
+            return;
+        }
+        tracing::warn!(
+            backtrace = %self.tracker.backtrace(),
+            "Transaction held past its deadline, rolling it back"
+        );
+        if self.defers_foreign_keys {
+            self.abort().await;
+        } else if let Err(e) = self.client.execute("ROLLBACK").await {
+            tracing::warn!("Failed to roll back an expired transaction: {e}");
+            self.client.schedule_rollback();
+        }
+        self.finished.set(true);
+    }
+
+    /// Runs `work` within the deadline of the transaction, if any, and rolls the transaction
+    /// back if the deadline interrupted it
+    async fn bounded<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(deadline) = self.deadline else {
+            return work.await;
+        };
+        let result = crate::deadline::with_deadline(deadline, work).await;
+        if let Err(e) = &result {
+            if e.downcast_ref::<DeadlineExceeded>().is_some() {
+                self.expire().await;
+            }
+        }
+        result
     }
 
     /// Executes a statement within the current transaction.
@@ -33,18 +252,137 @@ impl<'a, Client: DatabaseClient + ?Sized> Transaction<'a, Client> {
     ///   # }
     /// ```
     pub async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
-        self.client.execute(stmt.into()).await
+        self.tracker.check_duration();
+        self.check_deadline().await?;
+        self.bounded(self.send(stmt.into())).await
+    }
+
+    /// Executes a statement, after the deferred ones if any
+    async fn send(&self, stmt: Statement) -> Result<ResultSet> {
+        if self.deferred.borrow().is_empty() {
+            return self.client.execute(stmt).await;
+        }
+        // Deferred statements are flushed first, so that the statement observes their effects
+        let mut stmts = self.deferred.take();
+        stmts.push(stmt);
+        let result = self.flush(stmts).await?;
+        result
+            .into_iter()
+            .last()
+            .ok_or_else(|| anyhow!("Unexpected missing result set"))
+    }
+
+    /// Buffers a statement client-side instead of sending it right away.
+    /// Deferred statements are sent as a single batch when the transaction
+    /// is committed, or right before the next `execute()`, which saves
+    /// round trips for writes which don't need intermediate reads.
+    /// Errors of deferred statements are reported by the call which flushed them.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    ///   # async fn f() -> anyhow::Result<()> {
+    ///   # use crate::libsql_client::{DatabaseClient, Statement, args};
+    ///   let db = libsql_client::new_client().await?;
+    ///   let tx = db.transaction().await?;
+    ///   for name in ["John", "Jane", "Jack"] {
+    ///     tx.defer(Statement::with_args("INSERT INTO users (name) VALUES (?)", args![name]));
+    ///   }
+    ///   tx.commit().await?;
+    ///   # Ok(())
+    ///   # }
+    /// ```
+    pub fn defer(&self, stmt: impl Into<Statement>) {
+        self.deferred.borrow_mut().push(stmt.into());
     }
 
     /// Commits the transaction to the database.
+    /// If any of the deferred statements fails, the transaction is rolled back
+    /// and the error is returned.
     pub async fn commit(self) -> Result<()> {
-        self.client.execute("COMMIT").await?;
+        self.tracker.check_duration();
+        self.check_deadline().await?;
+        self.finished.set(true);
+        self.bounded(self.send_commit()).await
+    }
+
+    /// Flushes the deferred statements and commits, rolling back if they fail
+    async fn send_commit(&self) -> Result<()> {
+        let stmts = self.deferred.take();
+        if !stmts.is_empty() {
+            if let Err(e) = self.flush(stmts).await {
+                if self.defers_foreign_keys {
+                    self.abort().await;
+                } else {
+                    self.client.execute("ROLLBACK").await?;
+                }
+                return Err(e);
+            }
+        }
+        if let Err(e) = self.client.execute("COMMIT").await {
+            // A commit rejected because of deferred constraints leaves the transaction open
+            if self.defers_foreign_keys {
+                self.abort().await;
+            }
+            return Err(e);
+        }
         Ok(())
     }
 
     /// Rolls back the transaction, cancelling any of its side-effects.
+    /// Deferred statements are discarded without being sent.
     pub async fn rollback(self) -> Result<()> {
+        self.finished.set(true);
+        self.tracker.check_duration();
+        self.deferred.take();
+        if self.expired.get() {
+            return Ok(());
+        }
         self.client.execute("ROLLBACK").await?;
         Ok(())
     }
+
+    /// Rolls back a failed transaction which deferred foreign key constraints,
+    /// and turns deferring off in case the rollback did not go through
+    async fn abort(&self) {
+        self.finished.set(true);
+        for sql in ["ROLLBACK", "PRAGMA defer_foreign_keys = OFF"] {
+            if let Err(e) = self.client.execute(sql).await {
+                tracing::warn!("Failed to clean up a failed transaction with {sql}: {e}");
+            }
+        }
+    }
+
+    /// Sends statements in a single batch, failing on the first step error
+    async fn flush(&self, stmts: Vec<Statement>) -> Result<Vec<ResultSet>> {
+        let result = self.client.raw_batch(stmts).await?;
+        if let Some(error) = result.step_errors.into_iter().flatten().next() {
+            return Err(anyhow!(error.message));
+        }
+        result
+            .step_results
+            .into_iter()
+            .map(|r| {
+                r.map(ResultSet::from)
+                    .ok_or_else(|| anyhow!("Unexpected missing result set"))
+            })
+            .collect()
+    }
+}
+
+impl<Client: DatabaseClient + ?Sized> Drop for Transaction<'_, Client> {
+    fn drop(&mut self) {
+        if self.finished.get() {
+            return;
+        }
+        LEAKED.fetch_add(1, Ordering::Relaxed);
+        let outcome = if self.client.schedule_rollback() {
+            "it is rolled back"
+        } else {
+            "it stays open on the connection"
+        };
+        tracing::warn!(
+            backtrace = %self.tracker.backtrace(),
+            "Transaction dropped without commit() or rollback(), {outcome}"
+        );
+    }
 }
@@ -0,0 +1,187 @@
+//! Transport layer of the backends, decoupled from the protocol.
+//!
+//! HTTP-flavored backends send requests with an `HttpTransport`,
+//! WebSocket-flavored ones exchange frames with a `FrameTransport`.
+//! The Hrana encoding of statements and results is shared on top of them
+//! (see `pipeline` for HTTP), so a new platform only needs to implement one of the traits.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use crate::proto;
+
+/// Response of an HTTP request sent by an `HttpTransport`
+#[derive(Clone, Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Returns the value of a header, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Fails with the error reported by the server, unless the request succeeded
+    #[cfg_attr(
+        not(any(
+            feature = "reqwest_backend",
+            feature = "http_backend",
+            feature = "spin_backend"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn error_for_status(self) -> Result<Self> {
+        if self.status == 200 {
+            return Ok(self);
+        }
+        let body = String::from_utf8_lossy(&self.body);
+        Err(crate::Error::from_http_response(self.status, &body)
+            .with_retry_after(self.header("retry-after"))
+            .into())
+    }
+}
+
+/// Encoding of blobs in the JSON bodies of requests sent by the http backend, which depends
+/// on the server and its version. Responses are decoded in any of these encodings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlobEncoding {
+    /// `{"base64": "AAE="}`, with the standard alphabet and padding
+    #[default]
+    Base64,
+    /// `{"base64url": "AAE"}`, with the URL-safe alphabet and without padding
+    Base64Url,
+    /// `{"hex": "0001"}`
+    Hex,
+    /// Sends blobs in `Base64` until a response contains a blob in another encoding,
+    /// then in the encoding of the last blob received
+    Detect,
+}
+
+/// Transport layer of HTTP backends, responsible for sending requests
+/// and receiving responses.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # use anyhow::Result;
+///   use libsql_client::transport::{HttpResponse, HttpTransport};
+///
+///   struct HostTransport;
+///
+///   #[async_trait::async_trait(?Send)]
+///   impl HttpTransport for HostTransport {
+///       async fn post(&self, url: &str, headers: &[(&str, &str)], body: Vec<u8>) -> Result<HttpResponse> {
+///           // Call the HTTP host function of the runtime here
+///           # unimplemented!()
+///       }
+///   }
+/// ```
+#[async_trait(?Send)]
+pub trait HttpTransport {
+    /// Sends a POST request with given headers and body
+    async fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<HttpResponse>;
+}
+
+#[async_trait(?Send)]
+impl<T: HttpTransport + ?Sized> HttpTransport for Box<T> {
+    async fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<HttpResponse> {
+        (**self).post(url, headers, body).await
+    }
+}
+
+/// Transport layer of WebSocket backends, responsible for exchanging text frames
+#[async_trait(?Send)]
+pub trait FrameTransport {
+    /// Sends a text frame
+    async fn send(&self, frame: String) -> Result<()>;
+
+    /// Waits for the next text frame
+    async fn recv(&self) -> Result<String>;
+}
+
+/// Hrana stream opened over a `FrameTransport`.
+/// Requests are sent one at a time, on a single stream.
+#[derive(Debug)]
+pub struct HranaStream<T: FrameTransport> {
+    transport: T,
+    next_reqid: std::sync::atomic::AtomicI32,
+}
+
+impl<T: FrameTransport> HranaStream<T> {
+    /// Authenticates with the server and opens a stream
+    ///
+    /// # Arguments
+    /// * `transport` - transport of an established WebSocket connection
+    /// * `jwt` - auth token, if any
+    pub async fn open(transport: T, jwt: Option<String>) -> Result<Self> {
+        let stream = Self {
+            transport,
+            next_reqid: std::sync::atomic::AtomicI32::new(1),
+        };
+        stream.send(&proto::ClientMsg::Hello { jwt }).await?;
+        // NOTICE: only a single stream id is used for now
+        stream
+            .send(&proto::ClientMsg::Request {
+                request_id: 0,
+                request: proto::Request::OpenStream(proto::OpenStreamReq { stream_id: 0 }),
+            })
+            .await?;
+        // Wait for Hello and OpenStream responses
+        for _ in 0..2 {
+            if let proto::ServerMsg::HelloError { error } = stream.recv().await? {
+                anyhow::bail!("Authentication failed: {error}");
+            }
+        }
+        tracing::debug!("Stream opened");
+        Ok(stream)
+    }
+
+    /// Returns the transport of the stream
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Sends a request on the stream and waits for its response
+    pub async fn request(&self, request: proto::Request) -> Result<proto::Response> {
+        // NOTICE: we effectively allow concurrency of 1 here, until we implement
+        // request tracking
+        let request_id = self
+            .next_reqid
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.send(&proto::ClientMsg::Request {
+            request_id,
+            request,
+        })
+        .await?;
+        match self.recv().await? {
+            proto::ServerMsg::ResponseOk { response, .. } => Ok(response),
+            proto::ServerMsg::ResponseError { error, .. } => Err(anyhow!("{error}")),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    async fn send(&self, msg: &proto::ClientMsg) -> Result<()> {
+        self.transport.send(serde_json::to_string(msg)?).await
+    }
+
+    async fn recv(&self) -> Result<proto::ServerMsg> {
+        let frame = self.transport.recv().await?;
+        Ok(serde_json::from_str(&frame)?)
+    }
+}
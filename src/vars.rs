@@ -0,0 +1,155 @@
+//! `SessionVars` emulates session variables, which SQLite lacks: variables are kept
+//! by the client and substituted into statements as bound parameters.
+//!
+//! A variable is referenced as `{{name}}` anywhere a parameter is allowed. Placeholders
+//! within string literals, quoted identifiers and comments are left untouched, and values
+//! are always bound, never spliced into the SQL text, so they cannot inject SQL.
+//! Statements referencing variables may also use anonymous `?` parameters,
+//! but not numbered or named ones.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::{DatabaseClient, Statement};
+//!   use libsql_client::vars::SessionVars;
+//!
+//!   let db = SessionVars::new(libsql_client::new_client().await?);
+//!   db.set_session_var("tenant_id", 42);
+//!   let stmt = Statement::with_args(
+//!       "SELECT * FROM orders WHERE tenant_id = {{tenant_id}} AND status = ?",
+//!       &["paid"],
+//!   );
+//!   let orders = db.execute(stmt).await?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{BatchResult, ClientStats, DatabaseClient, ResultSet, Statement, Value};
+
+/// Client wrapper which substitutes `{{name}}` placeholders of statements
+/// with the values of its session variables
+pub struct SessionVars<Client: DatabaseClient> {
+    inner: Client,
+    vars: Mutex<HashMap<String, Value>>,
+}
+
+impl<Client: DatabaseClient> SessionVars<Client> {
+    /// Wraps a client, without any variables set
+    pub fn new(inner: Client) -> Self {
+        Self {
+            inner,
+            vars: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets a session variable, replacing its previous value
+    pub fn set_session_var(&self, key: impl Into<String>, value: impl Into<Value>) {
+        self.vars().insert(key.into(), value.into());
+    }
+
+    /// Returns the value of a session variable, if it is set
+    pub fn session_var(&self, key: &str) -> Option<Value> {
+        self.vars().get(key).cloned()
+    }
+
+    /// Unsets a session variable, returning its value
+    pub fn unset_session_var(&self, key: &str) -> Option<Value> {
+        self.vars().remove(key)
+    }
+
+    /// Substitutes the placeholders of a statement with parameters bound to the values
+    /// of the variables. It fails if a variable is not set.
+    pub fn render(&self, stmt: impl Into<Statement>) -> Result<Statement> {
+        let mut stmt: Statement = stmt.into();
+        if !stmt.sql.contains("{{") {
+            return Ok(stmt);
+        }
+        let refs = crate::sql::var_refs(&stmt.sql).map_err(|e| anyhow::anyhow!("{e}"))?;
+        if refs.is_empty() {
+            return Ok(stmt);
+        }
+        let vars = self.vars();
+        let mut sql = String::with_capacity(stmt.sql.len());
+        let mut last = 0;
+        for (bound, var) in refs.iter().enumerate() {
+            let Some(value) = vars.get(var.name) else {
+                anyhow::bail!("Session variable {} is not set: {}", var.name, stmt.sql);
+            };
+            sql.push_str(&stmt.sql[last..var.span.start]);
+            sql.push('?');
+            last = var.span.end;
+            // Arguments of the preceding placeholders were inserted already
+            let index = (var.position + bound).min(stmt.args.len());
+            stmt.args.insert(index, value.clone());
+        }
+        sql.push_str(&stmt.sql[last..]);
+        stmt.sql = sql;
+        Ok(stmt)
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+
+    fn vars(&self) -> std::sync::MutexGuard<'_, HashMap<String, Value>> {
+        self.vars.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for SessionVars<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
+        let stmt = self.render(stmt)?;
+        self.inner.execute(stmt).await
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> Result<BatchResult> {
+        let stmts = stmts
+            .into_iter()
+            .map(|stmt| self.render(stmt))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> Result<()> {
+        let stmts = stmts
+            .iter()
+            .map(|stmt| self.render(stmt.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.prewarm(&stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}
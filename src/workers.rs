@@ -4,14 +4,52 @@ use anyhow::Context;
 use async_trait::async_trait;
 use worker::*;
 
-use crate::{BatchResult, ResultSet, Statement};
+pub mod jobs;
+
+use crate::batch::Batch;
+use crate::stats::StatsCollector;
+use crate::transport::{FrameTransport, HranaStream};
+use crate::{BatchResult, ClientStats, ResultSet, Statement};
+
+/// `FrameTransport` implemented with a Workers WebSocket
+#[derive(Debug)]
+pub struct Socket(WebSocket);
+
+#[async_trait(?Send)]
+impl FrameTransport for Socket {
+    async fn send(&self, frame: String) -> anyhow::Result<()> {
+        self.0
+            .send_with_str(frame)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
+
+    async fn recv(&self) -> anyhow::Result<String> {
+        use futures_util::StreamExt;
+
+        let mut event_stream = self.0.events().map_err(|e| anyhow::anyhow!("{e}"))?;
+        match event_stream.next().await {
+            Some(Ok(WebsocketEvent::Message(msg))) => msg
+                .text()
+                .ok_or_else(|| anyhow::anyhow!("unexpected binary message")),
+            Some(Ok(WebsocketEvent::Close(msg))) => {
+                anyhow::bail!("connection closed: {msg:?}")
+            }
+            Some(Err(e)) => anyhow::bail!("{e}"),
+            None => anyhow::bail!("no response"),
+        }
+    }
+}
 
 /// Database client. This is the main structure used to
 /// communicate with the database.
 #[derive(Debug)]
 pub struct Client {
-    socket: WebSocket,
-    next_reqid: std::sync::atomic::AtomicI32,
+    stream: HranaStream<Socket>,
+    stats: StatsCollector,
+    validate_batches: bool,
+    collect_timings: bool,
+    read_only: bool,
+    max_requests: Option<u64>,
 }
 
 impl Client {
@@ -43,6 +81,11 @@ impl Client {
         headers.set("upgrade", "websocket")?;
         headers.set("Authentication", &format!("Bearer {token}"))?;
 
+        let stats = StatsCollector::default();
+        // Opening the WebSocket is a subrequest, counted before the limit is configured
+        stats
+            .start_request(None)
+            .map_err(|e| Error::RustError(format!("{e}")))?;
         let res = Fetch::Request(req).send().await?;
 
         let socket = match res.websocket() {
@@ -54,38 +97,37 @@ impl Client {
             }
         };
 
-        let mut event_stream = socket.events()?;
-
         socket.accept()?;
 
         let jwt = if token.is_empty() { None } else { Some(token) };
-
-        socket.send(&proto::ClientMsg::Hello { jwt })?;
-
-        // NOTICE: only a single stream id is used for now
-        socket.send(&proto::ClientMsg::Request {
-            request_id: 0,
-            request: proto::Request::OpenStream(proto::OpenStreamReq { stream_id: 0 }),
-        })?;
-
-        // Wait for Hello and OpenStream responses
-        // TODO: they could be pipelined with the first request to save latency.
-        // For that, we need to keep the event stream open in the Client,
-        // but that's tricky with the borrow checker.
-        Self::recv_response(&mut event_stream).await?;
-        Self::recv_response(&mut event_stream).await?;
-
-        tracing::debug!("Stream opened");
-        drop(event_stream);
+        let stream = HranaStream::open(Socket(socket), jwt)
+            .await
+            .map_err(|e| Error::RustError(format!("{e}")))?;
         Ok(Self {
-            socket,
-            next_reqid: std::sync::atomic::AtomicI32::new(1),
+            stream,
+            stats,
+            validate_batches: false,
+            collect_timings: false,
+            read_only: false,
+            max_requests: None,
         })
     }
 
     /// Creates a database client from a `Config` object.
+    /// Connection initialization statements are executed on the stream
+    /// right after it is opened.
     pub async fn from_config(config: Config) -> Result<Self> {
-        Self::new(config.url, config.auth_token.unwrap_or_default()).await
+        let init_statements = config.connection_statements();
+        let mut client = Self::new(config.url, config.auth_token.unwrap_or_default()).await?;
+        client.validate_batches = config.validate_batches;
+        client.collect_timings = config.collect_timings;
+        client.max_requests = config.max_requests;
+        for stmt in init_statements {
+            client.execute(stmt).await?;
+        }
+        // Set after the initialization statements, which include `PRAGMA query_only = ON`
+        client.read_only = config.read_only;
+        Ok(client)
     }
 
     /// Creates a database client, given a `Url`
@@ -135,47 +177,28 @@ impl Client {
         .await
     }
 
-    fn send_request(&self, request: proto::Request) -> Result<()> {
-        // NOTICE: we effective allow concurrency of 1 here, until we implement
-        // id allocation andfMe request tracking
-        let request_id = self
-            .next_reqid
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let msg = proto::ClientMsg::Request {
-            request_id,
-            request,
-        };
-
-        self.socket.send(&msg)
+    /// Returns cumulative counters describing the activity of this client
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
     }
 
-    async fn recv_response(event_stream: &mut EventStream<'_>) -> Result<proto::ServerMsg> {
-        use futures_util::StreamExt;
-
-        // NOTICE: we're effectively synchronously waiting for the response here
-        if let Some(event) = event_stream.next().await {
-            match event? {
-                WebsocketEvent::Message(msg) => {
-                    let stmt_result: proto::ServerMsg = msg.json::<proto::ServerMsg>()?;
-                    Ok(stmt_result)
-                }
-                WebsocketEvent::Close(msg) => {
-                    Err(Error::RustError(format!("connection closed: {msg:?}")))
-                }
-            }
-        } else {
-            Err(Error::RustError("no response".to_string()))
-        }
+    /// Returns how many requests may still be sent under the limit set with
+    /// `Config::max_requests()`, if any
+    pub fn requests_remaining(&self) -> Option<u64> {
+        self.stats.requests_remaining(self.max_requests)
     }
 
     async fn raw_batch(
         &self,
         stmts: impl IntoIterator<Item = impl Into<Statement>>,
     ) -> Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)
+            .map_err(|e| Error::RustError(format!("{e}")))?;
+        crate::client::trace_batch(&stmts);
         let mut batch = proto::Batch::new();
 
-        for stmt in stmts.into_iter() {
-            let stmt: Statement = stmt.into();
+        for stmt in stmts {
             let mut hrana_stmt = proto::Stmt::new(stmt.sql, true);
             for param in stmt.args {
                 hrana_stmt.bind(param);
@@ -183,53 +206,69 @@ impl Client {
             batch.step(None, hrana_stmt);
         }
 
-        let mut event_stream = self.socket.events()?;
-
         // NOTICE: if we want to support concurrent requests, we need to
         // actually start managing stream ids
-        self.send_request(proto::Request::Batch(proto::BatchReq {
-            stream_id: 0,
-            batch,
-        }))?;
-
-        match Self::recv_response(&mut event_stream).await? {
-            proto::ServerMsg::ResponseOk {
-                request_id: _,
-                response: proto::Response::Batch(proto::BatchResp { result }),
-            } => Ok(result),
-            proto::ServerMsg::ResponseError {
-                request_id: _,
-                error,
-            } => Err(Error::RustError(format!("{error}"))),
+        let response = self
+            .raw_request(proto::Request::Batch(proto::BatchReq {
+                stream_id: 0,
+                batch,
+            }))
+            .await?;
+
+        match response {
+            proto::Response::Batch(proto::BatchResp { result }) => {
+                self.stats.record_batch(&result);
+                Ok(result)
+            }
             _ => Err(Error::RustError("unexpected response".to_string())),
         }
     }
 
     async fn execute(&self, stmt: impl Into<Statement>) -> Result<ResultSet> {
-        let stmt: Statement = stmt.into();
+        let mut stmt: Statement = stmt.into();
+        crate::client::check_read_only(self.read_only, std::slice::from_ref(&stmt))
+            .map_err(|e| Error::RustError(format!("{e}")))?;
+        let options = crate::client::ResultOptions::take(&mut stmt);
         let mut hrana_stmt = proto::Stmt::new(stmt.sql, true);
         for param in stmt.args {
             hrana_stmt.bind(param);
         }
 
-        let mut event_stream = self.socket.events()?;
-
-        self.send_request(proto::Request::Execute(proto::ExecuteReq {
-            stream_id: 0,
-            stmt: hrana_stmt,
-        }))?;
-        match Self::recv_response(&mut event_stream).await? {
-            proto::ServerMsg::ResponseOk {
-                request_id: _,
-                response: proto::Response::Execute(proto::ExecuteResp { result }),
-            } => Ok(ResultSet::from(result)),
-            proto::ServerMsg::ResponseError {
-                request_id: _,
-                error,
-            } => Err(Error::RustError(format!("{error}"))),
+        let (response, server_stats) = crate::server_stats::collect(self.raw_request(
+            proto::Request::Execute(proto::ExecuteReq {
+                stream_id: 0,
+                stmt: hrana_stmt,
+            }),
+        ))
+        .await;
+        match response? {
+            proto::Response::Execute(proto::ExecuteResp { result }) => {
+                self.stats.record_result(&result);
+                options
+                    .finish(result, None, server_stats)
+                    .map_err(|e| Error::RustError(format!("{e}")))
+            }
             _ => Err(Error::RustError("unexpected response".to_string())),
         }
     }
+
+    /// Sends a raw Hrana request and waits for its response.
+    /// It's an escape hatch for using server features which are not covered
+    /// by the typed API yet. Statements are executed on stream 0,
+    /// which is opened when the client connects.
+    ///
+    /// # Arguments
+    /// * `request` - Hrana protocol request
+    pub async fn raw_request(&self, request: proto::Request) -> Result<proto::Response> {
+        crate::deadline::check().map_err(|e| Error::RustError(format!("{e}")))?;
+        self.stats
+            .start_request(self.max_requests)
+            .map_err(|e| Error::RustError(format!("{e}")))?;
+        self.stream
+            .request(request)
+            .await
+            .map_err(|e| Error::RustError(format!("{e}")))
+    }
 }
 
 #[async_trait(?Send)]
@@ -246,4 +285,198 @@ impl crate::DatabaseClient for Client {
     async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
         self.execute(stmt).await.map_err(|e| anyhow::anyhow!("{e}"))
     }
+
+    async fn run_batch(&self, batch: Batch) -> anyhow::Result<BatchResult> {
+        let stmts: Vec<Statement> = batch.steps().iter().map(|s| s.stmt.clone()).collect();
+        crate::client::check_read_only(self.read_only, &stmts)?;
+        crate::client::trace_batch(&stmts);
+        // Initialization statements were executed when the stream was opened
+        let response = self
+            .raw_request(proto::Request::Batch(proto::BatchReq {
+                stream_id: 0,
+                batch: batch.to_proto(&[]),
+            }))
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        match response {
+            proto::Response::Batch(proto::BatchResp { result }) => {
+                self.stats.record_batch(&result);
+                Ok(result)
+            }
+            _ => anyhow::bail!("unexpected response"),
+        }
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.validate_batches
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.collect_timings
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.stats()
+    }
+}
+
+/// Returns a JSON response with the rows of `result`, exported by `ResultSet::to_json()`
+/// with `integers` deciding how integers beyond 2^53 are sent, so that they do not lose
+/// precision once parsed by JavaScript.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f(db: libsql_client::workers::Client) -> worker::Result<worker::Response> {
+///   # use libsql_client::DatabaseClient;
+///   use libsql_client::json::JsonIntegers;
+///
+///   let result = db
+///       .execute("SELECT id, name FROM users")
+///       .await
+///       .map_err(|e| worker::Error::from(format!("{e}")))?;
+///   libsql_client::workers::json_response(&result, JsonIntegers::StringIfUnsafe)
+///   # }
+/// ```
+pub fn json_response(result: &ResultSet, integers: crate::json::JsonIntegers) -> Result<Response> {
+    Response::from_json(&result.to_json(integers))
+}
+
+/// Caching layer which stores results of read-only statements in Workers KV,
+/// to absorb read load at the edge. Results are keyed by a hash of the statement
+/// and its arguments and expire after a TTL - they are not invalidated by writes,
+/// so it is only suitable for data which may be stale for the duration of the TTL.
+/// Batches and writes are passed through to the wrapped client.
+///
+/// # Examples
+///
+/// ```rust,no_run
+///   # async fn f(env: worker::Env, db: libsql_client::workers::Client) -> anyhow::Result<()> {
+///   # use libsql_client::DatabaseClient;
+///   use libsql_client::workers::KvCache;
+///
+///   let store = env.kv("QUERY_CACHE").map_err(|e| anyhow::anyhow!("{e}"))?;
+///   let db = KvCache::new(db, store, std::time::Duration::from_secs(300));
+///   let result = db.execute("SELECT * FROM countries").await?;
+///   # Ok(())
+///   # }
+/// ```
+pub struct KvCache<C: crate::DatabaseClient> {
+    inner: C,
+    store: worker::kv::KvStore,
+    ttl: std::time::Duration,
+    stats: StatsCollector,
+}
+
+impl<C: crate::DatabaseClient> KvCache<C> {
+    /// Wraps a client, caching results in `store` for `ttl`.
+    /// Workers KV does not support TTLs shorter than 60 seconds, so shorter ones are rounded up.
+    pub fn new(inner: C, store: worker::kv::KvStore, ttl: std::time::Duration) -> Self {
+        Self {
+            inner,
+            store,
+            ttl: ttl.max(std::time::Duration::from_secs(60)),
+            stats: StatsCollector::default(),
+        }
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Computes the cache key of a statement, with a stable 64-bit FNV-1a hash
+    /// of its SQL text and arguments, and of the options which shape or check its result,
+    /// so that statements which only differ by them do not share a cached result
+    fn cache_key(stmt: &Statement) -> String {
+        // The wire encoding is used, since `Display` may redact arguments
+        let key = format!(
+            "{} {:?} {:?} {:?} {:?}",
+            crate::pipeline::encode_statement(stmt),
+            stmt.blob_policy,
+            stmt.decodes,
+            stmt.order_keys,
+            stmt.limits,
+        );
+        let hash = crate::redact::fnv1a(key.as_bytes());
+        format!("libsql:{hash:016x}")
+    }
+
+    async fn cached(&self, key: &str) -> Option<ResultSet> {
+        match self.store.get(key).text().await {
+            Ok(Some(json)) => serde_json::from_str(&json).ok(),
+            Ok(None) => None,
+            Err(e) => {
+                tracing::debug!("Failed to read cached result {key}: {e}");
+                None
+            }
+        }
+    }
+
+    async fn store(&self, key: &str, result: &ResultSet) {
+        let stored = async {
+            let json = serde_json::to_string(result).map_err(|e| anyhow::anyhow!("{e}"))?;
+            self.store
+                .put(key, json)
+                .map_err(|e| anyhow::anyhow!("{e}"))?
+                .expiration_ttl(self.ttl.as_secs())
+                .execute()
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))
+        };
+        if let Err(e) = stored.await {
+            tracing::debug!("Failed to cache result {key}: {e}");
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: crate::DatabaseClient> crate::DatabaseClient for KvCache<C> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        let read_only = matches!(
+            crate::sql::leading_keyword(&stmt.sql).as_deref(),
+            Some("SELECT" | "VALUES")
+        );
+        if !read_only {
+            return self.inner.execute(stmt).await;
+        }
+        let key = Self::cache_key(&stmt);
+        if let Some(result) = self.cached(&key).await {
+            self.stats.record_cache_hit();
+            return Ok(result);
+        }
+        let result = self.inner.execute(stmt).await?;
+        self.store(&key, &result).await;
+        Ok(result)
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        self.inner.raw_batch(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        let mut stats = self.inner.stats();
+        stats.cache_hits += self.stats.snapshot().cache_hits;
+        stats
+    }
 }
@@ -0,0 +1,203 @@
+//! `jobs` runs the writes of Queue consumers and Cron Triggers within the limits of a single
+//! Workers invocation, which may only make a fixed number of subrequests, 50 on the free plan.
+//!
+//! `write_messages()` applies the statements of a batch of queue messages in as few
+//! requests as the limit allows, each chunk of messages in its own transaction, and reports
+//! which messages were written so that only the others are retried.
+//!
+//! `Checkpoints` stores the progress of cron jobs, stored in the `_libsql_checkpoints` table
+//! by default, so that a job processing more rows than fit in an invocation resumes where the
+//! previous one stopped. `Checkpoints::advance()` commits the writes of a step together with
+//! the checkpoint, so that no step is applied twice.
+//!
+//! ```rust,no_run
+//!   # async fn f(db: &libsql_client::workers::Client, events: Vec<(i64, String)>) -> anyhow::Result<()> {
+//!   # use libsql_client::{args, DatabaseClient, Statement, Value};
+//!   use libsql_client::workers::jobs::{write_messages, Checkpoints};
+//!
+//!   // In a queue consumer
+//!   let writes = write_messages(db, &events, 50, |(id, payload)| {
+//!       vec![Statement::with_args(
+//!           "INSERT INTO events (id, payload) VALUES (?, ?)",
+//!           args!(*id, payload.as_str()),
+//!       )]
+//!   })
+//!   .await;
+//!   for index in writes.failed {
+//!       println!("Retrying event {}", events[index].0);
+//!   }
+//!
+//!   // In a scheduled handler
+//!   let checkpoints = Checkpoints::new(db);
+//!   checkpoints.create().await?;
+//!   let after = checkpoints.load("rollup").await?.unwrap_or_else(|| "0".to_string());
+//!   let rows = db
+//!       .execute(Statement::with_args(
+//!           "SELECT id FROM events WHERE id > ? ORDER BY id LIMIT 500",
+//!           args!(after.as_str()),
+//!       ))
+//!       .await?;
+//!   if let Some(Value::Integer { value: last }) = rows.rows.last().map(|r| &r.values[0]) {
+//!       let rollup = Statement::with_args(
+//!           "INSERT INTO rollups SELECT date(created_at), COUNT(*) FROM events \
+//!            WHERE id > ? AND id <= ? GROUP BY 1",
+//!           args!(after.as_str(), *last),
+//!       );
+//!       checkpoints.advance("rollup", &last.to_string(), vec![rollup]).await?;
+//!   }
+//!   # Ok(())
+//!   # }
+//! ```
+
+use anyhow::Result;
+
+use crate::sql::{quote_ident, NOW_MS};
+use crate::{DatabaseClient, Statement, Value};
+
+/// Default name of the table storing checkpoints
+const DEFAULT_CHECKPOINTS_TABLE: &str = "_libsql_checkpoints";
+
+/// Outcome of `write_messages()`, with messages referred to by their index
+#[derive(Debug, Default)]
+pub struct MessageWrites {
+    /// Messages whose statements were committed, to be acknowledged
+    pub written: Vec<usize>,
+    /// Messages whose chunk failed, to be retried
+    pub failed: Vec<usize>,
+    /// Error of each failed chunk
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl MessageWrites {
+    /// Whether the statements of all messages were committed
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Writes the statements of a batch of messages, produced by `stmts`, in at most
+/// `max_requests` requests. Consecutive messages are grouped into chunks of equal size,
+/// each executed as a transaction, so that a failing message only fails its chunk.
+/// Chunks are executed one after another, and a failed chunk does not stop the next ones.
+pub async fn write_messages<Client, Message>(
+    db: &Client,
+    messages: &[Message],
+    max_requests: usize,
+    stmts: impl Fn(&Message) -> Vec<Statement>,
+) -> MessageWrites
+where
+    Client: DatabaseClient + ?Sized,
+{
+    let mut writes = MessageWrites::default();
+    if messages.is_empty() {
+        return writes;
+    }
+    let chunk_size = messages.len().div_ceil(max_requests.max(1));
+    for (chunk, first) in messages.chunks(chunk_size).zip((0..).step_by(chunk_size)) {
+        let indexes = first..first + chunk.len();
+        let chunk_stmts: Vec<Statement> = chunk.iter().flat_map(&stmts).collect();
+        if chunk_stmts.is_empty() {
+            writes.written.extend(indexes);
+            continue;
+        }
+        match db.batch(chunk_stmts).await {
+            Ok(_) => writes.written.extend(indexes),
+            Err(e) => {
+                tracing::warn!("Failed to write messages {indexes:?}: {e}");
+                writes.failed.extend(indexes);
+                writes.errors.push(e);
+            }
+        }
+    }
+    writes
+}
+
+/// Progress of cron jobs, stored in the `_libsql_checkpoints` table by default.
+/// A checkpoint is an opaque cursor, e.g. the last processed id.
+pub struct Checkpoints<'a, Client: DatabaseClient + ?Sized> {
+    client: &'a Client,
+    table: String,
+}
+
+impl<'a, Client: DatabaseClient + ?Sized> Checkpoints<'a, Client> {
+    /// Creates checkpoints stored in the `_libsql_checkpoints` table
+    pub fn new(client: &'a Client) -> Self {
+        Self {
+            client,
+            table: quote_ident(DEFAULT_CHECKPOINTS_TABLE),
+        }
+    }
+
+    /// Sets the table storing checkpoints
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = quote_ident(table);
+        self
+    }
+
+    /// Creates the table of the checkpoints, if it does not exist yet
+    pub async fn create(&self) -> Result<()> {
+        self.client
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                 job TEXT PRIMARY KEY, \
+                 cursor TEXT NOT NULL, \
+                 updated_at INTEGER NOT NULL)",
+                self.table
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the cursor a job saved last, if any
+    pub async fn load(&self, job: &str) -> Result<Option<String>> {
+        let result = self
+            .client
+            .execute(Statement::with_args(
+                format!("SELECT cursor FROM {} WHERE job = ?", self.table),
+                &[job],
+            ))
+            .await?;
+        match result.rows.first().and_then(|row| row.values.first()) {
+            Some(Value::Text { value }) => Ok(Some(value.clone())),
+            None => Ok(None),
+            value => anyhow::bail!("Unexpected cursor of job {job}: {value:?}"),
+        }
+    }
+
+    /// Saves the cursor of a job
+    pub async fn save(&self, job: &str, cursor: &str) -> Result<()> {
+        self.client.execute(self.save_stmt(job, cursor)).await?;
+        Ok(())
+    }
+
+    /// Executes the writes of a step of a job and saves its cursor in a single transaction,
+    /// so that the step is applied exactly once even if the invocation is cut short
+    pub async fn advance(&self, job: &str, cursor: &str, stmts: Vec<Statement>) -> Result<()> {
+        let stmts = stmts.into_iter().chain([self.save_stmt(job, cursor)]);
+        self.client.batch(stmts).await?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint of a job, which starts over the next time
+    pub async fn reset(&self, job: &str) -> Result<()> {
+        self.client
+            .execute(Statement::with_args(
+                format!("DELETE FROM {} WHERE job = ?", self.table),
+                &[job],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    fn save_stmt(&self, job: &str, cursor: &str) -> Statement {
+        Statement::with_args(
+            format!(
+                "INSERT INTO {} (job, cursor, updated_at) VALUES (?, ?, {NOW_MS}) \
+                 ON CONFLICT (job) DO UPDATE SET \
+                 cursor = excluded.cursor, updated_at = excluded.updated_at",
+                self.table
+            ),
+            &[job, cursor],
+        )
+    }
+}
@@ -0,0 +1,134 @@
+//! `SerializedWrites` funnels the writes of many concurrent tasks through a single queue,
+//! one at a time, while reads keep running concurrently.
+//!
+//! Concurrent writers to a SQLite database contend for its write lock, and the ones
+//! which lose fail with `SQLITE_BUSY` (`database is locked`). Serializing the writes of
+//! a client avoids such storms, e.g. for a local database or a replica shared by
+//! many tasks. With the `tokio` feature, a write which still fails due to contention
+//! with other clients is retried according to `SerializedWrites::retry_busy()`,
+//! while holding the queue.
+//!
+//! ```rust,no_run
+//!   # async fn f() -> anyhow::Result<()> {
+//!   # use libsql_client::DatabaseClient;
+//!   use libsql_client::writes::SerializedWrites;
+//!
+//!   let db = SerializedWrites::new(libsql_client::local::Client::new("/tmp/example.db")?);
+//!   let (a, b) = tokio::join!(
+//!       db.execute("INSERT INTO events VALUES ('a')"),
+//!       db.execute("INSERT INTO events VALUES ('b')"),
+//!   );
+//!   a?;
+//!   b?;
+//!   # Ok(())
+//!   # }
+//! ```
+
+use async_trait::async_trait;
+
+use crate::priority::Permits;
+use crate::{
+    BatchResult, ClientStats, DatabaseClient, Priority, ResultSet, RetryPolicy, Statement,
+};
+
+/// Client wrapper which executes writes one at a time, in a single queue.
+/// Statements which cannot write, e.g. `SELECT`, bypass the queue, and batches
+/// wait in it if any of their statements may write. Statements of an interactive
+/// transaction are queued one by one, not for the whole transaction.
+/// Queued writes go through by priority, see `Statement::priority()`.
+pub struct SerializedWrites<Client: DatabaseClient> {
+    inner: Client,
+    lock: Permits,
+    busy_retry: RetryPolicy,
+}
+
+impl<Client: DatabaseClient> SerializedWrites<Client> {
+    /// Wraps a client, without retrying writes which fail with `SQLITE_BUSY`
+    pub fn new(inner: Client) -> Self {
+        Self {
+            inner,
+            lock: Permits::new(1),
+            busy_retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Retries single statements which fail because the database is busy or locked,
+    /// e.g. by writes of other clients, with the backoff of `policy`.
+    /// The queue is held while waiting, so that other writes do not add to the contention.
+    #[cfg(feature = "tokio")]
+    pub fn retry_busy(mut self, policy: RetryPolicy) -> Self {
+        self.busy_retry = policy;
+        self
+    }
+
+    /// Returns the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Returns the wrapped client
+    pub fn into_inner(self) -> Client {
+        self.inner
+    }
+}
+
+#[async_trait(?Send)]
+impl<Client: DatabaseClient> DatabaseClient for SerializedWrites<Client> {
+    async fn execute(&self, stmt: impl Into<Statement>) -> anyhow::Result<ResultSet> {
+        let stmt: Statement = stmt.into();
+        if crate::sql::is_read_only(&stmt.sql) {
+            return self.inner.execute(stmt).await;
+        }
+        let _guard = self.lock.acquire(stmt.priority).await;
+        let mut attempt = 0;
+        loop {
+            let err = match self.inner.execute(stmt.clone()).await {
+                Err(e) if crate::error::is_busy(&e.to_string()) => e,
+                result => return result,
+            };
+            let Some(delay) = self.busy_retry.backoff(attempt) else {
+                return Err(err);
+            };
+            tracing::debug!("Database is busy, retrying the write in {delay:?}: {err}");
+            #[cfg(feature = "tokio")]
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn raw_batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> anyhow::Result<BatchResult> {
+        let stmts: Vec<Statement> = stmts.into_iter().map(|s| s.into()).collect();
+        if stmts.iter().all(|stmt| crate::sql::is_read_only(&stmt.sql)) {
+            return self.inner.raw_batch(stmts).await;
+        }
+        let _guard = self.lock.acquire(Priority::of(&stmts)).await;
+        self.inner.raw_batch(stmts).await
+    }
+
+    async fn prewarm(&self, stmts: &[Statement]) -> anyhow::Result<()> {
+        self.inner.prewarm(stmts).await
+    }
+
+    fn validates_batches(&self) -> bool {
+        self.inner.validates_batches()
+    }
+
+    fn collects_timings(&self) -> bool {
+        self.inner.collects_timings()
+    }
+
+    fn schedule_rollback(&self) -> bool {
+        self.inner.schedule_rollback()
+    }
+
+    async fn barrier(&self) -> anyhow::Result<()> {
+        self.inner.barrier().await
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.inner.stats()
+    }
+}